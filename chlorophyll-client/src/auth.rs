@@ -0,0 +1,183 @@
+//! Optional shared-key packet authentication: signing what we send, verifying and
+//! replay-checking what we receive. The frame format lives in [`chlorophyll_protocol::auth`].
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use chlorophyll_protocol::Packet;
+use chlorophyll_protocol::auth::{AuthError, ReplayGuard, verify};
+
+/// Per-deployment shared key, and what to do with packets that don't carry a MAC.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthConfig {
+    pub key: Vec<u8>,
+    /// Drop unauthenticated packets. When `false` they are accepted, but the sensor is
+    /// flagged as unauthenticated in [`crate::DeviceInfo`].
+    pub require: bool,
+}
+
+impl AuthConfig {
+    #[must_use]
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: key.into(),
+            require: true,
+        }
+    }
+}
+
+impl fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("key", &"<redacted>")
+            .field("require", &self.require)
+            .finish()
+    }
+}
+
+/// Encodes outgoing packets, authenticated when a key is configured.
+#[derive(Debug)]
+pub struct Signer {
+    key: Option<Vec<u8>>,
+    counter: AtomicU64,
+}
+
+fn now_micros() -> u64 {
+    chrono::Utc::now().timestamp_micros().max(0).cast_unsigned()
+}
+
+impl Signer {
+    #[must_use]
+    pub fn new(auth: Option<&AuthConfig>) -> Self {
+        Self {
+            key: auth.map(|a| a.key.clone()),
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    /// Counters follow the clock in microseconds, so they keep increasing across restarts
+    /// without being stored anywhere. Sensors keep a single replay slot for all servers,
+    /// which therefore need roughly synchronised clocks.
+    pub fn encode(&self, packet: &Packet) -> Result<Vec<u8>> {
+        Ok(match &self.key {
+            Some(key) => {
+                let now = now_micros();
+                let prev = self
+                    .counter
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| Some(now.max(last + 1)))
+                    .unwrap_or_default();
+                packet.encode_authenticated(key, now.max(prev + 1))?
+            }
+            None => packet.encode()?,
+        })
+    }
+
+    /// The frames to send a server command as. With `legacy`, some sensor it reaches may
+    /// run firmware from before versioning, which only reads [`Packet::encode_legacy`]:
+    /// without a key that layout goes alone, since every decoder reads it and sending both
+    /// would have current firmware act twice; with one, it goes after the signed frame,
+    /// which current firmware acts on while dropping the unsigned copy.
+    pub fn command_frames(&self, packet: &Packet, legacy: bool) -> Result<Vec<Vec<u8>>> {
+        Ok(match (legacy, &self.key) {
+            (false, _) => vec![self.encode(packet)?],
+            (true, None) => vec![packet.encode_legacy()?],
+            (true, Some(_)) => vec![self.encode(packet)?, packet.encode_legacy()?],
+        })
+    }
+}
+
+/// Checks received packets against the configured key and per-sensor replay counters.
+#[derive(Debug)]
+pub(crate) struct Verifier {
+    auth: Option<AuthConfig>,
+    guard: ReplayGuard,
+}
+
+impl Verifier {
+    pub(crate) fn new(auth: Option<AuthConfig>) -> Self {
+        Self {
+            auth,
+            guard: ReplayGuard::new(),
+        }
+    }
+
+    /// Whether the already-decoded `packet` (from raw `bytes`) carried a valid MAC. An
+    /// error means it must be dropped.
+    pub(crate) fn check(&mut self, bytes: &[u8], packet: &Packet) -> Result<bool, AuthError> {
+        let Some(auth) = &self.auth else {
+            return Ok(false);
+        };
+        match verify(bytes, &auth.key) {
+            Ok(counter) => {
+                // Server commands seen on the group (our own, or another server's) name
+                // their target in `id`, so they don't advance that sensor's counter.
+                if packet.command().from_sensor() {
+                    self.guard.check(packet.id(), counter)?;
+                }
+                Ok(true)
+            }
+            Err(AuthError::Unauthenticated) if !auth.require => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chlorophyll_protocol::temperature::Celsius;
+    use chlorophyll_protocol::{DataType, PacketCommand};
+
+    fn reading() -> Packet {
+        Packet::new(PacketCommand::DataReading(DataType::Temperature(Celsius::new(21.5))), 7)
+    }
+
+    #[test]
+    fn signed_packets_pass_once_and_replays_are_rejected() {
+        let auth = AuthConfig::new(b"key".to_vec());
+        let signer = Signer::new(Some(&auth));
+        let mut verifier = Verifier::new(Some(auth));
+
+        let first = signer.encode(&reading()).unwrap();
+        let second = signer.encode(&reading()).unwrap();
+        assert_eq!(verifier.check(&first, &reading()), Ok(true));
+        assert_eq!(verifier.check(&second, &reading()), Ok(true));
+        assert_eq!(verifier.check(&first, &reading()), Err(AuthError::Replayed));
+    }
+
+    #[test]
+    fn commands_for_older_firmware_also_go_out_in_the_legacy_layout() {
+        let command = Packet::new(PacketCommand::SetName("bench".into()), 7);
+        let legacy = command.encode_legacy().unwrap();
+        let plain = Signer::new(None);
+        assert_eq!(plain.command_frames(&command, false).unwrap(), [command.encode().unwrap()]);
+        // One copy, which every decoder reads.
+        assert_eq!(plain.command_frames(&command, true).unwrap(), [legacy.as_slice()]);
+
+        let auth = AuthConfig::new(b"key".to_vec());
+        let frames = Signer::new(Some(&auth)).command_frames(&command, true).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1], legacy);
+        let mut verifier = Verifier::new(Some(auth));
+        assert_eq!(verifier.check(&frames[0], &command), Ok(true), "current firmware acts on the signed copy");
+        assert_eq!(verifier.check(&frames[1], &command), Err(AuthError::Unauthenticated), "and drops the other");
+    }
+
+    #[test]
+    fn unauthenticated_packets_are_rejected_or_flagged_per_config() {
+        let plain = reading().encode().unwrap();
+
+        let mut strict = Verifier::new(Some(AuthConfig::new(b"key".to_vec())));
+        assert_eq!(strict.check(&plain, &reading()), Err(AuthError::Unauthenticated));
+
+        let mut lenient = Verifier::new(Some(AuthConfig { key: b"key".to_vec(), require: false }));
+        assert_eq!(lenient.check(&plain, &reading()), Ok(false));
+
+        let forged = Signer::new(Some(&AuthConfig::new(b"wrong".to_vec()))).encode(&reading()).unwrap();
+        assert_eq!(lenient.check(&forged, &reading()), Err(AuthError::BadMac));
+
+        let mut open = Verifier::new(None);
+        assert_eq!(open.check(&plain, &reading()), Ok(false));
+    }
+}
@@ -0,0 +1,110 @@
+//! Per-sensor, per-metric corrections applied to raw values as packets are dispatched, so
+//! the registry's live values, the reading feed and everything stored downstream are
+//! already calibrated.
+
+use std::collections::HashMap;
+
+use crate::reading::ReadingKind;
+
+/// How raw values of one metric on one sensor map to true values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Calibration {
+    /// `raw * scale + offset`.
+    Linear { scale: f32, offset: f32 },
+    /// `(raw, true)` reference points, sorted by raw value. Values between points are
+    /// interpolated; values outside extend the nearest segment.
+    Table(Vec<(f32, f32)>),
+}
+
+impl Calibration {
+    /// A table from reference points in any order. `None` with fewer than two points or
+    /// two points at the same raw value, which would leave the mapping undefined.
+    #[must_use]
+    pub fn table(mut points: Vec<(f32, f32)>) -> Option<Self> {
+        if points.len() < 2 || points.iter().any(|(raw, value)| !raw.is_finite() || !value.is_finite()) {
+            return None;
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if points.windows(2).any(|pair| pair[0].0.total_cmp(&pair[1].0).is_ge()) {
+            return None;
+        }
+        Some(Calibration::Table(points))
+    }
+
+    #[must_use]
+    pub fn apply(&self, raw: f32) -> f32 {
+        match self {
+            Calibration::Linear { scale, offset } => raw * scale + offset,
+            Calibration::Table(points) => {
+                // The segment containing `raw`, or the first/last one outside the table.
+                let upper = points
+                    .iter()
+                    .position(|(x, _)| *x > raw)
+                    .unwrap_or(points.len() - 1)
+                    .clamp(1, points.len() - 1);
+                let ((x0, y0), (x1, y1)) = (points[upper - 1], points[upper]);
+                y0 + (raw - x0) * (y1 - y0) / (x1 - x0)
+            }
+        }
+    }
+}
+
+/// The calibrations currently in effect, keyed by sensor and metric. Readings without an
+/// entry pass through unchanged.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Calibrations(HashMap<(u128, ReadingKind), Calibration>);
+
+impl Calibrations {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, sensor_id: u128, kind: ReadingKind, calibration: Calibration) {
+        self.0.insert((sensor_id, kind), calibration);
+    }
+
+    #[must_use]
+    pub fn get(&self, sensor_id: u128, kind: ReadingKind) -> Option<&Calibration> {
+        self.0.get(&(sensor_id, kind))
+    }
+
+    /// `raw` with the sensor's calibration for `kind` applied, if it has one.
+    #[must_use]
+    pub fn apply(&self, sensor_id: u128, kind: ReadingKind, raw: f32) -> f32 {
+        self.get(sensor_id, kind).map_or(raw, |c| c.apply(raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_applies_scale_then_offset() {
+        let calibration = Calibration::Linear { scale: 1.1, offset: -1.5 };
+        assert!((calibration.apply(20.0) - 20.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn tables_interpolate_and_extrapolate_from_the_end_segments() {
+        let table = Calibration::table(vec![(100.0, 120.0), (0.0, 0.0), (50.0, 50.0)]).unwrap();
+        assert!((table.apply(25.0) - 25.0).abs() < 1e-5);
+        assert!((table.apply(75.0) - 85.0).abs() < 1e-5);
+        assert!((table.apply(50.0) - 50.0).abs() < 1e-5);
+        assert!((table.apply(-10.0) + 10.0).abs() < 1e-5);
+        assert!((table.apply(110.0) - 134.0).abs() < 1e-5);
+
+        assert!(Calibration::table(vec![(1.0, 1.0)]).is_none());
+        assert!(Calibration::table(vec![(1.0, 1.0), (1.0, 2.0)]).is_none());
+    }
+
+    #[test]
+    fn uncalibrated_readings_pass_through() {
+        let mut calibrations = Calibrations::new();
+        calibrations.insert(7, ReadingKind::Temperature, Calibration::Linear { scale: 1.0, offset: -1.5 });
+        assert!((calibrations.apply(7, ReadingKind::Temperature, 22.0) - 20.5).abs() < 1e-5);
+        assert!((calibrations.apply(7, ReadingKind::Humidity, 40.0) - 40.0).abs() < 1e-5);
+        assert!((calibrations.apply(8, ReadingKind::Temperature, 22.0) - 22.0).abs() < 1e-5);
+    }
+}
@@ -1,26 +1,72 @@
+use std::future::Future;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Result;
 use chlorophyll_protocol::PacketCommand;
+use chrono::Utc;
 use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
 
+use crate::auth::Signer;
+use crate::calibration::Calibrations;
 use crate::config::ClientConfig;
 use crate::listener;
 use crate::reading::{DeviceInfo, Reading};
-use crate::registry::Registry;
+use crate::registry::{Registry, RegistryEvent};
+use crate::stats::ListenerStats;
 
 const READING_CHANNEL_CAPACITY: usize = 256;
+/// Registry changes are rare next to readings, so a small buffer suffices.
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// How [`SensorClient::set_name`] waits for a sensor to confirm a rename.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenameRetry {
+    /// `SetName` is sent at most this many times.
+    pub attempts: u32,
+    /// How long each attempt waits for the sensor to announce the name.
+    pub timeout: Duration,
+}
+
+impl Default for RenameRetry {
+    fn default() -> Self {
+        Self { attempts: 3, timeout: Duration::from_secs(2) }
+    }
+}
+
+#[derive(Debug)]
+pub enum RenameError {
+    /// The sensor didn't announce the new name after any attempt.
+    TimedOut,
+    /// `SetName` couldn't be sent.
+    Send(anyhow::Error),
+}
+
+impl std::fmt::Display for RenameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenameError::TimedOut => f.write_str("the sensor didn't confirm the new name"),
+            RenameError::Send(e) => write!(f, "cannot send SetName: {e:#}"),
+        }
+    }
+}
+
+impl std::error::Error for RenameError {}
 
 /// Handle to a running multicast sensor listener.
 ///
 /// Spawns a dedicated blocking thread that joins the multicast group, decodes
 /// postcard `Packet`s, maintains a [`Registry`] of known devices, and fans out
-/// [`Reading`]s on a broadcast channel.
+/// [`Reading`]s and [`RegistryEvent`]s on broadcast channels.
 #[derive(Debug)]
 pub struct SensorClient {
     cfg: ClientConfig,
     registry: Arc<Mutex<Registry>>,
     tx: broadcast::Sender<Reading>,
+    events_tx: broadcast::Sender<RegistryEvent>,
+    stats: Arc<ListenerStats>,
+    signer: Arc<Signer>,
 }
 
 impl SensorClient {
@@ -28,14 +74,30 @@ impl SensorClient {
     /// thread for the receive loop (tokio's async UDP readiness doesn't fire for
     /// this multicast socket on macOS).
     pub fn start(cfg: ClientConfig) -> Result<Self> {
-        let registry = Arc::new(Mutex::new(Registry::new()));
+        let registry = Arc::new(Mutex::new(Registry::with_timeouts(cfg.status_timeouts)));
         let (tx, _rx) = broadcast::channel(READING_CHANNEL_CAPACITY);
+        let (events_tx, _events_rx) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
 
         let thread_registry = registry.clone();
         let thread_tx = tx.clone();
-        std::thread::spawn(move || listener::run(cfg, thread_registry, thread_tx));
+        let thread_events_tx = events_tx.clone();
+        let stats = Arc::new(ListenerStats::default());
+        let thread_stats = stats.clone();
+        let signer = Arc::new(Signer::new(cfg.auth.as_ref()));
+        let thread_signer = signer.clone();
+        let thread_cfg = cfg.clone();
+        std::thread::spawn(move || {
+            listener::run(thread_cfg, thread_registry, thread_tx, thread_events_tx, thread_stats, thread_signer);
+        });
 
-        Ok(Self { cfg, registry, tx })
+        Ok(Self {
+            cfg,
+            registry,
+            tx,
+            events_tx,
+            stats,
+            signer,
+        })
     }
 
     #[must_use]
@@ -43,19 +105,251 @@ impl SensorClient {
         self.tx.subscribe()
     }
 
+    /// Registry changes (new devices, renames, status transitions) as they are observed.
+    #[must_use]
+    pub fn subscribe_events(&self) -> broadcast::Receiver<RegistryEvent> {
+        self.events_tx.subscribe()
+    }
+
+    /// Packet counters from the listener thread.
+    #[must_use]
+    pub fn stats(&self) -> &ListenerStats {
+        &self.stats
+    }
+
+    /// Every sensor in the registry.
+    ///
+    /// # Panics
+    ///
+    /// If the listener thread panicked while holding the registry lock.
     #[must_use]
     pub fn devices(&self) -> Vec<DeviceInfo> {
         self.registry.lock().unwrap().devices()
     }
 
-    /// Send `SetName` to the multicast group for `id`. The matching sensor stores
-    /// it in NVM and announces a fresh `SensorsInfo` afterwards.
-    pub fn set_name(&self, id: u128, name: &str) -> Result<()> {
-        listener::send_command(self.cfg, PacketCommand::SetName(name.to_string()), id)
+    /// Add sensors remembered from a previous run to the registry, so they are listed by
+    /// name before they next announce themselves. See [`Registry::seed`].
+    ///
+    /// # Panics
+    ///
+    /// If the registry lock is poisoned.
+    pub fn seed(&self, devices: impl IntoIterator<Item = DeviceInfo>) {
+        self.registry.lock().unwrap().seed(devices, chrono::Utc::now());
+    }
+
+    /// Replace the calibrations applied to incoming readings.
+    ///
+    /// # Panics
+    ///
+    /// If the registry lock is poisoned.
+    pub fn set_calibrations(&self, calibrations: Calibrations) {
+        self.registry.lock().unwrap().set_calibrations(calibrations);
+    }
+
+    /// Send `SetName` to the multicast group for `id`, with [`RenameRetry::default`]. See
+    /// [`SensorClient::set_name_with`].
+    pub fn set_name(
+        &self,
+        id: u128,
+        name: &str,
+    ) -> Result<impl Future<Output = Result<(), RenameError>> + Send + 'static> {
+        self.set_name_with(id, name, RenameRetry::default())
+    }
+
+    /// Send `SetName` to the multicast group for `id` and mark the rename pending in the
+    /// registry. The matching sensor stores the name in NVM and announces a fresh
+    /// `SensorsInfo` afterwards; the returned future resolves once that arrives, resending
+    /// `SetName` each time an attempt times out, and gives up after `retry.attempts`.
+    ///
+    /// Fails straight away only if the first `SetName` can't be sent. Dropping the future
+    /// stops the retries and leaves the rename pending until the sensor confirms it, so
+    /// spawn it rather than drop it when the outcome doesn't matter.
+    ///
+    /// # Panics
+    ///
+    /// If the registry lock is poisoned.
+    pub fn set_name_with(
+        &self,
+        id: u128,
+        name: &str,
+        retry: RenameRetry,
+    ) -> Result<impl Future<Output = Result<(), RenameError>> + Send + 'static> {
+        // Subscribed before sending, so a quick answer isn't missed.
+        let events = self.events_tx.subscribe();
+        let (cfg, signer, command) = (self.cfg.clone(), self.signer.clone(), PacketCommand::SetName(name.to_string()));
+        let registry = self.registry.clone();
+        let send = move || {
+            let legacy = registry.lock().unwrap().legacy_commands(Some(id));
+            listener::send_command(&cfg, &signer, command.clone(), id, legacy)
+        };
+
+        update(&self.registry, &self.events_tx, |registry| registry.begin_rename(id, name, Utc::now()));
+        if let Err(e) = send() {
+            update(&self.registry, &self.events_tx, |registry| registry.abandon_rename(id, name, Utc::now()));
+            return Err(e);
+        }
+        let rename = Rename {
+            registry: self.registry.clone(),
+            events_tx: self.events_tx.clone(),
+            events,
+            id,
+            name: name.to_string(),
+        };
+        Ok(rename.confirm(retry, send))
+    }
+
+    /// Handle `packet` as if the listener had received it, unauthenticated, for tests
+    /// that have no socket. Skips the verifier, so it is never built into a release.
+    #[cfg(feature = "test-util")]
+    pub fn receive(&self, packet: &chlorophyll_protocol::Packet) {
+        listener::apply(&self.registry, &self.tx, &self.events_tx, packet, false, Utc::now());
     }
 
     /// Broadcast `RequestSensorInfo` to the multicast group.
+    ///
+    /// # Panics
+    ///
+    /// If the registry lock is poisoned.
     pub fn request_sensor_info(&self) -> Result<()> {
-        listener::send_command(self.cfg, PacketCommand::RequestSensorInfo, 0)
+        let legacy = self.registry.lock().unwrap().legacy_commands(None);
+        listener::send_command(&self.cfg, &self.signer, PacketCommand::RequestSensorInfo, 0, legacy)
+    }
+}
+
+/// Change the registry outside the listener thread, and fan out the events that records.
+fn update(
+    registry: &Mutex<Registry>,
+    events_tx: &broadcast::Sender<RegistryEvent>,
+    change: impl FnOnce(&mut Registry),
+) {
+    let events = {
+        let mut registry = registry.lock().unwrap();
+        change(&mut registry);
+        registry.take_events()
+    };
+    for event in events {
+        let _ = events_tx.send(event);
+    }
+}
+
+/// A rename sent once, waiting on its confirmation.
+struct Rename {
+    registry: Arc<Mutex<Registry>>,
+    events_tx: broadcast::Sender<RegistryEvent>,
+    events: broadcast::Receiver<RegistryEvent>,
+    id: u128,
+    name: String,
+}
+
+impl Rename {
+    async fn confirm(mut self, retry: RenameRetry, send: impl Fn() -> Result<()> + Send) -> Result<(), RenameError> {
+        for attempt in 1..=retry.attempts.max(1) {
+            if attempt > 1
+                && let Err(e) = send()
+            {
+                self.abandon();
+                return Err(RenameError::Send(e));
+            }
+            if tokio::time::timeout(retry.timeout, self.confirmed()).await.is_ok() {
+                return Ok(());
+            }
+            tracing::debug!("{:032x} hasn't confirmed its new name after attempt {attempt}", self.id);
+        }
+        self.abandon();
+        Err(RenameError::TimedOut)
+    }
+
+    /// Resolves once the sensor has announced the name.
+    async fn confirmed(&mut self) {
+        loop {
+            match self.events.recv().await {
+                Ok(RegistryEvent::RenameSettled { id, name, confirmed: true, .. })
+                    if id == self.id && name == self.name =>
+                {
+                    return;
+                }
+                // Missed events may have held the confirmation; the registry still knows.
+                Err(RecvError::Lagged(_)) if self.settled() => return,
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                // Never happens: `events_tx` is held here.
+                Err(RecvError::Closed) => std::future::pending().await,
+            }
+        }
+    }
+
+    /// Whether the sensor now goes by the name and the rename is no longer pending.
+    fn settled(&self) -> bool {
+        let registry = self.registry.lock().unwrap();
+        registry.devices().iter().any(|device| {
+            device.id == self.id && device.name.as_ref() == Some(&self.name) && device.pending_name.is_none()
+        })
+    }
+
+    fn abandon(&self) {
+        update(&self.registry, &self.events_tx, |registry| registry.abandon_rename(self.id, &self.name, Utc::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use chlorophyll_protocol::{Packet, SensorInfo};
+
+    use super::*;
+    use crate::registry::dispatch;
+
+    const RETRY: RenameRetry = RenameRetry { attempts: 3, timeout: Duration::from_millis(20) };
+
+    fn rename(registry: &Arc<Mutex<Registry>>, events_tx: &broadcast::Sender<RegistryEvent>) -> Rename {
+        update(registry, events_tx, |registry| registry.begin_rename(7, "bench", Utc::now()));
+        Rename {
+            registry: registry.clone(),
+            events_tx: events_tx.clone(),
+            events: events_tx.subscribe(),
+            id: 7,
+            name: "bench".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn renames_are_resent_until_the_sensor_announces_the_name() {
+        let registry = Arc::new(Mutex::new(Registry::new()));
+        let (events_tx, _) = broadcast::channel(16);
+        let sent = AtomicU32::new(1);
+        let answer = Packet::new(PacketCommand::SensorsInfo(SensorInfo::new(Some("bench".into()))), 7);
+
+        // The sensor misses the first SetName and answers the resend.
+        let resend = || {
+            sent.fetch_add(1, Ordering::Relaxed);
+            update(&registry, &events_tx, |registry| {
+                dispatch(registry, &answer, Utc::now());
+            });
+            Ok(())
+        };
+        rename(&registry, &events_tx).confirm(RETRY, resend).await.unwrap();
+        assert_eq!(sent.load(Ordering::Relaxed), 2);
+        assert_eq!(registry.lock().unwrap().devices()[0].pending_name, None);
+    }
+
+    #[tokio::test]
+    async fn unanswered_renames_time_out_after_every_attempt() {
+        let registry = Arc::new(Mutex::new(Registry::new()));
+        let (events_tx, mut events) = broadcast::channel(16);
+        let sent = AtomicU32::new(1);
+
+        let result = rename(&registry, &events_tx)
+            .confirm(RETRY, || {
+                sent.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+            .await;
+        assert!(matches!(result, Err(RenameError::TimedOut)), "{result:?}");
+        assert_eq!(sent.load(Ordering::Relaxed), 3);
+        assert!(matches!(events.try_recv(), Ok(RegistryEvent::RenamePending { .. })));
+        assert!(matches!(events.try_recv(), Ok(RegistryEvent::RenameSettled { confirmed: false, .. })));
+
+        let result = rename(&registry, &events_tx).confirm(RETRY, || anyhow::bail!("no route")).await;
+        assert!(matches!(result, Err(RenameError::Send(_))), "{result:?}");
     }
 }
@@ -1,9 +1,16 @@
 use std::net::Ipv4Addr;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use crate::auth::AuthConfig;
+use crate::registry::StatusTimeouts;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ClientConfig {
     pub group: Ipv4Addr,
     pub port: u16,
+    /// Shared key for packet authentication; `None` sends and accepts plain packets.
+    pub auth: Option<AuthConfig>,
+    /// When silent sensors are marked stale and offline.
+    pub status_timeouts: StatusTimeouts,
 }
 
 impl Default for ClientConfig {
@@ -11,6 +18,8 @@ impl Default for ClientConfig {
         Self {
             group: Ipv4Addr::new(239, 0, 0, 1),
             port: 5000,
+            auth: None,
+            status_timeouts: StatusTimeouts::default(),
         }
     }
 }
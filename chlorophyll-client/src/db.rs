@@ -1,102 +1,240 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
 use chrono::{DateTime, Utc};
+use futures_util::stream::{self, BoxStream, StreamExt};
 use sqlx::sqlite::SqlitePoolOptions;
-use sqlx::SqlitePool;
+use sqlx::{SqliteConnection, SqlitePool};
+use tokio::sync::mpsc;
+
+use crate::calibration::{Calibration, Calibrations};
+use crate::derived;
+use crate::reading::{DeviceInfo, Reading, ReadingKind};
+use crate::rollup::{Averaged, READINGS_PER_SEC};
 
-use crate::reading::{Reading, ReadingKind};
+mod migrations;
+mod writer;
+
+pub use writer::{BatchWriter, WriterConfig, WriterStats};
 
 #[derive(Debug, Clone)]
-pub struct Db(SqlitePool);
+pub struct Db(SqlitePool, SensorKeys);
 
 /// One point in a metric's history: `(timestamp, value)`.
 pub type Point = (DateTime<Utc>, f32);
 
+/// One bucket of downsampled history: the mean of the readings in it, their extremes, how
+/// many there were and how much of the bucket the stored rows behind it span. Derived
+/// series only have a mean, so their `min` and `max` equal it and `count` and `secs` are 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryPoint {
+    pub at: DateTime<Utc>,
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+    pub count: i64,
+    /// Seconds of the bucket covered by averaged rows; raw readings add nothing.
+    pub secs: i64,
+}
+
+impl derived::Sample for HistoryPoint {
+    fn point(&self) -> Point {
+        (self.at, self.value)
+    }
+
+    fn covered_secs(&self, _bucket_secs: i64) -> i64 {
+        self.secs
+    }
+
+    fn from_point((at, value): Point) -> Self {
+        Self { at, value, min: value, max: value, count: 0, secs: 0 }
+    }
+}
+
+/// Readings refer to sensors by a small integer `key` from `sensor_keys` rather than the
+/// 32-character hex id, so rows and their indexes stay narrow. Keys are never reassigned,
+/// so each one is looked up (or allocated) once per process and remembered.
+#[derive(Debug, Clone, Default)]
+struct SensorKeys(Arc<Mutex<HashMap<u128, i64>>>);
+
+impl SensorKeys {
+    async fn get(&self, conn: &mut SqliteConnection, sensor_id: u128) -> anyhow::Result<i64> {
+        if let Some(&key) = self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner).get(&sensor_id) {
+            return Ok(key);
+        }
+        let id_hex = format!("{sensor_id:032x}");
+        sqlx::query("INSERT INTO sensor_keys (sensor_id) VALUES (?) ON CONFLICT (sensor_id) DO NOTHING")
+            .bind(&id_hex)
+            .execute(&mut *conn)
+            .await?;
+        let key: i64 = sqlx::query_scalar("SELECT key FROM sensor_keys WHERE sensor_id = ?")
+            .bind(&id_hex)
+            .fetch_one(&mut *conn)
+            .await?;
+        self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner).insert(sensor_id, key);
+        Ok(key)
+    }
+
+    /// The key of a sensor already allocated one, without allocating.
+    async fn find(&self, conn: &mut SqliteConnection, sensor_id: u128) -> anyhow::Result<Option<i64>> {
+        if let Some(&key) = self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner).get(&sensor_id) {
+            return Ok(Some(key));
+        }
+        Ok(sqlx::query_scalar("SELECT key FROM sensor_keys WHERE sensor_id = ?")
+            .bind(format!("{sensor_id:032x}"))
+            .fetch_optional(&mut *conn)
+            .await?)
+    }
+}
+
 impl Db {
-    /// Open (or create) the SQLite database at `path` and run migrations.
+    /// Open (or create) the `SQLite` database at `path` and run migrations.
     pub async fn open(path: &str) -> anyhow::Result<Self> {
         let pool = SqlitePoolOptions::new()
             .connect(&format!("sqlite:{path}?mode=rwc"))
             .await?;
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS readings (
-                 id        INTEGER PRIMARY KEY AUTOINCREMENT,
-                 sensor_id TEXT    NOT NULL,
-                 timestamp TEXT    NOT NULL,
-                 data_type TEXT    NOT NULL,
-                 value     REAL    NOT NULL
-             );
-             CREATE INDEX IF NOT EXISTS idx_readings_sensor_timestamp
-                 ON readings (sensor_id, timestamp);",
-        )
-        .execute(&pool)
-        .await?;
+        migrations::run(&pool).await?;
+
+        Ok(Self(pool, SensorKeys::default()))
+    }
 
-        // Resolution of each row, in seconds; 0 marks pre-rollup rows written at the raw
-        // sensor rate. Compaction uses it to tell which rows still need coarsening.
-        // ALTER fails once the column exists, which is the normal case after first run.
-        let _ = sqlx::query("ALTER TABLE readings ADD COLUMN bucket_secs INTEGER NOT NULL DEFAULT 0")
-            .execute(&pool)
-            .await;
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_readings_bucket ON readings (bucket_secs, timestamp)")
-            .execute(&pool)
+    /// Open an existing database without writing to it, for inspecting it while it may be
+    /// in use. Errors if its schema isn't at the version this build migrates to, since
+    /// queries assume that schema.
+    pub async fn open_read_only(path: &str) -> anyhow::Result<Self> {
+        let pool = SqlitePoolOptions::new()
+            .connect(&format!("sqlite:{path}?mode=ro"))
             .await?;
+        if let Err(e) = migrations::ensure_current(&pool).await {
+            pool.close().await;
+            return Err(e);
+        }
+
+        Ok(Self(pool, SensorKeys::default()))
+    }
 
-        Ok(Self(pool))
+    /// Wait for queries in flight, then close every connection. Later queries fail.
+    pub async fn close(&self) {
+        self.0.close().await;
     }
 
     pub async fn insert_reading(&self, reading: &Reading) -> anyhow::Result<()> {
-        self.insert_reading_at(reading, 0).await
+        self.insert_reading_at(&Averaged::from(reading.clone()), 0).await
     }
 
     /// Insert a reading already averaged over a `bucket_secs`-wide window.
-    pub async fn insert_reading_at(&self, reading: &Reading, bucket_secs: i64) -> anyhow::Result<()> {
-        let sensor_id = format!("{:032x}", reading.sensor_id);
-        let timestamp = reading.at.to_rfc3339();
-        sqlx::query(
-            "INSERT INTO readings (sensor_id, timestamp, data_type, value, bucket_secs)
-             VALUES (?, ?, ?, ?, ?)",
-        )
-        .bind(&sensor_id)
-        .bind(&timestamp)
-        .bind(reading.kind.as_str())
-        .bind(f64::from(reading.value))
-        .bind(bucket_secs)
-        .execute(&self.0)
-        .await?;
+    pub async fn insert_reading_at(&self, averaged: &Averaged, bucket_secs: i64) -> anyhow::Result<()> {
+        let mut conn = self.0.acquire().await?;
+        let sensor = self.1.get(&mut conn, averaged.reading.sensor_id).await?;
+        insert_averaged(&mut conn, sensor, averaged, bucket_secs).await
+    }
+
+    /// Insert `(reading, bucket_secs)` rows as [`Db::insert_reading_at`] does, all in one
+    /// transaction.
+    pub async fn insert_readings(&self, rows: &[(Averaged, i64)]) -> anyhow::Result<()> {
+        let mut conn = self.0.acquire().await?;
+        // Keys are allocated outside the transaction, so a rollback can't strand a cached one.
+        let mut sensors = Vec::with_capacity(rows.len());
+        for (averaged, _) in rows {
+            sensors.push(self.1.get(&mut conn, averaged.reading.sensor_id).await?);
+        }
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        for ((averaged, bucket_secs), sensor) in rows.iter().zip(sensors) {
+            insert_averaged(&mut tx, sensor, averaged, *bucket_secs).await?;
+        }
+        tx.commit().await?;
         Ok(())
     }
 
+    /// Insert readings averaged over `bucket_secs`-wide windows, as
+    /// [`Db::insert_reading_at`] does, but in one transaction and skipping any already
+    /// stored for the same sensor, metric and timestamp. Returns how many were inserted.
+    ///
+    /// The readings behind a rollup are gone, so one falling in a bucket already rolled up
+    /// for its sensor and metric counts as stored too. Each row weighs as much as the
+    /// readings a sensor sends over its window, so it neither swamps nor vanishes next to
+    /// live history when compaction averages the two.
+    pub async fn import_readings(&self, readings: &[Reading], bucket_secs: i64) -> anyhow::Result<u64> {
+        let mut stored = "SELECT 1 FROM readings WHERE sensor = ?1 AND data_type = ?2 AND ts = ?3".to_string();
+        for tier in self.tiers().await? {
+            let _ = write!(
+                stored,
+                " UNION ALL SELECT 1 FROM {} WHERE ts = ?3 / {width} * {width} AND sensor = ?1 AND data_type = ?2",
+                tier.table(),
+                width = tier.bucket_secs * 1000
+            );
+        }
+        let sql = format!(
+            "INSERT INTO readings (sensor, data_type, ts, value, min, max, samples, bucket_secs)
+             SELECT ?1, ?2, ?3, ?4, ?4, ?4, ?5, ?6
+             WHERE NOT EXISTS ({stored})"
+        );
+        let samples = (bucket_secs * READINGS_PER_SEC).max(1);
+
+        let mut conn = self.0.acquire().await?;
+        let mut sensors = Vec::with_capacity(readings.len());
+        for reading in readings {
+            sensors.push(self.1.get(&mut conn, reading.sensor_id).await?);
+        }
+        let mut tx = sqlx::Connection::begin(&mut *conn).await?;
+        let mut inserted = 0;
+        for (reading, sensor) in readings.iter().zip(sensors) {
+            inserted += sqlx::query(&sql)
+                .bind(sensor)
+                .bind(reading.kind.as_str())
+                .bind(reading.at.timestamp_millis())
+                .bind(f64::from(reading.value))
+                .bind(samples)
+                .bind(bucket_secs)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        tx.commit().await?;
+        Ok(inserted)
+    }
+
     /// Latest value for `(sensor_id, kind)`, if any reading has been stored.
     pub async fn latest(&self, sensor_id: u128, kind: ReadingKind) -> anyhow::Result<Option<Point>> {
-        let sensor_id = format!("{sensor_id:032x}");
-        let row = sqlx::query_as::<_, (String, f64)>(
-            "SELECT timestamp, value FROM readings
-             WHERE sensor_id = ? AND data_type = ?
-             ORDER BY timestamp DESC LIMIT 1",
-        )
-        .bind(&sensor_id)
-        .bind(kind.as_str())
-        .fetch_optional(&self.0)
-        .await?;
+        let sql = format!(
+            "SELECT r.ts, r.value
+             FROM ({}) r JOIN sensor_keys k ON k.key = r.sensor
+             WHERE k.sensor_id = ?3 AND r.data_type = ?4
+             ORDER BY r.ts DESC LIMIT 1",
+            rows_sql(&self.tiers().await?, None)
+        );
+        let row = sqlx::query_as::<_, (i64, f64)>(&sql)
+            .bind(i64::MIN)
+            .bind(i64::MAX)
+            .bind(format!("{sensor_id:032x}"))
+            .bind(kind.as_str())
+            .fetch_optional(&self.0)
+            .await?;
 
-        row.map(|(ts, value)| parse_point(&ts, value)).transpose()
+        row.map(|(ts, value)| parse_point(ts, value)).transpose()
     }
 
     /// All `(sensor_id, kind)` series with at least one reading since `since`, as
-    /// `(sensor_id_hex, kind, points)` ordered by timestamp ascending.
+    /// `(sensor_id_hex, kind, points)` ordered by timestamp ascending. Rolled-up history
+    /// comes from the finest rollup still holding it.
     pub async fn history_since(&self, since: DateTime<Utc>) -> anyhow::Result<Vec<(String, ReadingKind, Vec<Point>)>> {
-        let rows = sqlx::query_as::<_, (String, String, String, f64)>(
-            "SELECT sensor_id, data_type, timestamp, value FROM readings
-             WHERE timestamp >= ?
-             ORDER BY sensor_id, data_type, timestamp ASC",
-        )
-        .bind(since.to_rfc3339())
-        .fetch_all(&self.0)
-        .await?;
+        let sql = format!(
+            "SELECT k.sensor_id, r.data_type, r.ts, r.value
+             FROM ({}) r JOIN sensor_keys k ON k.key = r.sensor
+             ORDER BY k.sensor_id, r.data_type, r.ts ASC",
+            rows_sql(&self.tiers().await?, None)
+        );
+        let rows = sqlx::query_as::<_, (String, String, i64, f64)>(&sql)
+            .bind(since.timestamp_millis())
+            .bind(i64::MAX)
+            .fetch_all(&self.0)
+            .await?;
 
         let mut series: Vec<(String, ReadingKind, Vec<Point>)> = Vec::new();
         for (sensor_id, data_type, ts, value) in rows {
             let kind = parse_kind(&data_type)?;
-            let point = parse_point(&ts, value)?;
+            let point = parse_point(ts, value)?;
             match series.last_mut() {
                 Some((last_id, last_kind, points)) if *last_id == sensor_id && *last_kind == kind => {
                     points.push(point);
@@ -112,10 +250,13 @@ impl Db {
     /// Lets callers turn an open-ended "everything" request into the real data span,
     /// rather than sizing buckets against the epoch.
     pub async fn earliest(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
-        let row = sqlx::query_as::<_, (String,)>("SELECT MIN(timestamp) FROM readings")
-            .fetch_optional(&self.0)
-            .await?;
-        row.map(|(ts,)| Ok(ts.parse::<DateTime<Utc>>()?)).transpose()
+        let mut sql = "SELECT MIN(ts) FROM (SELECT MIN(ts) AS ts FROM readings".to_string();
+        for tier in self.tiers().await? {
+            let _ = write!(sql, " UNION ALL SELECT MIN(ts) FROM {}", tier.table());
+        }
+        sql.push(')');
+        let ts: Option<i64> = sqlx::query_scalar(&sql).fetch_one(&self.0).await?;
+        ts.map(parse_millis).transpose()
     }
 
     /// Downsampled history over `[from, to]`: one averaged point per `bucket_secs` window,
@@ -124,36 +265,43 @@ impl Db {
     /// The sensors emit roughly five readings per second per metric, so the raw row count
     /// scales with the window (a day is >1M rows). Averaging in SQL keeps the result
     /// proportional to the chart's pixel width instead, which is what makes multi-day
-    /// windows viable at all.
+    /// windows viable at all. Rolled-up history is read from the coarsest rollup whose
+    /// buckets tile `bucket_secs`, so a year at daily resolution reads a row per day
+    /// rather than a row per minute. Stored rows are weighted by the readings behind them,
+    /// and each bucket keeps the extremes of those readings.
+    ///
+    /// Each sensor's measured series are followed by those derived from them (see
+    /// [`crate::derived`]).
     pub async fn history_bucketed(
         &self,
         from: DateTime<Utc>,
         to: DateTime<Utc>,
         bucket_secs: i64,
-    ) -> anyhow::Result<Vec<(String, ReadingKind, Vec<Point>)>> {
+    ) -> anyhow::Result<Vec<(String, ReadingKind, Vec<HistoryPoint>)>> {
         let bucket_secs = bucket_secs.max(1);
-        let rows = sqlx::query_as::<_, (String, String, i64, f64)>(
-            "SELECT sensor_id, data_type,
-                    CAST(strftime('%s', timestamp) AS INTEGER) / ? AS bucket,
-                    AVG(value)
-             FROM readings
-             WHERE timestamp >= ? AND timestamp <= ?
-             GROUP BY sensor_id, data_type, bucket
-             ORDER BY sensor_id, data_type, bucket ASC",
-        )
-        .bind(bucket_secs)
-        .bind(from.to_rfc3339())
-        .bind(to.to_rfc3339())
-        .fetch_all(&self.0)
-        .await?;
+        let sql = format!(
+            "SELECT k.sensor_id, r.data_type, r.ts / ?3 AS bucket,
+                    SUM(r.value * r.samples) / SUM(r.samples), MIN(r.min), MAX(r.max), SUM(r.samples),
+                    MIN(SUM(r.secs), ?3 / 1000)
+             FROM ({}) r JOIN sensor_keys k ON k.key = r.sensor
+             GROUP BY r.sensor, r.data_type, bucket
+             ORDER BY k.sensor_id, r.data_type, bucket ASC",
+            rows_sql(&self.tiers().await?, Some(bucket_secs))
+        );
+        let rows = sqlx::query_as::<_, (String, String, i64, f64, f64, f64, i64, i64)>(&sql)
+            .bind(from.timestamp_millis())
+            .bind(to.timestamp_millis())
+            .bind(bucket_secs * 1000)
+            .fetch_all(&self.0)
+            .await?;
 
-        let mut series: Vec<(String, ReadingKind, Vec<Point>)> = Vec::new();
-        for (sensor_id, data_type, bucket, value) in rows {
+        let mut series: Vec<(String, ReadingKind, Vec<HistoryPoint>)> = Vec::new();
+        for (sensor_id, data_type, bucket, value, min, max, count, secs) in rows {
             let kind = parse_kind(&data_type)?;
             let at = DateTime::from_timestamp(bucket * bucket_secs, 0)
                 .ok_or_else(|| anyhow::anyhow!("bucket {bucket} out of range"))?;
             #[allow(clippy::cast_possible_truncation)]
-            let point = (at, value as f32);
+            let point = HistoryPoint { at, value: value as f32, min: min as f32, max: max as f32, count, secs };
             match series.last_mut() {
                 Some((last_id, last_kind, points)) if *last_id == sensor_id && *last_kind == kind => {
                     points.push(point);
@@ -161,23 +309,44 @@ impl Db {
                 _ => series.push((sensor_id, kind, vec![point])),
             }
         }
-        Ok(series)
+        Ok(derived::with_derived(series, bucket_secs))
     }
 }
 
+async fn insert_averaged(
+    conn: &mut SqliteConnection,
+    sensor: i64,
+    averaged: &Averaged,
+    bucket_secs: i64,
+) -> anyhow::Result<()> {
+    let reading = &averaged.reading;
+    sqlx::query(
+        "INSERT INTO readings (sensor, data_type, ts, value, min, max, samples, bucket_secs)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(sensor)
+    .bind(reading.kind.as_str())
+    .bind(reading.at.timestamp_millis())
+    .bind(f64::from(reading.value))
+    .bind(f64::from(averaged.min))
+    .bind(f64::from(averaged.max))
+    .bind(averaged.count)
+    .bind(bucket_secs)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
 /// One rung of the retention ladder: rows older than `older_than_secs` are averaged down
 /// to at most one point per `bucket_secs`.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RetentionTier {
     pub older_than_secs: i64,
     pub bucket_secs: i64,
 }
 
-/// Progressive retention, finest first. Storage per sensor settles around 47k rows
+/// Progressive retention, finest first. Storage per sensor settles around 16k rows
 /// instead of growing without bound at ~1.3M rows/day.
-///
-/// The first rung exists to collapse pre-rollup rows (`bucket_secs = 0`, written at the
-/// raw ~5 Hz sensor rate) down to the ingest resolution.
 pub const DEFAULT_TIERS: &[RetentionTier] = &[
     RetentionTier { older_than_secs: 3_600, bucket_secs: 60 },           // > 1h   -> 1 min
     RetentionTier { older_than_secs: 172_800, bucket_secs: 300 },        // > 48h  -> 5 min
@@ -185,252 +354,1735 @@ pub const DEFAULT_TIERS: &[RetentionTier] = &[
     RetentionTier { older_than_secs: 31_536_000, bucket_secs: 86_400 },  // > 1y   -> 1 day
 ];
 
-impl Db {
-    /// Apply `tiers` as of `now`, returning how many rows were removed.
-    ///
-    /// Tiers are applied coarsest first so an old row is rewritten once rather than once
-    /// per rung. Each pass is idempotent: rows already at or beyond a rung's resolution
-    /// fail its `bucket_secs <` predicate and are skipped.
-    pub async fn compact(
-        &self,
-        now: DateTime<Utc>,
-        tiers: &[RetentionTier],
-    ) -> anyhow::Result<u64> {
-        let mut removed = 0;
+pub const DEFAULT_COMPACTION_INTERVAL_SECS: i64 = 3_600;
 
-        for tier in tiers.iter().rev() {
-            let cutoff = (now - chrono::Duration::seconds(tier.older_than_secs)).to_rfc3339();
-            let bucket = tier.bucket_secs.max(1);
+/// What to do with the pages compaction frees. The database keeps them for reuse, so
+/// the file only shrinks if asked to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Vacuum {
+    #[default]
+    Off,
+    /// `PRAGMA incremental_vacuum`, switching the database to incremental auto-vacuum
+    /// (which takes one full `VACUUM`) the first time.
+    Incremental,
+    /// A full `VACUUM`, rewriting the whole file.
+    Full,
+}
 
-            let mut tx = self.0.begin().await?;
+impl Vacuum {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Vacuum::Off => "off",
+            Vacuum::Incremental => "incremental",
+            Vacuum::Full => "full",
+        }
+    }
 
-            // Write the averaged replacements first. They carry the tier's bucket_secs, so
-            // the DELETE below — which only matches strictly finer rows — cannot reap them.
-            sqlx::query(
-                "INSERT INTO readings (sensor_id, timestamp, data_type, value, bucket_secs)
-                 SELECT sensor_id,
-                        strftime('%Y-%m-%dT%H:%M:%S+00:00',
-                                 CAST(strftime('%s', timestamp) AS INTEGER) / ?1 * ?1,
-                                 'unixepoch'),
-                        data_type,
-                        AVG(value),
-                        ?1
-                 FROM readings
-                 WHERE timestamp < ?2 AND bucket_secs < ?1
-                 GROUP BY sensor_id, data_type,
-                          CAST(strftime('%s', timestamp) AS INTEGER) / ?1",
-            )
-            .bind(bucket)
-            .bind(&cutoff)
-            .execute(&mut *tx)
-            .await?;
+    #[must_use]
+    pub fn parse(vacuum: &str) -> Option<Self> {
+        match vacuum {
+            "off" => Some(Vacuum::Off),
+            "incremental" => Some(Vacuum::Incremental),
+            "full" => Some(Vacuum::Full),
+            _ => None,
+        }
+    }
+}
 
-            let deleted = sqlx::query(
-                "DELETE FROM readings WHERE timestamp < ?2 AND bucket_secs < ?1",
-            )
-            .bind(bucket)
-            .bind(&cutoff)
-            .execute(&mut *tx)
-            .await?
-            .rows_affected();
+/// A sensor whose history is kept differently from the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SensorRetention {
+    pub sensor_id: u128,
+    /// How long its readings stay as stored before rolling up, in place of the first
+    /// tier's age. Past `max_age_secs`, they are never rolled up at all.
+    pub full_resolution_secs: Option<i64>,
+    /// Replaces the policy's `max_age_secs` for this sensor.
+    pub max_age_secs: Option<i64>,
+}
 
-            tx.commit().await?;
-            removed += deleted;
-        }
+/// How long history is kept, and how coarsely.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// The rollup ladder, finest first. Empty keeps every reading as stored.
+    pub tiers: Vec<RetentionTier>,
+    /// Rows older than this are deleted from every table; `None` keeps the coarsest tier
+    /// for good.
+    pub max_age_secs: Option<i64>,
+    pub sensors: Vec<SensorRetention>,
+    pub vacuum: Vacuum,
+    /// How often the server compacts.
+    pub interval_secs: i64,
+}
 
-        Ok(removed)
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            tiers: DEFAULT_TIERS.to_vec(),
+            max_age_secs: None,
+            sensors: Vec::new(),
+            vacuum: Vacuum::Off,
+            interval_secs: DEFAULT_COMPACTION_INTERVAL_SECS,
+        }
     }
 }
 
-fn parse_kind(data_type: &str) -> anyhow::Result<ReadingKind> {
-    match data_type {
-        "temperature" => Ok(ReadingKind::Temperature),
-        "humidity" => Ok(ReadingKind::Humidity),
-        "light" => Ok(ReadingKind::Light),
-        other => Err(anyhow::anyhow!("unknown data_type: {other}")),
+impl RetentionPolicy {
+    /// Check the policy can be applied: positive ages, widths and interval, tiers ordered
+    /// by age with each bucket width dividing the next, and no sensor listed twice.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.interval_secs > 0, "the compaction interval must be positive");
+        let ages = self.sensors.iter().flat_map(|sensor| [sensor.full_resolution_secs, sensor.max_age_secs]);
+        anyhow::ensure!(
+            self.tiers.iter().all(|tier| tier.older_than_secs > 0 && tier.bucket_secs > 0)
+                && ages.chain([self.max_age_secs]).flatten().all(|secs| secs > 0),
+            "retention ages and buckets must be positive"
+        );
+        for pair in self.tiers.windows(2) {
+            anyhow::ensure!(
+                pair[0].older_than_secs < pair[1].older_than_secs && pair[1].bucket_secs % pair[0].bucket_secs == 0,
+                "retention tiers must be ordered by age, each bucket a multiple of the last"
+            );
+        }
+        for (i, sensor) in self.sensors.iter().enumerate() {
+            anyhow::ensure!(
+                !self.sensors[..i].iter().any(|other| other.sensor_id == sensor.sensor_id),
+                "sensor {:032x} has more than one retention override",
+                sensor.sensor_id
+            );
+        }
+        Ok(())
     }
 }
 
-fn parse_point(ts: &str, value: f64) -> anyhow::Result<Point> {
-    let timestamp = ts.parse::<DateTime<Utc>>()?;
-    #[allow(clippy::cast_possible_truncation)]
-    Ok((timestamp, value as f32))
+/// What a compaction did, or would do for a dry run, to each table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Rows moved out of `readings` into the rollups.
+    pub rolled_up: u64,
+    /// Rows deleted from `readings` for being past their maximum age.
+    pub expired: u64,
+    pub tiers: Vec<TierReport>,
+    /// Rollups no longer in the policy, whose tables were dropped.
+    pub dropped: Vec<DroppedTier>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TierReport {
+    pub bucket_secs: i64,
+    /// Buckets added or merged into.
+    pub written: u64,
+    /// Buckets dropped for being covered by the next coarser tier.
+    pub pruned: u64,
+    /// Buckets deleted for being past their maximum age.
+    pub expired: u64,
+}
 
-    /// Two readings per bucket at a known epoch offset; each bucket should collapse to the
-    /// mean of its members, which is what keeps multi-day windows chart-sized.
-    #[tokio::test]
-    async fn history_bucketed_averages_within_each_bucket() {
-        let path = std::env::temp_dir().join(format!("chlorophyll-bucket-{}.db", std::process::id()));
-        let db = Db::open(path.to_str().unwrap()).await.unwrap();
+/// A rollup dropped from the policy, its buckets merged into the next coarser tier left.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DroppedTier {
+    pub bucket_secs: i64,
+    /// Rows its table held.
+    pub rows: u64,
+    /// The tier its buckets were merged into; `None` when it held none.
+    pub merged_into: Option<i64>,
+    /// Buckets added to that tier. Ones it already held cover the same readings.
+    pub written: u64,
+}
 
-        let base = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
-        for (offset_secs, value) in [(0, 10.0), (30, 20.0), (60, 30.0), (90, 50.0)] {
-            db.insert_reading(&Reading {
-                sensor_id: 1,
-                kind: ReadingKind::Temperature,
-                value,
-                at: base + chrono::Duration::seconds(offset_secs),
-            })
-            .await
-            .unwrap();
-        }
+impl CompactionReport {
+    /// Rows removed across every table.
+    #[must_use]
+    pub fn removed(&self) -> u64 {
+        self.rolled_up
+            + self.expired
+            + self.tiers.iter().map(|tier| tier.pruned + tier.expired).sum::<u64>()
+            + self.dropped.iter().map(|tier| tier.rows).sum::<u64>()
+    }
+}
 
-        let series = db
-            .history_bucketed(base, base + chrono::Duration::seconds(120), 60)
-            .await
-            .unwrap();
+/// A rollup table, as registered in `rollup_tiers`.
+///
+/// Each tier's table `rollup_{bucket_secs}` holds one averaged row per bucket, with the
+/// extremes and number of the readings behind it and the seconds of the bucket those
+/// covered, for everything rolled up since it was
+/// created, except that rows before `kept_from` (unix ms, on a bucket boundary of the
+/// next coarser tier) have been dropped. A tier therefore answers for `[kept_from, ∞)`
+/// and the next coarser one for the stretch before that; `None` means nothing dropped.
+#[derive(Debug, Clone, Copy)]
+struct Tier {
+    bucket_secs: i64,
+    kept_from: Option<i64>,
+}
 
-        assert_eq!(series.len(), 1);
-        let (_, kind, points) = &series[0];
-        assert_eq!(*kind, ReadingKind::Temperature);
-        assert_eq!(points.len(), 2, "4 readings across 2 buckets collapse to 2 points");
-        assert!((points[0].1 - 15.0).abs() < 0.001, "got {}", points[0].1);
-        assert!((points[1].1 - 40.0).abs() < 0.001, "got {}", points[1].1);
-        assert!(points[0].0 < points[1].0);
+impl Tier {
+    fn table(self) -> String {
+        format!("rollup_{}", self.bucket_secs)
+    }
+}
 
-        for suffix in ["", "-wal", "-shm"] {
-            let mut p = path.clone().into_os_string();
-            p.push(suffix);
-            let _ = std::fs::remove_file(p);
+/// `(sensor, data_type, ts, value, min, max, samples, secs)` for every reading with `ts` in
+/// `[?1, ?2]` (unix ms), `secs` being how much of its bucket the row covers (0 for raw
+/// readings): rows not rolled up yet from `readings`, then rolled-up ones from the
+/// coarsest tier whose buckets tile `bucket_secs` (the finest for `None`), falling back to
+/// coarser tiers for what it no longer keeps.
+fn rows_sql(tiers: &[Tier], bucket_secs: Option<i64>) -> String {
+    let start = bucket_secs
+        .and_then(|bucket| tiers.iter().rposition(|tier| bucket % tier.bucket_secs == 0))
+        .unwrap_or(0);
+    let mut sql =
+        "SELECT sensor, data_type, ts, value, min, max, samples, bucket_secs AS secs FROM readings \
+         WHERE ts >= ?1 AND ts <= ?2"
+            .to_string();
+    let mut before = None;
+    for tier in tiers.iter().skip(start) {
+        let _ = write!(
+            sql,
+            " UNION ALL SELECT sensor, data_type, ts, value, min, max, samples, secs FROM {} \
+             WHERE ts >= ?1 AND ts <= ?2",
+            tier.table()
+        );
+        if let Some(before) = before {
+            let _ = write!(sql, " AND ts < {before}");
         }
+        let Some(kept_from) = tier.kept_from else { break };
+        let _ = write!(sql, " AND ts >= {kept_from}");
+        before = Some(kept_from);
     }
+    sql
 }
 
-#[cfg(test)]
-mod compaction_tests {
-    use super::*;
-
-    async fn temp_db(tag: &str) -> (Db, std::path::PathBuf) {
-        let path = std::env::temp_dir().join(format!(
-            "chlorophyll-compact-{}-{tag}.db",
-            std::process::id()
-        ));
-        for suffix in ["", "-wal", "-shm"] {
-            let mut p = path.clone().into_os_string();
-            p.push(suffix);
-            let _ = std::fs::remove_file(p);
-        }
-        let db = Db::open(path.to_str().unwrap()).await.unwrap();
-        (db, path)
+impl Db {
+    /// Registered rollup tiers, finest first.
+    async fn tiers(&self) -> anyhow::Result<Vec<Tier>> {
+        let rows = sqlx::query_as::<_, (i64, Option<i64>)>(
+            "SELECT bucket_secs, kept_from FROM rollup_tiers ORDER BY bucket_secs",
+        )
+        .fetch_all(&self.0)
+        .await?;
+        Ok(rows.into_iter().map(|(bucket_secs, kept_from)| Tier { bucket_secs, kept_from }).collect())
     }
 
-    fn cleanup(path: &std::path::Path) {
-        for suffix in ["", "-wal", "-shm"] {
-            let mut p = path.to_path_buf().into_os_string();
-            p.push(suffix);
-            let _ = std::fs::remove_file(p);
-        }
+    /// Apply `policy` as of `now`, reporting what changed.
+    ///
+    /// Rows past their maximum age go first, from every table. Readings older than the
+    /// first rung (or their sensor's own full-resolution age) are then averaged into every
+    /// tier's rollup table at once and removed from `readings`, so each row is read once
+    /// however many rungs it will pass. Averages are weighted by the readings behind them,
+    /// both within a pass and when merging into a bucket that already holds some, and
+    /// extremes carry through. Each rollup then drops what the next coarser one takes over
+    /// from. Rollups for widths no longer listed are merged into the next coarser tier
+    /// left, then dropped. Running again as of the same `now` removes nothing. Vacuums
+    /// afterwards as the policy asks.
+    pub async fn compact(&self, now: DateTime<Utc>, policy: &RetentionPolicy) -> anyhow::Result<CompactionReport> {
+        let report = self.apply_retention(now, policy, true).await?;
+        self.vacuum(policy.vacuum).await?;
+        Ok(report)
     }
 
-    async fn count(db: &Db) -> i64 {
-        sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM readings")
-            .fetch_one(&db.0)
-            .await
-            .unwrap()
-            .0
+    /// What [`Db::compact`] would do, without changing anything.
+    pub async fn compact_dry_run(
+        &self,
+        now: DateTime<Utc>,
+        policy: &RetentionPolicy,
+    ) -> anyhow::Result<CompactionReport> {
+        self.apply_retention(now, policy, false).await
     }
 
-    #[tokio::test]
-    async fn compaction_coarsens_old_rows_and_leaves_recent_ones_alone() {
-        let (db, path) = temp_db("ladder").await;
-        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    /// Compact in one transaction, committed only if `commit`.
+    async fn apply_retention(
+        &self,
+        now: DateTime<Utc>,
+        policy: &RetentionPolicy,
+        commit: bool,
+    ) -> anyhow::Result<CompactionReport> {
+        policy.validate()?;
+        let millis = |secs: i64| (now - chrono::Duration::seconds(secs)).timestamp_millis();
+        let tiers = &policy.tiers;
 
-        // 120 raw readings spread over two hours, ending 3h ago: all past the 1h rung.
-        for i in 0..120 {
-            db.insert_reading(&Reading {
-                sensor_id: 1,
-                kind: ReadingKind::Temperature,
-                value: 20.0,
-                at: now - chrono::Duration::minutes(180 - i),
-            })
-            .await
-            .unwrap();
+        let mut tx = self.0.begin().await?;
+        let dropped = sync_tier_tables(&mut tx, tiers).await?;
+        let mut report = CompactionReport {
+            dropped,
+            tiers: tiers
+                .iter()
+                .map(|tier| TierReport { bucket_secs: tier.bucket_secs, ..TierReport::default() })
+                .collect(),
+            ..CompactionReport::default()
+        };
+
+        // Cutoffs per sensor key; sensors never seen have nothing to apply them to.
+        let mut expiry = Vec::new();
+        let mut full_resolution = Vec::new();
+        for sensor in &policy.sensors {
+            let Some(key) = self.1.find(&mut tx, sensor.sensor_id).await? else { continue };
+            expiry.push((key, sensor.max_age_secs.or(policy.max_age_secs).map_or(i64::MIN, millis)));
+            if let Some(secs) = sensor.full_resolution_secs {
+                full_resolution.push((key, millis(secs)));
+            }
         }
-        // 10 raw readings inside the last 10 minutes: must survive untouched.
-        for i in 0..10 {
-            db.insert_reading(&Reading {
-                sensor_id: 1,
-                kind: ReadingKind::Temperature,
-                value: 30.0,
-                at: now - chrono::Duration::minutes(i),
-            })
-            .await
-            .unwrap();
+
+        if policy.max_age_secs.is_some() || policy.sensors.iter().any(|sensor| sensor.max_age_secs.is_some()) {
+            let cutoff = per_sensor(policy.max_age_secs.map_or(i64::MIN, millis), &expiry);
+            report.expired = delete_before(&mut tx, "readings", &cutoff).await?;
+            for (tier, tier_report) in tiers.iter().zip(&mut report.tiers) {
+                tier_report.expired = delete_before(&mut tx, &format!("rollup_{}", tier.bucket_secs), &cutoff).await?;
+            }
+        }
+
+        if let Some(first) = tiers.first() {
+            let cutoff = per_sensor(millis(first.older_than_secs), &full_resolution);
+            for (tier, tier_report) in tiers.iter().zip(&mut report.tiers) {
+                tier_report.written = roll_up(&mut tx, tier.bucket_secs, &cutoff).await?;
+            }
+            report.rolled_up = delete_before(&mut tx, "readings", &cutoff).await?;
         }
-        assert_eq!(count(&db).await, 130);
 
-        let removed = db.compact(now, DEFAULT_TIERS).await.unwrap();
-        assert!(removed > 0, "expected the old rows to be coarsened");
+        for (i, pair) in tiers.windows(2).enumerate() {
+            let (tier, next) = (pair[0], pair[1]);
+            let width = next.bucket_secs * 1000;
+            let kept_from = millis(next.older_than_secs).div_euclid(width) * width;
+            report.tiers[i].pruned =
+                delete_before(&mut tx, &format!("rollup_{}", tier.bucket_secs), &kept_from.to_string()).await?;
+            sqlx::query("UPDATE rollup_tiers SET kept_from = MAX(COALESCE(kept_from, ?1), ?1) WHERE bucket_secs = ?2")
+                .bind(kept_from)
+                .bind(tier.bucket_secs)
+                .execute(&mut *tx)
+                .await?;
+        }
 
-        let recent = sqlx::query_as::<_, (i64,)>(
-            "SELECT COUNT(*) FROM readings WHERE bucket_secs = 0",
-        )
-        .fetch_one(&db.0)
-        .await
-        .unwrap()
-        .0;
-        assert_eq!(recent, 10, "rows inside the finest rung stay at full resolution");
+        if commit {
+            tx.commit().await?;
+        } else {
+            tx.rollback().await?;
+        }
+        Ok(report)
+    }
+
+    async fn vacuum(&self, vacuum: Vacuum) -> anyhow::Result<()> {
+        // Pragmas are per connection, so everything here runs on one.
+        let mut conn = self.0.acquire().await?;
+        match vacuum {
+            Vacuum::Off => {}
+            Vacuum::Full => {
+                sqlx::query("VACUUM").execute(&mut *conn).await?;
+            }
+            Vacuum::Incremental => {
+                // The mode is read from the file header, which a read refreshes.
+                sqlx::query("SELECT 1 FROM sqlite_master LIMIT 1").execute(&mut *conn).await?;
+                let mode: i64 = sqlx::query_scalar("PRAGMA auto_vacuum").fetch_one(&mut *conn).await?;
+                if mode != 2 {
+                    // Switching an existing database over only takes effect on a VACUUM.
+                    sqlx::query("PRAGMA auto_vacuum = INCREMENTAL").execute(&mut *conn).await?;
+                    sqlx::query("VACUUM").execute(&mut *conn).await?;
+                }
+                sqlx::query("PRAGMA incremental_vacuum").execute(&mut *conn).await?;
+            }
+        }
+        Ok(())
+    }
 
-        // The two hours of 1/minute readings collapse to one row per minute.
-        let coarsened = sqlx::query_as::<_, (i64,)>(
-            "SELECT COUNT(*) FROM readings WHERE bucket_secs = 60",
+    /// The stored retention policy, or `None` if one was never set.
+    pub async fn retention_policy(&self) -> anyhow::Result<Option<RetentionPolicy>> {
+        let Some((max_age_secs, vacuum, interval_secs)) = sqlx::query_as::<_, (Option<i64>, String, i64)>(
+            "SELECT max_age_secs, vacuum, interval_secs FROM retention_policy WHERE id = 1",
         )
-        .fetch_one(&db.0)
-        .await
-        .unwrap()
-        .0;
-        assert_eq!(coarsened, 120, "one row per minute bucket");
+        .fetch_optional(&self.0)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let tiers = sqlx::query_as::<_, (i64, i64)>(
+            "SELECT older_than_secs, bucket_secs FROM retention_tiers ORDER BY older_than_secs",
+        )
+        .fetch_all(&self.0)
+        .await?
+        .into_iter()
+        .map(|(older_than_secs, bucket_secs)| RetentionTier { older_than_secs, bucket_secs })
+        .collect();
+        let sensors = sqlx::query_as::<_, (String, Option<i64>, Option<i64>)>(
+            "SELECT sensor_id, full_resolution_secs, max_age_secs FROM retention_sensors ORDER BY sensor_id",
+        )
+        .fetch_all(&self.0)
+        .await?
+        .into_iter()
+        .filter_map(|(id_hex, full_resolution_secs, max_age_secs)| {
+            let sensor_id = u128::from_str_radix(&id_hex, 16).ok()?;
+            Some(SensorRetention { sensor_id, full_resolution_secs, max_age_secs })
+        })
+        .collect();
+        Ok(Some(RetentionPolicy {
+            tiers,
+            max_age_secs,
+            sensors,
+            vacuum: Vacuum::parse(&vacuum).unwrap_or_default(),
+            interval_secs,
+        }))
+    }
 
-        cleanup(&path);
+    /// Errors if `policy` fails to validate, or drops a rollup holding history that no
+    /// coarser tier is left to take over.
+    pub async fn check_retention_policy(&self, policy: &RetentionPolicy) -> anyhow::Result<()> {
+        policy.validate()?;
+        let mut conn = self.0.acquire().await?;
+        dropped_tiers(&mut conn, &policy.tiers).await?;
+        Ok(())
     }
 
-    #[tokio::test]
-    async fn compaction_is_idempotent_and_preserves_the_mean() {
-        let (db, path) = temp_db("idempotent").await;
-        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
-        // Align to a minute boundary so all four readings land in one bucket.
-        let old = DateTime::from_timestamp(
-            (now - chrono::Duration::hours(3)).timestamp() / 60 * 60,
-            0,
+    /// Replace the stored retention policy, once it passes [`Db::check_retention_policy`].
+    pub async fn set_retention_policy(&self, policy: &RetentionPolicy) -> anyhow::Result<()> {
+        policy.validate()?;
+        let mut tx = self.0.begin().await?;
+        dropped_tiers(&mut tx, &policy.tiers).await?;
+        sqlx::query("DELETE FROM retention_tiers; DELETE FROM retention_sensors;")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO retention_policy (id, max_age_secs, vacuum, interval_secs) VALUES (1, ?, ?, ?)
+             ON CONFLICT (id) DO UPDATE SET
+                 max_age_secs = excluded.max_age_secs,
+                 vacuum = excluded.vacuum,
+                 interval_secs = excluded.interval_secs",
         )
-        .unwrap();
-
-        // Four readings inside a single minute, averaging 25.
-        for (offset, value) in [(0, 10.0), (15, 20.0), (30, 30.0), (45, 40.0)] {
-            db.insert_reading(&Reading {
-                sensor_id: 1,
-                kind: ReadingKind::Temperature,
-                value,
-                at: old + chrono::Duration::seconds(offset),
-            })
-            .await
-            .unwrap();
+        .bind(policy.max_age_secs)
+        .bind(policy.vacuum.as_str())
+        .bind(policy.interval_secs)
+        .execute(&mut *tx)
+        .await?;
+        for tier in &policy.tiers {
+            sqlx::query("INSERT INTO retention_tiers (older_than_secs, bucket_secs) VALUES (?, ?)")
+                .bind(tier.older_than_secs)
+                .bind(tier.bucket_secs)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for sensor in &policy.sensors {
+            sqlx::query(
+                "INSERT INTO retention_sensors (sensor_id, full_resolution_secs, max_age_secs) VALUES (?, ?, ?)",
+            )
+            .bind(format!("{:032x}", sensor.sensor_id))
+            .bind(sensor.full_resolution_secs)
+            .bind(sensor.max_age_secs)
+            .execute(&mut *tx)
+            .await?;
         }
+        tx.commit().await?;
+        Ok(())
+    }
+}
 
-        assert!(db.compact(now, DEFAULT_TIERS).await.unwrap() > 0);
-        assert_eq!(count(&db).await, 1, "one minute bucket remains");
+/// `default`, or the cutoff listed for the row's sensor key, as a SQL expression. Plain
+/// numbers when nothing is listed keep the `ts` index usable.
+fn per_sensor(default: i64, cutoffs: &[(i64, i64)]) -> String {
+    if cutoffs.is_empty() {
+        return default.to_string();
+    }
+    let mut sql = "CASE sensor".to_string();
+    for (key, cutoff) in cutoffs {
+        let _ = write!(sql, " WHEN {key} THEN {cutoff}");
+    }
+    let _ = write!(sql, " ELSE {default} END");
+    sql
+}
 
-        let (value,) = sqlx::query_as::<_, (f64,)>("SELECT value FROM readings")
-            .fetch_one(&db.0)
-            .await
-            .unwrap();
-        assert!((value - 25.0).abs() < 0.001, "got {value}");
+async fn delete_before(conn: &mut SqliteConnection, table: &str, cutoff: &str) -> anyhow::Result<u64> {
+    Ok(sqlx::query(&format!("DELETE FROM {table} WHERE ts < {cutoff}"))
+        .execute(&mut *conn)
+        .await?
+        .rows_affected())
+}
 
-        // Running again must be a no-op rather than re-averaging or deleting.
-        assert_eq!(db.compact(now, DEFAULT_TIERS).await.unwrap(), 0);
-        assert_eq!(count(&db).await, 1);
+/// Average readings from before `cutoff` into `rollup_{bucket_secs}`, returning how many
+/// buckets were written.
+async fn roll_up(conn: &mut SqliteConnection, bucket_secs: i64, cutoff: &str) -> anyhow::Result<u64> {
+    Ok(sqlx::query(&format!(
+        "INSERT INTO rollup_{bucket_secs} (ts, sensor, data_type, value, min, max, samples, secs)
+         SELECT ts / ?1 * ?1 AS bucket, sensor, data_type,
+                SUM(value * samples) / SUM(samples), MIN(min), MAX(max), SUM(samples),
+                MIN(SUM(bucket_secs), {bucket_secs})
+         FROM readings
+         WHERE ts < {cutoff}
+         GROUP BY bucket, sensor, data_type
+         ON CONFLICT (ts, sensor, data_type) DO UPDATE SET
+             value = (value * samples + excluded.value * excluded.samples) / (samples + excluded.samples),
+             min = MIN(min, excluded.min),
+             max = MAX(max, excluded.max),
+             samples = samples + excluded.samples,
+             secs = MIN(secs + excluded.secs, {bucket_secs})"
+    ))
+    .bind(bucket_secs * 1000)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected())
+}
 
-        cleanup(&path);
+/// Each registered rollup missing from `tiers`, with the rows its table holds and the
+/// finest of `tiers` whose buckets tile its own, coarser, to merge them into. Errors if a
+/// rollup holding rows has no such tier, since dropping it would lose that history.
+async fn dropped_tiers(
+    conn: &mut SqliteConnection,
+    tiers: &[RetentionTier],
+) -> anyhow::Result<Vec<(i64, u64, Option<i64>)>> {
+    let registered: Vec<i64> = sqlx::query_scalar("SELECT bucket_secs FROM rollup_tiers ORDER BY bucket_secs")
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut dropped = Vec::new();
+    for bucket_secs in registered {
+        if tiers.iter().any(|tier| tier.bucket_secs == bucket_secs) {
+            continue;
+        }
+        let rows: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM rollup_{bucket_secs}"))
+            .fetch_one(&mut *conn)
+            .await?;
+        let rows = rows.unsigned_abs();
+        let into = tiers
+            .iter()
+            .map(|tier| tier.bucket_secs)
+            .find(|&coarser| coarser > bucket_secs && coarser % bucket_secs == 0);
+        anyhow::ensure!(
+            rows == 0 || into.is_some(),
+            "dropping the {bucket_secs}s rollup would lose its {rows} rows: keep it, or a coarser tier \
+             whose buckets are a multiple of it"
+        );
+        dropped.push((bucket_secs, rows, into.filter(|_| rows > 0)));
     }
+    Ok(dropped)
+}
 
-    #[tokio::test]
+/// Create a rollup table for each of `tiers` that lacks one, and drop those of tiers no
+/// longer configured once their buckets are merged into a coarser one, as
+/// [`dropped_tiers`] picks. A bucket already held there covers the same readings, so is
+/// left alone.
+async fn sync_tier_tables(conn: &mut SqliteConnection, tiers: &[RetentionTier]) -> anyhow::Result<Vec<DroppedTier>> {
+    let dropped = dropped_tiers(conn, tiers).await?;
+    for tier in tiers {
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS rollup_{} (
+                 ts        INTEGER NOT NULL,
+                 sensor    INTEGER NOT NULL,
+                 data_type TEXT    NOT NULL,
+                 value     REAL    NOT NULL,
+                 min       REAL    NOT NULL,
+                 max       REAL    NOT NULL,
+                 samples   INTEGER NOT NULL,
+                 secs      INTEGER NOT NULL,
+                 PRIMARY KEY (ts, sensor, data_type)
+             ) WITHOUT ROWID",
+            tier.bucket_secs
+        ))
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query("INSERT INTO rollup_tiers (bucket_secs) VALUES (?) ON CONFLICT (bucket_secs) DO NOTHING")
+            .bind(tier.bucket_secs)
+            .execute(&mut *conn)
+            .await?;
+    }
+    let mut report = Vec::with_capacity(dropped.len());
+    for (bucket_secs, rows, merged_into) in dropped {
+        let mut written = 0;
+        if let Some(into) = merged_into {
+            written = sqlx::query(&format!(
+                "INSERT INTO rollup_{into} (ts, sensor, data_type, value, min, max, samples, secs)
+                 SELECT ts / ?1 * ?1 AS bucket, sensor, data_type,
+                        SUM(value * samples) / SUM(samples), MIN(min), MAX(max), SUM(samples),
+                        MIN(SUM(secs), {into})
+                 FROM rollup_{bucket_secs}
+                 GROUP BY bucket, sensor, data_type
+                 ON CONFLICT (ts, sensor, data_type) DO NOTHING"
+            ))
+            .bind(into * 1000)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+        }
+        sqlx::query(&format!("DROP TABLE IF EXISTS rollup_{bucket_secs}"))
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DELETE FROM rollup_tiers WHERE bucket_secs = ?")
+            .bind(bucket_secs)
+            .execute(&mut *conn)
+            .await?;
+        report.push(DroppedTier { bucket_secs, rows, merged_into, written });
+    }
+    Ok(report)
+}
+
+/// What an [`AlertRule`] watches for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertCondition {
+    /// Value rises above the threshold.
+    Above(f32),
+    /// Value falls below the threshold.
+    Below(f32),
+    /// Value changes faster than this many units per minute, in either direction.
+    RateOfChange(f32),
+    /// Nothing heard from the sensor for this many minutes.
+    NoData(f32),
+}
+
+impl AlertCondition {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlertCondition::Above(_) => "above",
+            AlertCondition::Below(_) => "below",
+            AlertCondition::RateOfChange(_) => "rate_of_change",
+            AlertCondition::NoData(_) => "no_data",
+        }
+    }
+
+    #[must_use]
+    pub fn threshold(self) -> f32 {
+        match self {
+            AlertCondition::Above(t)
+            | AlertCondition::Below(t)
+            | AlertCondition::RateOfChange(t)
+            | AlertCondition::NoData(t) => t,
+        }
+    }
+
+    /// Inverse of [`Self::as_str`] paired with [`Self::threshold`].
+    #[must_use]
+    pub fn parse(condition: &str, threshold: f32) -> Option<Self> {
+        match condition {
+            "above" => Some(AlertCondition::Above(threshold)),
+            "below" => Some(AlertCondition::Below(threshold)),
+            "rate_of_change" => Some(AlertCondition::RateOfChange(threshold)),
+            "no_data" => Some(AlertCondition::NoData(threshold)),
+            _ => None,
+        }
+    }
+}
+
+/// A stored threshold rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertRule {
+    pub id: i64,
+    pub name: String,
+    /// `None` applies the rule to every sensor.
+    pub sensor_id: Option<u128>,
+    /// Metric the rule watches. `None` only for [`AlertCondition::NoData`].
+    pub kind: Option<ReadingKind>,
+    pub condition: AlertCondition,
+    /// How far back past the threshold the value must go before a firing alert resolves.
+    pub hysteresis: f32,
+    /// How long the condition must hold before the alert fires.
+    pub min_duration_secs: i64,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertState {
+    Fired,
+    Resolved,
+}
+
+impl AlertState {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AlertState::Fired => "fired",
+            AlertState::Resolved => "resolved",
+        }
+    }
+}
+
+/// One fired or resolved transition of a rule for one sensor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertEvent {
+    pub id: i64,
+    pub rule_id: i64,
+    pub sensor_id: u128,
+    pub state: AlertState,
+    /// The value that caused the transition; `None` for `no_data` rules.
+    pub value: Option<f32>,
+    pub at: DateTime<Utc>,
+}
+
+type AlertRuleRow = (i64, String, Option<String>, Option<String>, String, f64, f64, i64, bool);
+type AlertEventRow = (i64, i64, String, String, Option<f64>, String);
+
+const ALERT_RULE_COLUMNS: &str =
+    "id, name, sensor_id, data_type, condition, threshold, hysteresis, min_duration_secs, enabled";
+
+impl Db {
+    pub async fn alert_rules(&self) -> anyhow::Result<Vec<AlertRule>> {
+        let rows = sqlx::query_as::<_, AlertRuleRow>(&format!(
+            "SELECT {ALERT_RULE_COLUMNS} FROM alert_rules ORDER BY id"
+        ))
+        .fetch_all(&self.0)
+        .await?;
+        rows.into_iter().map(parse_alert_rule).collect()
+    }
+
+    pub async fn alert_rule(&self, id: i64) -> anyhow::Result<Option<AlertRule>> {
+        let row = sqlx::query_as::<_, AlertRuleRow>(&format!(
+            "SELECT {ALERT_RULE_COLUMNS} FROM alert_rules WHERE id = ?"
+        ))
+        .bind(id)
+        .fetch_optional(&self.0)
+        .await?;
+        row.map(parse_alert_rule).transpose()
+    }
+
+    /// Store a new rule, ignoring `rule.id`, and return the id it was given.
+    pub async fn insert_alert_rule(&self, rule: &AlertRule) -> anyhow::Result<i64> {
+        let id = sqlx::query(
+            "INSERT INTO alert_rules
+                 (name, sensor_id, data_type, condition, threshold, hysteresis, min_duration_secs, enabled)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&rule.name)
+        .bind(rule.sensor_id.map(|id| format!("{id:032x}")))
+        .bind(rule.kind.map(ReadingKind::as_str))
+        .bind(rule.condition.as_str())
+        .bind(f64::from(rule.condition.threshold()))
+        .bind(f64::from(rule.hysteresis))
+        .bind(rule.min_duration_secs)
+        .bind(rule.enabled)
+        .execute(&self.0)
+        .await?
+        .last_insert_rowid();
+        Ok(id)
+    }
+
+    /// Overwrite the rule with `rule.id`. Returns `false` if there is no such rule.
+    pub async fn update_alert_rule(&self, rule: &AlertRule) -> anyhow::Result<bool> {
+        let updated = sqlx::query(
+            "UPDATE alert_rules
+             SET name = ?, sensor_id = ?, data_type = ?, condition = ?, threshold = ?,
+                 hysteresis = ?, min_duration_secs = ?, enabled = ?
+             WHERE id = ?",
+        )
+        .bind(&rule.name)
+        .bind(rule.sensor_id.map(|id| format!("{id:032x}")))
+        .bind(rule.kind.map(ReadingKind::as_str))
+        .bind(rule.condition.as_str())
+        .bind(f64::from(rule.condition.threshold()))
+        .bind(f64::from(rule.hysteresis))
+        .bind(rule.min_duration_secs)
+        .bind(rule.enabled)
+        .bind(rule.id)
+        .execute(&self.0)
+        .await?
+        .rows_affected();
+        Ok(updated > 0)
+    }
+
+    /// Returns `false` if there is no such rule. Its history is kept.
+    pub async fn delete_alert_rule(&self, id: i64) -> anyhow::Result<bool> {
+        let deleted = sqlx::query("DELETE FROM alert_rules WHERE id = ?")
+            .bind(id)
+            .execute(&self.0)
+            .await?
+            .rows_affected();
+        Ok(deleted > 0)
+    }
+
+    /// Record a transition, ignoring `event.id`, and return the id it was given.
+    pub async fn insert_alert_event(&self, event: &AlertEvent) -> anyhow::Result<i64> {
+        let id = sqlx::query(
+            "INSERT INTO alert_events (rule_id, sensor_id, state, value, timestamp)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(event.rule_id)
+        .bind(format!("{:032x}", event.sensor_id))
+        .bind(event.state.as_str())
+        .bind(event.value.map(f64::from))
+        .bind(event.at.to_rfc3339())
+        .execute(&self.0)
+        .await?
+        .last_insert_rowid();
+        Ok(id)
+    }
+
+    /// Transitions at or after `since`, newest first, optionally for one rule.
+    pub async fn alert_history(
+        &self,
+        since: DateTime<Utc>,
+        rule_id: Option<i64>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<AlertEvent>> {
+        let rows = sqlx::query_as::<_, AlertEventRow>(
+            "SELECT id, rule_id, sensor_id, state, value, timestamp FROM alert_events
+             WHERE timestamp >= ?1 AND (?2 IS NULL OR rule_id = ?2)
+             ORDER BY timestamp DESC, id DESC
+             LIMIT ?3",
+        )
+        .bind(since.to_rfc3339())
+        .bind(rule_id)
+        .bind(limit)
+        .fetch_all(&self.0)
+        .await?;
+        rows.into_iter().map(parse_alert_event).collect()
+    }
+
+    /// The latest event of every `(rule, sensor)` pair whose latest event is a firing, i.e.
+    /// the alerts that are currently open. Alerts of deleted rules are never open.
+    pub async fn active_alerts(&self) -> anyhow::Result<Vec<AlertEvent>> {
+        let rows = sqlx::query_as::<_, AlertEventRow>(
+            "SELECT id, rule_id, sensor_id, state, value, timestamp FROM alert_events
+             WHERE id IN (SELECT MAX(id) FROM alert_events GROUP BY rule_id, sensor_id)
+               AND state = 'fired'
+               AND rule_id IN (SELECT id FROM alert_rules)
+             ORDER BY timestamp DESC",
+        )
+        .fetch_all(&self.0)
+        .await?;
+        rows.into_iter().map(parse_alert_event).collect()
+    }
+}
+
+/// An HTTP endpoint notified of sensor state changes, alerts and its own threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookTarget {
+    pub id: i64,
+    pub url: String,
+    /// HMAC-SHA256 key for the signature header; `None` sends unsigned requests.
+    pub secret: Option<String>,
+    /// `None` notifies about every sensor.
+    pub sensor_id: Option<u128>,
+    /// Metric the threshold applies to; `None` disables threshold notifications.
+    pub kind: Option<ReadingKind>,
+    pub above: Option<f32>,
+    pub below: Option<f32>,
+    /// How far back inside the threshold a value must go before it counts as cleared.
+    pub hysteresis: f32,
+    pub enabled: bool,
+}
+
+/// Outcome of one webhook delivery, after all retries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub target_id: i64,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i64,
+    /// HTTP status of the last attempt, if the request got that far.
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub delivered: bool,
+    pub at: DateTime<Utc>,
+}
+
+type WebhookTargetRow = (i64, String, Option<String>, Option<String>, Option<String>, Option<f64>, Option<f64>, f64, bool);
+type WebhookDeliveryRow = (i64, i64, String, String, i64, Option<i64>, Option<String>, bool, String);
+
+const WEBHOOK_TARGET_COLUMNS: &str = "id, url, secret, sensor_id, data_type, above, below, hysteresis, enabled";
+
+impl Db {
+    pub async fn webhook_targets(&self) -> anyhow::Result<Vec<WebhookTarget>> {
+        let rows = sqlx::query_as::<_, WebhookTargetRow>(&format!(
+            "SELECT {WEBHOOK_TARGET_COLUMNS} FROM webhook_targets ORDER BY id"
+        ))
+        .fetch_all(&self.0)
+        .await?;
+        rows.into_iter().map(parse_webhook_target).collect()
+    }
+
+    pub async fn webhook_target(&self, id: i64) -> anyhow::Result<Option<WebhookTarget>> {
+        let row = sqlx::query_as::<_, WebhookTargetRow>(&format!(
+            "SELECT {WEBHOOK_TARGET_COLUMNS} FROM webhook_targets WHERE id = ?"
+        ))
+        .bind(id)
+        .fetch_optional(&self.0)
+        .await?;
+        row.map(parse_webhook_target).transpose()
+    }
+
+    /// Store a new target, ignoring `target.id`, and return the id it was given.
+    pub async fn insert_webhook_target(&self, target: &WebhookTarget) -> anyhow::Result<i64> {
+        let id = sqlx::query(
+            "INSERT INTO webhook_targets
+                 (url, secret, sensor_id, data_type, above, below, hysteresis, enabled)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&target.url)
+        .bind(&target.secret)
+        .bind(target.sensor_id.map(|id| format!("{id:032x}")))
+        .bind(target.kind.map(ReadingKind::as_str))
+        .bind(target.above.map(f64::from))
+        .bind(target.below.map(f64::from))
+        .bind(f64::from(target.hysteresis))
+        .bind(target.enabled)
+        .execute(&self.0)
+        .await?
+        .last_insert_rowid();
+        Ok(id)
+    }
+
+    /// Overwrite the target with `target.id`. Returns `false` if there is no such target.
+    pub async fn update_webhook_target(&self, target: &WebhookTarget) -> anyhow::Result<bool> {
+        let updated = sqlx::query(
+            "UPDATE webhook_targets
+             SET url = ?, secret = ?, sensor_id = ?, data_type = ?, above = ?, below = ?,
+                 hysteresis = ?, enabled = ?
+             WHERE id = ?",
+        )
+        .bind(&target.url)
+        .bind(&target.secret)
+        .bind(target.sensor_id.map(|id| format!("{id:032x}")))
+        .bind(target.kind.map(ReadingKind::as_str))
+        .bind(target.above.map(f64::from))
+        .bind(target.below.map(f64::from))
+        .bind(f64::from(target.hysteresis))
+        .bind(target.enabled)
+        .bind(target.id)
+        .execute(&self.0)
+        .await?
+        .rows_affected();
+        Ok(updated > 0)
+    }
+
+    /// Returns `false` if there is no such target. Its delivery log is kept.
+    pub async fn delete_webhook_target(&self, id: i64) -> anyhow::Result<bool> {
+        let deleted = sqlx::query("DELETE FROM webhook_targets WHERE id = ?")
+            .bind(id)
+            .execute(&self.0)
+            .await?
+            .rows_affected();
+        Ok(deleted > 0)
+    }
+
+    /// Log a finished delivery, ignoring `delivery.id`, and return the id it was given.
+    pub async fn insert_webhook_delivery(&self, delivery: &WebhookDelivery) -> anyhow::Result<i64> {
+        let id = sqlx::query(
+            "INSERT INTO webhook_deliveries
+                 (target_id, event, payload, attempts, status, error, delivered, timestamp)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(delivery.target_id)
+        .bind(&delivery.event)
+        .bind(&delivery.payload)
+        .bind(delivery.attempts)
+        .bind(delivery.status.map(i64::from))
+        .bind(&delivery.error)
+        .bind(delivery.delivered)
+        .bind(delivery.at.to_rfc3339())
+        .execute(&self.0)
+        .await?
+        .last_insert_rowid();
+        Ok(id)
+    }
+
+    /// Most recent deliveries to `target_id`, newest first.
+    pub async fn webhook_deliveries(&self, target_id: i64, limit: i64) -> anyhow::Result<Vec<WebhookDelivery>> {
+        let rows = sqlx::query_as::<_, WebhookDeliveryRow>(
+            "SELECT id, target_id, event, payload, attempts, status, error, delivered, timestamp
+             FROM webhook_deliveries
+             WHERE target_id = ?
+             ORDER BY timestamp DESC, id DESC
+             LIMIT ?",
+        )
+        .bind(target_id)
+        .bind(limit)
+        .fetch_all(&self.0)
+        .await?;
+        rows.into_iter()
+            .map(|(id, target_id, event, payload, attempts, status, error, delivered, ts)| {
+                Ok(WebhookDelivery {
+                    id,
+                    target_id,
+                    event,
+                    payload,
+                    attempts,
+                    status: status.map(u16::try_from).transpose()?,
+                    error,
+                    delivered,
+                    at: ts.parse::<DateTime<Utc>>()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A sensor as last recorded in the `sensors` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorRecord {
+    pub id: u128,
+    pub name: Option<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub firmware_version: Option<String>,
+    pub sample_interval_ms: Option<u32>,
+    /// Where the sensor is mounted, e.g. "bench 3".
+    pub location: Option<String>,
+    /// Free text set by an operator.
+    pub notes: Option<String>,
+}
+
+impl From<&SensorRecord> for DeviceInfo {
+    /// The registry entry to seed for a sensor not heard from since startup.
+    fn from(record: &SensorRecord) -> Self {
+        DeviceInfo {
+            id: record.id,
+            name: record.name.clone(),
+            last_seen: Some(record.last_seen),
+            firmware_version: record.firmware_version.clone(),
+            sample_interval_ms: record.sample_interval_ms,
+            ..DeviceInfo::default()
+        }
+    }
+}
+
+type SensorRow = (String, Option<String>, String, String, Option<String>, Option<i64>, Option<String>, Option<String>);
+
+const SENSOR_COLUMNS: &str =
+    "id, name, first_seen, last_seen, firmware_version, sample_interval_ms, location, notes";
+
+impl Db {
+    pub async fn sensors(&self) -> anyhow::Result<Vec<SensorRecord>> {
+        let rows = sqlx::query_as::<_, SensorRow>(&format!("SELECT {SENSOR_COLUMNS} FROM sensors ORDER BY id"))
+            .fetch_all(&self.0)
+            .await?;
+        rows.into_iter().map(parse_sensor).collect()
+    }
+
+    pub async fn sensor(&self, id: u128) -> anyhow::Result<Option<SensorRecord>> {
+        let row = sqlx::query_as::<_, SensorRow>(&format!("SELECT {SENSOR_COLUMNS} FROM sensors WHERE id = ?"))
+            .bind(format!("{id:032x}"))
+            .fetch_optional(&self.0)
+            .await?;
+        row.map(parse_sensor).transpose()
+    }
+
+    /// Record what the registry knows about `device`. Fields the device doesn't know
+    /// (yet) keep their stored values, so a sensor that hasn't re-announced itself since
+    /// a restart keeps its name. No-op for a device never seen.
+    pub async fn upsert_sensor(&self, device: &DeviceInfo) -> anyhow::Result<()> {
+        let Some(last_seen) = device.last_seen else {
+            return Ok(());
+        };
+        let last_seen = last_seen.to_rfc3339();
+        sqlx::query(
+            "INSERT INTO sensors (id, name, first_seen, last_seen, firmware_version, sample_interval_ms)
+             VALUES (?, ?, ?, ?, ?, ?)
+             ON CONFLICT (id) DO UPDATE SET
+                 name               = COALESCE(excluded.name, name),
+                 last_seen          = MAX(excluded.last_seen, last_seen),
+                 firmware_version   = COALESCE(excluded.firmware_version, firmware_version),
+                 sample_interval_ms = COALESCE(excluded.sample_interval_ms, sample_interval_ms)",
+        )
+        .bind(format!("{:032x}", device.id))
+        .bind(&device.name)
+        .bind(&last_seen)
+        .bind(&last_seen)
+        .bind(&device.firmware_version)
+        .bind(device.sample_interval_ms.map(i64::from))
+        .execute(&self.0)
+        .await?;
+        Ok(())
+    }
+
+    /// Record a sensor known from imported history: widens its first/last seen to cover
+    /// `[first_seen, last_seen]`, and takes `name` only if it has none.
+    pub async fn upsert_imported_sensor(
+        &self,
+        id: u128,
+        name: Option<&str>,
+        first_seen: DateTime<Utc>,
+        last_seen: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO sensors (id, name, first_seen, last_seen)
+             VALUES (?, ?, ?, ?)
+             ON CONFLICT (id) DO UPDATE SET
+                 name       = COALESCE(name, excluded.name),
+                 first_seen = MIN(excluded.first_seen, first_seen),
+                 last_seen  = MAX(excluded.last_seen, last_seen)",
+        )
+        .bind(format!("{id:032x}"))
+        .bind(name)
+        .bind(first_seen.to_rfc3339())
+        .bind(last_seen.to_rfc3339())
+        .execute(&self.0)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns `false` if the sensor has never been recorded.
+    pub async fn set_sensor_notes(&self, id: u128, notes: Option<&str>) -> anyhow::Result<bool> {
+        let updated = sqlx::query("UPDATE sensors SET notes = ? WHERE id = ?")
+            .bind(notes)
+            .bind(format!("{id:032x}"))
+            .execute(&self.0)
+            .await?
+            .rows_affected();
+        Ok(updated > 0)
+    }
+
+    /// Returns `false` if the sensor has never been recorded.
+    pub async fn set_sensor_location(&self, id: u128, location: Option<&str>) -> anyhow::Result<bool> {
+        let updated = sqlx::query("UPDATE sensors SET location = ? WHERE id = ?")
+            .bind(location)
+            .bind(format!("{id:032x}"))
+            .execute(&self.0)
+            .await?
+            .rows_affected();
+        Ok(updated > 0)
+    }
+
+    /// Every `(sensor_id, tag)` pair, ordered by sensor then tag.
+    pub async fn sensor_tags(&self) -> anyhow::Result<Vec<(u128, String)>> {
+        let rows = sqlx::query_as::<_, (String, String)>(
+            "SELECT sensor_id, tag FROM sensor_tags ORDER BY sensor_id, tag",
+        )
+        .fetch_all(&self.0)
+        .await?;
+        rows.into_iter()
+            .map(|(id, tag)| Ok((parse_sensor_id(&id)?, tag)))
+            .collect()
+    }
+
+    /// Tags of one sensor, sorted.
+    pub async fn tags_of(&self, id: u128) -> anyhow::Result<Vec<String>> {
+        let rows = sqlx::query_as::<_, (String,)>("SELECT tag FROM sensor_tags WHERE sensor_id = ? ORDER BY tag")
+            .bind(format!("{id:032x}"))
+            .fetch_all(&self.0)
+            .await?;
+        Ok(rows.into_iter().map(|(tag,)| tag).collect())
+    }
+
+    /// Replace the tags of sensor `id` with `tags`.
+    pub async fn set_sensor_tags(&self, id: u128, tags: &[String]) -> anyhow::Result<()> {
+        let sensor_id = format!("{id:032x}");
+        let mut tx = self.0.begin().await?;
+        sqlx::query("DELETE FROM sensor_tags WHERE sensor_id = ?")
+            .bind(&sensor_id)
+            .execute(&mut *tx)
+            .await?;
+        for tag in tags {
+            sqlx::query("INSERT OR IGNORE INTO sensor_tags (sensor_id, tag) VALUES (?, ?)")
+                .bind(&sensor_id)
+                .bind(tag)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Returns `false` if the sensor already had the tag.
+    pub async fn add_sensor_tag(&self, id: u128, tag: &str) -> anyhow::Result<bool> {
+        let inserted = sqlx::query("INSERT OR IGNORE INTO sensor_tags (sensor_id, tag) VALUES (?, ?)")
+            .bind(format!("{id:032x}"))
+            .bind(tag)
+            .execute(&self.0)
+            .await?
+            .rows_affected();
+        Ok(inserted > 0)
+    }
+
+    /// Returns `false` if the sensor didn't have the tag.
+    pub async fn remove_sensor_tag(&self, id: u128, tag: &str) -> anyhow::Result<bool> {
+        let deleted = sqlx::query("DELETE FROM sensor_tags WHERE sensor_id = ? AND tag = ?")
+            .bind(format!("{id:032x}"))
+            .bind(tag)
+            .execute(&self.0)
+            .await?
+            .rows_affected();
+        Ok(deleted > 0)
+    }
+}
+
+/// A named set of sensors, e.g. a room or a bench. A sensor may be in several groups.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorGroup {
+    pub id: i64,
+    pub name: String,
+    /// Sorted by id.
+    pub members: Vec<u128>,
+}
+
+impl Db {
+    /// All groups, ordered by name.
+    pub async fn groups(&self) -> anyhow::Result<Vec<SensorGroup>> {
+        let groups = sqlx::query_as::<_, (i64, String)>("SELECT id, name FROM sensor_groups ORDER BY name")
+            .fetch_all(&self.0)
+            .await?;
+        let members = sqlx::query_as::<_, (i64, String)>(
+            "SELECT group_id, sensor_id FROM sensor_group_members ORDER BY group_id, sensor_id",
+        )
+        .fetch_all(&self.0)
+        .await?;
+        groups
+            .into_iter()
+            .map(|(id, name)| {
+                let members = members
+                    .iter()
+                    .filter(|(group_id, _)| *group_id == id)
+                    .map(|(_, sensor_id)| parse_sensor_id(sensor_id))
+                    .collect::<anyhow::Result<_>>()?;
+                Ok(SensorGroup { id, name, members })
+            })
+            .collect()
+    }
+
+    pub async fn group(&self, id: i64) -> anyhow::Result<Option<SensorGroup>> {
+        Ok(self.groups().await?.into_iter().find(|group| group.id == id))
+    }
+
+    /// Store a new group, ignoring `group.id`, and return the id it was given. Fails if
+    /// the name is taken.
+    pub async fn insert_group(&self, group: &SensorGroup) -> anyhow::Result<i64> {
+        let mut tx = self.0.begin().await?;
+        let id = sqlx::query("INSERT INTO sensor_groups (name) VALUES (?)")
+            .bind(&group.name)
+            .execute(&mut *tx)
+            .await?
+            .last_insert_rowid();
+        insert_members(&mut tx, id, &group.members).await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    /// Overwrite the name and members of the group with `group.id`. Returns `false` if
+    /// there is no such group.
+    pub async fn update_group(&self, group: &SensorGroup) -> anyhow::Result<bool> {
+        let mut tx = self.0.begin().await?;
+        let updated = sqlx::query("UPDATE sensor_groups SET name = ? WHERE id = ?")
+            .bind(&group.name)
+            .bind(group.id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if updated == 0 {
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM sensor_group_members WHERE group_id = ?")
+            .bind(group.id)
+            .execute(&mut *tx)
+            .await?;
+        insert_members(&mut tx, group.id, &group.members).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Returns `false` if there is no such group. Its sensors are left alone.
+    pub async fn delete_group(&self, id: i64) -> anyhow::Result<bool> {
+        let mut tx = self.0.begin().await?;
+        sqlx::query("DELETE FROM sensor_group_members WHERE group_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let deleted = sqlx::query("DELETE FROM sensor_groups WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+        Ok(deleted > 0)
+    }
+}
+
+async fn insert_members(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    group_id: i64,
+    members: &[u128],
+) -> anyhow::Result<()> {
+    for sensor_id in members {
+        sqlx::query("INSERT OR IGNORE INTO sensor_group_members (group_id, sensor_id) VALUES (?, ?)")
+            .bind(group_id)
+            .bind(format!("{sensor_id:032x}"))
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(())
+}
+
+/// One version of a sensor's calibration for one metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationVersion {
+    /// Increases with every change; identifies the version.
+    pub id: i64,
+    pub sensor_id: u128,
+    pub kind: ReadingKind,
+    /// `None` records that the calibration was removed.
+    pub calibration: Option<Calibration>,
+    pub valid_from: DateTime<Utc>,
+}
+
+type CalibrationRow = (i64, String, String, String, Option<f64>, Option<f64>, Option<String>, String);
+
+const CALIBRATION_COLUMNS: &str = "id, sensor_id, data_type, method, scale, offset, points, valid_from";
+
+impl Db {
+    /// Record a new calibration version, ignoring `version.id`, and return its id.
+    pub async fn insert_calibration(&self, version: &CalibrationVersion) -> anyhow::Result<i64> {
+        let (method, scale, offset, points) = match &version.calibration {
+            Some(Calibration::Linear { scale, offset }) => {
+                ("linear", Some(f64::from(*scale)), Some(f64::from(*offset)), None)
+            }
+            Some(Calibration::Table(points)) => {
+                let points = points
+                    .iter()
+                    .map(|(raw, value)| format!("{raw}:{value}"))
+                    .collect::<Vec<_>>()
+                    .join(";");
+                ("table", None, None, Some(points))
+            }
+            None => ("none", None, None, None),
+        };
+        let id = sqlx::query(
+            "INSERT INTO calibrations (sensor_id, data_type, method, scale, offset, points, valid_from)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(format!("{:032x}", version.sensor_id))
+        .bind(version.kind.as_str())
+        .bind(method)
+        .bind(scale)
+        .bind(offset)
+        .bind(points)
+        .bind(version.valid_from.to_rfc3339())
+        .execute(&self.0)
+        .await?
+        .last_insert_rowid();
+        Ok(id)
+    }
+
+    /// Every version for `sensor_id`, optionally only of `kind`, oldest first.
+    pub async fn calibration_history(
+        &self,
+        sensor_id: u128,
+        kind: Option<ReadingKind>,
+    ) -> anyhow::Result<Vec<CalibrationVersion>> {
+        let rows = sqlx::query_as::<_, CalibrationRow>(&format!(
+            "SELECT {CALIBRATION_COLUMNS} FROM calibrations
+             WHERE sensor_id = ? AND (?2 IS NULL OR data_type = ?2)
+             ORDER BY valid_from, id"
+        ))
+        .bind(format!("{sensor_id:032x}"))
+        .bind(kind.map(ReadingKind::as_str))
+        .fetch_all(&self.0)
+        .await?;
+        rows.into_iter().map(parse_calibration).collect()
+    }
+
+    /// The latest version per sensor and metric, including removals.
+    pub async fn current_calibration_versions(&self) -> anyhow::Result<Vec<CalibrationVersion>> {
+        let rows = sqlx::query_as::<_, CalibrationRow>(&format!(
+            "SELECT {CALIBRATION_COLUMNS} FROM calibrations
+             WHERE id IN (SELECT MAX(id) FROM calibrations GROUP BY sensor_id, data_type)
+             ORDER BY sensor_id, data_type"
+        ))
+        .fetch_all(&self.0)
+        .await?;
+        rows.into_iter().map(parse_calibration).collect()
+    }
+
+    /// The calibrations in effect now, ready for [`crate::SensorClient::set_calibrations`].
+    pub async fn current_calibrations(&self) -> anyhow::Result<Calibrations> {
+        let mut calibrations = Calibrations::new();
+        for version in self.current_calibration_versions().await? {
+            if let Some(calibration) = version.calibration {
+                calibrations.insert(version.sensor_id, version.kind, calibration);
+            }
+        }
+        Ok(calibrations)
+    }
+}
+
+/// Which stored readings [`Db::export`] returns. `None` fields don't filter.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExportQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Average into windows this many seconds wide; `None` exports rows as stored.
+    pub bucket_secs: Option<i64>,
+    pub sensor_id: Option<u128>,
+    pub kind: Option<ReadingKind>,
+}
+
+/// One exported reading, labelled with its sensor's recorded name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportRow {
+    pub at: DateTime<Utc>,
+    pub sensor_id: u128,
+    pub name: Option<String>,
+    pub kind: ReadingKind,
+    pub value: f32,
+}
+
+type ExportRowRaw = (i64, String, Option<String>, String, f64);
+
+/// Stored rows for [`Db::export`], from `rows` (see [`rows_sql`]); `?1`..`?4` are from,
+/// to, sensor and metric, the last two ignored when null.
+fn export_sql(rows: &str) -> String {
+    format!(
+        "SELECT r.ts, k.sensor_id, s.name, r.data_type, r.value
+         FROM ({rows}) r JOIN sensor_keys k ON k.key = r.sensor LEFT JOIN sensors s ON s.id = k.sensor_id
+         WHERE (?3 IS NULL OR k.sensor_id = ?3) AND (?4 IS NULL OR r.data_type = ?4)
+         ORDER BY r.ts, k.sensor_id, r.data_type"
+    )
+}
+
+/// As [`export_sql`], averaged into `?5`-millisecond buckets labelled by their start.
+fn export_bucketed_sql(rows: &str) -> String {
+    format!(
+        "SELECT r.ts / ?5 * ?5 AS bucket, k.sensor_id, s.name, r.data_type,
+                SUM(r.value * r.samples) / SUM(r.samples)
+         FROM ({rows}) r JOIN sensor_keys k ON k.key = r.sensor LEFT JOIN sensors s ON s.id = k.sensor_id
+         WHERE (?3 IS NULL OR k.sensor_id = ?3) AND (?4 IS NULL OR r.data_type = ?4)
+         GROUP BY bucket, r.sensor, r.data_type
+         ORDER BY bucket, k.sensor_id, r.data_type"
+    )
+}
+
+impl Db {
+    /// Readings matching `query`, oldest first. Rows are read as the stream is polled
+    /// rather than collected up front, so exports of any size run in constant memory.
+    #[must_use]
+    pub fn export(&self, query: &ExportQuery) -> BoxStream<'_, anyhow::Result<ExportRow>> {
+        // The query text depends on the rollup tiers, so a task owns it while rows are
+        // read; the bounded channel keeps it from running ahead of the consumer.
+        let (tx, rx) = mpsc::channel(256);
+        let (db, query) = (self.clone(), query.clone());
+        tokio::spawn(async move {
+            let tiers = match db.tiers().await {
+                Ok(tiers) => tiers,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            };
+            let rows = rows_sql(&tiers, query.bucket_secs);
+            let sql = if query.bucket_secs.is_some() { export_bucketed_sql(&rows) } else { export_sql(&rows) };
+            let mut rows = sqlx::query_as::<_, ExportRowRaw>(&sql)
+                .bind(query.from.map_or(i64::MIN, |at| at.timestamp_millis()))
+                .bind(query.to.map_or(i64::MAX, |at| at.timestamp_millis()))
+                .bind(query.sensor_id.map(|id| format!("{id:032x}")))
+                .bind(query.kind.map(ReadingKind::as_str))
+                .bind(query.bucket_secs.map(|secs| secs.max(1) * 1000))
+                .fetch(&db.0);
+            while let Some(row) = rows.next().await {
+                let row = row.map_err(anyhow::Error::from).and_then(parse_export);
+                if tx.send(row).await.is_err() {
+                    break;
+                }
+            }
+        });
+        stream::unfold(rx, |mut rx| async { rx.recv().await.map(|row| (row, rx)) }).boxed()
+    }
+}
+
+fn parse_calibration(row: CalibrationRow) -> anyhow::Result<CalibrationVersion> {
+    let (id, sensor_id, data_type, method, scale, offset, points, valid_from) = row;
+    #[allow(clippy::cast_possible_truncation)]
+    let calibration = match method.as_str() {
+        "linear" => Some(Calibration::Linear {
+            scale: scale.unwrap_or(1.0) as f32,
+            offset: offset.unwrap_or(0.0) as f32,
+        }),
+        "table" => {
+            let points = points
+                .unwrap_or_default()
+                .split(';')
+                .map(|pair| {
+                    let (raw, value) = pair
+                        .split_once(':')
+                        .ok_or_else(|| anyhow::anyhow!("bad calibration point: {pair}"))?;
+                    Ok((raw.parse()?, value.parse()?))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Some(Calibration::table(points).ok_or_else(|| anyhow::anyhow!("bad calibration table {id}"))?)
+        }
+        "none" => None,
+        other => return Err(anyhow::anyhow!("unknown calibration method: {other}")),
+    };
+    Ok(CalibrationVersion {
+        id,
+        sensor_id: parse_sensor_id(&sensor_id)?,
+        kind: parse_kind(&data_type)?,
+        calibration,
+        valid_from: valid_from.parse::<DateTime<Utc>>()?,
+    })
+}
+
+fn parse_sensor(row: SensorRow) -> anyhow::Result<SensorRecord> {
+    let (id, name, first_seen, last_seen, firmware_version, sample_interval_ms, location, notes) = row;
+    Ok(SensorRecord {
+        id: parse_sensor_id(&id)?,
+        name,
+        first_seen: first_seen.parse::<DateTime<Utc>>()?,
+        last_seen: last_seen.parse::<DateTime<Utc>>()?,
+        firmware_version,
+        sample_interval_ms: sample_interval_ms.map(u32::try_from).transpose()?,
+        location,
+        notes,
+    })
+}
+
+fn parse_webhook_target(row: WebhookTargetRow) -> anyhow::Result<WebhookTarget> {
+    let (id, url, secret, sensor_id, data_type, above, below, hysteresis, enabled) = row;
+    #[allow(clippy::cast_possible_truncation)]
+    Ok(WebhookTarget {
+        id,
+        url,
+        secret,
+        sensor_id: sensor_id.as_deref().map(parse_sensor_id).transpose()?,
+        kind: data_type.as_deref().map(parse_kind).transpose()?,
+        above: above.map(|v| v as f32),
+        below: below.map(|v| v as f32),
+        hysteresis: hysteresis as f32,
+        enabled,
+    })
+}
+
+fn parse_sensor_id(id_hex: &str) -> anyhow::Result<u128> {
+    Ok(u128::from_str_radix(id_hex, 16)?)
+}
+
+fn parse_alert_rule(row: AlertRuleRow) -> anyhow::Result<AlertRule> {
+    let (id, name, sensor_id, data_type, condition, threshold, hysteresis, min_duration_secs, enabled) = row;
+    #[allow(clippy::cast_possible_truncation)]
+    let condition = AlertCondition::parse(&condition, threshold as f32)
+        .ok_or_else(|| anyhow::anyhow!("unknown alert condition: {condition}"))?;
+    #[allow(clippy::cast_possible_truncation)]
+    Ok(AlertRule {
+        id,
+        name,
+        sensor_id: sensor_id.as_deref().map(parse_sensor_id).transpose()?,
+        kind: data_type.as_deref().map(parse_kind).transpose()?,
+        condition,
+        hysteresis: hysteresis as f32,
+        min_duration_secs,
+        enabled,
+    })
+}
+
+fn parse_alert_event(row: AlertEventRow) -> anyhow::Result<AlertEvent> {
+    let (id, rule_id, sensor_id, state, value, ts) = row;
+    let state = match state.as_str() {
+        "fired" => AlertState::Fired,
+        "resolved" => AlertState::Resolved,
+        other => return Err(anyhow::anyhow!("unknown alert state: {other}")),
+    };
+    #[allow(clippy::cast_possible_truncation)]
+    Ok(AlertEvent {
+        id,
+        rule_id,
+        sensor_id: parse_sensor_id(&sensor_id)?,
+        state,
+        value: value.map(|v| v as f32),
+        at: ts.parse::<DateTime<Utc>>()?,
+    })
+}
+
+fn parse_export(row: ExportRowRaw) -> anyhow::Result<ExportRow> {
+    let (ts, sensor_id, name, data_type, value) = row;
+    let (at, value) = parse_point(ts, value)?;
+    Ok(ExportRow {
+        at,
+        sensor_id: parse_sensor_id(&sensor_id)?,
+        name,
+        kind: parse_kind(&data_type)?,
+        value,
+    })
+}
+
+fn parse_kind(data_type: &str) -> anyhow::Result<ReadingKind> {
+    ReadingKind::from_id(data_type).ok_or_else(|| anyhow::anyhow!("unknown data_type: {data_type}"))
+}
+
+fn parse_millis(ms: i64) -> anyhow::Result<DateTime<Utc>> {
+    DateTime::from_timestamp_millis(ms).ok_or_else(|| anyhow::anyhow!("timestamp {ms} out of range"))
+}
+
+fn parse_point(ts: i64, value: f64) -> anyhow::Result<Point> {
+    #[allow(clippy::cast_possible_truncation)]
+    Ok((parse_millis(ts)?, value as f32))
+}
+
+/// A database path under the temp dir for tests. It and its `-wal`/`-shm` siblings are
+/// removed when it is created and again when it is dropped, so a failing assertion doesn't
+/// leave the file behind.
+#[cfg(test)]
+struct TempDb(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDb {
+    fn new(tag: &str) -> Self {
+        let temp = Self(std::env::temp_dir().join(format!("chlorophyll-{tag}-{}.db", std::process::id())));
+        temp.remove();
+        temp
+    }
+
+    /// A fresh, migrated database at a new temp path.
+    async fn open(tag: &str) -> (Db, Self) {
+        let temp = Self::new(tag);
+        (Db::open(temp.path()).await.unwrap(), temp)
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+
+    fn remove(&self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut p = self.0.clone().into_os_string();
+            p.push(suffix);
+            let _ = std::fs::remove_file(p);
+        }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two readings per bucket at a known epoch offset; each bucket should collapse to the
+    /// mean of its members, which is what keeps multi-day windows chart-sized.
+    #[tokio::test]
+    async fn history_bucketed_averages_within_each_bucket() {
+        let (db, _path) = TempDb::open("bucket").await;
+
+        let base = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        for (offset_secs, value) in [(0, 10.0), (30, 20.0), (60, 30.0), (90, 50.0)] {
+            db.insert_reading(&Reading {
+                sensor_id: 1,
+                kind: ReadingKind::Temperature,
+                value,
+                at: base + chrono::Duration::seconds(offset_secs),
+            })
+            .await
+            .unwrap();
+        }
+
+        let series = db
+            .history_bucketed(base, base + chrono::Duration::seconds(120), 60)
+            .await
+            .unwrap();
+
+        assert_eq!(series.len(), 1);
+        let (_, kind, points) = &series[0];
+        assert_eq!(*kind, ReadingKind::Temperature);
+        assert_eq!(points.len(), 2, "4 readings across 2 buckets collapse to 2 points");
+        assert!((points[0].value - 15.0).abs() < 0.001, "got {}", points[0].value);
+        assert!((points[1].value - 40.0).abs() < 0.001, "got {}", points[1].value);
+        assert_eq!((points[1].min, points[1].max, points[1].count), (30.0, 50.0, 2));
+        assert!(points[0].at < points[1].at);
+    }
+}
+
+#[cfg(test)]
+mod compaction_tests {
+    use super::*;
+
+    async fn temp_db(tag: &str) -> (Db, TempDb) {
+        TempDb::open(&format!("compact-{tag}")).await
+    }
+
+    async fn count(db: &Db, table: &str) -> i64 {
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+            .fetch_one(&db.0)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn compaction_rolls_old_rows_up_and_leaves_recent_ones_alone() {
+        let (db, _path) = temp_db("ladder").await;
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        // 120 raw readings spread over two hours, ending 3h ago: all past the 1h rung.
+        for i in 0..120 {
+            db.insert_reading(&Reading {
+                sensor_id: 1,
+                kind: ReadingKind::Temperature,
+                value: 20.0,
+                at: now - chrono::Duration::minutes(180 - i),
+            })
+            .await
+            .unwrap();
+        }
+        // 10 raw readings inside the last 10 minutes: must survive untouched.
+        for i in 0..10 {
+            db.insert_reading(&Reading {
+                sensor_id: 1,
+                kind: ReadingKind::Temperature,
+                value: 30.0,
+                at: now - chrono::Duration::minutes(i),
+            })
+            .await
+            .unwrap();
+        }
+        assert_eq!(count(&db, "readings").await, 130);
+
+        let removed = db.compact(now, &RetentionPolicy::default()).await.unwrap().removed();
+        assert_eq!(removed, 120, "the old rows leave `readings`");
+        assert_eq!(count(&db, "readings").await, 10, "rows inside the finest rung stay at full resolution");
+
+        // The two hours of 1/minute readings land in every tier at its own resolution.
+        assert_eq!(count(&db, "rollup_60").await, 120, "one row per minute bucket");
+        assert_eq!(count(&db, "rollup_3600").await, 3, "two hours straddling three hour buckets");
+
+        // History reads the same whichever tier answers.
+        let history = db.history_bucketed(now - chrono::Duration::hours(4), now, 60).await.unwrap();
+        assert_eq!(history[0].2.len(), 130);
+    }
+
+    #[tokio::test]
+    async fn compaction_is_idempotent_and_merges_late_rows_by_weight() {
+        let (db, _path) = temp_db("idempotent").await;
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        // Align to a minute boundary so all readings land in one bucket.
+        let old = DateTime::from_timestamp(
+            (now - chrono::Duration::hours(3)).timestamp() / 60 * 60,
+            0,
+        )
+        .unwrap();
+        let insert = |offset, value| {
+            let db = db.clone();
+            async move {
+                db.insert_reading(&Reading {
+                    sensor_id: 1,
+                    kind: ReadingKind::Temperature,
+                    value,
+                    at: old + chrono::Duration::seconds(offset),
+                })
+                .await
+                .unwrap();
+            }
+        };
+
+        // Four readings inside a single minute, averaging 25.
+        for (offset, value) in [(0, 10.0), (15, 20.0), (30, 30.0), (45, 40.0)] {
+            insert(offset, value).await;
+        }
+
+        assert_eq!(db.compact(now, &RetentionPolicy::default()).await.unwrap().removed(), 4);
+        let bucket = || {
+            sqlx::query_as::<_, (f64, i64)>("SELECT value, samples FROM rollup_60").fetch_all(&db.0)
+        };
+        let rows = bucket().await.unwrap();
+        assert_eq!(rows.len(), 1, "one minute bucket remains");
+        assert!((rows[0].0 - 25.0).abs() < 0.001, "got {}", rows[0].0);
+
+        // Running again must be a no-op rather than re-averaging or deleting.
+        assert_eq!(db.compact(now, &RetentionPolicy::default()).await.unwrap().removed(), 0);
+        assert_eq!(bucket().await.unwrap(), rows);
+
+        // Late rows for the same minute join its average in proportion to their number.
+        insert(50, 40.0).await;
+        insert(55, 40.0).await;
+        db.compact(now, &RetentionPolicy::default()).await.unwrap();
+        let rows = bucket().await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert!((rows[0].0 - 30.0).abs() < 0.001, "got {}", rows[0].0);
+        assert_eq!(rows[0].1, 6);
+    }
+
+    #[tokio::test]
     async fn year_old_data_collapses_to_daily_points() {
-        let (db, path) = temp_db("yearly").await;
+        let (db, _path) = temp_db("yearly").await;
         let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
         // Align to a day boundary so the readings cover exactly two daily buckets.
         let ancient = DateTime::from_timestamp(
@@ -451,16 +2103,641 @@ mod compaction_tests {
             .unwrap();
         }
 
-        db.compact(now, DEFAULT_TIERS).await.unwrap();
+        db.compact(now, &RetentionPolicy::default()).await.unwrap();
 
-        let rows = sqlx::query_as::<_, (i64, i64)>(
-            "SELECT bucket_secs, COUNT(*) FROM readings GROUP BY bucket_secs",
-        )
-        .fetch_all(&db.0)
+        for table in ["readings", "rollup_60", "rollup_300", "rollup_3600"] {
+            assert_eq!(count(&db, table).await, 0, "{table} no longer keeps year-old data");
+        }
+        assert_eq!(count(&db, "rollup_86400").await, 2, "two days -> two daily averages");
+
+        // Asking for hours gets the days, as that is all that is left.
+        let history = db
+            .history_bucketed(ancient, ancient + chrono::Duration::days(2), 3_600)
+            .await
+            .unwrap();
+        assert_eq!(history[0].2.len(), 2);
+    }
+
+    /// Two minutes of frost inside two days of ingest buckets, compacted once it is past
+    /// every rung but the daily one: the day's mean barely moves, but its minimum keeps it.
+    #[tokio::test]
+    async fn extremes_and_weights_survive_every_rung() {
+        let (db, _path) = temp_db("extremes").await;
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let day = (now - chrono::Duration::days(400)).timestamp() / 86_400 * 86_400;
+        let day = DateTime::from_timestamp(day, 0).unwrap();
+        for minute in 0..2_880 {
+            let (value, min, count) = if (600..602).contains(&minute) { (1.0, -2.5, 60) } else { (10.0, 9.0, 300) };
+            let averaged = Averaged {
+                reading: Reading {
+                    sensor_id: 1,
+                    kind: ReadingKind::Temperature,
+                    value,
+                    at: day + chrono::Duration::minutes(minute),
+                },
+                min,
+                max: 11.0,
+                count,
+            };
+            db.insert_reading_at(&averaged, 60).await.unwrap();
+        }
+
+        db.compact(now, &RetentionPolicy::default()).await.unwrap();
+        let history = db.history_bucketed(day, day + chrono::Duration::days(2), 86_400).await.unwrap();
+        let points = &history[0].2;
+        assert_eq!(points.len(), 2);
+        assert_eq!((points[0].min, points[0].max), (-2.5, 11.0), "the frost survives as the day's minimum");
+        assert_eq!(points[0].count, 1_438 * 300 + 2 * 60);
+        // Weighted by readings, two short minutes barely move the mean.
+        let expected = (1_438.0 * 300.0 * 10.0 + 2.0 * 60.0) / (1_438.0 * 300.0 + 120.0);
+        assert!((points[0].value - expected).abs() < 1e-4, "got {}", points[0].value);
+        assert_eq!((points[1].min, points[1].max), (9.0, 11.0));
+    }
+
+    /// Ten minutes of light in an hour: DLI counts those ten minutes, whether read from
+    /// ingest buckets or from the hourly rollup they were compacted into.
+    #[tokio::test]
+    async fn dli_counts_the_time_the_rows_cover() {
+        let (db, _path) = temp_db("dli").await;
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let noon = DateTime::from_timestamp((now.timestamp() / 86_400 - 400) * 86_400 + 43_200, 0).unwrap();
+        for minute in 0..10 {
+            let at = noon + chrono::Duration::minutes(minute);
+            let reading = Reading { sensor_id: 1, kind: ReadingKind::Light, value: 10_000.0, at };
+            db.insert_reading_at(&Averaged { count: 300, ..Averaged::from(reading) }, 60).await.unwrap();
+        }
+        let expected = 10_000.0 * derived::LUX_TO_PPFD * 600.0 / 1_000_000.0;
+        let dli = |history: Vec<(String, ReadingKind, Vec<HistoryPoint>)>| {
+            let (_, _, points) = history.into_iter().find(|(_, kind, _)| *kind == ReadingKind::Dli).unwrap();
+            points[0].value
+        };
+
+        let history = db.history_bucketed(noon, noon + chrono::Duration::hours(1), 3_600).await.unwrap();
+        assert!((dli(history) - expected).abs() < 1e-6);
+        let policy = RetentionPolicy {
+            tiers: vec![RetentionTier { older_than_secs: 86_400, bucket_secs: 3_600 }],
+            ..RetentionPolicy::default()
+        };
+        db.compact(now, &policy).await.unwrap();
+        let history = db.history_bucketed(noon, noon + chrono::Duration::hours(1), 3_600).await.unwrap();
+        assert!((dli(history) - expected).abs() < 1e-6, "a rollup covers no more than its rows did");
+    }
+
+    #[test]
+    fn history_reads_the_coarsest_tier_that_tiles_the_bucket() {
+        let tiers = [
+            Tier { bucket_secs: 60, kept_from: Some(3_000) },
+            Tier { bucket_secs: 300, kept_from: Some(2_000) },
+            Tier { bucket_secs: 3_600, kept_from: Some(1_000) },
+            Tier { bucket_secs: 86_400, kept_from: None },
+        ];
+        let tables = |bucket_secs| {
+            let sql = rows_sql(&tiers, bucket_secs);
+            tiers
+                .iter()
+                .filter(|tier| sql.contains(&format!("FROM {} ", tier.table())))
+                .map(|tier| tier.bucket_secs)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tables(None), [60, 300, 3_600, 86_400]);
+        assert_eq!(tables(Some(900)), [300, 3_600, 86_400]);
+        assert_eq!(tables(Some(7_200)), [3_600, 86_400]);
+        assert_eq!(tables(Some(90)), [60, 300, 3_600, 86_400], "nothing tiles 90s, so the finest");
+        assert_eq!(tables(Some(604_800)), [86_400]);
+
+        // Each tier answers only for what the finer ones have dropped.
+        let sql = rows_sql(&tiers, Some(900));
+        assert!(sql.contains("FROM rollup_3600 WHERE ts >= ?1 AND ts <= ?2 AND ts < 2000 AND ts >= 1000"), "{sql}");
+        assert!(sql.ends_with("FROM rollup_86400 WHERE ts >= ?1 AND ts <= ?2 AND ts < 1000"), "{sql}");
+    }
+
+    #[tokio::test]
+    async fn retention_policies_round_trip_once_valid() {
+        let (db, _path) = temp_db("policy").await;
+        assert_eq!(db.retention_policy().await.unwrap(), None, "nothing stored until set");
+
+        let policy = RetentionPolicy {
+            tiers: vec![
+                RetentionTier { older_than_secs: 86_400, bucket_secs: 300 },
+                RetentionTier { older_than_secs: 2_592_000, bucket_secs: 3_600 },
+            ],
+            max_age_secs: Some(31_536_000),
+            sensors: vec![SensorRetention { sensor_id: 7, full_resolution_secs: None, max_age_secs: None }],
+            vacuum: Vacuum::Incremental,
+            interval_secs: 600,
+        };
+        db.set_retention_policy(&policy).await.unwrap();
+        assert_eq!(db.retention_policy().await.unwrap(), Some(policy.clone()));
+
+        let unordered = RetentionPolicy { tiers: policy.tiers.iter().rev().copied().collect(), ..policy.clone() };
+        assert!(db.set_retention_policy(&unordered).await.is_err());
+        let twice = RetentionPolicy { sensors: vec![policy.sensors[0]; 2], ..policy.clone() };
+        assert!(db.set_retention_policy(&twice).await.is_err());
+        let never = RetentionPolicy { max_age_secs: Some(0), ..policy.clone() };
+        assert!(db.set_retention_policy(&never).await.is_err());
+        assert_eq!(db.retention_policy().await.unwrap(), Some(policy), "rejected policies change nothing");
+    }
+
+    /// Sensor 1 follows the policy: a week's history, rolled up after an hour. Sensor 2 is
+    /// kept for a month, at full resolution throughout.
+    #[tokio::test]
+    async fn max_age_and_sensor_overrides_apply_per_sensor_and_dry_runs_match() {
+        let (db, _path) = temp_db("overrides").await;
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let insert = |sensor_id, age| {
+            let db = db.clone();
+            async move {
+                let at = now - chrono::Duration::hours(age);
+                db.insert_reading(&Reading { sensor_id, kind: ReadingKind::Temperature, value: 20.0, at })
+                    .await
+                    .unwrap();
+            }
+        };
+        // Rolled up while nothing expired, leaving it in the hourly and daily tiers.
+        insert(1, 20 * 24).await;
+        db.compact(now, &RetentionPolicy::default()).await.unwrap();
+        for (sensor_id, age) in [(1, 3), (1, 10 * 24), (2, 3), (2, 10 * 24)] {
+            insert(sensor_id, age).await;
+        }
+
+        let policy = RetentionPolicy {
+            max_age_secs: Some(7 * 86_400),
+            sensors: vec![SensorRetention {
+                sensor_id: 2,
+                full_resolution_secs: Some(30 * 86_400),
+                max_age_secs: Some(30 * 86_400),
+            }],
+            ..RetentionPolicy::default()
+        };
+        let dry_run = db.compact_dry_run(now, &policy).await.unwrap();
+        assert_eq!((count(&db, "readings").await, count(&db, "rollup_3600").await), (4, 1), "dry runs change nothing");
+
+        let report = db.compact(now, &policy).await.unwrap();
+        assert_eq!(report, dry_run);
+        assert_eq!((report.expired, report.rolled_up), (1, 1), "sensor 1's old row expires, its recent one rolls up");
+        let tier = |bucket_secs| *report.tiers.iter().find(|tier| tier.bucket_secs == bucket_secs).unwrap();
+        assert_eq!(tier(60), TierReport { bucket_secs: 60, written: 1, pruned: 0, expired: 0 });
+        assert_eq!((tier(3_600).expired, tier(86_400).expired), (1, 1), "expiry reaches the rollups");
+        assert_eq!(report.removed(), 4);
+
+        let kept: Vec<i64> = sqlx::query_scalar("SELECT sensor FROM readings").fetch_all(&db.0).await.unwrap();
+        let mut conn = db.0.acquire().await.unwrap();
+        let sensor_2 = db.1.get(&mut conn, 2).await.unwrap();
+        assert_eq!(kept, [sensor_2, sensor_2], "sensor 2 stays at full resolution inside its own max age");
+    }
+
+    #[tokio::test]
+    async fn removed_tiers_hand_their_history_to_a_coarser_one_or_are_refused() {
+        let (db, _path) = temp_db("drop-tier").await;
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let policy = |tiers: &[(i64, i64)]| RetentionPolicy {
+            tiers: tiers
+                .iter()
+                .map(|&(older_than_secs, bucket_secs)| RetentionTier { older_than_secs, bucket_secs })
+                .collect(),
+            ..RetentionPolicy::default()
+        };
+        let at = now - chrono::Duration::days(3);
+        db.insert_reading(&Reading { sensor_id: 1, kind: ReadingKind::Temperature, value: 20.0, at }).await.unwrap();
+        db.compact(now, &policy(&[(3_600, 60), (86_400, 3_600)])).await.unwrap();
+        assert_eq!((count(&db, "rollup_60").await, count(&db, "rollup_3600").await), (0, 1));
+
+        let coarsest_removed = policy(&[(3_600, 60)]);
+        assert!(db.compact(now, &coarsest_removed).await.is_err(), "no coarser tier is left for the hourly rows");
+        assert!(db.check_retention_policy(&coarsest_removed).await.is_err());
+        assert!(db.set_retention_policy(&coarsest_removed).await.is_err());
+        assert_eq!(count(&db, "rollup_3600").await, 1);
+
+        let replaced = policy(&[(3_600, 60), (86_400, 86_400)]);
+        db.check_retention_policy(&replaced).await.unwrap();
+        let dry_run = db.compact_dry_run(now, &replaced).await.unwrap();
+        assert_eq!(count(&db, "rollup_3600").await, 1, "dry runs change nothing");
+        let report = db.compact(now, &replaced).await.unwrap();
+        assert_eq!(report, dry_run);
+        assert_eq!(
+            report.dropped,
+            [DroppedTier { bucket_secs: 3_600, rows: 1, merged_into: Some(86_400), written: 1 }]
+        );
+        assert_eq!(report.removed(), 1);
+        assert_eq!(count(&db, "rollup_86400").await, 1, "the daily tier took the hour over");
+        assert_eq!(db.latest(1, ReadingKind::Temperature).await.unwrap().map(|(_, value)| value), Some(20.0));
+    }
+
+    #[tokio::test]
+    async fn incremental_vacuum_switches_the_database_over_once() {
+        let (db, path) = temp_db("vacuum").await;
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        // Connections only notice the mode change once they reread the file, so ask a new one.
+        let auto_vacuum = async || {
+            let db = Db::open(path.path()).await.unwrap();
+            sqlx::query_scalar::<_, i64>("PRAGMA auto_vacuum").fetch_one(&db.0).await.unwrap()
+        };
+        assert_eq!(auto_vacuum().await, 0);
+
+        let policy = RetentionPolicy { vacuum: Vacuum::Incremental, ..RetentionPolicy::default() };
+        db.compact(now, &policy).await.unwrap();
+        assert_eq!(auto_vacuum().await, 2);
+        db.compact(now, &policy).await.unwrap();
+        db.compact(now, &RetentionPolicy { vacuum: Vacuum::Full, ..policy }).await.unwrap();
+        assert_eq!(auto_vacuum().await, 2, "a full vacuum keeps the mode");
+    }
+}
+
+/// Read- and write-path benchmarks, ignored by default as they take a while:
+/// `cargo test --release -p chlorophyll-client --features sqlite -- --ignored --nocapture bench`
+#[cfg(test)]
+mod bench_tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    const SENSORS: u128 = 4;
+    const DAYS: i64 = 60;
+
+    /// Minute-resolution history for [`SENSORS`] sensors over [`DAYS`] days up to `now`,
+    /// written in one transaction.
+    async fn seed(db: &Db, now: DateTime<Utc>) {
+        let mut tx = db.0.begin().await.unwrap();
+        for sensor_id in 1..=SENSORS {
+            let sensor = db.1.get(&mut tx, sensor_id).await.unwrap();
+            for minute in 0..DAYS * 1_440 {
+                #[allow(clippy::cast_precision_loss)]
+                let value = 20.0 + (minute % 1_440) as f64 / 100.0;
+                sqlx::query(
+                    "INSERT INTO readings (sensor, data_type, ts, value, bucket_secs)
+                     VALUES (?, 'temperature', ?, ?, 60)",
+                )
+                    .bind(sensor)
+                    .bind((now - chrono::Duration::minutes(minute)).timestamp_millis())
+                    .bind(value)
+                    .execute(&mut *tx)
+                    .await
+                    .unwrap();
+            }
+        }
+        tx.commit().await.unwrap();
+    }
+
+    async fn time<T>(label: &str, run: impl AsyncFn() -> T) -> T {
+        let start = Instant::now();
+        let mut out = run().await;
+        for _ in 0..4 {
+            out = run().await;
+        }
+        println!("{label:<40} {:>8.2} ms", start.elapsed().as_secs_f64() * 1000.0 / 5.0);
+        out
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark"]
+    async fn bench_history_reads() {
+        let (db, _path) = TempDb::open("bench").await;
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        seed(&db, now).await;
+        let month = now - chrono::Duration::days(30);
+
+        let unrolled =
+            time("30 days hourly, before compaction", || db.history_bucketed(month, now, 3_600)).await.unwrap();
+        let policy = RetentionPolicy::default();
+        time("compaction", || db.compact(now, &policy)).await.unwrap();
+        let rolled = time("30 days hourly, from rollup_3600", || db.history_bucketed(month, now, 3_600)).await.unwrap();
+        time("30 days daily, from rollup_86400", || db.history_bucketed(month, now, 86_400)).await.unwrap();
+        time("1 day by minute", || db.history_bucketed(now - chrono::Duration::days(1), now, 60)).await.unwrap();
+        assert_eq!(unrolled.len(), rolled.len());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark"]
+    async fn bench_batched_writes() {
+        const ROWS: i32 = 20_000;
+        let (db, _path) = TempDb::open("bench-writes").await;
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let averaged = |i: i32| {
+            let at = now + chrono::Duration::seconds(i64::from(i));
+            let sensor_id = u128::from(i.unsigned_abs() % 8);
+            Averaged::from(Reading { sensor_id, kind: ReadingKind::Temperature, value: 20.0, at })
+        };
+
+        let start = Instant::now();
+        for i in 0..ROWS {
+            db.insert_reading_at(&averaged(i), 60).await.unwrap();
+        }
+        let single = start.elapsed().as_secs_f64();
+        println!("{:<40} {:>10.0} rows/s", "one transaction per row", f64::from(ROWS) / single);
+
+        let writer = db.writer(WriterConfig::default());
+        let start = Instant::now();
+        for i in ROWS..2 * ROWS {
+            writer.write(averaged(i), 60).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        let batched = start.elapsed().as_secs_f64();
+        println!("{:<40} {:>10.0} rows/s", "batch writer", f64::from(ROWS) / batched);
+        println!("{} batches, {} stalls", writer.stats().batches(), writer.stats().stalls());
+        assert_eq!(writer.stats().written(), u64::from(ROWS.unsigned_abs()));
+    }
+}
+
+#[cfg(test)]
+mod alert_tests {
+    use super::*;
+
+    async fn temp_db(tag: &str) -> (Db, TempDb) {
+        TempDb::open(&format!("alerts-{tag}")).await
+    }
+
+    #[tokio::test]
+    async fn rules_roundtrip_and_history_tracks_open_alerts() {
+        let (db, _path) = temp_db("roundtrip").await;
+
+        let mut rule = AlertRule {
+            id: 0,
+            name: "too hot".into(),
+            sensor_id: Some(7),
+            kind: Some(ReadingKind::Temperature),
+            condition: AlertCondition::Above(30.0),
+            hysteresis: 0.5,
+            min_duration_secs: 120,
+            enabled: true,
+        };
+        rule.id = db.insert_alert_rule(&rule).await.unwrap();
+        assert_eq!(db.alert_rule(rule.id).await.unwrap().as_ref(), Some(&rule));
+
+        rule.condition = AlertCondition::Below(5.0);
+        assert!(db.update_alert_rule(&rule).await.unwrap());
+        assert_eq!(db.alert_rules().await.unwrap(), vec![rule.clone()]);
+
+        let at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let fired = AlertEvent {
+            id: 0,
+            rule_id: rule.id,
+            sensor_id: 7,
+            state: AlertState::Fired,
+            value: Some(4.0),
+            at,
+        };
+        db.insert_alert_event(&fired).await.unwrap();
+        assert_eq!(db.active_alerts().await.unwrap().len(), 1, "fired and not yet resolved");
+
+        db.insert_alert_event(&AlertEvent {
+            state: AlertState::Resolved,
+            value: Some(6.0),
+            at: at + chrono::Duration::minutes(5),
+            ..fired
+        })
+        .await
+        .unwrap();
+        assert!(db.active_alerts().await.unwrap().is_empty());
+
+        // Deleting a rule closes what it left firing.
+        db.insert_alert_event(&AlertEvent { at: at + chrono::Duration::minutes(10), ..fired }).await.unwrap();
+        assert_eq!(db.active_alerts().await.unwrap().len(), 1);
+        assert!(db.delete_alert_rule(rule.id).await.unwrap());
+        assert!(db.active_alerts().await.unwrap().is_empty());
+
+        // History outlives the rule and comes back newest first.
+        assert!(!db.delete_alert_rule(rule.id).await.unwrap());
+        assert!(!db.delete_alert_rule(rule.id).await.unwrap());
+        let history = db.alert_history(at, Some(rule.id), 10).await.unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[1].state, AlertState::Resolved);
+    }
+}
+
+#[cfg(test)]
+mod sensor_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sensors_keep_known_fields_and_first_seen_across_updates() {
+        let (db, _path) = TempDb::open("sensors").await;
+
+        let first = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut device = DeviceInfo {
+            id: 7,
+            name: Some("greenhouse".into()),
+            last_seen: Some(first),
+            firmware_version: Some("1.2.0".into()),
+            sample_interval_ms: Some(5_000),
+            ..DeviceInfo::default()
+        };
+        db.upsert_sensor(&device).await.unwrap();
+        assert!(db.set_sensor_notes(7, Some("north wall")).await.unwrap());
+        assert!(!db.set_sensor_notes(8, Some("nowhere")).await.unwrap());
+
+        // After a restart the registry knows only the id until the sensor re-announces.
+        let later = first + chrono::Duration::hours(1);
+        device = DeviceInfo { id: 7, last_seen: Some(later), ..DeviceInfo::default() };
+        db.upsert_sensor(&device).await.unwrap();
+
+        let record = db.sensor(7).await.unwrap().expect("recorded");
+        assert_eq!(record.name.as_deref(), Some("greenhouse"));
+        assert_eq!(record.first_seen, first);
+        assert_eq!(record.last_seen, later);
+        assert_eq!(record.firmware_version.as_deref(), Some("1.2.0"));
+        assert_eq!(record.sample_interval_ms, Some(5_000));
+        assert_eq!(record.notes.as_deref(), Some("north wall"));
+        assert_eq!(db.sensors().await.unwrap(), vec![record.clone()]);
+
+        let seeded = DeviceInfo::from(&record);
+        assert_eq!(seeded.name.as_deref(), Some("greenhouse"));
+        assert_eq!(seeded.last_seen, Some(later));
+    }
+
+    #[tokio::test]
+    async fn tags_and_groups_roundtrip() {
+        let (db, _path) = TempDb::open("groups").await;
+
+        db.set_sensor_tags(7, &["shade".into(), "tomato".into()]).await.unwrap();
+        assert!(db.add_sensor_tag(8, "tomato").await.unwrap());
+        assert!(!db.add_sensor_tag(8, "tomato").await.unwrap());
+        assert!(db.remove_sensor_tag(7, "shade").await.unwrap());
+        assert_eq!(db.tags_of(7).await.unwrap(), vec!["tomato".to_string()]);
+        assert_eq!(
+            db.sensor_tags().await.unwrap(),
+            vec![(7, "tomato".to_string()), (8, "tomato".to_string())]
+        );
+
+        let mut group = SensorGroup { id: 0, name: "bench 1".into(), members: vec![7, 8] };
+        group.id = db.insert_group(&group).await.unwrap();
+        assert!(db.insert_group(&group).await.is_err(), "names are unique");
+        assert_eq!(db.group(group.id).await.unwrap().as_ref(), Some(&group));
+
+        group.members = vec![8];
+        assert!(db.update_group(&group).await.unwrap());
+        assert_eq!(db.groups().await.unwrap(), vec![group.clone()]);
+        assert!(db.delete_group(group.id).await.unwrap());
+        assert!(!db.update_group(&group).await.unwrap());
+        assert!(db.groups().await.unwrap().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod calibration_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn calibration_versions_are_appended_and_the_latest_is_current() {
+        let (db, _path) = TempDb::open("calibration").await;
+        let at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        let mut linear = CalibrationVersion {
+            id: 0,
+            sensor_id: 7,
+            kind: ReadingKind::Temperature,
+            calibration: Some(Calibration::Linear { scale: 1.0, offset: -1.5 }),
+            valid_from: at,
+        };
+        linear.id = db.insert_calibration(&linear).await.unwrap();
+        let mut table = CalibrationVersion {
+            id: 0,
+            kind: ReadingKind::Light,
+            calibration: Calibration::table(vec![(0.0, 0.0), (100.0, 130.0)]),
+            ..linear.clone()
+        };
+        table.id = db.insert_calibration(&table).await.unwrap();
+        let removed = CalibrationVersion {
+            id: 0,
+            calibration: None,
+            valid_from: at + chrono::Duration::hours(1),
+            ..linear.clone()
+        };
+        let removed_id = db.insert_calibration(&removed).await.unwrap();
+
+        let history = db.calibration_history(7, Some(ReadingKind::Temperature)).await.unwrap();
+        assert_eq!(history, vec![linear, CalibrationVersion { id: removed_id, ..removed }]);
+        assert_eq!(db.calibration_history(7, None).await.unwrap().len(), 3);
+
+        let current = db.current_calibrations().await.unwrap();
+        assert_eq!(current.get(7, ReadingKind::Temperature), None);
+        assert_eq!(current.get(7, ReadingKind::Light), table.calibration.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod export_tests {
+    use futures_util::TryStreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn export_streams_named_rows_raw_or_bucketed() {
+        let (db, _path) = TempDb::open("export").await;
+        let base = DateTime::from_timestamp(1_700_000_040, 0).unwrap();
+        db.upsert_sensor(&DeviceInfo {
+            id: 1,
+            name: Some("bench".into()),
+            last_seen: Some(base),
+            ..DeviceInfo::default()
+        })
         .await
         .unwrap();
-        assert_eq!(rows, vec![(86_400, 2)], "two days -> two daily averages");
+        for (sensor_id, kind, offset_secs, value) in [
+            (1, ReadingKind::Temperature, 0, 20.0),
+            (1, ReadingKind::Temperature, 10, 22.0),
+            (1, ReadingKind::Humidity, 10, 50.0),
+            (2, ReadingKind::Temperature, 30, 18.0),
+        ] {
+            let at = base + chrono::Duration::seconds(offset_secs);
+            db.insert_reading(&Reading { sensor_id, kind, value, at }).await.unwrap();
+        }
+
+        let raw: Vec<ExportRow> = db.export(&ExportQuery::default()).try_collect().await.unwrap();
+        assert_eq!(raw.len(), 4);
+        assert_eq!(raw[0].name.as_deref(), Some("bench"));
+        assert_eq!(raw[3].name, None, "sensor 2 was never recorded");
+        assert!(raw.windows(2).all(|pair| pair[0].at <= pair[1].at));
+
+        let query = ExportQuery {
+            bucket_secs: Some(60),
+            sensor_id: Some(1),
+            kind: Some(ReadingKind::Temperature),
+            ..ExportQuery::default()
+        };
+        let bucketed: Vec<ExportRow> = db.export(&query).try_collect().await.unwrap();
+        assert_eq!(bucketed.len(), 1);
+        assert_eq!(bucketed[0].at, DateTime::from_timestamp(1_700_000_040 / 60 * 60, 0).unwrap());
+        assert!((bucketed[0].value - 21.0).abs() < 1e-5);
+
+        let later = ExportQuery { from: Some(base + chrono::Duration::seconds(20)), ..ExportQuery::default() };
+        let later: Vec<ExportRow> = db.export(&later).try_collect().await.unwrap();
+        assert_eq!(later.iter().map(|row| row.sensor_id).collect::<Vec<_>>(), [2]);
+    }
+
+    #[tokio::test]
+    async fn imports_skip_rows_already_stored_and_widen_the_sensor_record() {
+        let (db, _path) = TempDb::open("import").await;
+        let at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let reading = |offset_mins, value| Reading {
+            sensor_id: 3,
+            kind: ReadingKind::Temperature,
+            value,
+            at: at + chrono::Duration::minutes(offset_mins),
+        };
+        db.insert_reading_at(&reading(0, 20.0).into(), 60).await.unwrap();
+
+        let inserted = db
+            .import_readings(&[reading(0, 99.0), reading(1, 21.0), reading(1, 21.0)], 60)
+            .await
+            .unwrap();
+        assert_eq!(inserted, 1, "the stored row and the repeat are skipped");
+        let rows: Vec<ExportRow> = db.export(&ExportQuery::default()).try_collect().await.unwrap();
+        assert_eq!(rows.iter().map(|row| row.value).collect::<Vec<_>>(), [20.0, 21.0]);
+
+        let earlier = at - chrono::Duration::days(30);
+        db.upsert_sensor(&DeviceInfo { id: 3, name: Some("bench".into()), last_seen: Some(at), ..DeviceInfo::default() })
+            .await
+            .unwrap();
+        db.upsert_imported_sensor(3, Some("old logger"), earlier, at - chrono::Duration::days(1)).await.unwrap();
+        let record = db.sensor(3).await.unwrap().unwrap();
+        assert_eq!(record.name.as_deref(), Some("bench"));
+        assert_eq!((record.first_seen, record.last_seen), (earlier, at));
+    }
+
+    #[tokio::test]
+    async fn imports_skip_rolled_up_buckets_and_weigh_like_live_rows() {
+        let (db, _path) = TempDb::open("import-rollup").await;
+        let now = DateTime::from_timestamp(1_699_999_980, 0).unwrap();
+        let at = now - chrono::Duration::hours(2);
+        let reading = |offset_secs, value| Reading {
+            sensor_id: 4,
+            kind: ReadingKind::Temperature,
+            value,
+            at: at + chrono::Duration::seconds(offset_secs),
+        };
+        let live = Averaged { reading: reading(0, 20.0), min: 19.0, max: 21.0, count: 300 };
+        db.insert_reading_at(&live, 60).await.unwrap();
+        assert_eq!(db.import_readings(&[reading(30, 10.0)], 60).await.unwrap(), 1);
+        db.compact(now, &RetentionPolicy::default()).await.unwrap();
+
+        let rows: Vec<ExportRow> = db.export(&ExportQuery::default()).try_collect().await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert!((rows[0].value - 15.0).abs() < 1e-5, "a minute of imports weighs a minute of readings");
+
+        let inserted = db.import_readings(&[reading(0, 20.0), reading(30, 10.0)], 60).await.unwrap();
+        assert_eq!(inserted, 0, "both fall in a bucket already rolled up");
+        db.compact(now, &RetentionPolicy::default()).await.unwrap();
+        let rows: Vec<ExportRow> = db.export(&ExportQuery::default()).try_collect().await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert!((rows[0].value - 15.0).abs() < 1e-5);
+    }
+
+    #[tokio::test]
+    async fn a_failed_import_leaves_no_stale_sensor_key() {
+        let (db, _path) = TempDb::open("import-rollback").await;
+        let at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let reading = |sensor_id, value| Reading { sensor_id, kind: ReadingKind::Temperature, value, at };
+
+        // SQLite stores NaN as NULL, so the second row fails the NOT NULL on `value`.
+        let failed = db.import_readings(&[reading(5, 20.0), reading(6, f32::NAN)], 60).await;
+        assert!(failed.is_err());
+        assert_eq!(db.export(&ExportQuery::default()).try_collect::<Vec<_>>().await.unwrap().len(), 0);
 
-        cleanup(&path);
+        db.insert_reading(&reading(7, 30.0)).await.unwrap();
+        db.import_readings(&[reading(5, 20.0)], 60).await.unwrap();
+        let rows: Vec<ExportRow> = db.export(&ExportQuery::default()).try_collect().await.unwrap();
+        let mut sensors: Vec<(u128, f32)> = rows.iter().map(|row| (row.sensor_id, row.value)).collect();
+        sensors.sort_by_key(|&(sensor_id, _)| sensor_id);
+        assert_eq!(sensors, [(5, 20.0), (7, 30.0)], "each sensor keeps a key of its own");
     }
 }
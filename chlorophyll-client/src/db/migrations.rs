@@ -0,0 +1,572 @@
+//! Versioned schema migrations.
+//!
+//! Each migration runs once, in its own transaction, and is recorded in `schema_version`
+//! along with when it was applied. The database is at the highest recorded version; a
+//! database newer than this build knows is refused rather than written to.
+//!
+//! Databases from before versioning have no `schema_version` and so run every migration.
+//! The migrations that existed then (1 to 6) are written to be no-ops against tables
+//! they already created: `IF NOT EXISTS` throughout, and columns added only when missing.
+//! Later migrations can assume the schema the earlier ones leave behind.
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{SqliteConnection, SqlitePool};
+
+enum Step {
+    /// One or more statements.
+    Sql(&'static str),
+    /// `ALTER TABLE {table} ADD COLUMN {column} {definition}`, unless the column exists.
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        definition: &'static str,
+    },
+    /// Statements run against each registered rollup table, named by `{table}`, with its
+    /// width as `{bucket_secs}`.
+    Rollups(&'static str),
+}
+
+struct Migration {
+    version: i64,
+    description: &'static str,
+    steps: &'static [Step],
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "readings",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS readings (
+                 id        INTEGER PRIMARY KEY AUTOINCREMENT,
+                 sensor_id TEXT    NOT NULL,
+                 timestamp TEXT    NOT NULL,
+                 data_type TEXT    NOT NULL,
+                 value     REAL    NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS idx_readings_sensor_timestamp
+                 ON readings (sensor_id, timestamp);",
+        )],
+    },
+    // Resolution of each row, in seconds; 0 marks pre-rollup rows written at the raw
+    // sensor rate. Compaction uses it to tell which rows still need coarsening.
+    Migration {
+        version: 2,
+        description: "readings.bucket_secs",
+        steps: &[
+            Step::AddColumn {
+                table: "readings",
+                column: "bucket_secs",
+                definition: "INTEGER NOT NULL DEFAULT 0",
+            },
+            Step::Sql("CREATE INDEX IF NOT EXISTS idx_readings_bucket ON readings (bucket_secs, timestamp)"),
+        ],
+    },
+    // `sensor_id` / `data_type` are NULL when a rule applies to every sensor / carries no
+    // metric (`no_data`). Events keep their `rule_id` after the rule is deleted, so
+    // history survives rule churn.
+    Migration {
+        version: 3,
+        description: "alert rules and events",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS alert_rules (
+                 id                INTEGER PRIMARY KEY AUTOINCREMENT,
+                 name              TEXT    NOT NULL,
+                 sensor_id         TEXT,
+                 data_type         TEXT,
+                 condition         TEXT    NOT NULL,
+                 threshold         REAL    NOT NULL,
+                 hysteresis        REAL    NOT NULL DEFAULT 0,
+                 min_duration_secs INTEGER NOT NULL DEFAULT 0,
+                 enabled           INTEGER NOT NULL DEFAULT 1
+             );
+             CREATE TABLE IF NOT EXISTS alert_events (
+                 id        INTEGER PRIMARY KEY AUTOINCREMENT,
+                 rule_id   INTEGER NOT NULL,
+                 sensor_id TEXT    NOT NULL,
+                 state     TEXT    NOT NULL,
+                 value     REAL,
+                 timestamp TEXT    NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS idx_alert_events_timestamp
+                 ON alert_events (timestamp);",
+        )],
+    },
+    // A target's optional threshold (`data_type` plus `above` and/or `below`) is its own,
+    // independent of alert rules. One row per delivery, written once retries are done.
+    Migration {
+        version: 4,
+        description: "webhook targets and deliveries",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS webhook_targets (
+                 id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                 url        TEXT    NOT NULL,
+                 secret     TEXT,
+                 sensor_id  TEXT,
+                 data_type  TEXT,
+                 above      REAL,
+                 below      REAL,
+                 hysteresis REAL    NOT NULL DEFAULT 0,
+                 enabled    INTEGER NOT NULL DEFAULT 1
+             );
+             CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                 id        INTEGER PRIMARY KEY AUTOINCREMENT,
+                 target_id INTEGER NOT NULL,
+                 event     TEXT    NOT NULL,
+                 payload   TEXT    NOT NULL,
+                 attempts  INTEGER NOT NULL,
+                 status    INTEGER,
+                 error     TEXT,
+                 delivered INTEGER NOT NULL,
+                 timestamp TEXT    NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_target
+                 ON webhook_deliveries (target_id, timestamp);",
+        )],
+    },
+    // What the server knows about each sensor, so names and firmware details survive
+    // restarts and outlive the sensor. `location` and `notes` are set by operators; the
+    // rest is fed by packets. Tags and group membership are keyed by sensor id alone, so
+    // a sensor can be labelled before it is first heard from.
+    Migration {
+        version: 5,
+        description: "sensors, tags and groups",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS sensors (
+                 id                 TEXT    PRIMARY KEY,
+                 name               TEXT,
+                 first_seen         TEXT    NOT NULL,
+                 last_seen          TEXT    NOT NULL,
+                 firmware_version   TEXT,
+                 sample_interval_ms INTEGER,
+                 location           TEXT,
+                 notes              TEXT
+             );
+             CREATE TABLE IF NOT EXISTS sensor_tags (
+                 sensor_id TEXT NOT NULL,
+                 tag       TEXT NOT NULL,
+                 PRIMARY KEY (sensor_id, tag)
+             );
+             CREATE TABLE IF NOT EXISTS sensor_groups (
+                 id   INTEGER PRIMARY KEY AUTOINCREMENT,
+                 name TEXT    NOT NULL UNIQUE
+             );
+             CREATE TABLE IF NOT EXISTS sensor_group_members (
+                 group_id  INTEGER NOT NULL,
+                 sensor_id TEXT    NOT NULL,
+                 PRIMARY KEY (group_id, sensor_id)
+             );",
+        )],
+    },
+    // Calibration history: rows are only ever appended, one per change, so the
+    // calibration behind any stored reading is the latest row for its sensor and metric
+    // with `valid_from` at or before the reading. `method` is `linear`, `table` or `none`,
+    // the last marking a removed calibration; `points` holds a table as `raw:value` pairs
+    // separated by `;`.
+    Migration {
+        version: 6,
+        description: "calibrations",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS calibrations (
+                 id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                 sensor_id  TEXT    NOT NULL,
+                 data_type  TEXT    NOT NULL,
+                 method     TEXT    NOT NULL,
+                 scale      REAL,
+                 offset     REAL,
+                 points     TEXT,
+                 valid_from TEXT    NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS idx_calibrations_sensor
+                 ON calibrations (sensor_id, data_type, valid_from);",
+        )],
+    },
+    // Readings keyed for range scans: unix-millisecond `ts` instead of RFC 3339 text that
+    // every bucketed query had to parse per row, and a `sensor_keys` integer instead of
+    // the 32-character hex id. Rolled-up history moves out to one `rollup_{bucket_secs}`
+    // table per retention tier, registered in `rollup_tiers`; compaction creates those
+    // tables, and existing rows of any resolution reach them at the next compaction.
+    Migration {
+        version: 7,
+        description: "integer reading keys and rollup tiers",
+        steps: &[Step::Sql(
+            "CREATE TABLE sensor_keys (
+                 key       INTEGER PRIMARY KEY,
+                 sensor_id TEXT    NOT NULL UNIQUE
+             );
+             INSERT INTO sensor_keys (sensor_id) SELECT DISTINCT sensor_id FROM readings ORDER BY sensor_id;
+             CREATE TABLE readings_v7 (
+                 sensor      INTEGER NOT NULL,
+                 data_type   TEXT    NOT NULL,
+                 ts          INTEGER NOT NULL,
+                 value       REAL    NOT NULL,
+                 bucket_secs INTEGER NOT NULL DEFAULT 0
+             );
+             INSERT INTO readings_v7 (sensor, data_type, ts, value, bucket_secs)
+                 SELECT k.key, r.data_type, CAST(ROUND(unixepoch(r.timestamp, 'subsec') * 1000) AS INTEGER),
+                        r.value, r.bucket_secs
+                 FROM readings r JOIN sensor_keys k ON k.sensor_id = r.sensor_id
+                 ORDER BY r.id;
+             DROP TABLE readings;
+             ALTER TABLE readings_v7 RENAME TO readings;
+             CREATE INDEX idx_readings_ts ON readings (ts);
+             CREATE INDEX idx_readings_sensor_ts ON readings (sensor, data_type, ts);
+             CREATE TABLE rollup_tiers (
+                 bucket_secs INTEGER PRIMARY KEY,
+                 kept_from   INTEGER
+             );",
+        )],
+    },
+    // The extremes and number of the readings behind each row, so averaging keeps brief
+    // spikes visible and merges weigh rows by what they stand for. Rows written before
+    // this are counted like imported ones: one per raw reading, and averaged rows as the
+    // readings their bucket held at `rollup::READINGS_PER_SEC`.
+    Migration {
+        version: 8,
+        description: "reading extremes and sample counts",
+        steps: &[
+            Step::Sql(
+                "ALTER TABLE readings ADD COLUMN min REAL;
+                 ALTER TABLE readings ADD COLUMN max REAL;
+                 ALTER TABLE readings ADD COLUMN samples INTEGER NOT NULL DEFAULT 1;
+                 UPDATE readings SET min = value, max = value, samples = MAX(bucket_secs * 5, 1);",
+            ),
+            Step::Rollups(
+                "ALTER TABLE {table} ADD COLUMN min REAL;
+                 ALTER TABLE {table} ADD COLUMN max REAL;
+                 UPDATE {table} SET min = value, max = value;",
+            ),
+        ],
+    },
+    // The retention policy, once set: a single `retention_policy` row, its ladder and any
+    // sensors kept differently. Null ages mean "forever". With no row, the built-in
+    // default applies.
+    Migration {
+        version: 9,
+        description: "retention policy",
+        steps: &[Step::Sql(
+            "CREATE TABLE retention_policy (
+                 id            INTEGER PRIMARY KEY CHECK (id = 1),
+                 max_age_secs  INTEGER,
+                 vacuum        TEXT    NOT NULL,
+                 interval_secs INTEGER NOT NULL
+             );
+             CREATE TABLE retention_tiers (
+                 older_than_secs INTEGER PRIMARY KEY,
+                 bucket_secs     INTEGER NOT NULL
+             );
+             CREATE TABLE retention_sensors (
+                 sensor_id            TEXT PRIMARY KEY,
+                 full_resolution_secs INTEGER,
+                 max_age_secs         INTEGER
+             );",
+        )],
+    },
+    // Seconds of each rollup bucket its rows covered, so a bucket the sensor only reported
+    // for part of isn't taken to span all of it. Rows written before this are assumed full.
+    Migration {
+        version: 10,
+        description: "rollup coverage",
+        steps: &[Step::Rollups("ALTER TABLE {table} ADD COLUMN secs INTEGER NOT NULL DEFAULT {bucket_secs}")],
+    },
+];
+
+/// The version a fully migrated database is at.
+fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Bring the database up to [`latest_version`], returning the version it was at.
+pub(super) async fn run(pool: &SqlitePool) -> anyhow::Result<i64> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+             version     INTEGER PRIMARY KEY,
+             description TEXT    NOT NULL,
+             applied_at  TEXT    NOT NULL
+         )",
+    )
+    .execute(pool)
+    .await?;
+    let initial = current_version(pool).await?;
+    anyhow::ensure!(
+        initial <= latest_version(),
+        "database schema is at version {initial}, newer than this build supports ({})",
+        latest_version()
+    );
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > initial) {
+        let mut tx = pool.begin().await?;
+        // Another process opening the same file may have got here first.
+        let applied: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM schema_version WHERE version = ?)")
+            .bind(migration.version)
+            .fetch_one(&mut *tx)
+            .await?;
+        if applied {
+            continue;
+        }
+        for step in migration.steps {
+            apply(&mut tx, step)
+                .await
+                .with_context(|| format!("migration {} ({})", migration.version, migration.description))?;
+        }
+        sqlx::query("INSERT INTO schema_version (version, description, applied_at) VALUES (?, ?, ?)")
+            .bind(migration.version)
+            .bind(migration.description)
+            .bind(Utc::now().to_rfc3339())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        tracing::info!("applied database migration {} ({})", migration.version, migration.description);
+    }
+    Ok(initial)
+}
+
+/// Errors unless the database is at [`latest_version`], without changing it.
+pub(super) async fn ensure_current(pool: &SqlitePool) -> anyhow::Result<()> {
+    let versioned: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')",
+    )
+    .fetch_one(pool)
+    .await?;
+    let version = if versioned { current_version(pool).await? } else { 0 };
+    anyhow::ensure!(
+        version == latest_version(),
+        "database schema is at version {version}, this build expects {}; open it read-write to migrate it",
+        latest_version()
+    );
+    Ok(())
+}
+
+async fn current_version(pool: &SqlitePool) -> anyhow::Result<i64> {
+    Ok(sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+        .fetch_one(pool)
+        .await?)
+}
+
+async fn apply(conn: &mut SqliteConnection, step: &Step) -> anyhow::Result<()> {
+    match step {
+        Step::Sql(sql) => {
+            sqlx::query(sql).execute(&mut *conn).await?;
+        }
+        Step::AddColumn { table, column, definition } => {
+            let exists: bool =
+                sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info(?) WHERE name = ?)")
+                    .bind(table)
+                    .bind(column)
+                    .fetch_one(&mut *conn)
+                    .await?;
+            if !exists {
+                sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"))
+                    .execute(&mut *conn)
+                    .await?;
+            }
+        }
+        Step::Rollups(sql) => {
+            let tiers: Vec<i64> = sqlx::query_scalar("SELECT bucket_secs FROM rollup_tiers")
+                .fetch_all(&mut *conn)
+                .await?;
+            for bucket_secs in tiers {
+                let sql = sql.replace("{table}", &format!("rollup_{bucket_secs}"));
+                sqlx::query(&sql.replace("{bucket_secs}", &bucket_secs.to_string()))
+                    .execute(&mut *conn)
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use futures_util::TryStreamExt;
+
+    use super::*;
+    use crate::db::{Db, ExportQuery, RetentionPolicy, TempDb};
+
+    async fn connect(path: &TempDb) -> SqlitePool {
+        sqlx::sqlite::SqlitePoolOptions::new()
+            .connect(&format!("sqlite:{}?mode=rwc", path.path()))
+            .await
+            .unwrap()
+    }
+
+    /// The `readings` table as first created, before rollups added `bucket_secs`.
+    const PRE_ROLLUP_SCHEMA: &str = "
+        CREATE TABLE readings (
+            id        INTEGER PRIMARY KEY AUTOINCREMENT,
+            sensor_id TEXT    NOT NULL,
+            timestamp TEXT    NOT NULL,
+            data_type TEXT    NOT NULL,
+            value     REAL    NOT NULL
+        );
+        CREATE INDEX idx_readings_sensor_timestamp ON readings (sensor_id, timestamp);";
+
+    #[tokio::test]
+    async fn unversioned_databases_upgrade_and_keep_their_rows() {
+        let path = TempDb::new("migrate-unversioned");
+        let pool = connect(&path).await;
+        sqlx::query(PRE_ROLLUP_SCHEMA).execute(&pool).await.unwrap();
+        // Pre-rollup rows, written at the raw sensor rate before `bucket_secs` existed.
+        let old = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        for offset_secs in 0..10 {
+            sqlx::query("INSERT INTO readings (sensor_id, timestamp, data_type, value) VALUES (?, ?, 'temperature', ?)")
+                .bind(format!("{:032x}", 1))
+                .bind((old + chrono::Duration::seconds(offset_secs)).to_rfc3339())
+                .bind(20.0 + f64::from(i32::try_from(offset_secs).unwrap()))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        // Then today's unversioned `Db::open`: the unconditional `ALTER` and every table
+        // since, with nothing recorded in `schema_version`.
+        sqlx::query("ALTER TABLE readings ADD COLUMN bucket_secs INTEGER NOT NULL DEFAULT 0")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        for migration in MIGRATIONS.iter().filter(|m| (3..=6).contains(&m.version)) {
+            for step in migration.steps {
+                apply(&mut conn, step).await.unwrap();
+            }
+        }
+        drop(conn);
+        sqlx::query("INSERT INTO sensors (id, name, first_seen, last_seen) VALUES (?, 'bench', ?, ?)")
+            .bind(format!("{:032x}", 1))
+            .bind(old.to_rfc3339())
+            .bind(old.to_rfc3339())
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO readings (sensor_id, timestamp, data_type, value, bucket_secs) VALUES (?, ?, 'humidity', 50, 60)")
+            .bind(format!("{:032x}", 1))
+            .bind(old.to_rfc3339())
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool.close().await;
+
+        let db = Db::open(path.path()).await.unwrap();
+        assert_eq!(current_version(&db.0).await.unwrap(), latest_version());
+        let legacy: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM readings WHERE bucket_secs = 0")
+            .fetch_one(&db.0)
+            .await
+            .unwrap();
+        assert_eq!(legacy, 10, "legacy rows keep bucket_secs = 0");
+        let samples: Vec<(i64, i64)> =
+            sqlx::query_as("SELECT DISTINCT bucket_secs, samples FROM readings ORDER BY bucket_secs")
+                .fetch_all(&db.0)
+                .await
+                .unwrap();
+        assert_eq!(samples, [(0, 1), (60, 60 * crate::rollup::READINGS_PER_SEC)], "weighed like imported rows");
+        assert_eq!(db.earliest().await.unwrap(), Some(old), "timestamps carry over to the millisecond");
+
+        // Compaction still recognises the legacy rows and coarsens them to a minute.
+        db.compact(old + chrono::Duration::hours(2), &RetentionPolicy::default()).await.unwrap();
+        let rows: Vec<_> = db.export(&ExportQuery::default()).try_collect().await.unwrap();
+        assert_eq!(rows.len(), 2, "one minute of temperature and the humidity row: {rows:?}");
+        assert!(rows.iter().any(|row| (row.value - 24.5).abs() < 1e-4));
+
+        assert_eq!(db.sensor(1).await.unwrap().unwrap().name.as_deref(), Some("bench"));
+        assert!(db.alert_rules().await.unwrap().is_empty());
+        assert!(db.webhook_targets().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rollups_created_before_extremes_gain_them() {
+        let path = TempDb::new("migrate-rollups");
+        let pool = connect(&path).await;
+        let mut conn = pool.acquire().await.unwrap();
+        apply(&mut conn, &Step::Sql(PRE_ROLLUP_SCHEMA)).await.unwrap();
+        sqlx::query("CREATE TABLE schema_version (version INTEGER PRIMARY KEY, description TEXT, applied_at TEXT)")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        for migration in MIGRATIONS.iter().filter(|m| m.version <= 7) {
+            if migration.version > 1 {
+                for step in migration.steps {
+                    apply(&mut conn, step).await.unwrap();
+                }
+            }
+            sqlx::query("INSERT INTO schema_version VALUES (?, ?, '')")
+                .bind(migration.version)
+                .bind(migration.description)
+                .execute(&mut *conn)
+                .await
+                .unwrap();
+        }
+        drop(conn);
+        // A tier table as compaction created it at version 7.
+        sqlx::query(
+            "CREATE TABLE rollup_300 (
+                 ts INTEGER NOT NULL, sensor INTEGER NOT NULL, data_type TEXT NOT NULL,
+                 value REAL NOT NULL, samples INTEGER NOT NULL,
+                 PRIMARY KEY (ts, sensor, data_type)
+             ) WITHOUT ROWID;
+             INSERT INTO rollup_tiers (bucket_secs) VALUES (300);
+             INSERT INTO rollup_300 VALUES (0, 1, 'temperature', 21.5, 4);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+
+        let db = Db::open(path.path()).await.unwrap();
+        let row: (f64, f64, i64, i64) = sqlx::query_as("SELECT min, max, samples, secs FROM rollup_300")
+            .fetch_one(&db.0)
+            .await
+            .unwrap();
+        assert_eq!(row, (21.5, 21.5, 4, 300), "older buckets are taken to be covered in full");
+    }
+
+    #[tokio::test]
+    async fn migrations_run_once_and_refuse_newer_databases() {
+        let (db, _path) = TempDb::open("migrate-versions").await;
+        let pool = db.0.clone();
+        assert_eq!(run(&pool).await.unwrap(), latest_version(), "already migrated");
+        let applied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM schema_version")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(applied, i64::try_from(MIGRATIONS.len()).unwrap());
+
+        sqlx::query("INSERT INTO schema_version (version, description, applied_at) VALUES (?, 'from the future', '')")
+            .bind(latest_version() + 1)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let error = run(&pool).await.unwrap_err().to_string();
+        assert!(error.contains("newer than this build"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn read_only_opens_refuse_outdated_schemas_and_leave_them_be() {
+        let path = TempDb::new("migrate-read-only");
+        let pool = connect(&path).await;
+        sqlx::query(PRE_ROLLUP_SCHEMA).execute(&pool).await.unwrap();
+        pool.close().await;
+
+        let error = Db::open_read_only(path.path()).await.unwrap_err().to_string();
+        assert!(error.contains("at version 0"), "{error}");
+        let pool = connect(&path).await;
+        let tables: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name != 'sqlite_sequence'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(tables, 1, "nothing migrated");
+        pool.close().await;
+
+        Db::open(path.path()).await.unwrap().close().await;
+        let db = Db::open_read_only(path.path()).await.unwrap();
+        assert_eq!(db.retention_policy().await.unwrap(), None);
+        assert!(sqlx::query("DELETE FROM readings").execute(&db.0).await.is_err(), "opened read-only");
+    }
+
+    #[test]
+    fn versions_increase() {
+        assert!(MIGRATIONS.windows(2).all(|pair| pair[0].version < pair[1].version));
+        assert_eq!(MIGRATIONS[0].version, 1);
+    }
+}
@@ -0,0 +1,252 @@
+//! Write-behind reading inserts.
+//!
+//! Each insert on its own is a transaction, and so a sync of the database file; at the
+//! rate readings arrive that sync dominates. A [`BatchWriter`] queues readings on a
+//! bounded channel instead, and a task commits them many rows to a transaction, once
+//! enough are queued or the oldest has waited long enough. When the queue is full,
+//! writers wait for room rather than readings being dropped or memory growing.
+//!
+//! A batch that fails to commit is dropped and counted in [`WriterStats::failed`]; the
+//! callers have moved on by then, so there is no one to hand the error back to.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use super::Db;
+use crate::rollup::Averaged;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WriterConfig {
+    /// Commit once this many rows are queued.
+    pub max_rows: usize,
+    /// Commit once the oldest queued row has waited this long.
+    pub max_delay: Duration,
+    /// Rows the queue holds before writers have to wait.
+    pub capacity: usize,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self { max_rows: 512, max_delay: Duration::from_millis(500), capacity: 8_192 }
+    }
+}
+
+/// Running totals for one writer.
+#[derive(Debug, Default)]
+pub struct WriterStats {
+    written: AtomicU64,
+    batches: AtomicU64,
+    failed: AtomicU64,
+    stalls: AtomicU64,
+}
+
+impl WriterStats {
+    /// Rows committed.
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// Transactions committed.
+    pub fn batches(&self) -> u64 {
+        self.batches.load(Ordering::Relaxed)
+    }
+
+    /// Rows lost to failed commits.
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Writes that found the queue full and had to wait.
+    pub fn stalls(&self) -> u64 {
+        self.stalls.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Write(Averaged, i64),
+    /// Commit what is queued, then acknowledge.
+    Flush(oneshot::Sender<()>),
+    /// Commit what is queued and stop, then acknowledge.
+    Close(oneshot::Sender<()>),
+}
+
+/// Handle for queueing readings; clones share one queue and task.
+#[derive(Debug, Clone)]
+pub struct BatchWriter {
+    tx: mpsc::Sender<Command>,
+    stats: Arc<WriterStats>,
+}
+
+impl Db {
+    /// Start a [`BatchWriter`] for this database. Must be called within a Tokio runtime.
+    #[must_use]
+    pub fn writer(&self, config: WriterConfig) -> BatchWriter {
+        let (tx, rx) = mpsc::channel(config.capacity.max(1));
+        let stats = Arc::new(WriterStats::default());
+        tokio::spawn(run(self.clone(), rx, config, stats.clone()));
+        BatchWriter { tx, stats }
+    }
+}
+
+impl BatchWriter {
+    /// Queue a reading averaged over `bucket_secs`, waiting while the queue is full. Fails
+    /// only once the writer is closed.
+    pub async fn write(&self, averaged: Averaged, bucket_secs: i64) -> anyhow::Result<()> {
+        let command = match self.tx.try_send(Command::Write(averaged, bucket_secs)) {
+            Ok(()) => return Ok(()),
+            Err(mpsc::error::TrySendError::Full(command)) => command,
+            Err(mpsc::error::TrySendError::Closed(_)) => anyhow::bail!("the database writer is closed"),
+        };
+        self.stats.stalls.fetch_add(1, Ordering::Relaxed);
+        self.tx.send(command).await.map_err(|_| anyhow::anyhow!("the database writer is closed"))
+    }
+
+    /// Wait until everything queued so far is committed (or has failed to be).
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let (done, acked) = oneshot::channel();
+        self.tx.send(Command::Flush(done)).await.map_err(|_| anyhow::anyhow!("the database writer is closed"))?;
+        Ok(acked.await?)
+    }
+
+    /// Commit everything queued and stop the writer; later writes through any clone fail.
+    pub async fn close(&self) {
+        let (done, acked) = oneshot::channel();
+        if self.tx.send(Command::Close(done)).await.is_ok() {
+            let _ = acked.await;
+        }
+    }
+
+    /// Rows waiting to be committed.
+    #[must_use]
+    pub fn queued(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> &WriterStats {
+        &self.stats
+    }
+}
+
+async fn commit(db: &Db, batch: &mut Vec<(Averaged, i64)>, stats: &WriterStats) {
+    if batch.is_empty() {
+        return;
+    }
+    let rows = batch.len() as u64;
+    match db.insert_readings(batch).await {
+        Ok(()) => {
+            stats.written.fetch_add(rows, Ordering::Relaxed);
+            stats.batches.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(e) => {
+            stats.failed.fetch_add(rows, Ordering::Relaxed);
+            tracing::error!("dropping {rows} readings, batched insert failed: {e}");
+        }
+    }
+    batch.clear();
+}
+
+async fn run(db: Db, mut rx: mpsc::Receiver<Command>, config: WriterConfig, stats: Arc<WriterStats>) {
+    let mut batch = Vec::with_capacity(config.max_rows);
+    let mut deadline = Instant::now();
+    let mut closed = Vec::new();
+    loop {
+        let command = if batch.is_empty() {
+            rx.recv().await
+        } else if let Ok(command) = tokio::time::timeout_at(deadline, rx.recv()).await {
+            command
+        } else {
+            commit(&db, &mut batch, &stats).await;
+            continue;
+        };
+        match command {
+            Some(Command::Write(averaged, bucket_secs)) => {
+                if batch.is_empty() {
+                    deadline = Instant::now() + config.max_delay;
+                }
+                batch.push((averaged, bucket_secs));
+                if batch.len() >= config.max_rows {
+                    commit(&db, &mut batch, &stats).await;
+                }
+            }
+            Some(Command::Flush(done)) => {
+                commit(&db, &mut batch, &stats).await;
+                let _ = done.send(());
+            }
+            // Stop taking more, but drain what was sent before the close.
+            Some(Command::Close(done)) => {
+                rx.close();
+                closed.push(done);
+            }
+            None => break,
+        }
+    }
+    commit(&db, &mut batch, &stats).await;
+    for done in closed {
+        let _ = done.send(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::*;
+    use crate::db::TempDb;
+    use crate::reading::{Reading, ReadingKind};
+
+    async fn temp_db(tag: &str) -> (Db, TempDb) {
+        TempDb::open(&format!("writer-{tag}")).await
+    }
+
+    fn reading(i: i64) -> Averaged {
+        let at = DateTime::<Utc>::from_timestamp(1_700_000_000 + i, 0).unwrap();
+        Averaged::from(Reading { sensor_id: 1, kind: ReadingKind::Temperature, value: 20.0, at })
+    }
+
+    async fn stored(db: &Db) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM readings").fetch_one(&db.0).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn batches_commit_on_size_time_and_flush() {
+        let (db, _path) = temp_db("batches").await;
+        let config = WriterConfig { max_rows: 4, max_delay: Duration::from_millis(50), capacity: 16 };
+        let writer = db.writer(config);
+
+        for i in 0..10 {
+            writer.write(reading(i), 0).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+        assert_eq!(stored(&db).await, 10);
+        assert_eq!((writer.stats().written(), writer.stats().batches()), (10, 3), "4 + 4, then 2 on flush");
+
+        // A lone row is committed once it has waited `max_delay`.
+        writer.write(reading(10), 0).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(stored(&db).await, 11);
+        assert_eq!(writer.queued(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_makes_writers_wait_and_close_drains_it() {
+        let (db, _path) = temp_db("backpressure").await;
+        let config = WriterConfig { max_rows: 64, max_delay: Duration::from_mins(1), capacity: 2 };
+        let writer = db.writer(config);
+
+        for i in 0..100 {
+            writer.write(reading(i), 60).await.unwrap();
+        }
+        assert!(writer.stats().stalls() > 0, "a two-row queue can't take a hundred rows unhindered");
+        writer.close().await;
+        assert_eq!(stored(&db).await, 100, "closing commits what was queued");
+        assert!(writer.write(reading(100), 60).await.is_err());
+        assert!(writer.flush().await.is_err());
+        assert_eq!(writer.stats().failed(), 0);
+    }
+}
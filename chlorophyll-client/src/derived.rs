@@ -0,0 +1,271 @@
+//! Metrics computed from measured ones: dew point, vapour pressure deficit and heat index
+//! from paired temperature and humidity, and daily light integral from integrated lux.
+//!
+//! These are what plant work acts on, but sensors don't report them, so they only exist
+//! as history: [`with_derived`] adds them to bucketed series after the fact.
+
+use chrono::{DateTime, Utc};
+
+use crate::reading::ReadingKind;
+
+/// PPFD in µmol/m²/s per lux, for sunlight. Other light sources differ (LED grow lights
+/// run higher), so DLI from a lux sensor is an estimate.
+pub const LUX_TO_PPFD: f32 = 0.0185;
+
+const SECS_PER_DAY: i64 = 86_400;
+
+type Point = (DateTime<Utc>, f32);
+
+/// `(sensor, kind, points)`, the shape history queries return.
+pub type Series<S, P = Point> = (S, ReadingKind, Vec<P>);
+
+/// A point in a history series. Derived series are computed from each point's value and
+/// built back from bare `(at, value)` pairs.
+pub trait Sample {
+    fn point(&self) -> Point;
+    /// Seconds of its `bucket_secs`-wide bucket the point stands for.
+    fn covered_secs(&self, bucket_secs: i64) -> i64;
+    #[must_use]
+    fn from_point(point: Point) -> Self;
+}
+
+impl Sample for Point {
+    fn point(&self) -> Point {
+        *self
+    }
+
+    /// A bare value says nothing of gaps, so it is taken to cover its whole bucket.
+    fn covered_secs(&self, bucket_secs: i64) -> i64 {
+        bucket_secs
+    }
+
+    fn from_point(point: Point) -> Self {
+        point
+    }
+}
+
+/// Saturation vapour pressure over water in kPa (Tetens).
+fn saturation_vapour_pressure(celsius: f32) -> f32 {
+    0.6108 * (17.27 * celsius / (celsius + 237.3)).exp()
+}
+
+/// Dew point in °C, or `None` for a relative humidity of zero or less.
+#[must_use]
+pub fn dew_point(celsius: f32, humidity: f32) -> Option<f32> {
+    if humidity <= 0.0 {
+        return None;
+    }
+    let gamma = (humidity.min(100.0) / 100.0).ln() + 17.27 * celsius / (celsius + 237.3);
+    Some(237.3 * gamma / (17.27 - gamma))
+}
+
+/// Vapour pressure deficit in kPa.
+#[must_use]
+pub fn vpd(celsius: f32, humidity: f32) -> f32 {
+    saturation_vapour_pressure(celsius) * (1.0 - humidity.clamp(0.0, 100.0) / 100.0)
+}
+
+/// Apparent temperature in °C, per the US National Weather Service: Steadman's simple
+/// formula in mild conditions, the Rothfusz regression (with its adjustments) when hot.
+#[must_use]
+pub fn heat_index(celsius: f32, humidity: f32) -> f32 {
+    let t = celsius * 9.0 / 5.0 + 32.0;
+    let rh = humidity.clamp(0.0, 100.0);
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let fahrenheit = if f32::midpoint(simple, t) < 80.0 {
+        simple
+    } else {
+        let mut hi = -42.379 + 2.049_015_2 * t + 10.143_331 * rh
+            - 0.224_755_4 * t * rh
+            - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh
+            + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh;
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            hi -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            hi += (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0);
+        }
+        hi
+    };
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+/// Daily light integral in mol/m²/day, one point per UTC day at midnight, from lux
+/// averaged over `bucket_secs`-wide buckets. Each point counts for the seconds its bucket
+/// actually covers, so gaps in the data lower the day's total rather than being guessed.
+/// Empty unless `bucket_secs` divides a day, as otherwise buckets straddle midnight.
+#[must_use]
+pub fn daily_light_integral<P: Sample>(lux: &[P], bucket_secs: i64) -> Vec<Point> {
+    if bucket_secs <= 0 || SECS_PER_DAY % bucket_secs != 0 {
+        return Vec::new();
+    }
+    let mut days: Vec<Point> = Vec::new();
+    for sample in lux {
+        let (at, value) = sample.point();
+        let Some(day) = DateTime::from_timestamp(at.timestamp().div_euclid(SECS_PER_DAY) * SECS_PER_DAY, 0) else {
+            continue;
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let secs = sample.covered_secs(bucket_secs).clamp(0, bucket_secs) as f32;
+        let mol = value * LUX_TO_PPFD * secs / 1_000_000.0;
+        match days.last_mut() {
+            Some((last, total)) if *last == day => *total += mol,
+            _ => days.push((day, mol)),
+        }
+    }
+    days
+}
+
+/// `(at, temperature, humidity)` wherever both series have a point at the same time, as
+/// they do when bucketed together.
+fn paired(temperature: &[Point], humidity: &[Point]) -> Vec<(DateTime<Utc>, f32, f32)> {
+    let mut out = Vec::new();
+    let mut humidity = humidity.iter().peekable();
+    for &(at, celsius) in temperature {
+        while humidity.next_if(|(h_at, _)| *h_at < at).is_some() {}
+        if let Some(&&(h_at, rh)) = humidity.peek()
+            && h_at == at
+        {
+            out.push((at, celsius, rh));
+        }
+    }
+    out
+}
+
+/// Series derived from one sensor's measured series.
+fn derive<S: Clone, P: Sample>(sensor: &S, measured: &[Series<S, P>], bucket_secs: i64) -> Vec<Series<S, P>> {
+    let series = |kind| measured.iter().find(|(_, k, _)| *k == kind).map(|(_, _, points)| points);
+    let points = |kind| series(kind).map(|points| points.iter().map(Sample::point).collect::<Vec<_>>());
+    let mut derived: Vec<(ReadingKind, Vec<Point>)> = Vec::new();
+    if let (Some(temperature), Some(humidity)) = (points(ReadingKind::Temperature), points(ReadingKind::Humidity)) {
+        let pairs = paired(&temperature, &humidity);
+        if !pairs.is_empty() {
+            let dew: Vec<_> = pairs.iter().filter_map(|&(at, t, rh)| Some((at, dew_point(t, rh)?))).collect();
+            derived.push((ReadingKind::DewPoint, dew));
+            derived.push((ReadingKind::Vpd, pairs.iter().map(|&(at, t, rh)| (at, vpd(t, rh))).collect()));
+            derived.push((ReadingKind::HeatIndex, pairs.iter().map(|&(at, t, rh)| (at, heat_index(t, rh))).collect()));
+        }
+    }
+    if let Some(light) = series(ReadingKind::Light) {
+        derived.push((ReadingKind::Dli, daily_light_integral(light, bucket_secs)));
+    }
+    derived
+        .into_iter()
+        .filter(|(_, points)| !points.is_empty())
+        .map(|(kind, points)| (sensor.clone(), kind, points.into_iter().map(P::from_point).collect()))
+        .collect()
+}
+
+/// `series` (ordered by sensor, as history queries return it) with each sensor's derived
+/// series added after its measured ones.
+#[must_use]
+pub fn with_derived<S: Clone + PartialEq, P: Sample>(series: Vec<Series<S, P>>, bucket_secs: i64) -> Vec<Series<S, P>> {
+    let mut out = Vec::with_capacity(series.len());
+    let mut start = 0;
+    for entry in series {
+        if out.get(start).is_some_and(|(sensor, _, _)| *sensor != entry.0) {
+            let derived = derive(&out[start].0, &out[start..], bucket_secs);
+            out.extend(derived);
+            start = out.len();
+        }
+        out.push(entry);
+    }
+    if let Some((sensor, _, _)) = out.get(start) {
+        let derived = derive(&sensor.clone(), &out[start..], bucket_secs);
+        out.extend(derived);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: f32, expected: f32, tolerance: f32) {
+        assert!((actual - expected).abs() < tolerance, "expected {expected}, got {actual}");
+    }
+
+    #[test]
+    fn psychrometrics_match_reference_values() {
+        close(vpd(25.0, 60.0), 1.267, 0.01);
+        close(vpd(25.0, 100.0), 0.0, 1e-6);
+        close(dew_point(25.0, 60.0).unwrap(), 16.7, 0.1);
+        close(dew_point(20.0, 100.0).unwrap(), 20.0, 0.01);
+        assert_eq!(dew_point(20.0, 0.0), None);
+        // NWS tables: 86 °F at 70 % feels like 95 °F; mild air is left about as is.
+        close(heat_index(30.0, 70.0), 35.0, 0.6);
+        close(heat_index(20.0, 50.0), 19.7, 0.5);
+    }
+
+    #[test]
+    fn dli_integrates_lux_per_day() {
+        let day = DateTime::from_timestamp(1_699_920_000, 0).unwrap(); // a UTC midnight
+        // Twelve hours at 10 klx in hourly buckets, then a few at the start of the next day.
+        let mut lux: Vec<_> = (0..12).map(|h| (day + chrono::Duration::hours(h + 6), 10_000.0)).collect();
+        lux.push((day + chrono::Duration::hours(25), 10_000.0));
+        let dli = daily_light_integral(&lux, 3_600);
+        assert_eq!(dli.len(), 2);
+        assert_eq!(dli[0].0, day);
+        close(dli[0].1, 7.99, 0.01);
+        close(dli[1].1, 0.666, 0.001);
+
+        // Buckets that straddle midnight, or span days, can't be split between them.
+        assert!(daily_light_integral(&lux, 7 * 3_600).is_empty());
+        assert!(daily_light_integral(&lux, 2 * SECS_PER_DAY).is_empty());
+        assert_eq!(daily_light_integral(&lux, SECS_PER_DAY).len(), 2);
+    }
+
+    /// A bucket that knows how much of itself was measured.
+    struct Partial(Point, i64);
+
+    impl Sample for Partial {
+        fn point(&self) -> Point {
+            self.0
+        }
+
+        fn covered_secs(&self, _bucket_secs: i64) -> i64 {
+            self.1
+        }
+
+        fn from_point(point: Point) -> Self {
+            Self(point, 0)
+        }
+    }
+
+    #[test]
+    fn dli_counts_only_the_time_a_bucket_covers() {
+        let noon = DateTime::from_timestamp(1_699_920_000 + 12 * 3_600, 0).unwrap();
+        // An hour's bucket, but the sensor reported for ten minutes of it.
+        let dli = daily_light_integral(&[Partial((noon, 10_000.0), 600)], 3_600);
+        close(dli[0].1, 10_000.0 * LUX_TO_PPFD * 600.0 / 1_000_000.0, 1e-6);
+        // More than the bucket is never counted.
+        let dli = daily_light_integral(&[Partial((noon, 10_000.0), 7_200)], 3_600);
+        close(dli[0].1, 0.666, 0.001);
+    }
+
+    #[test]
+    fn derived_series_follow_each_sensors_measured_ones() {
+        let at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let later = at + chrono::Duration::minutes(1);
+        let series = vec![
+            ("a", ReadingKind::Humidity, vec![(at, 60.0), (later, 60.0)]),
+            ("a", ReadingKind::Temperature, vec![(later, 25.0)]),
+            ("b", ReadingKind::Light, vec![(at, 1_000.0)]),
+        ];
+        let kinds: Vec<_> = with_derived(series, 60).into_iter().map(|(s, k, p)| (s, k, p.len())).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("a", ReadingKind::Humidity, 2),
+                ("a", ReadingKind::Temperature, 1),
+                ("a", ReadingKind::DewPoint, 1),
+                ("a", ReadingKind::Vpd, 1),
+                ("a", ReadingKind::HeatIndex, 1),
+                ("b", ReadingKind::Light, 1),
+                ("b", ReadingKind::Dli, 1),
+            ]
+        );
+    }
+}
@@ -1,14 +1,23 @@
 #![warn(clippy::pedantic)]
+#![allow(clippy::missing_errors_doc)]
 
+pub mod auth;
+pub mod calibration;
 pub mod client;
 pub mod config;
 #[cfg(feature = "sqlite")]
 pub mod db;
+pub mod derived;
 pub mod listener;
 pub mod reading;
 pub mod registry;
 pub mod rollup;
+pub mod stats;
 
-pub use client::SensorClient;
+pub use auth::AuthConfig;
+pub use calibration::{Calibration, Calibrations};
+pub use client::{RenameError, RenameRetry, SensorClient};
 pub use config::ClientConfig;
-pub use reading::{DeviceInfo, Reading, ReadingKind};
+pub use reading::{DeviceInfo, MetricInfo, Reading, ReadingKind};
+pub use registry::{DeviceStatus, RegistryEvent, StatusTimeouts};
+pub use stats::ListenerStats;
@@ -3,15 +3,16 @@ use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use chlorophyll_protocol::postcard::from_bytes;
 use chlorophyll_protocol::Packet;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use socket2::{Domain, Socket, Type};
 use tokio::sync::broadcast;
 
+use crate::auth::{Signer, Verifier};
 use crate::config::ClientConfig;
-use crate::registry::{dispatch, Registry};
+use crate::registry::{dispatch, Registry, RegistryEvent};
 use crate::reading::Reading;
+use crate::stats::ListenerStats;
 
 /// Re-send `RequestSensorInfo` roughly this often (one tick per `recv_from` timeout).
 const REQUEST_INFO_INTERVAL: Duration = Duration::from_secs(30);
@@ -35,10 +36,18 @@ fn bind_multicast(group: Ipv4Addr, port: u16) -> Result<UdpSocket> {
 ///
 /// tokio's async UDP readiness for this multicast socket does not fire on macOS, so we
 /// use a blocking `recv_from` with a read timeout instead.
+///
+/// # Panics
+///
+/// If the registry lock is poisoned.
+#[allow(clippy::needless_pass_by_value)] // owned so the thread can be spawned with `move`
 pub fn run(
     cfg: ClientConfig,
     registry: Arc<Mutex<Registry>>,
     tx: broadcast::Sender<Reading>,
+    events_tx: broadcast::Sender<RegistryEvent>,
+    stats: Arc<ListenerStats>,
+    signer: Arc<Signer>,
 ) {
     let socket = match bind_multicast(cfg.group, cfg.port) {
         Ok(s) => s,
@@ -49,10 +58,11 @@ pub fn run(
     };
     tracing::info!("chlorophyll-client: listening on multicast {}:{}", cfg.group, cfg.port);
 
-    if let Err(e) = send_request_sensor_info(&socket, cfg) {
+    if let Err(e) = send_request_sensor_info(&socket, &cfg, &signer, &registry) {
         tracing::warn!("chlorophyll-client: initial RequestSensorInfo failed: {e:#}");
     }
 
+    let mut verifier = Verifier::new(cfg.auth.clone());
     let mut buf = [0u8; 1500];
     let mut last_request = Instant::now();
 
@@ -60,23 +70,41 @@ pub fn run(
         match socket.recv_from(&mut buf) {
             Ok((len, _src)) => {
                 let now = Utc::now();
-                match from_bytes::<Packet>(&buf[..len]) {
-                    Ok(packet) => {
-                        let mut reg = registry.lock().unwrap();
-                        if let Some(reading) = dispatch(&mut reg, &packet, now) {
-                            drop(reg);
-                            let _ = tx.send(reading);
+                match Packet::decode(&buf[..len]) {
+                    Ok(Some(packet)) => {
+                        stats.record_decoded();
+                        match verifier.check(&buf[..len], &packet) {
+                            Ok(authenticated) => apply(&registry, &tx, &events_tx, &packet, authenticated, now),
+                            Err(e) => {
+                                stats.record_rejected();
+                                tracing::warn!("chlorophyll-client: rejected packet from {:032x}: {e}", packet.id());
+                            }
                         }
                     }
-                    Err(e) => tracing::warn!("chlorophyll-client: decode failed (len {len}): {e}"),
+                    // From a newer protocol version: well formed, but nothing we can use.
+                    Ok(None) => stats.record_skipped(),
+                    Err(e) => {
+                        stats.record_decode_failure();
+                        tracing::warn!("chlorophyll-client: decode failed (len {len}): {e}");
+                    }
                 }
             }
             Err(ref e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
             Err(e) => tracing::warn!("chlorophyll-client: recv error: {e}"),
         }
 
+        // Runs at least once per read timeout, so status changes go out within a second.
+        let events = {
+            let mut reg = registry.lock().unwrap();
+            reg.update_status(Utc::now());
+            reg.take_events()
+        };
+        for event in events {
+            let _ = events_tx.send(event);
+        }
+
         if last_request.elapsed() >= REQUEST_INFO_INTERVAL {
-            if let Err(e) = send_request_sensor_info(&socket, cfg) {
+            if let Err(e) = send_request_sensor_info(&socket, &cfg, &signer, &registry) {
                 tracing::warn!("chlorophyll-client: periodic RequestSensorInfo failed: {e:#}");
             }
             last_request = Instant::now();
@@ -84,23 +112,60 @@ pub fn run(
     }
 }
 
-/// Send a postcard-encoded `Packet` with the given command to the multicast group.
-pub fn send_command(cfg: ClientConfig, command: chlorophyll_protocol::PacketCommand, sensor_id: u128) -> Result<()> {
-    use chlorophyll_protocol::postcard::to_allocvec;
+/// Dispatch an accepted packet into the registry and fan out what it produced.
+pub(crate) fn apply(
+    registry: &Mutex<Registry>,
+    tx: &broadcast::Sender<Reading>,
+    events_tx: &broadcast::Sender<RegistryEvent>,
+    packet: &Packet,
+    authenticated: bool,
+    now: DateTime<Utc>,
+) {
+    let mut reg = registry.lock().unwrap();
+    let reading = dispatch(&mut reg, packet, now);
+    if packet.command().from_sensor() {
+        reg.set_authenticated(packet.id(), authenticated);
+    }
+    let events = reg.take_events();
+    drop(reg);
+    // Send registry changes first, so a new device is announced ahead of its first reading.
+    for event in events {
+        let _ = events_tx.send(event);
+    }
+    if let Some(reading) = reading {
+        let _ = tx.send(reading);
+    }
+}
+
+/// Send a `Packet` with the given command to the multicast group, as
+/// [`Signer::command_frames`] frames it; `legacy` as [`Registry::legacy_commands`] says.
+pub fn send_command(
+    cfg: &ClientConfig,
+    signer: &Signer,
+    command: chlorophyll_protocol::PacketCommand,
+    sensor_id: u128,
+    legacy: bool,
+) -> Result<()> {
     let socket = UdpSocket::bind("0.0.0.0:0")?;
     socket.set_multicast_loop_v4(false).ok();
     let dest = SocketAddrV4::new(cfg.group, cfg.port);
-    let packet = Packet::new(command, sensor_id);
-    let data = to_allocvec(&packet).map_err(|e| anyhow::anyhow!("{e}"))?;
-    socket.send_to(&data, dest)?;
+    for data in signer.command_frames(&Packet::new(command, sensor_id), legacy)? {
+        socket.send_to(&data, dest)?;
+    }
     Ok(())
 }
 
-fn send_request_sensor_info(socket: &UdpSocket, cfg: ClientConfig) -> Result<()> {
-    use chlorophyll_protocol::postcard::to_allocvec;
+fn send_request_sensor_info(
+    socket: &UdpSocket,
+    cfg: &ClientConfig,
+    signer: &Signer,
+    registry: &Mutex<Registry>,
+) -> Result<()> {
+    let legacy = registry.lock().unwrap().legacy_commands(None);
     let packet = Packet::new(chlorophyll_protocol::PacketCommand::RequestSensorInfo, 0);
-    let data = to_allocvec(&packet).map_err(|e| anyhow::anyhow!("{e}"))?;
     let dest = SocketAddrV4::new(cfg.group, cfg.port);
-    socket.send_to(&data, dest)?;
+    for data in signer.command_frames(&packet, legacy)? {
+        socket.send_to(&data, dest)?;
+    }
     Ok(())
 }
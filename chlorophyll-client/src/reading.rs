@@ -1,19 +1,151 @@
+use std::collections::BTreeMap;
+
+use chlorophyll_protocol::Metric;
 use chrono::{DateTime, Utc};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+use crate::registry::DeviceStatus;
+
+/// Everything about a metric that is needed to store, serve and display it. One entry per
+/// [`ReadingKind`], returned by [`ReadingKind::info`]; consumers iterate
+/// [`ReadingKind::ALL`] rather than naming kinds, so a new metric only needs a variant and
+/// its entry here.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetricInfo {
+    /// Stable identifier used in the database, the API and MQTT topics.
+    pub id: &'static str,
+    pub name: &'static str,
+    /// Unit symbol for display; empty for unitless metrics.
+    pub unit: &'static str,
+    /// The unit spelled out, for metric names like `chlorophyll_temperature_celsius`.
+    pub unit_name: &'static str,
+    /// Decimal places worth showing.
+    pub precision: usize,
+    /// Default chart range: a y-axis never spans less than this, so sensor noise on a
+    /// steady value isn't stretched to full height.
+    pub chart_span: f32,
+}
+
+impl MetricInfo {
+    /// The unit as written after a value: `"°C"` and `"%"` attach, others take a space.
+    #[must_use]
+    pub fn suffix(&self) -> String {
+        match self.unit {
+            "" | "%" | "\u{b0}C" => self.unit.to_string(),
+            unit => format!(" {unit}"),
+        }
+    }
+
+    /// `value` at the metric's precision, with its unit.
+    #[must_use]
+    pub fn format(&self, value: f32) -> String {
+        format!("{value:.precision$}{}", self.suffix(), precision = self.precision)
+    }
+}
+
+/// Kinds of reading, in display order: what sensors measure, then what
+/// [`crate::derived`] computes from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ReadingKind {
     Temperature,
     Humidity,
     Light,
+    Co2,
+    Pressure,
+    SoilMoisture,
+    Voc,
+    Battery,
+    DewPoint,
+    /// Vapour pressure deficit.
+    Vpd,
+    HeatIndex,
+    /// Daily light integral.
+    Dli,
 }
 
 impl ReadingKind {
+    pub const ALL: [ReadingKind; 12] = [
+        ReadingKind::Temperature,
+        ReadingKind::Humidity,
+        ReadingKind::Light,
+        ReadingKind::Co2,
+        ReadingKind::Pressure,
+        ReadingKind::SoilMoisture,
+        ReadingKind::Voc,
+        ReadingKind::Battery,
+        ReadingKind::DewPoint,
+        ReadingKind::Vpd,
+        ReadingKind::HeatIndex,
+        ReadingKind::Dli,
+    ];
+
+    /// Kinds that arrive from sensors; the rest only exist in history.
+    pub const MEASURED: [ReadingKind; 8] = [
+        ReadingKind::Temperature,
+        ReadingKind::Humidity,
+        ReadingKind::Light,
+        ReadingKind::Co2,
+        ReadingKind::Pressure,
+        ReadingKind::SoilMoisture,
+        ReadingKind::Voc,
+        ReadingKind::Battery,
+    ];
+
+    /// Computed from other metrics rather than measured, so never a live value.
     #[must_use]
-    pub fn as_str(self) -> &'static str {
+    pub fn is_derived(self) -> bool {
+        !Self::MEASURED.contains(&self)
+    }
+
+    #[must_use]
+    pub fn info(self) -> &'static MetricInfo {
+        const fn metric(
+            id: &'static str,
+            name: &'static str,
+            unit: &'static str,
+            unit_name: &'static str,
+            precision: usize,
+            chart_span: f32,
+        ) -> MetricInfo {
+            MetricInfo { id, name, unit, unit_name, precision, chart_span }
+        }
         match self {
-            ReadingKind::Temperature => "temperature",
-            ReadingKind::Humidity => "humidity",
-            ReadingKind::Light => "light",
+            ReadingKind::Temperature => &const { metric("temperature", "Temperature", "\u{b0}C", "celsius", 1, 2.0) },
+            ReadingKind::Humidity => &const { metric("humidity", "Humidity", "%", "percent", 1, 5.0) },
+            ReadingKind::Light => &const { metric("light", "Light", "lx", "lux", 0, 50.0) },
+            ReadingKind::Co2 => &const { metric("co2", "CO2", "ppm", "ppm", 0, 100.0) },
+            ReadingKind::Pressure => &const { metric("pressure", "Pressure", "hPa", "hectopascals", 1, 5.0) },
+            ReadingKind::SoilMoisture => &const { metric("soil_moisture", "Soil moisture", "%", "percent", 1, 5.0) },
+            ReadingKind::Voc => &const { metric("voc", "VOC index", "", "index", 0, 20.0) },
+            ReadingKind::Battery => &const { metric("battery", "Battery", "V", "volts", 2, 0.2) },
+            ReadingKind::DewPoint => &const { metric("dew_point", "Dew point", "\u{b0}C", "celsius", 1, 2.0) },
+            ReadingKind::Vpd => &const { metric("vpd", "VPD", "kPa", "kilopascals", 2, 0.2) },
+            ReadingKind::HeatIndex => &const { metric("heat_index", "Heat index", "\u{b0}C", "celsius", 1, 2.0) },
+            ReadingKind::Dli => &const { metric("dli", "Daily light integral", "mol/m\u{b2}/d", "mol_per_m2_day", 1, 2.0) },
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        self.info().id
+    }
+
+    /// The kind whose [`MetricInfo::id`] is `id`.
+    #[must_use]
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.as_str() == id)
+    }
+
+    #[must_use]
+    pub fn from_metric(metric: Metric) -> Self {
+        match metric {
+            Metric::Temperature => ReadingKind::Temperature,
+            Metric::RelativeHumidity => ReadingKind::Humidity,
+            Metric::Light => ReadingKind::Light,
+            Metric::Co2 => ReadingKind::Co2,
+            Metric::Pressure => ReadingKind::Pressure,
+            Metric::SoilMoisture => ReadingKind::SoilMoisture,
+            Metric::Voc => ReadingKind::Voc,
+            Metric::BatteryVoltage => ReadingKind::Battery,
         }
     }
 }
@@ -31,7 +163,48 @@ pub struct DeviceInfo {
     pub id: u128,
     pub name: Option<String>,
     pub last_seen: Option<DateTime<Utc>>,
-    pub temperature: Option<f32>,
-    pub humidity: Option<f32>,
-    pub light: Option<f32>,
+    /// Latest value of each metric the sensor has reported.
+    pub values: BTreeMap<ReadingKind, f32>,
+    /// From the `SensorsInfo` capabilities extension; `None` for older firmware.
+    pub firmware_version: Option<String>,
+    /// Metrics the sensor advertises; empty until it sends capabilities.
+    pub supported_metrics: Vec<ReadingKind>,
+    pub sample_interval_ms: Option<u32>,
+    /// Online, stale or offline, from how long ago `last_seen` was.
+    pub status: DeviceStatus,
+    /// Whether the sensor's latest packet carried a valid MAC. Always `false` when no key
+    /// is configured.
+    pub authenticated: bool,
+    /// Name sent with `SetName` that the sensor hasn't announced back yet.
+    pub pending_name: Option<String>,
+}
+
+impl DeviceInfo {
+    /// Latest value of `kind`, if the sensor has reported it.
+    #[must_use]
+    pub fn value(&self, kind: ReadingKind) -> Option<f32> {
+        self.values.get(&kind).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metric_ids_are_unique_and_parse_back() {
+        for kind in ReadingKind::ALL {
+            assert_eq!(ReadingKind::from_id(kind.as_str()), Some(kind));
+        }
+        assert_eq!(ReadingKind::from_metric(Metric::RelativeHumidity), ReadingKind::Humidity);
+        assert_eq!(ReadingKind::from_id("radiation"), None);
+    }
+
+    #[test]
+    fn values_format_with_precision_and_unit() {
+        assert_eq!(ReadingKind::Temperature.info().format(21.46), "21.5\u{b0}C");
+        assert_eq!(ReadingKind::Co2.info().format(812.4), "812 ppm");
+        assert_eq!(ReadingKind::Battery.info().format(3.7), "3.70 V");
+        assert_eq!(ReadingKind::Voc.info().format(104.0), "104");
+    }
 }
@@ -1,16 +1,120 @@
 use std::collections::BTreeMap;
+use std::time::Duration;
 
 use chlorophyll_protocol::light::Light;
 use chlorophyll_protocol::temperature::Temperature;
 use chlorophyll_protocol::{DataType, Packet, PacketCommand};
 use chrono::{DateTime, Utc};
 
+use crate::calibration::Calibrations;
 use crate::reading::{DeviceInfo, Reading, ReadingKind};
 
+/// A change to the set of known sensors, as opposed to a [`Reading`] from one of them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistryEvent {
+    /// First packet ever seen from this sensor.
+    DeviceAdded { id: u128, at: DateTime<Utc> },
+    /// The sensor announced a name different from the one on record.
+    NameChanged { id: u128, name: String, at: DateTime<Utc> },
+    /// The sensor went quiet for long enough to change [`DeviceStatus`], or was heard from
+    /// again.
+    StatusChanged {
+        id: u128,
+        from: DeviceStatus,
+        to: DeviceStatus,
+        at: DateTime<Utc>,
+    },
+    /// `SetName` was sent to the sensor, which hasn't announced the new name yet.
+    RenamePending { id: u128, name: String, at: DateTime<Utc> },
+    /// A pending rename ended: the sensor announced the name (`confirmed`), or it was
+    /// given up on.
+    RenameSettled {
+        id: u128,
+        name: String,
+        confirmed: bool,
+        at: DateTime<Utc>,
+    },
+}
+
+impl RegistryEvent {
+    /// The sensor the event is about.
+    #[must_use]
+    pub fn id(&self) -> u128 {
+        match self {
+            RegistryEvent::DeviceAdded { id, .. }
+            | RegistryEvent::NameChanged { id, .. }
+            | RegistryEvent::StatusChanged { id, .. }
+            | RegistryEvent::RenamePending { id, .. }
+            | RegistryEvent::RenameSettled { id, .. } => *id,
+        }
+    }
+}
+
+/// Whether a sensor is still reporting, judged by how long it has been silent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub enum DeviceStatus {
+    #[default]
+    Online,
+    /// Missed a few samples; may just be a flaky link.
+    Stale,
+    Offline,
+}
+
+impl DeviceStatus {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeviceStatus::Online => "online",
+            DeviceStatus::Stale => "stale",
+            DeviceStatus::Offline => "offline",
+        }
+    }
+}
+
+/// How long a sensor may be silent before it counts as stale, then offline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusTimeouts {
+    pub stale_after: Duration,
+    pub offline_after: Duration,
+}
+
+impl Default for StatusTimeouts {
+    fn default() -> Self {
+        Self {
+            stale_after: Duration::from_mins(1),
+            offline_after: Duration::from_mins(5),
+        }
+    }
+}
+
+impl StatusTimeouts {
+    /// Status of a sensor last heard from at `last_seen`.
+    #[must_use]
+    pub fn status(&self, last_seen: DateTime<Utc>, now: DateTime<Utc>) -> DeviceStatus {
+        let silent = (now - last_seen).to_std().unwrap_or_default();
+        if silent >= self.offline_after {
+            DeviceStatus::Offline
+        } else if silent >= self.stale_after {
+            DeviceStatus::Stale
+        } else {
+            DeviceStatus::Online
+        }
+    }
+}
+
 /// Tracks known sensors keyed by id. Updated by [`dispatch`] as packets arrive.
 #[derive(Debug, Default)]
 pub struct Registry {
     devices: BTreeMap<u128, DeviceInfo>,
+    /// Changes recorded by [`dispatch`] and [`Self::update_status`] since the last
+    /// [`Self::take_events`].
+    events: Vec<RegistryEvent>,
+    timeouts: StatusTimeouts,
+    /// Applied by [`dispatch`] to every reading.
+    calibrations: Calibrations,
+    /// Names sent with `SetName` and not yet announced back, by sensor. Kept apart from
+    /// `devices` so a rename can be confirmed by a sensor not heard from before.
+    renames: BTreeMap<u128, String>,
 }
 
 impl Registry {
@@ -19,45 +123,175 @@ impl Registry {
         Self::default()
     }
 
+    #[must_use]
+    pub fn with_timeouts(timeouts: StatusTimeouts) -> Self {
+        Self {
+            timeouts,
+            ..Self::default()
+        }
+    }
+
     #[must_use]
     pub fn devices(&self) -> Vec<DeviceInfo> {
-        self.devices.values().cloned().collect()
+        self.devices
+            .values()
+            .map(|device| DeviceInfo {
+                pending_name: self.renames.get(&device.id).cloned(),
+                ..device.clone()
+            })
+            .collect()
+    }
+
+    /// Whether server commands for `id` (every sensor for `None`) should also go out in
+    /// the pre-versioning layout: a sensor they reach hasn't advertised capabilities yet,
+    /// so may run firmware that can't read versioned frames. Until any sensor is heard
+    /// from, that can't be ruled out.
+    #[must_use]
+    pub fn legacy_commands(&self, id: Option<u128>) -> bool {
+        match id {
+            Some(id) => self.devices.get(&id).is_none_or(|device| device.firmware_version.is_none()),
+            None => self.devices.is_empty() || self.devices.values().any(|device| device.firmware_version.is_none()),
+        }
+    }
+
+    /// Add devices known from a previous run, e.g. the database's `sensors` table, with
+    /// their status judged as of `now`. Devices already present are left alone, and no
+    /// events are recorded: these sensors aren't new.
+    pub fn seed(&mut self, devices: impl IntoIterator<Item = DeviceInfo>, now: DateTime<Utc>) {
+        for mut device in devices {
+            if let Some(last_seen) = device.last_seen {
+                device.status = self.timeouts.status(last_seen, now);
+            }
+            self.devices.entry(device.id).or_insert(device);
+        }
+    }
+
+    /// Replace the calibrations applied to readings from now on.
+    pub fn set_calibrations(&mut self, calibrations: Calibrations) {
+        self.calibrations = calibrations;
+    }
+
+    /// Drain the changes recorded since the last call, oldest first.
+    pub fn take_events(&mut self) -> Vec<RegistryEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Record whether the latest packet from `id` was authenticated. No-op for unknown ids.
+    pub fn set_authenticated(&mut self, id: u128, authenticated: bool) {
+        if let Some(device) = self.devices.get_mut(&id) {
+            device.authenticated = authenticated;
+        }
+    }
+
+    /// Record that `name` was sent to `id` with `SetName`, replacing any rename already
+    /// pending for it. [`dispatch`] settles it once the sensor announces that name.
+    pub fn begin_rename(&mut self, id: u128, name: &str, now: DateTime<Utc>) {
+        self.renames.insert(id, name.to_string());
+        self.events.push(RegistryEvent::RenamePending { id, name: name.to_string(), at: now });
+    }
+
+    /// Give up on renaming `id` to `name`, if that rename is still pending.
+    pub fn abandon_rename(&mut self, id: u128, name: &str, now: DateTime<Utc>) {
+        if self.renames.get(&id).is_some_and(|pending| pending == name) {
+            self.renames.remove(&id);
+            self.events.push(RegistryEvent::RenameSettled { id, name: name.to_string(), confirmed: false, at: now });
+        }
+    }
+
+    /// Re-evaluate every device's status against the timeouts, recording a
+    /// [`RegistryEvent::StatusChanged`] for each that changed. Call periodically.
+    pub fn update_status(&mut self, now: DateTime<Utc>) {
+        for device in self.devices.values_mut() {
+            let Some(last_seen) = device.last_seen else { continue };
+            let status = self.timeouts.status(last_seen, now);
+            if status != device.status {
+                self.events.push(RegistryEvent::StatusChanged {
+                    id: device.id,
+                    from: device.status,
+                    to: status,
+                    at: now,
+                });
+                device.status = status;
+            }
+        }
+    }
+
+    /// The device for a packet from `id` just received: created if new, marked seen and
+    /// back online.
+    fn seen(&mut self, id: u128, now: DateTime<Utc>) -> &mut DeviceInfo {
+        let events = &mut self.events;
+        let device = self.devices.entry(id).or_insert_with(|| {
+            events.push(RegistryEvent::DeviceAdded { id, at: now });
+            DeviceInfo {
+                id,
+                ..Default::default()
+            }
+        });
+        device.last_seen = Some(now);
+        if device.status != DeviceStatus::Online {
+            events.push(RegistryEvent::StatusChanged {
+                id,
+                from: device.status,
+                to: DeviceStatus::Online,
+                at: now,
+            });
+            device.status = DeviceStatus::Online;
+        }
+        device
     }
 }
 
 /// Apply a decoded packet to the registry, returning a [`Reading`] for `DataReading`
-/// packets (so the caller can fan it out on a broadcast channel).
+/// packets (so the caller can fan it out on a broadcast channel). Reading values are
+/// calibrated here, before they reach the device's live values or the caller.
 pub fn dispatch(registry: &mut Registry, packet: &Packet, now: DateTime<Utc>) -> Option<Reading> {
     let id = packet.id();
 
     match packet.command() {
-        PacketCommand::SensorsInfo(name) => {
-            let device = registry.devices.entry(id).or_insert_with(|| DeviceInfo {
-                id,
-                ..Default::default()
-            });
-            device.last_seen = Some(now);
-            if let Some(name) = name {
+        PacketCommand::SensorsInfo(info) => {
+            let device = registry.seen(id, now);
+            if let Some(capabilities) = &info.capabilities {
+                device.firmware_version = Some(capabilities.firmware_version.clone());
+                device.supported_metrics = capabilities.metrics().map(ReadingKind::from_metric).collect();
+                device.sample_interval_ms = Some(capabilities.sample_interval_ms);
+            }
+            if let Some(name) = &info.name
+                && device.name.as_ref() != Some(name)
+            {
                 device.name = Some(name.clone());
+                registry.events.push(RegistryEvent::NameChanged {
+                    id,
+                    name: name.clone(),
+                    at: now,
+                });
+            }
+            // A sensor announces a fresh SensorsInfo once it has stored a SetName.
+            if let Some(name) = &info.name
+                && registry.renames.get(&id) == Some(name)
+            {
+                registry.renames.remove(&id);
+                registry.events.push(RegistryEvent::RenameSettled {
+                    id,
+                    name: name.clone(),
+                    confirmed: true,
+                    at: now,
+                });
             }
             None
         }
         PacketCommand::DataReading(data) => {
-            let device = registry.devices.entry(id).or_insert_with(|| DeviceInfo {
-                id,
-                ..Default::default()
-            });
-            device.last_seen = Some(now);
-            let (kind, value) = match data {
+            let (kind, raw) = match data {
                 DataType::Temperature(t) => (ReadingKind::Temperature, t.get_as_c()),
                 DataType::RelativeHumidity(h) => (ReadingKind::Humidity, h.percent()),
                 DataType::Light(l) => (ReadingKind::Light, l.get_as_lux()),
+                DataType::Co2(c) => (ReadingKind::Co2, c.ppm()),
+                DataType::Pressure(p) => (ReadingKind::Pressure, p.hpa()),
+                DataType::SoilMoisture(m) => (ReadingKind::SoilMoisture, m.percent()),
+                DataType::Voc(v) => (ReadingKind::Voc, v.index()),
+                DataType::BatteryVoltage(b) => (ReadingKind::Battery, b.volts()),
             };
-            match kind {
-                ReadingKind::Temperature => device.temperature = Some(value),
-                ReadingKind::Humidity => device.humidity = Some(value),
-                ReadingKind::Light => device.light = Some(value),
-            }
+            let value = registry.calibrations.apply(id, kind, raw);
+            registry.seen(id, now).values.insert(kind, value);
             Some(Reading {
                 sensor_id: id,
                 kind,
@@ -73,19 +307,61 @@ pub fn dispatch(registry: &mut Registry, packet: &Packet, now: DateTime<Utc>) ->
 mod tests {
     use super::*;
     use chlorophyll_protocol::humidity::RelativeHumidity;
+    use chlorophyll_protocol::battery::Volts;
+    use chlorophyll_protocol::co2::Ppm;
     use chlorophyll_protocol::light::Lux;
-    use chlorophyll_protocol::postcard::{from_bytes, to_allocvec};
+    use chlorophyll_protocol::pressure::Hectopascals;
+    use chlorophyll_protocol::soil_moisture::SoilMoisture;
+    use chlorophyll_protocol::voc::VocIndex;
     use chlorophyll_protocol::temperature::Celsius;
+    use chlorophyll_protocol::{Capabilities, Metric, SensorInfo};
+
+    fn sensors_info(name: &str) -> PacketCommand {
+        PacketCommand::SensorsInfo(SensorInfo::new(Some(name.into())))
+    }
 
     #[test]
-    fn packet_roundtrips_through_postcard() {
+    fn packet_roundtrips_through_the_wire_format() {
         let packet = Packet::new(
             PacketCommand::DataReading(DataType::Temperature(Celsius::new(21.5))),
             42,
         );
-        let bytes = to_allocvec(&packet).unwrap();
-        let decoded: Packet = from_bytes(&bytes).unwrap();
-        assert_eq!(packet, decoded);
+        let bytes = packet.encode().unwrap();
+        assert_eq!(Packet::decode(&bytes).unwrap(), Some(packet));
+    }
+
+    #[test]
+    fn dispatch_records_advertised_capabilities() {
+        let mut registry = Registry::new();
+        let capabilities = Capabilities::new("1.2.0", &[Metric::Temperature, Metric::Light], 5_000);
+        let info = Packet::new(
+            PacketCommand::SensorsInfo(SensorInfo::new(None).with_capabilities(capabilities)),
+            7,
+        );
+        dispatch(&mut registry, &info, Utc::now());
+
+        let device = &registry.devices()[0];
+        assert_eq!(device.firmware_version.as_deref(), Some("1.2.0"));
+        assert_eq!(device.supported_metrics, vec![ReadingKind::Temperature, ReadingKind::Light]);
+        assert_eq!(device.sample_interval_ms, Some(5_000));
+        assert_eq!(device.name, None);
+    }
+
+    #[test]
+    fn legacy_commands_go_out_until_sensors_advertise_capabilities() {
+        let mut registry = Registry::new();
+        assert!(registry.legacy_commands(None), "nothing heard yet");
+        let capabilities = Capabilities::new("1.2.0", &[Metric::Temperature], 5_000);
+        let info = Packet::new(PacketCommand::SensorsInfo(SensorInfo::new(None).with_capabilities(capabilities)), 7);
+        dispatch(&mut registry, &info, Utc::now());
+        assert!(!registry.legacy_commands(None));
+        assert!(!registry.legacy_commands(Some(7)));
+        assert!(registry.legacy_commands(Some(8)), "never heard from");
+
+        dispatch(&mut registry, &Packet::new(PacketCommand::SensorsInfo(SensorInfo::new(None)), 8), Utc::now());
+        assert!(registry.legacy_commands(None));
+        assert!(!registry.legacy_commands(Some(7)));
+        assert!(registry.legacy_commands(Some(8)), "old firmware sends no capabilities");
     }
 
     #[test]
@@ -93,7 +369,7 @@ mod tests {
         let mut registry = Registry::new();
         let now = Utc::now();
 
-        let info = Packet::new(PacketCommand::SensorsInfo(Some("greenhouse".into())), 7);
+        let info = Packet::new(sensors_info("greenhouse"), 7);
         assert!(dispatch(&mut registry, &info, now).is_none());
 
         let temp = Packet::new(
@@ -122,12 +398,50 @@ mod tests {
         let device = &devices[0];
         assert_eq!(device.id, 7);
         assert_eq!(device.name.as_deref(), Some("greenhouse"));
-        assert_eq!(device.temperature, Some(22.0));
-        assert_eq!(device.humidity, Some(55.0));
-        assert_eq!(device.light, Some(123.0));
+        assert_eq!(device.value(ReadingKind::Temperature), Some(22.0));
+        assert_eq!(device.value(ReadingKind::Humidity), Some(55.0));
+        assert_eq!(device.value(ReadingKind::Light), Some(123.0));
         assert_eq!(device.last_seen, Some(now));
     }
 
+    #[test]
+    fn dispatch_stores_extended_metrics() {
+        let mut registry = Registry::new();
+        let now = Utc::now();
+        let readings = [
+            (DataType::Co2(Ppm::new(812.0)), ReadingKind::Co2),
+            (DataType::Pressure(Hectopascals::new(1013.0)), ReadingKind::Pressure),
+            (DataType::SoilMoisture(SoilMoisture::new(31.5)), ReadingKind::SoilMoisture),
+            (DataType::Voc(VocIndex::new(104.0)), ReadingKind::Voc),
+            (DataType::BatteryVoltage(Volts::new(3.75)), ReadingKind::Battery),
+        ];
+        for (data, kind) in readings {
+            let reading = dispatch(&mut registry, &Packet::new(PacketCommand::DataReading(data), 7), now)
+                .expect("reading");
+            assert_eq!(reading.kind, kind);
+        }
+
+        let device = &registry.devices()[0];
+        assert_eq!(device.value(ReadingKind::Co2), Some(812.0));
+        assert_eq!(device.value(ReadingKind::Pressure), Some(1013.0));
+        assert_eq!(device.value(ReadingKind::SoilMoisture), Some(31.5));
+        assert_eq!(device.value(ReadingKind::Voc), Some(104.0));
+        assert_eq!(device.value(ReadingKind::Battery), Some(3.75));
+    }
+
+    #[test]
+    fn dispatch_calibrates_live_values_and_readings() {
+        let mut registry = Registry::new();
+        let mut calibrations = Calibrations::new();
+        calibrations.insert(7, ReadingKind::Temperature, crate::Calibration::Linear { scale: 1.0, offset: -1.5 });
+        registry.set_calibrations(calibrations);
+
+        let temp = Packet::new(PacketCommand::DataReading(DataType::Temperature(Celsius::new(22.0))), 7);
+        let reading = dispatch(&mut registry, &temp, Utc::now()).expect("reading");
+        assert!((reading.value - 20.5).abs() < 1e-5);
+        assert_eq!(registry.devices()[0].value(ReadingKind::Temperature), Some(reading.value));
+    }
+
     #[test]
     fn dispatch_ignores_control_packets() {
         let mut registry = Registry::new();
@@ -140,5 +454,130 @@ mod tests {
         assert!(dispatch(&mut registry, &set_name, now).is_none());
 
         assert!(registry.devices().is_empty());
+        assert!(registry.take_events().is_empty());
+    }
+
+    #[test]
+    fn dispatch_records_new_devices_and_name_changes_once() {
+        let mut registry = Registry::new();
+        let now = Utc::now();
+
+        let temp = Packet::new(
+            PacketCommand::DataReading(DataType::Temperature(Celsius::new(22.0))),
+            7,
+        );
+        dispatch(&mut registry, &temp, now);
+        dispatch(&mut registry, &temp, now);
+        assert_eq!(registry.take_events(), vec![RegistryEvent::DeviceAdded { id: 7, at: now }]);
+
+        let info = Packet::new(sensors_info("greenhouse"), 7);
+        dispatch(&mut registry, &info, now);
+        // The periodic RequestSensorInfo makes sensors re-announce the same name; only the
+        // first announcement is a change.
+        dispatch(&mut registry, &info, now);
+        assert_eq!(
+            registry.take_events(),
+            vec![RegistryEvent::NameChanged { id: 7, name: "greenhouse".into(), at: now }]
+        );
+        assert!(registry.take_events().is_empty(), "events are drained, not replayed");
+    }
+
+    #[test]
+    fn renames_stay_pending_until_the_sensor_announces_the_name() {
+        let mut registry = Registry::new();
+        let now = Utc::now();
+        registry.begin_rename(7, "bench", now);
+        dispatch(&mut registry, &Packet::new(sensors_info("greenhouse"), 7), now);
+        assert_eq!(registry.devices()[0].pending_name.as_deref(), Some("bench"), "an older name isn't a confirmation");
+        registry.take_events();
+
+        dispatch(&mut registry, &Packet::new(sensors_info("bench"), 7), now);
+        assert_eq!(
+            registry.take_events(),
+            vec![
+                RegistryEvent::NameChanged { id: 7, name: "bench".into(), at: now },
+                RegistryEvent::RenameSettled { id: 7, name: "bench".into(), confirmed: true, at: now },
+            ]
+        );
+        assert_eq!(registry.devices()[0].pending_name, None);
+
+        registry.begin_rename(7, "potting", now);
+        registry.abandon_rename(7, "bench", now);
+        assert_eq!(registry.devices()[0].pending_name.as_deref(), Some("potting"), "only the same rename is abandoned");
+        registry.abandon_rename(7, "potting", now);
+        assert_eq!(
+            registry.take_events(),
+            vec![
+                RegistryEvent::RenamePending { id: 7, name: "potting".into(), at: now },
+                RegistryEvent::RenameSettled { id: 7, name: "potting".into(), confirmed: false, at: now },
+            ]
+        );
+        assert_eq!(registry.devices()[0].pending_name, None);
+    }
+
+    #[test]
+    fn seeded_devices_keep_their_names_and_are_not_announced_as_new() {
+        let mut registry = Registry::new();
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let known = DeviceInfo {
+            id: 7,
+            name: Some("greenhouse".into()),
+            last_seen: Some(start),
+            ..DeviceInfo::default()
+        };
+        let later = start + chrono::Duration::hours(1);
+        registry.seed([known], later);
+        assert_eq!(registry.devices()[0].status, DeviceStatus::Offline);
+        assert!(registry.take_events().is_empty());
+
+        let temp = Packet::new(PacketCommand::DataReading(DataType::Temperature(Celsius::new(22.0))), 7);
+        dispatch(&mut registry, &temp, later);
+        assert_eq!(
+            registry.take_events(),
+            vec![RegistryEvent::StatusChanged {
+                id: 7,
+                from: DeviceStatus::Offline,
+                to: DeviceStatus::Online,
+                at: later
+            }]
+        );
+        assert_eq!(registry.devices()[0].name.as_deref(), Some("greenhouse"));
+    }
+
+    #[test]
+    fn status_goes_stale_then_offline_and_recovers_on_the_next_packet() {
+        let timeouts = StatusTimeouts::default();
+        let mut registry = Registry::with_timeouts(timeouts);
+        let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        dispatch(&mut registry, &Packet::new(sensors_info("bench"), 7), start);
+        registry.take_events();
+
+        let status_events = |registry: &mut Registry| -> Vec<(DeviceStatus, DeviceStatus)> {
+            registry
+                .take_events()
+                .into_iter()
+                .filter_map(|e| match e {
+                    RegistryEvent::StatusChanged { from, to, .. } => Some((from, to)),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        registry.update_status(start + chrono::Duration::seconds(30));
+        assert!(status_events(&mut registry).is_empty());
+
+        registry.update_status(start + chrono::Duration::seconds(90));
+        assert_eq!(status_events(&mut registry), vec![(DeviceStatus::Online, DeviceStatus::Stale)]);
+        registry.update_status(start + chrono::Duration::seconds(120));
+        assert!(status_events(&mut registry).is_empty(), "transitions are reported once");
+
+        let later = start + chrono::Duration::minutes(6);
+        registry.update_status(later);
+        assert_eq!(status_events(&mut registry), vec![(DeviceStatus::Stale, DeviceStatus::Offline)]);
+        assert_eq!(registry.devices()[0].status, DeviceStatus::Offline);
+
+        dispatch(&mut registry, &Packet::new(sensors_info("bench"), 7), later);
+        assert_eq!(status_events(&mut registry), vec![(DeviceStatus::Offline, DeviceStatus::Online)]);
+        assert_eq!(registry.devices()[0].status, DeviceStatus::Online);
     }
 }
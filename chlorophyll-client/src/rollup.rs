@@ -14,21 +14,61 @@ use crate::reading::{Reading, ReadingKind};
 /// here costs no visible detail.
 pub const INGEST_BUCKET_SECS: i64 = 60;
 
+/// Readings a sensor sends per second per metric, for weighting averaged rows whose own
+/// count isn't known.
+pub const READINGS_PER_SEC: i64 = 5;
+
 struct OpenBucket {
     index: i64,
     sum: f64,
+    min: f32,
+    max: f32,
     count: u32,
 }
 
 impl OpenBucket {
-    fn average(&self, bucket_secs: i64) -> Option<(DateTime<Utc>, f32)> {
+    fn new(index: i64, value: f32) -> Self {
+        Self { index, sum: f64::from(value), min: value, max: value, count: 1 }
+    }
+
+    fn add(&mut self, value: f32) {
+        self.sum += f64::from(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.count += 1;
+    }
+
+    fn close(&self, sensor_id: u128, kind: ReadingKind, bucket_secs: i64) -> Option<Averaged> {
         if self.count == 0 {
             return None;
         }
         let at = DateTime::from_timestamp(self.index * bucket_secs, 0)?;
         #[allow(clippy::cast_possible_truncation)]
-        let mean = (self.sum / f64::from(self.count)) as f32;
-        Some((at, mean))
+        let value = (self.sum / f64::from(self.count)) as f32;
+        Some(Averaged {
+            reading: Reading { sensor_id, kind, value, at },
+            min: self.min,
+            max: self.max,
+            count: self.count,
+        })
+    }
+}
+
+/// A closed bucket: the mean as a reading stamped with the bucket's start, plus the
+/// extremes and number of the readings averaged into it, so a brief spike survives
+/// averaging.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Averaged {
+    pub reading: Reading,
+    pub min: f32,
+    pub max: f32,
+    pub count: u32,
+}
+
+impl From<Reading> for Averaged {
+    /// A lone reading, as a bucket of one.
+    fn from(reading: Reading) -> Self {
+        Self { min: reading.value, max: reading.value, count: 1, reading }
     }
 }
 
@@ -55,40 +95,24 @@ impl ReadingAggregator {
         at.timestamp().div_euclid(self.bucket_secs)
     }
 
-    /// Feed one reading. Returns the completed average for the previous bucket, if this
-    /// reading closed one.
-    pub fn push(&mut self, reading: &Reading) -> Option<Reading> {
+    /// Feed one reading. Returns the completed previous bucket, if this reading closed one.
+    pub fn push(&mut self, reading: &Reading) -> Option<Averaged> {
         let index = self.bucket_index(reading.at);
         let key = (reading.sensor_id, reading.kind);
         let bucket_secs = self.bucket_secs;
 
         match self.open.get_mut(&key) {
             Some(open) if open.index == index => {
-                open.sum += f64::from(reading.value);
-                open.count += 1;
+                open.add(reading.value);
                 None
             }
             Some(open) => {
-                let finished = open.average(bucket_secs);
-                open.index = index;
-                open.sum = f64::from(reading.value);
-                open.count = 1;
-                finished.map(|(at, value)| Reading {
-                    sensor_id: reading.sensor_id,
-                    kind: reading.kind,
-                    value,
-                    at,
-                })
+                let finished = open.close(reading.sensor_id, reading.kind, bucket_secs);
+                *open = OpenBucket::new(index, reading.value);
+                finished
             }
             None => {
-                self.open.insert(
-                    key,
-                    OpenBucket {
-                        index,
-                        sum: f64::from(reading.value),
-                        count: 1,
-                    },
-                );
+                self.open.insert(key, OpenBucket::new(index, reading.value));
                 None
             }
         }
@@ -98,23 +122,16 @@ impl ReadingAggregator {
     ///
     /// Without this, the last bucket of a sensor that stops transmitting would sit
     /// unwritten indefinitely.
-    pub fn drain_before(&mut self, now: DateTime<Utc>) -> Vec<Reading> {
+    pub fn drain_before(&mut self, now: DateTime<Utc>) -> Vec<Averaged> {
         let current = self.bucket_index(now);
         let bucket_secs = self.bucket_secs;
         let mut done = Vec::new();
 
-        self.open.retain(|(sensor_id, kind), open| {
+        self.open.retain(|&(sensor_id, kind), open| {
             if open.index >= current {
                 return true;
             }
-            if let Some((at, value)) = open.average(bucket_secs) {
-                done.push(Reading {
-                    sensor_id: *sensor_id,
-                    kind: *kind,
-                    value,
-                    at,
-                });
-            }
+            done.extend(open.close(sensor_id, kind, bucket_secs));
             false
         });
 
@@ -146,8 +163,9 @@ mod tests {
 
         // A reading in the next minute closes the first.
         let emitted = agg.push(&reading(99.0, 60)).expect("bucket should close");
-        assert!((emitted.value - 20.0).abs() < 0.001, "got {}", emitted.value);
-        assert_eq!(emitted.at.timestamp(), 0, "timestamp is the bucket start");
+        assert!((emitted.reading.value - 20.0).abs() < 0.001, "got {}", emitted.reading.value);
+        assert_eq!(emitted.reading.at.timestamp(), 0, "timestamp is the bucket start");
+        assert_eq!((emitted.min, emitted.max, emitted.count), (10.0, 30.0, 3));
     }
 
     #[test]
@@ -161,7 +179,7 @@ mod tests {
 
         let drained = agg.drain_before(DateTime::from_timestamp(120, 0).unwrap());
         assert_eq!(drained.len(), 1);
-        assert!((drained[0].value - 10.0).abs() < 0.001);
+        assert!((drained[0].reading.value - 10.0).abs() < 0.001);
 
         // Draining twice must not duplicate the row.
         assert!(agg.drain_before(DateTime::from_timestamp(180, 0).unwrap()).is_empty());
@@ -175,10 +193,10 @@ mod tests {
         agg.push(&Reading { sensor_id: 1, kind: ReadingKind::Humidity, value: 50.0, at: DateTime::from_timestamp(0, 0).unwrap() });
 
         let mut drained = agg.drain_before(DateTime::from_timestamp(120, 0).unwrap());
-        drained.sort_by_key(|r| (r.sensor_id, r.kind.as_str()));
+        drained.sort_by_key(|r| (r.reading.sensor_id, r.reading.kind.as_str()));
         assert_eq!(drained.len(), 3);
-        assert!((drained[0].value - 50.0).abs() < 0.001, "sensor 1 humidity");
-        assert!((drained[1].value - 10.0).abs() < 0.001, "sensor 1 temperature");
-        assert!((drained[2].value - 30.0).abs() < 0.001, "sensor 2 temperature");
+        assert!((drained[0].reading.value - 50.0).abs() < 0.001, "sensor 1 humidity");
+        assert!((drained[1].reading.value - 10.0).abs() < 0.001, "sensor 1 temperature");
+        assert!((drained[2].reading.value - 30.0).abs() < 0.001, "sensor 2 temperature");
     }
 }
@@ -0,0 +1,57 @@
+//! Counters kept by the listener thread, for export by whoever embeds the client.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Running totals since the client started. Updated from the listener thread with relaxed
+/// atomics; readers see a recent, not necessarily consistent, snapshot.
+#[derive(Debug, Default)]
+pub struct ListenerStats {
+    packets_decoded: AtomicU64,
+    decode_failures: AtomicU64,
+    packets_skipped: AtomicU64,
+    packets_rejected: AtomicU64,
+}
+
+impl ListenerStats {
+    pub(crate) fn record_decoded(&self) {
+        self.packets_decoded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_decode_failure(&self) {
+        self.decode_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_skipped(&self) {
+        self.packets_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rejected(&self) {
+        self.packets_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Datagrams that decoded into a `Packet`.
+    #[must_use]
+    pub fn packets_decoded(&self) -> u64 {
+        self.packets_decoded.load(Ordering::Relaxed)
+    }
+
+    /// Datagrams that arrived on the group but failed to decode.
+    #[must_use]
+    pub fn decode_failures(&self) -> u64 {
+        self.decode_failures.load(Ordering::Relaxed)
+    }
+
+    /// Well-formed packets skipped because they carry a command or metric from a newer
+    /// protocol version.
+    #[must_use]
+    pub fn packets_skipped(&self) -> u64 {
+        self.packets_skipped.load(Ordering::Relaxed)
+    }
+
+    /// Packets dropped by authentication: bad MAC, replayed counter, or no MAC when one is
+    /// required.
+    #[must_use]
+    pub fn packets_rejected(&self) -> u64 {
+        self.packets_rejected.load(Ordering::Relaxed)
+    }
+}
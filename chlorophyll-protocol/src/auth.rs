@@ -0,0 +1,206 @@
+//! Optional shared-key authentication of frames.
+//!
+//! An authenticated frame sets [`FLAG_AUTHENTICATED`] and appends a trailer after the body:
+//!
+//! ```text
+//! counter  8 bytes, u64 little-endian, strictly increasing per sender
+//! ...      fields appended by later versions
+//! mac      16 bytes, HMAC-SHA256 over everything before it, truncated
+//! ```
+//!
+//! The MAC covers the header (including the flag), body and counter, so none of them can be
+//! altered. Receivers reject counters they have already seen from a sender with
+//! [`ReplayGuard`]. Decoders that predate authentication ignore the trailer and still read
+//! the packet.
+//!
+//! Counters must survive restarts: servers follow the clock, sensors count from a boot epoch
+//! kept in flash. Sensors keep the last server counter they accepted in flash too, so their
+//! guard can resume from it.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::Packet;
+use crate::wire::{Header, WireError};
+
+/// Header flag marking a frame that carries a counter and MAC.
+pub const FLAG_AUTHENTICATED: u8 = 0b0000_0001;
+pub const COUNTER_LEN: usize = 8;
+pub const MAC_LEN: usize = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AuthError {
+    /// The frame carries no MAC.
+    Unauthenticated,
+    /// The MAC doesn't match: wrong key, or the frame was altered.
+    BadMac,
+    /// The counter is not newer than the last one accepted from this sender.
+    Replayed,
+    Wire(WireError),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Unauthenticated => f.write_str("packet is not authenticated"),
+            AuthError::BadMac => f.write_str("packet MAC does not match"),
+            AuthError::Replayed => f.write_str("packet counter was already used"),
+            AuthError::Wire(e) => e.fmt(f),
+        }
+    }
+}
+
+impl core::error::Error for AuthError {}
+
+impl From<WireError> for AuthError {
+    fn from(e: WireError) -> Self {
+        AuthError::Wire(e)
+    }
+}
+
+fn mac(key: &[u8]) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+impl Packet {
+    /// Encode as an authenticated frame under `key` with the sender's next `counter`.
+    ///
+    /// # Errors
+    ///
+    /// As [`Packet::encode`].
+    pub fn encode_authenticated(&self, key: &[u8], counter: u64) -> Result<Vec<u8>, WireError> {
+        let mut frame = self.encode_with_flags(FLAG_AUTHENTICATED)?;
+        frame.extend(counter.to_le_bytes());
+        let mut mac = mac(key);
+        mac.update(&frame);
+        frame.extend(&mac.finalize().into_bytes()[..MAC_LEN]);
+        Ok(frame)
+    }
+}
+
+/// Check the MAC of a frame and return its counter.
+///
+/// The counter still has to go through a [`ReplayGuard`] before the packet is trusted.
+///
+/// # Errors
+///
+/// [`AuthError::Unauthenticated`] for a frame without a MAC (including pre-versioning
+/// frames), [`AuthError::BadMac`] if the MAC is wrong or the trailer is too short.
+pub fn verify(bytes: &[u8], key: &[u8]) -> Result<u64, AuthError> {
+    if bytes.first() != Some(&crate::wire::MAGIC) {
+        return Err(AuthError::Unauthenticated);
+    }
+    let (header, _body, trailer) = Header::parse(bytes)?;
+    if header.flags & FLAG_AUTHENTICATED == 0 {
+        return Err(AuthError::Unauthenticated);
+    }
+    if trailer.len() < COUNTER_LEN + MAC_LEN {
+        return Err(AuthError::BadMac);
+    }
+
+    let (signed, tag) = bytes.split_at(bytes.len() - MAC_LEN);
+    let mut mac = mac(key);
+    mac.update(signed);
+    mac.verify_truncated_left(tag).map_err(|_| AuthError::BadMac)?;
+
+    let mut counter = [0u8; COUNTER_LEN];
+    counter.copy_from_slice(&trailer[..COUNTER_LEN]);
+    Ok(u64::from_le_bytes(counter))
+}
+
+/// Last counter accepted per sender, for rejecting replayed frames.
+#[derive(Debug, Default, Clone)]
+pub struct ReplayGuard {
+    last: BTreeMap<u128, u64>,
+}
+
+impl ReplayGuard {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A guard that has already accepted `counter` from `sender`, e.g. the last one
+    /// persisted before a restart.
+    #[must_use]
+    pub fn resuming(sender: u128, counter: u64) -> Self {
+        Self { last: BTreeMap::from([(sender, counter)]) }
+    }
+
+    /// Accept `counter` from `sender` if it is newer than any accepted before.
+    ///
+    /// # Errors
+    ///
+    /// [`AuthError::Replayed`] if it is not.
+    pub fn check(&mut self, sender: u128, counter: u64) -> Result<(), AuthError> {
+        match self.last.get(&sender) {
+            Some(&last) if counter <= last => Err(AuthError::Replayed),
+            _ => {
+                self.last.insert(sender, counter);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temperature::Celsius;
+    use crate::{DataType, PacketCommand};
+
+    const KEY: &[u8] = b"greenhouse-key";
+
+    fn reading() -> Packet {
+        Packet::new(PacketCommand::DataReading(DataType::Temperature(Celsius::new(21.5))), 7)
+    }
+
+    #[test]
+    fn authenticated_frames_verify_and_still_decode() {
+        let bytes = reading().encode_authenticated(KEY, 42).unwrap();
+        assert_eq!(verify(&bytes, KEY), Ok(42));
+        // Receivers without a key read the packet and ignore the trailer.
+        assert_eq!(Packet::decode(&bytes), Ok(Some(reading())));
+    }
+
+    #[test]
+    fn wrong_keys_and_tampering_are_rejected() {
+        let bytes = reading().encode_authenticated(KEY, 42).unwrap();
+        assert_eq!(verify(&bytes, b"other-key"), Err(AuthError::BadMac));
+
+        for i in 0..bytes.len() {
+            let mut tampered = bytes.clone();
+            tampered[i] ^= 0x10;
+            assert!(verify(&tampered, KEY).is_err(), "flipping byte {i} went unnoticed");
+        }
+    }
+
+    #[test]
+    fn unauthenticated_frames_are_reported_as_such() {
+        let bytes = reading().encode().unwrap();
+        assert_eq!(verify(&bytes, KEY), Err(AuthError::Unauthenticated));
+
+        // Setting the flag without a trailer doesn't make a frame authentic.
+        let mut flagged = bytes.clone();
+        flagged[2] |= FLAG_AUTHENTICATED;
+        assert_eq!(verify(&flagged, KEY), Err(AuthError::BadMac));
+    }
+
+    #[test]
+    fn replayed_and_stale_counters_are_rejected_per_sender() {
+        let mut guard = ReplayGuard::new();
+        assert_eq!(guard.check(7, 10), Ok(()));
+        assert_eq!(guard.check(7, 10), Err(AuthError::Replayed));
+        assert_eq!(guard.check(7, 9), Err(AuthError::Replayed));
+        assert_eq!(guard.check(8, 1), Ok(()), "senders are tracked separately");
+        assert_eq!(guard.check(7, 11), Ok(()));
+
+        let mut resumed = ReplayGuard::resuming(0, 10);
+        assert_eq!(resumed.check(0, 10), Err(AuthError::Replayed), "captured before the restart");
+        assert_eq!(resumed.check(0, 11), Ok(()));
+    }
+}
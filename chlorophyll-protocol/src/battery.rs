@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// Battery voltage.
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub struct Volts {
+    volts: f32,
+}
+
+impl Volts {
+    #[must_use] 
+    pub fn new(volts: f32) -> Self {
+        Self { volts }
+    }
+
+    /// Return the voltage in volts
+    #[must_use] 
+    pub fn volts(&self) -> f32 {
+        self.volts
+    }
+}
+
+impl Default for Volts {
+    fn default() -> Self { Self { volts: 0.0 } }
+}
+
+impl core::ops::Add for Volts {
+    type Output = Volts;
+    fn add(self, rhs: Volts) -> Volts { Volts { volts: self.volts + rhs.volts } }
+}
+
+impl core::ops::Div<usize> for Volts {
+    type Output = Volts;
+    #[allow(clippy::cast_precision_loss)]
+    fn div(self, rhs: usize) -> Volts { Volts { volts: self.volts / rhs as f32 } }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// CO2 concentration in parts per million.
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub struct Ppm {
+    ppm: f32,
+}
+
+impl Ppm {
+    #[must_use] 
+    pub fn new(ppm: f32) -> Self {
+        Self { ppm }
+    }
+
+    /// Return the concentration in parts per million
+    #[must_use] 
+    pub fn ppm(&self) -> f32 {
+        self.ppm
+    }
+}
+
+impl Default for Ppm {
+    fn default() -> Self { Self { ppm: 0.0 } }
+}
+
+impl core::ops::Add for Ppm {
+    type Output = Ppm;
+    fn add(self, rhs: Ppm) -> Ppm { Ppm { ppm: self.ppm + rhs.ppm } }
+}
+
+impl core::ops::Div<usize> for Ppm {
+    type Output = Ppm;
+    #[allow(clippy::cast_precision_loss)]
+    fn div(self, rhs: usize) -> Ppm { Ppm { ppm: self.ppm / rhs as f32 } }
+}
@@ -31,6 +31,7 @@ impl core::ops::Add for RelativeHumidity {
 
 impl core::ops::Div<usize> for RelativeHumidity {
     type Output = RelativeHumidity;
+    #[allow(clippy::cast_precision_loss)]
     fn div(self, rhs: usize) -> RelativeHumidity { RelativeHumidity { percent: self.percent / rhs as f32 } }
 }
 
@@ -2,11 +2,24 @@
 #![warn(clippy::pedantic)]
 extern crate alloc;
 use alloc::string::String;
+use alloc::vec::Vec;
 
+pub mod auth;
 pub mod temperature;
 pub mod humidity;
 pub mod light;
+pub mod co2;
+pub mod pressure;
+pub mod soil_moisture;
+pub mod voc;
+pub mod battery;
+pub mod wire;
 
+use crate::battery::Volts;
+use crate::co2::Ppm;
+use crate::pressure::Hectopascals;
+use crate::soil_moisture::SoilMoisture;
+use crate::voc::VocIndex;
 use crate::{humidity::RelativeHumidity, light::Lux, temperature::Celsius};
 pub use postcard;
 use serde::{Deserialize, Serialize};
@@ -16,23 +29,143 @@ pub enum DataType {
     Temperature(Celsius),
     RelativeHumidity(RelativeHumidity),
     Light(Lux),
+    Co2(Ppm),
+    Pressure(Hectopascals),
+    SoilMoisture(SoilMoisture),
+    Voc(VocIndex),
+    BatteryVoltage(Volts),
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+impl DataType {
+    #[must_use]
+    pub fn metric(&self) -> Metric {
+        match self {
+            DataType::Temperature(_) => Metric::Temperature,
+            DataType::RelativeHumidity(_) => Metric::RelativeHumidity,
+            DataType::Light(_) => Metric::Light,
+            DataType::Co2(_) => Metric::Co2,
+            DataType::Pressure(_) => Metric::Pressure,
+            DataType::SoilMoisture(_) => Metric::SoilMoisture,
+            DataType::Voc(_) => Metric::Voc,
+            DataType::BatteryVoltage(_) => Metric::BatteryVoltage,
+        }
+    }
+}
+
+/// The kinds of [`DataType`], by their wire tag.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[repr(u8)]
+pub enum Metric {
+    Temperature = 0,
+    RelativeHumidity = 1,
+    Light = 2,
+    Co2 = 3,
+    Pressure = 4,
+    SoilMoisture = 5,
+    Voc = 6,
+    BatteryVoltage = 7,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 8] = [
+        Metric::Temperature,
+        Metric::RelativeHumidity,
+        Metric::Light,
+        Metric::Co2,
+        Metric::Pressure,
+        Metric::SoilMoisture,
+        Metric::Voc,
+        Metric::BatteryVoltage,
+    ];
+
+    #[must_use]
+    pub fn tag(self) -> u8 {
+        self as u8
+    }
+
+    /// `None` for tags added by a newer protocol version.
+    #[must_use]
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.tag() == tag)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum PacketCommand {
     DataReading(DataType),
     /// Server → multicast: request info from all online sensors.
     RequestSensorInfo,
     /// Sensor → server: sensor info, including NVM name if configured.
     /// Sent unicast in response to `RequestSensorInfo`, and to multicast on boot / after `SetName`.
-    SensorsInfo(Option<String>),
+    SensorsInfo(SensorInfo),
     /// Server → multicast: instruct the sensor matching `packet.id` to set its name.
     SetName(String),
 }
 
-type SensorID = u128;
-/// Chlorophyll packet that can hold a variety of commands
+impl PacketCommand {
+    /// Whether sensors send this command, as opposed to servers. Replay counters are kept
+    /// per direction, since a server command's id names its target, not its sender.
+    #[must_use]
+    pub fn from_sensor(&self) -> bool {
+        matches!(self, PacketCommand::DataReading(_) | PacketCommand::SensorsInfo(_))
+    }
+}
+
+/// Payload of [`PacketCommand::SensorsInfo`].
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SensorInfo {
+    pub name: Option<String>,
+    /// `None` from firmware that predates the extension.
+    pub capabilities: Option<Capabilities>,
+}
+
+impl SensorInfo {
+    #[must_use]
+    pub fn new(name: Option<String>) -> Self {
+        Self { name, capabilities: None }
+    }
+
+    #[must_use]
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+}
+
+/// What a sensor's firmware reports about itself in `SensorsInfo`.
+///
+/// Newer versions may append fields; older decoders ignore them.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Capabilities {
+    pub firmware_version: String,
+    /// Wire tags of the metrics this sensor reports; see [`Metric::tag`].
+    pub metrics: Vec<u8>,
+    /// How often the sensor emits a reading of each metric.
+    pub sample_interval_ms: u32,
+}
+
+impl Capabilities {
+    #[must_use]
+    pub fn new(firmware_version: &str, metrics: &[Metric], sample_interval_ms: u32) -> Self {
+        Self {
+            firmware_version: firmware_version.into(),
+            metrics: metrics.iter().map(|m| m.tag()).collect(),
+            sample_interval_ms,
+        }
+    }
+
+    /// Advertised metrics this build knows about; unknown tags are skipped.
+    pub fn metrics(&self) -> impl Iterator<Item = Metric> + '_ {
+        self.metrics.iter().filter_map(|&tag| Metric::from_tag(tag))
+    }
+}
+
+type SensorID = u128;
+/// Chlorophyll packet that can hold a variety of commands.
+///
+/// Sent as a versioned frame; see [`wire`] for the layout, [`Packet::encode`] and
+/// [`Packet::decode`].
+#[derive(Debug, PartialEq, Clone)]
 pub struct Packet {
     command: PacketCommand,
     /// Unique ID to identify the sensor
@@ -29,7 +29,7 @@ impl FootCandle {
     }
 }
 
-const LUX_TO_FC: f32 = 0.09290304;
+const LUX_TO_FC: f32 = 0.092_903_04;
 const FC_TO_LUX: f32 = 10.7639;
 
 impl Light for Lux {
@@ -63,6 +63,7 @@ impl core::ops::Add for Lux {
 
 impl core::ops::Div<usize> for Lux {
     type Output = Lux;
+    #[allow(clippy::cast_precision_loss)]
     fn div(self, rhs: usize) -> Lux { Lux { value: self.value / rhs as f32 } }
 }
 
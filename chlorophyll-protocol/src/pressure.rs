@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// Barometric pressure in hectopascals (millibars).
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub struct Hectopascals {
+    hpa: f32,
+}
+
+impl Hectopascals {
+    #[must_use] 
+    pub fn new(hpa: f32) -> Self {
+        Self { hpa }
+    }
+
+    /// Return the pressure in hectopascals
+    #[must_use] 
+    pub fn hpa(&self) -> f32 {
+        self.hpa
+    }
+}
+
+impl Default for Hectopascals {
+    fn default() -> Self { Self { hpa: 0.0 } }
+}
+
+impl core::ops::Add for Hectopascals {
+    type Output = Hectopascals;
+    fn add(self, rhs: Hectopascals) -> Hectopascals { Hectopascals { hpa: self.hpa + rhs.hpa } }
+}
+
+impl core::ops::Div<usize> for Hectopascals {
+    type Output = Hectopascals;
+    #[allow(clippy::cast_precision_loss)]
+    fn div(self, rhs: usize) -> Hectopascals { Hectopascals { hpa: self.hpa / rhs as f32 } }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// Volumetric soil water content, as a percentage of soil volume.
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub struct SoilMoisture {
+    percent: f32,
+}
+
+impl SoilMoisture {
+    #[must_use] 
+    pub fn new(percent: f32) -> Self {
+        Self { percent }
+    }
+
+    /// Return the volumetric water content as a percent
+    #[must_use] 
+    pub fn percent(&self) -> f32 {
+        self.percent
+    }
+}
+
+impl Default for SoilMoisture {
+    fn default() -> Self { Self { percent: 0.0 } }
+}
+
+impl core::ops::Add for SoilMoisture {
+    type Output = SoilMoisture;
+    fn add(self, rhs: SoilMoisture) -> SoilMoisture { SoilMoisture { percent: self.percent + rhs.percent } }
+}
+
+impl core::ops::Div<usize> for SoilMoisture {
+    type Output = SoilMoisture;
+    #[allow(clippy::cast_precision_loss)]
+    fn div(self, rhs: usize) -> SoilMoisture { SoilMoisture { percent: self.percent / rhs as f32 } }
+}
@@ -84,6 +84,7 @@ impl core::ops::Add for Celsius {
 
 impl core::ops::Div<usize> for Celsius {
     type Output = Celsius;
+    #[allow(clippy::cast_precision_loss)]
     fn div(self, rhs: usize) -> Celsius { Celsius { value: self.value / rhs as f32 } }
 }
 
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+/// VOC index as reported by Sensirion-style gas sensors: 100 is the learned baseline,
+/// higher is worse air, range 1..=500.
+#[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Clone, Copy)]
+pub struct VocIndex {
+    index: f32,
+}
+
+impl VocIndex {
+    #[must_use] 
+    pub fn new(index: f32) -> Self {
+        Self { index }
+    }
+
+    /// Return the unitless index
+    #[must_use] 
+    pub fn index(&self) -> f32 {
+        self.index
+    }
+}
+
+impl Default for VocIndex {
+    fn default() -> Self { Self { index: 0.0 } }
+}
+
+impl core::ops::Add for VocIndex {
+    type Output = VocIndex;
+    fn add(self, rhs: VocIndex) -> VocIndex { VocIndex { index: self.index + rhs.index } }
+}
+
+impl core::ops::Div<usize> for VocIndex {
+    type Output = VocIndex;
+    #[allow(clippy::cast_precision_loss)]
+    fn div(self, rhs: usize) -> VocIndex { VocIndex { index: self.index / rhs as f32 } }
+}
@@ -0,0 +1,408 @@
+//! Versioned wire format for [`Packet`].
+//!
+//! Every frame starts with a fixed header, and every command and data type carries its own
+//! tag and length, so a decoder can skip anything added by a newer protocol version instead
+//! of failing on it:
+//!
+//! ```text
+//! offset  size  field
+//! 0       1     MAGIC (0xC7)
+//! 1       1     protocol version
+//! 2       1     flags; see [`crate::auth::FLAG_AUTHENTICATED`], other bits reserved
+//! 3       16    sensor id, u128 little-endian
+//! 19      1     command tag
+//! 20      2     body length, u16 little-endian
+//! 22      n     body
+//! ```
+//!
+//! Bodies, by command:
+//!
+//! - `DataReading`: metric tag (u8), then the postcard-encoded value
+//! - `RequestSensorInfo`: empty
+//! - `SensorsInfo`: postcard `Option<String>` name, then optionally postcard [`Capabilities`]
+//! - `SetName`: postcard `String`
+//!
+//! Bytes after a body, or after the known fields of a body, are ignored. That is where
+//! later versions append fields, and where [`crate::auth`] puts its counter and MAC.
+//!
+//! Frames from before versioning (a bare postcard `Packet`) are still decoded: their first
+//! byte is a small enum tag, never [`MAGIC`]. [`Packet::encode_legacy`] still writes them,
+//! for sensors whose firmware predates versioning.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use postcard::{take_from_bytes, to_allocvec};
+use serde::{Deserialize, Serialize};
+
+use crate::battery::Volts;
+use crate::co2::Ppm;
+use crate::humidity::RelativeHumidity;
+use crate::light::Lux;
+use crate::pressure::Hectopascals;
+use crate::soil_moisture::SoilMoisture;
+use crate::temperature::Celsius;
+use crate::voc::VocIndex;
+use crate::{Capabilities, DataType, Metric, Packet, PacketCommand, SensorInfo};
+
+/// First byte of every versioned frame.
+pub const MAGIC: u8 = 0xC7;
+/// Version written by this build. Decoders accept any version, relying on the framing to
+/// skip what they don't understand.
+pub const PROTOCOL_VERSION: u8 = 1;
+/// Length of the fixed header that precedes the body.
+pub const HEADER_LEN: usize = 22;
+
+const TAG_DATA_READING: u8 = 0;
+const TAG_REQUEST_SENSOR_INFO: u8 = 1;
+const TAG_SENSORS_INFO: u8 = 2;
+const TAG_SET_NAME: u8 = 3;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WireError {
+    /// Shorter than its header or declared body length.
+    Truncated,
+    /// A known command or data type whose body doesn't parse, or a pre-versioning frame
+    /// that doesn't parse.
+    Malformed,
+    /// Body longer than the 16-bit length field can describe (encoding only).
+    TooLong,
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::Truncated => f.write_str("truncated packet"),
+            WireError::Malformed => f.write_str("malformed packet"),
+            WireError::TooLong => f.write_str("packet body too long"),
+        }
+    }
+}
+
+impl core::error::Error for WireError {}
+
+impl From<postcard::Error> for WireError {
+    fn from(_: postcard::Error) -> Self {
+        WireError::Malformed
+    }
+}
+
+/// A decoded frame's header.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Header {
+    pub version: u8,
+    pub flags: u8,
+    pub id: u128,
+    pub command: u8,
+}
+
+impl Header {
+    /// Parse the header and return it with the body and anything after the body.
+    ///
+    /// # Errors
+    ///
+    /// [`WireError::Truncated`] if `bytes` is shorter than the header plus declared body,
+    /// [`WireError::Malformed`] if it doesn't start with [`MAGIC`].
+    pub fn parse(bytes: &[u8]) -> Result<(Self, &[u8], &[u8]), WireError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(WireError::Truncated);
+        }
+        if bytes[0] != MAGIC {
+            return Err(WireError::Malformed);
+        }
+        let mut id = [0u8; 16];
+        id.copy_from_slice(&bytes[3..19]);
+        let header = Self {
+            version: bytes[1],
+            flags: bytes[2],
+            id: u128::from_le_bytes(id),
+            command: bytes[19],
+        };
+        let len = usize::from(u16::from_le_bytes([bytes[20], bytes[21]]));
+        let rest = &bytes[HEADER_LEN..];
+        if rest.len() < len {
+            return Err(WireError::Truncated);
+        }
+        let (body, trailer) = rest.split_at(len);
+        Ok((header, body, trailer))
+    }
+}
+
+fn encode_body(command: &PacketCommand) -> Result<(u8, Vec<u8>), postcard::Error> {
+    Ok(match command {
+        PacketCommand::DataReading(data) => {
+            let mut body = alloc::vec![data.metric().tag()];
+            body.extend(match data {
+                DataType::Temperature(v) => to_allocvec(v)?,
+                DataType::RelativeHumidity(v) => to_allocvec(v)?,
+                DataType::Light(v) => to_allocvec(v)?,
+                DataType::Co2(v) => to_allocvec(v)?,
+                DataType::Pressure(v) => to_allocvec(v)?,
+                DataType::SoilMoisture(v) => to_allocvec(v)?,
+                DataType::Voc(v) => to_allocvec(v)?,
+                DataType::BatteryVoltage(v) => to_allocvec(v)?,
+            });
+            (TAG_DATA_READING, body)
+        }
+        PacketCommand::RequestSensorInfo => (TAG_REQUEST_SENSOR_INFO, Vec::new()),
+        PacketCommand::SensorsInfo(info) => {
+            let mut body = to_allocvec(&info.name)?;
+            if let Some(capabilities) = &info.capabilities {
+                body.extend(to_allocvec(capabilities)?);
+            }
+            (TAG_SENSORS_INFO, body)
+        }
+        PacketCommand::SetName(name) => (TAG_SET_NAME, to_allocvec(name)?),
+    })
+}
+
+/// `None` for a metric tag from a newer version.
+fn decode_data(body: &[u8]) -> Result<Option<DataType>, WireError> {
+    let (&tag, value) = body.split_first().ok_or(WireError::Malformed)?;
+    let Some(metric) = Metric::from_tag(tag) else {
+        return Ok(None);
+    };
+    Ok(Some(match metric {
+        Metric::Temperature => DataType::Temperature(take_from_bytes::<Celsius>(value)?.0),
+        Metric::RelativeHumidity => {
+            DataType::RelativeHumidity(take_from_bytes::<RelativeHumidity>(value)?.0)
+        }
+        Metric::Light => DataType::Light(take_from_bytes::<Lux>(value)?.0),
+        Metric::Co2 => DataType::Co2(take_from_bytes::<Ppm>(value)?.0),
+        Metric::Pressure => DataType::Pressure(take_from_bytes::<Hectopascals>(value)?.0),
+        Metric::SoilMoisture => DataType::SoilMoisture(take_from_bytes::<SoilMoisture>(value)?.0),
+        Metric::Voc => DataType::Voc(take_from_bytes::<VocIndex>(value)?.0),
+        Metric::BatteryVoltage => DataType::BatteryVoltage(take_from_bytes::<Volts>(value)?.0),
+    }))
+}
+
+fn decode_sensor_info(body: &[u8]) -> Result<SensorInfo, WireError> {
+    let (name, rest) = take_from_bytes::<Option<String>>(body)?;
+    let capabilities = if rest.is_empty() {
+        None
+    } else {
+        Some(take_from_bytes::<Capabilities>(rest)?.0)
+    };
+    Ok(SensorInfo { name, capabilities })
+}
+
+impl Packet {
+    /// Encode as a versioned frame.
+    ///
+    /// # Errors
+    ///
+    /// [`WireError::TooLong`] if the body exceeds 65535 bytes.
+    pub fn encode(&self) -> Result<Vec<u8>, WireError> {
+        self.encode_with_flags(0)
+    }
+
+    pub(crate) fn encode_with_flags(&self, flags: u8) -> Result<Vec<u8>, WireError> {
+        let (tag, body) = encode_body(&self.command)?;
+        let len = u16::try_from(body.len()).map_err(|_| WireError::TooLong)?;
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + body.len());
+        frame.extend([MAGIC, PROTOCOL_VERSION, flags]);
+        frame.extend(self.id.to_le_bytes());
+        frame.push(tag);
+        frame.extend(len.to_le_bytes());
+        frame.extend(body);
+        Ok(frame)
+    }
+
+    /// Encode in the pre-versioning layout, which firmware from before versioning reads and
+    /// current decoders still accept. It has no room for flags or a trailer, so can't be
+    /// authenticated, and new metrics can't be read by the decoders it is meant for.
+    ///
+    /// # Errors
+    ///
+    /// [`WireError::Malformed`] if postcard fails to encode it.
+    pub fn encode_legacy(&self) -> Result<Vec<u8>, WireError> {
+        let command = match &self.command {
+            PacketCommand::DataReading(data) => LegacyCommand::DataReading(data.clone()),
+            PacketCommand::RequestSensorInfo => LegacyCommand::RequestSensorInfo,
+            PacketCommand::SensorsInfo(info) => LegacyCommand::SensorsInfo(info.name.clone()),
+            PacketCommand::SetName(name) => LegacyCommand::SetName(name.clone()),
+        };
+        Ok(to_allocvec(&LegacyPacket { command, id: self.id })?)
+    }
+
+    /// Decode a versioned or pre-versioning frame.
+    ///
+    /// `Ok(None)` means the frame is well formed but carries a command or metric this build
+    /// doesn't know, and should be skipped.
+    ///
+    /// # Errors
+    ///
+    /// [`WireError::Truncated`] or [`WireError::Malformed`] if the frame is damaged, or a
+    /// known command's body doesn't parse.
+    pub fn decode(bytes: &[u8]) -> Result<Option<Self>, WireError> {
+        if bytes.first() != Some(&MAGIC) {
+            let legacy: LegacyPacket = postcard::from_bytes(bytes)?;
+            return Ok(Some(legacy.into()));
+        }
+
+        let (header, body, _trailer) = Header::parse(bytes)?;
+        let command = match header.command {
+            TAG_DATA_READING => match decode_data(body)? {
+                Some(data) => PacketCommand::DataReading(data),
+                None => return Ok(None),
+            },
+            TAG_REQUEST_SENSOR_INFO => PacketCommand::RequestSensorInfo,
+            TAG_SENSORS_INFO => PacketCommand::SensorsInfo(decode_sensor_info(body)?),
+            TAG_SET_NAME => PacketCommand::SetName(take_from_bytes::<String>(body)?.0),
+            _ => return Ok(None),
+        };
+        Ok(Some(Packet::new(command, header.id)))
+    }
+}
+
+/// The pre-versioning encoding: postcard of `{ command, id }`.
+#[derive(Serialize, Deserialize)]
+enum LegacyCommand {
+    DataReading(DataType),
+    RequestSensorInfo,
+    SensorsInfo(Option<String>),
+    SetName(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct LegacyPacket {
+    command: LegacyCommand,
+    id: u128,
+}
+
+impl From<LegacyPacket> for Packet {
+    fn from(legacy: LegacyPacket) -> Self {
+        let command = match legacy.command {
+            LegacyCommand::DataReading(data) => PacketCommand::DataReading(data),
+            LegacyCommand::RequestSensorInfo => PacketCommand::RequestSensorInfo,
+            LegacyCommand::SensorsInfo(name) => PacketCommand::SensorsInfo(SensorInfo::new(name)),
+            LegacyCommand::SetName(name) => PacketCommand::SetName(name),
+        };
+        Packet::new(command, legacy.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn frame(version: u8, id: u128, tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut frame = vec![MAGIC, version, 0];
+        frame.extend(id.to_le_bytes());
+        frame.push(tag);
+        frame.extend(u16::try_from(body.len()).unwrap().to_le_bytes());
+        frame.extend(body);
+        frame
+    }
+
+    #[test]
+    fn every_command_roundtrips() {
+        let capabilities = Capabilities::new("1.2.0", &Metric::ALL, 5_000);
+        let packets = [
+            Packet::new(PacketCommand::DataReading(DataType::Temperature(Celsius::new(21.5))), 42),
+            Packet::new(PacketCommand::DataReading(DataType::RelativeHumidity(RelativeHumidity::new(55.0))), 42),
+            Packet::new(PacketCommand::DataReading(DataType::Light(Lux::new(123.0))), 42),
+            Packet::new(PacketCommand::DataReading(DataType::Co2(Ppm::new(812.0))), 42),
+            Packet::new(PacketCommand::DataReading(DataType::Pressure(Hectopascals::new(1013.2))), 42),
+            Packet::new(PacketCommand::DataReading(DataType::SoilMoisture(SoilMoisture::new(31.5))), 42),
+            Packet::new(PacketCommand::DataReading(DataType::Voc(VocIndex::new(104.0))), 42),
+            Packet::new(PacketCommand::DataReading(DataType::BatteryVoltage(Volts::new(3.71))), 42),
+            Packet::new(PacketCommand::RequestSensorInfo, 0),
+            Packet::new(PacketCommand::SensorsInfo(SensorInfo::new(None)), 7),
+            Packet::new(
+                PacketCommand::SensorsInfo(SensorInfo::new(Some("bench".into())).with_capabilities(capabilities)),
+                u128::MAX,
+            ),
+            Packet::new(PacketCommand::SetName("greenhouse".into()), 7),
+        ];
+        for packet in packets {
+            let bytes = packet.encode().unwrap();
+            assert_eq!(&bytes[..2], &[MAGIC, PROTOCOL_VERSION]);
+            assert_eq!(Packet::decode(&bytes), Ok(Some(packet)));
+        }
+    }
+
+    #[test]
+    fn pre_versioning_frames_still_decode() {
+        let legacy = LegacyPacket {
+            command: LegacyCommand::SensorsInfo(Some("greenhouse".into())),
+            id: 7,
+        };
+        let bytes = postcard::to_allocvec(&legacy).unwrap();
+        assert_ne!(bytes[0], MAGIC);
+        assert_eq!(
+            Packet::decode(&bytes),
+            Ok(Some(Packet::new(PacketCommand::SensorsInfo(SensorInfo::new(Some("greenhouse".into()))), 7)))
+        );
+
+        let legacy = LegacyPacket {
+            command: LegacyCommand::DataReading(DataType::Temperature(Celsius::new(20.0))),
+            id: 7,
+        };
+        let bytes = postcard::to_allocvec(&legacy).unwrap();
+        assert_eq!(
+            Packet::decode(&bytes),
+            Ok(Some(Packet::new(PacketCommand::DataReading(DataType::Temperature(Celsius::new(20.0))), 7)))
+        );
+    }
+
+    #[test]
+    fn pre_versioning_decoders_read_legacy_server_commands() {
+        for packet in [
+            Packet::new(PacketCommand::RequestSensorInfo, 0),
+            Packet::new(PacketCommand::SetName("greenhouse".into()), 7),
+        ] {
+            let bytes = packet.encode_legacy().unwrap();
+            let legacy: LegacyPacket = postcard::from_bytes(&bytes).unwrap();
+            assert_eq!(Packet::from(legacy), packet);
+            assert_eq!(Packet::decode(&bytes), Ok(Some(packet)), "current decoders read them too");
+        }
+    }
+
+    #[test]
+    fn unknown_commands_and_metrics_from_newer_versions_are_skipped() {
+        // A command tag this build doesn't know, with a body it can't interpret.
+        assert_eq!(Packet::decode(&frame(2, 7, 200, &[1, 2, 3, 4])), Ok(None));
+        // A known command carrying a metric tag this build doesn't know.
+        assert_eq!(Packet::decode(&frame(2, 7, TAG_DATA_READING, &[99, 0, 0, 0x80, 0x3f])), Ok(None));
+    }
+
+    #[test]
+    fn fields_appended_by_newer_versions_are_ignored() {
+        let capabilities = Capabilities::new("2.0.0", &[Metric::Temperature], 1_000);
+        let mut body = postcard::to_allocvec(&Some(String::from("bench"))).unwrap();
+        body.extend(postcard::to_allocvec(&capabilities).unwrap());
+        body.extend([0xAA, 0xBB]); // a field this build doesn't know
+        let mut bytes = frame(3, 7, TAG_SENSORS_INFO, &body);
+        bytes.extend([0xCC; 8]); // a trailer after the body
+
+        let expected = SensorInfo::new(Some("bench".into())).with_capabilities(capabilities);
+        assert_eq!(
+            Packet::decode(&bytes),
+            Ok(Some(Packet::new(PacketCommand::SensorsInfo(expected), 7)))
+        );
+    }
+
+    #[test]
+    fn unknown_advertised_metrics_are_skipped() {
+        let capabilities = Capabilities {
+            firmware_version: "2.0.0".into(),
+            metrics: vec![Metric::Light.tag(), 42, Metric::Temperature.tag()],
+            sample_interval_ms: 1_000,
+        };
+        assert_eq!(capabilities.metrics().collect::<Vec<_>>(), vec![Metric::Light, Metric::Temperature]);
+    }
+
+    #[test]
+    fn short_and_garbled_frames_are_errors() {
+        let bytes = Packet::new(PacketCommand::SetName("greenhouse".into()), 7).encode().unwrap();
+        assert_eq!(Packet::decode(&bytes[..HEADER_LEN - 1]), Err(WireError::Truncated));
+        assert_eq!(Packet::decode(&bytes[..bytes.len() - 1]), Err(WireError::Truncated));
+        assert_eq!(Packet::decode(&frame(1, 7, TAG_SET_NAME, &[0xFF])), Err(WireError::Malformed));
+        assert_eq!(Packet::decode(&[0xFF, 0xFF]), Err(WireError::Malformed));
+        assert_eq!(Packet::decode(&[]), Err(WireError::Malformed));
+    }
+}
@@ -31,6 +31,13 @@ pub type SensorName = heapless::String<MAX_NAME_LEN>;
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct DeviceConfig {
     pub name: SensorName,
+    /// Bumped on every boot with authentication enabled; the high half of the packet
+    /// counter, so counters never repeat across restarts. Configs written before this field
+    /// existed read it as zero from the zero-padded tail of the payload.
+    pub boot_epoch: u32,
+    /// The newest server command counter accepted, so commands captured before a restart
+    /// stay rejected after it. Zero in configs written before this field existed.
+    pub last_command_counter: u64,
 }
 
 fn checksum(data: &[u8]) -> u32 {
@@ -10,8 +10,9 @@ extern crate alloc;
 mod temp_humidity_sensor;
 
 use alloc::sync::Arc;
-use chlorophyll_protocol::postcard::to_allocvec;
-use chlorophyll_protocol::{DataType, temperature, humidity, light, PacketBuilder, postcard, Packet, PacketCommand};
+use chlorophyll_protocol::auth::{self, AuthError, ReplayGuard};
+use chlorophyll_protocol::wire::WireError;
+use chlorophyll_protocol::{Capabilities, DataType, Metric, temperature, humidity, light, PacketBuilder, Packet, PacketCommand, SensorInfo};
 use chlorophyll_sensor_lib::config::{self as device_config, DeviceConfig};
 use embassy_rp::flash::{Flash, ERASE_SIZE};
 use chlorophyll_ui::display::{DisplayState, SensorDisplay};
@@ -192,10 +193,68 @@ async fn i2c1_sensor_task(i2c_bus: &'static I2c1Bus, tx: SensorDataSender) {
             tx.send(DataType::Light(lux_value)).await;
         }
 
-        Timer::after(Duration::from_millis(100)).await;
+        Timer::after(Duration::from_millis(SAMPLE_INTERVAL_MS)).await;
     }
 }
 
+/// Delay between sensor reads; advertised in `SensorsInfo`.
+const SAMPLE_INTERVAL_MS: u64 = 100;
+
+/// What this board's AHT20 and TSL2591 measure.
+const METRICS: [Metric; 3] = [Metric::Temperature, Metric::RelativeHumidity, Metric::Light];
+
+/// `SensorsInfo` for this device: the stored name plus firmware capabilities.
+fn sensor_info(cfg: &DeviceConfig) -> SensorInfo {
+    let name = if cfg.name.is_empty() { None } else { Some(cfg.name.as_str().into()) };
+    #[allow(clippy::cast_possible_truncation)]
+    let capabilities = Capabilities::new(env!("CARGO_PKG_VERSION"), &METRICS, SAMPLE_INTERVAL_MS as u32);
+    SensorInfo::new(name).with_capabilities(capabilities)
+}
+
+/// Shared deployment key, baked in at build time. When set, everything we send is signed and
+/// server commands without a valid MAC are ignored.
+const AUTH_KEY: Option<&str> = option_env!("CHLOROPHYLL_AUTH_KEY");
+
+/// Encodes outgoing packets, signed under [`AUTH_KEY`] with `boot_epoch << 32 | seq` as the
+/// counter.
+struct Outbox {
+    boot_epoch: u32,
+    seq: u32,
+}
+
+impl Outbox {
+    fn encode(&mut self, packet: &Packet) -> Result<alloc::vec::Vec<u8>, WireError> {
+        match AUTH_KEY {
+            Some(key) => {
+                self.seq = self.seq.wrapping_add(1);
+                let counter = (u64::from(self.boot_epoch) << 32) | u64::from(self.seq);
+                packet.encode_authenticated(key.as_bytes(), counter)
+            }
+            None => packet.encode(),
+        }
+    }
+}
+
+/// How far, in server clock microseconds, accepted command counters may run ahead of the
+/// one in flash before it is saved again. Servers ask for `SensorsInfo` every 30 s, and
+/// saving each counter would wear the sector out within weeks; a request replayed after a
+/// reboot only draws an extra reply. `SetName` saves the config, counter included, anyway.
+const COUNTER_SAVE_INTERVAL: u64 = 3_600_000_000;
+
+/// Whether a received server command may be acted on, returning its counter (0 without
+/// [`AUTH_KEY`]).
+///
+/// All servers share one replay slot, since their counters follow their clocks: a server
+/// whose clock is behind the last command accepted is locked out until it catches up. The
+/// slot starts from [`DeviceConfig::last_command_counter`], so captures from before a
+/// reboot are still rejected.
+fn accept_command(bytes: &[u8], guard: &mut ReplayGuard) -> Result<u64, AuthError> {
+    let Some(key) = AUTH_KEY else { return Ok(0) };
+    let counter = auth::verify(bytes, key.as_bytes())?;
+    guard.check(0, counter)?;
+    Ok(counter)
+}
+
 fn get_unique_id() -> u128 {
     u128::from(embassy_rp::otp::get_chipid().expect("error fetching chip ID"))
 }
@@ -213,6 +272,13 @@ async fn network_task(stack: Stack<'static>, rx: SensorDataReceiver, _shared_sta
     if !cfg.name.is_empty() {
         info!("Loaded sensor name from NVM: {}", cfg.name.as_str());
     }
+    if AUTH_KEY.is_some() {
+        cfg.boot_epoch = cfg.boot_epoch.wrapping_add(1);
+        device_config::save(&mut flash, SETTINGS_OFFSET, ERASE_SIZE as u32, &cfg);
+    }
+    let mut outbox = Outbox { boot_epoch: cfg.boot_epoch, seq: 0 };
+    let mut replay_guard = ReplayGuard::resuming(0, cfg.last_command_counter);
+    let mut saved_counter = cfg.last_command_counter;
     info!("network_task: waiting for DHCP");
     while !stack.is_config_up() {
         Timer::after_millis(100).await;
@@ -244,8 +310,8 @@ async fn network_task(stack: Stack<'static>, rx: SensorDataReceiver, _shared_sta
 
     // On boot, multicast our name so any already-running server learns it immediately.
     if !cfg.name.is_empty() {
-        let ann = packet_builder.build(PacketCommand::SensorsInfo(Some(cfg.name.as_str().into())));
-        if let Ok(data) = to_allocvec(&ann) {
+        let ann = packet_builder.build(PacketCommand::SensorsInfo(sensor_info(&cfg)));
+        if let Ok(data) = outbox.encode(&ann) {
             if let Err(e) = socket.send_to(&data, multicast_ep).await {
                 warn!("boot announce send error: {:?}", e);
             } else {
@@ -259,40 +325,56 @@ async fn network_task(stack: Stack<'static>, rx: SensorDataReceiver, _shared_sta
             Either::First(recv_result) => match recv_result {
                 Ok((len, meta)) => {
                     let src = meta.endpoint;
-                    if let Ok(packet) = postcard::from_bytes::<Packet>(&recv_buf[..len]) {
-                        if packet.command() == &PacketCommand::RequestSensorInfo {
-                            info!("RequestSensorInfo from {:?}", src);
-                            let name = if cfg.name.is_empty() { None } else { Some(cfg.name.as_str().into()) };
-                            // Unicast back to the requester — they have our address from this packet.
-                            let resp = packet_builder.build(PacketCommand::SensorsInfo(name));
-                            if let Ok(data) = to_allocvec(&resp) {
-                                if let Err(e) = socket.send_to(&data, src).await {
-                                    warn!("SensorsInfo send error: {:?}", e);
-                                }
-                            } else {
-                                warn!("SensorsInfo serialize error");
+                    match Packet::decode(&recv_buf[..len]) {
+                        Ok(Some(packet)) if packet.command().from_sensor() => {}
+                        Ok(Some(packet)) => {
+                            let accepted = accept_command(&recv_buf[..len], &mut replay_guard);
+                            if let Ok(&counter) = accepted.as_ref() {
+                                cfg.last_command_counter = cfg.last_command_counter.max(counter);
                             }
-                        } else if let PacketCommand::SetName(ref name) = packet.command().clone() {
-                            if packet.id() == get_unique_id() {
-                                info!("SetName: storing \"{}\" to NVM", name.as_str());
-                                cfg.name = name.as_str().try_into().unwrap_or_default();
-                                device_config::save(&mut flash, SETTINGS_OFFSET, ERASE_SIZE as u32, &cfg);
-                                // Multicast confirmation so all servers learn the new name immediately.
-                                let ann = packet_builder.build(PacketCommand::SensorsInfo(Some(cfg.name.as_str().into())));
-                                if let Ok(data) = to_allocvec(&ann) {
-                                    if let Err(e) = socket.send_to(&data, multicast_ep).await {
-                                        warn!("SetName confirm send error: {:?}", e);
+                            if let Err(e) = accepted {
+                                warn!("ignoring command from {:?}: {}", src, defmt::Display2Format(&e));
+                            } else if packet.command() == &PacketCommand::RequestSensorInfo {
+                                info!("RequestSensorInfo from {:?}", src);
+                                // Unicast back to the requester — they have our address from this packet.
+                                let resp = packet_builder.build(PacketCommand::SensorsInfo(sensor_info(&cfg)));
+                                if let Ok(data) = outbox.encode(&resp) {
+                                    if let Err(e) = socket.send_to(&data, src).await {
+                                        warn!("SensorsInfo send error: {:?}", e);
+                                    }
+                                } else {
+                                    warn!("SensorsInfo serialize error");
+                                }
+                            } else if let PacketCommand::SetName(ref name) = packet.command().clone() {
+                                if packet.id() == get_unique_id() {
+                                    info!("SetName: storing \"{}\" to NVM", name.as_str());
+                                    cfg.name = name.as_str().try_into().unwrap_or_default();
+                                    device_config::save(&mut flash, SETTINGS_OFFSET, ERASE_SIZE as u32, &cfg);
+                                    saved_counter = cfg.last_command_counter;
+                                    // Multicast confirmation so all servers learn the new name immediately.
+                                    let ann = packet_builder.build(PacketCommand::SensorsInfo(sensor_info(&cfg)));
+                                    if let Ok(data) = outbox.encode(&ann) {
+                                        if let Err(e) = socket.send_to(&data, multicast_ep).await {
+                                            warn!("SetName confirm send error: {:?}", e);
+                                        }
                                     }
                                 }
                             }
+                            if cfg.last_command_counter - saved_counter >= COUNTER_SAVE_INTERVAL {
+                                device_config::save(&mut flash, SETTINGS_OFFSET, ERASE_SIZE as u32, &cfg);
+                                saved_counter = cfg.last_command_counter;
+                            }
                         }
-                    } else { warn!("packet parse error") }
+                        // A command from a newer protocol version: nothing for us to do.
+                        Ok(None) => {}
+                        Err(_) => warn!("packet parse error"),
+                    }
                 }
                 Err(e) => warn!("recv_from error: {:?}", e),
             },
             Either::Second(reading) => {
                 let packet = packet_builder.build(PacketCommand::DataReading(reading));
-                if let Ok(data) = to_allocvec(&packet) {
+                if let Ok(data) = outbox.encode(&packet) {
                     if let Err(e) = socket.send_to(&data, multicast_ep).await {
                         warn!("DataReading send error: {:?}", e);
                     }
@@ -0,0 +1,731 @@
+//! Threshold alerting: stored rules evaluated against the live reading feed.
+//!
+//! The engine consumes the same `Reading` broadcast as ingest, so it reacts at the raw
+//! sensor rate rather than once per averaged bucket. Fired and resolved transitions are
+//! persisted, which is what `/api/alerts/history` and `/api/alerts/active` read back.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use chlorophyll_client::db::{AlertCondition, AlertEvent, AlertRule, AlertState, Db};
+use chlorophyll_client::{DeviceInfo, Reading, ReadingKind};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::api::{parse_id_hex, parse_measured_metric};
+use crate::state::AppState;
+
+/// Rate of change is measured over windows this wide. Consecutive raw readings are only
+/// ~200 ms apart, so their difference is mostly sensor noise.
+const RATE_WINDOW_SECS: i64 = 60;
+
+/// How often `no_data` rules are checked against the registry's `last_seen`.
+const SILENCE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+const DEFAULT_HISTORY_LIMIT: i64 = 500;
+const MAX_HISTORY_LIMIT: i64 = 5000;
+
+/// Per `(rule, sensor)` progress towards firing or resolving.
+#[derive(Debug, Default)]
+struct Tracker {
+    /// When the condition started holding continuously, while not yet firing.
+    pending_since: Option<DateTime<Utc>>,
+    firing: bool,
+}
+
+impl Tracker {
+    /// Advance with one observation. `breached` means the condition holds; `cleared` means
+    /// the value is back past the hysteresis band. Between the two nothing changes, which
+    /// is what keeps a value hovering at the threshold from flapping.
+    fn step(
+        &mut self,
+        breached: bool,
+        cleared: bool,
+        now: DateTime<Utc>,
+        min_duration_secs: i64,
+    ) -> Option<AlertState> {
+        if self.firing {
+            if cleared {
+                self.firing = false;
+                self.pending_since = None;
+                return Some(AlertState::Resolved);
+            }
+            return None;
+        }
+        if !breached {
+            self.pending_since = None;
+            return None;
+        }
+        let since = *self.pending_since.get_or_insert(now);
+        if (now - since).num_seconds() >= min_duration_secs {
+            self.firing = true;
+            return Some(AlertState::Fired);
+        }
+        None
+    }
+}
+
+/// Evaluates [`AlertRule`]s and yields the transitions to persist.
+#[derive(Debug, Default)]
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    trackers: HashMap<(i64, u128), Tracker>,
+    /// Start of the current rate window per series: `(at, value)`.
+    rate_windows: HashMap<(u128, ReadingKind), (DateTime<Utc>, f32)>,
+}
+
+impl AlertEngine {
+    #[must_use]
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        Self {
+            rules,
+            ..Self::default()
+        }
+    }
+
+    /// An engine that picks up where the last one stopped. `open` are the alerts still
+    /// firing then: those whose rule still covers them are resumed rather than fired a
+    /// second time, and those whose rule was disabled or redefined since are resolved, and
+    /// those transitions are returned.
+    ///
+    /// Open alerts don't record the metric they fired on, so a rule moved to another metric
+    /// with a threshold its last value still breaches is resumed.
+    #[must_use]
+    pub fn resume(rules: Vec<AlertRule>, open: &[AlertEvent], now: DateTime<Utc>) -> (Self, Vec<AlertEvent>) {
+        let mut engine = Self::new(rules);
+        let mut events = Vec::new();
+        for event in open {
+            let applies = engine.rules.iter().any(|rule| rule.id == event.rule_id && still_breached(rule, event));
+            if applies {
+                let tracker = Tracker {
+                    pending_since: None,
+                    firing: true,
+                };
+                engine.trackers.insert((event.rule_id, event.sensor_id), tracker);
+            } else {
+                events.push(AlertEvent {
+                    id: 0,
+                    rule_id: event.rule_id,
+                    sensor_id: event.sensor_id,
+                    state: AlertState::Resolved,
+                    value: None,
+                    at: now,
+                });
+            }
+        }
+        (engine, events)
+    }
+
+    /// Replace the rule set. Progress is kept for rules whose definition is unchanged;
+    /// alerts still firing for a rule that was deleted, disabled or redefined are resolved,
+    /// and those transitions are returned.
+    pub fn set_rules(&mut self, rules: Vec<AlertRule>, now: DateTime<Utc>) -> Vec<AlertEvent> {
+        let unchanged = |rule_id: i64| {
+            let old = self.rules.iter().find(|r| r.id == rule_id);
+            let new = rules.iter().find(|r| r.id == rule_id);
+            matches!((old, new), (Some(old), Some(new)) if new.enabled && same_definition(old, new))
+        };
+        let mut events = Vec::new();
+        self.trackers.retain(|&(rule_id, sensor_id), tracker| {
+            if unchanged(rule_id) {
+                return true;
+            }
+            if tracker.firing {
+                events.push(AlertEvent {
+                    id: 0,
+                    rule_id,
+                    sensor_id,
+                    state: AlertState::Resolved,
+                    value: None,
+                    at: now,
+                });
+            }
+            false
+        });
+        self.rules = rules;
+        events
+    }
+
+    /// Rate per minute if `reading` completes a rate window for its series.
+    fn rate(&mut self, reading: &Reading) -> Option<f32> {
+        let key = (reading.sensor_id, reading.kind);
+        let (start, start_value) = *self
+            .rate_windows
+            .entry(key)
+            .or_insert((reading.at, reading.value));
+        let elapsed = (reading.at - start).num_milliseconds();
+        if elapsed < RATE_WINDOW_SECS * 1000 {
+            return None;
+        }
+        self.rate_windows.insert(key, (reading.at, reading.value));
+        #[allow(clippy::cast_precision_loss)]
+        let minutes = elapsed as f32 / 60_000.0;
+        Some((reading.value - start_value) / minutes)
+    }
+
+    /// Evaluate every value-based rule that covers `reading`.
+    pub fn on_reading(&mut self, reading: &Reading) -> Vec<AlertEvent> {
+        let rate = self.rate(reading);
+        let mut events = Vec::new();
+
+        for rule in &self.rules {
+            if !rule.enabled
+                || rule.kind != Some(reading.kind)
+                || rule.sensor_id.is_some_and(|id| id != reading.sensor_id)
+            {
+                continue;
+            }
+            let h = rule.hysteresis;
+            let (breached, cleared, value) = match rule.condition {
+                AlertCondition::Above(t) => (reading.value > t, reading.value <= t - h, reading.value),
+                AlertCondition::Below(t) => (reading.value < t, reading.value >= t + h, reading.value),
+                AlertCondition::RateOfChange(t) => {
+                    let Some(rate) = rate else { continue };
+                    (rate.abs() > t, rate.abs() <= t - h, rate)
+                }
+                AlertCondition::NoData(_) => continue,
+            };
+            let tracker = self.trackers.entry((rule.id, reading.sensor_id)).or_default();
+            if let Some(state) = tracker.step(breached, cleared, reading.at, rule.min_duration_secs) {
+                events.push(AlertEvent {
+                    id: 0,
+                    rule_id: rule.id,
+                    sensor_id: reading.sensor_id,
+                    state,
+                    value: Some(value),
+                    at: reading.at,
+                });
+            }
+        }
+
+        events
+    }
+
+    /// Evaluate `no_data` rules against each device's `last_seen`.
+    pub fn check_silence(&mut self, devices: &[DeviceInfo], now: DateTime<Utc>) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+
+        for rule in &self.rules {
+            let AlertCondition::NoData(minutes) = rule.condition else { continue };
+            if !rule.enabled {
+                continue;
+            }
+            for device in devices {
+                if rule.sensor_id.is_some_and(|id| id != device.id) {
+                    continue;
+                }
+                let Some(last_seen) = device.last_seen else { continue };
+                #[allow(clippy::cast_precision_loss)]
+                let silent_minutes = (now - last_seen).num_seconds() as f32 / 60.0;
+                let breached = silent_minutes >= minutes;
+                let tracker = self.trackers.entry((rule.id, device.id)).or_default();
+                if let Some(state) = tracker.step(breached, !breached, now, rule.min_duration_secs) {
+                    events.push(AlertEvent {
+                        id: 0,
+                        rule_id: rule.id,
+                        sensor_id: device.id,
+                        state,
+                        value: None,
+                        at: now,
+                    });
+                }
+            }
+        }
+
+        events
+    }
+}
+
+/// Whether two versions of a rule watch for the same thing; only the name may differ.
+fn same_definition(a: &AlertRule, b: &AlertRule) -> bool {
+    a.sensor_id == b.sensor_id
+        && a.kind == b.kind
+        && a.condition == b.condition
+        && a.hysteresis.to_bits() == b.hysteresis.to_bits()
+        && a.min_duration_secs == b.min_duration_secs
+}
+
+/// Whether `rule`, as it is now, would still be firing with the value `fired` recorded.
+fn still_breached(rule: &AlertRule, fired: &AlertEvent) -> bool {
+    if !rule.enabled || rule.sensor_id.is_some_and(|id| id != fired.sensor_id) {
+        return false;
+    }
+    match (rule.condition, fired.value) {
+        (AlertCondition::Above(t), Some(value)) => value > t,
+        (AlertCondition::Below(t), Some(value)) => value < t,
+        (AlertCondition::RateOfChange(t), Some(rate)) => rate.abs() > t,
+        (AlertCondition::NoData(_), None) => true,
+        // Fired under a condition of another kind.
+        _ => false,
+    }
+}
+
+async fn load_engine(db: &Db) -> anyhow::Result<(AlertEngine, Vec<AlertEvent>)> {
+    let rules = db.alert_rules().await?;
+    let open = db.active_alerts().await?;
+    Ok(AlertEngine::resume(rules, &open, Utc::now()))
+}
+
+/// Alert task: evaluate rules against the reading feed and the registry, persist every
+/// transition and publish it on [`AppState::alerts_tx`]. Runs until the reading channel
+/// closes.
+pub async fn run(state: AppState) {
+    let mut readings = state.client.subscribe();
+    let (mut engine, resolved) = match load_engine(&state.db).await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            tracing::error!("alerts: cannot load rules: {e}");
+            (AlertEngine::default(), Vec::new())
+        }
+    };
+    record(&state, resolved).await;
+    let mut silence_check = tokio::time::interval(SILENCE_CHECK_INTERVAL);
+
+    loop {
+        let events = tokio::select! {
+            received = readings.recv() => match received {
+                Ok(reading) => engine.on_reading(&reading),
+                Err(RecvError::Lagged(n)) => {
+                    state.counters.record_lagged(n);
+                    tracing::warn!("alerts: readings channel lagged, dropped {n} messages");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = silence_check.tick() => engine.check_silence(&state.client.devices(), Utc::now()),
+            () = state.alert_rules_changed.notified() => match state.db.alert_rules().await {
+                Ok(rules) => engine.set_rules(rules, Utc::now()),
+                Err(e) => {
+                    tracing::error!("alerts: cannot reload rules: {e}");
+                    continue;
+                }
+            },
+        };
+        record(&state, events).await;
+    }
+}
+
+/// Persist each transition and publish it on [`AppState::alerts_tx`].
+async fn record(state: &AppState, events: Vec<AlertEvent>) {
+    for mut event in events {
+        tracing::info!(
+            "alert rule {} {} for sensor {:032x}",
+            event.rule_id,
+            event.state.as_str(),
+            event.sensor_id
+        );
+        match state.db.insert_alert_event(&event).await {
+            Ok(id) => event.id = id,
+            Err(e) => tracing::error!("alerts: cannot record event: {e}"),
+        }
+        let _ = state.alerts_tx.send(event);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlertRuleRequest {
+    pub name: String,
+    /// Hex id; omitted applies the rule to every sensor.
+    pub sensor: Option<String>,
+    /// Required for every condition except `no_data`.
+    pub metric: Option<String>,
+    /// `above`, `below`, `rate_of_change` (units per minute) or `no_data` (minutes).
+    pub condition: String,
+    pub threshold: f32,
+    #[serde(default)]
+    pub hysteresis: f32,
+    #[serde(default)]
+    pub min_duration_secs: i64,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl AlertRuleRequest {
+    /// Validate into a rule with the given id; `None` if any field is out of range.
+    fn into_rule(self, id: i64) -> Option<AlertRule> {
+        if self.name.trim().is_empty()
+            || !self.threshold.is_finite()
+            || !self.hysteresis.is_finite()
+            || self.hysteresis < 0.0
+            || self.min_duration_secs < 0
+        {
+            return None;
+        }
+        let sensor_id = match self.sensor.as_deref() {
+            Some(id_hex) => Some(parse_id_hex(id_hex)?),
+            None => None,
+        };
+        let kind = match self.metric.as_deref() {
+            Some(metric) => Some(parse_measured_metric(metric)?),
+            None => None,
+        };
+        let condition = AlertCondition::parse(&self.condition, self.threshold)?;
+        match condition {
+            AlertCondition::NoData(minutes) if minutes <= 0.0 => return None,
+            AlertCondition::NoData(_) => {}
+            _ if kind.is_none() => return None,
+            // The rate must fall below `threshold - hysteresis` to clear, which a magnitude
+            // can't do unless that is positive.
+            AlertCondition::RateOfChange(threshold) if self.hysteresis >= threshold => return None,
+            _ => {}
+        }
+        Some(AlertRule {
+            id,
+            name: self.name,
+            sensor_id,
+            kind,
+            condition,
+            hysteresis: self.hysteresis,
+            min_duration_secs: self.min_duration_secs,
+            enabled: self.enabled,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlertRuleJson {
+    pub id: i64,
+    pub name: String,
+    pub sensor: Option<String>,
+    pub metric: Option<&'static str>,
+    pub condition: &'static str,
+    pub threshold: f32,
+    pub hysteresis: f32,
+    pub min_duration_secs: i64,
+    pub enabled: bool,
+}
+
+impl From<AlertRule> for AlertRuleJson {
+    fn from(rule: AlertRule) -> Self {
+        Self {
+            id: rule.id,
+            name: rule.name,
+            sensor: rule.sensor_id.map(|id| format!("{id:032x}")),
+            metric: rule.kind.map(ReadingKind::as_str),
+            condition: rule.condition.as_str(),
+            threshold: rule.condition.threshold(),
+            hysteresis: rule.hysteresis,
+            min_duration_secs: rule.min_duration_secs,
+            enabled: rule.enabled,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlertEventJson {
+    pub id: i64,
+    pub rule_id: i64,
+    pub id_hex: String,
+    /// `fired` or `resolved`.
+    pub state: &'static str,
+    pub value: Option<f32>,
+    /// Unix timestamp in milliseconds.
+    pub t: i64,
+}
+
+impl From<AlertEvent> for AlertEventJson {
+    fn from(event: AlertEvent) -> Self {
+        Self {
+            id: event.id,
+            rule_id: event.rule_id,
+            id_hex: format!("{:032x}", event.sensor_id),
+            state: event.state.as_str(),
+            value: event.value,
+            t: event.at.timestamp_millis(),
+        }
+    }
+}
+
+async fn list_rules(State(state): State<AppState>) -> Result<Json<Vec<AlertRuleJson>>, StatusCode> {
+    let rules = state
+        .db
+        .alert_rules()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(rules.into_iter().map(AlertRuleJson::from).collect()))
+}
+
+async fn create_rule(
+    State(state): State<AppState>,
+    Json(body): Json<AlertRuleRequest>,
+) -> Result<(StatusCode, Json<AlertRuleJson>), StatusCode> {
+    let mut rule = body.into_rule(0).ok_or(StatusCode::BAD_REQUEST)?;
+    rule.id = state
+        .db
+        .insert_alert_rule(&rule)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.alert_rules_changed.notify_one();
+    Ok((StatusCode::CREATED, Json(AlertRuleJson::from(rule))))
+}
+
+async fn get_rule(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<AlertRuleJson>, StatusCode> {
+    state
+        .db
+        .alert_rule(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(|rule| Json(AlertRuleJson::from(rule)))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn update_rule(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(body): Json<AlertRuleRequest>,
+) -> Result<Json<AlertRuleJson>, StatusCode> {
+    let rule = body.into_rule(id).ok_or(StatusCode::BAD_REQUEST)?;
+    let updated = state
+        .db
+        .update_alert_rule(&rule)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !updated {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    state.alert_rules_changed.notify_one();
+    Ok(Json(AlertRuleJson::from(rule)))
+}
+
+async fn delete_rule(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+    let deleted = state
+        .db
+        .delete_alert_rule(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !deleted {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    state.alert_rules_changed.notify_one();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct AlertHistoryQuery {
+    /// Unix milliseconds; only transitions at or after this time are returned.
+    #[serde(default)]
+    since: i64,
+    /// Only transitions of this rule.
+    rule: Option<i64>,
+    limit: Option<i64>,
+}
+
+async fn history(
+    State(state): State<AppState>,
+    Query(query): Query<AlertHistoryQuery>,
+) -> Result<Json<Vec<AlertEventJson>>, StatusCode> {
+    let since = Utc
+        .timestamp_millis_opt(query.since)
+        .single()
+        .unwrap_or(DateTime::UNIX_EPOCH);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .clamp(1, MAX_HISTORY_LIMIT);
+    let events = state
+        .db
+        .alert_history(since, query.rule, limit)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(events.into_iter().map(AlertEventJson::from).collect()))
+}
+
+/// Alerts that have fired and not yet resolved.
+async fn active(State(state): State<AppState>) -> Result<Json<Vec<AlertEventJson>>, StatusCode> {
+    let events = state
+        .db
+        .active_alerts()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(events.into_iter().map(AlertEventJson::from).collect()))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/alerts/active", get(active))
+        .route("/api/alerts/history", get(history))
+        .route("/api/alerts/rules", get(list_rules).post(create_rule))
+        .route(
+            "/api/alerts/rules/{id}",
+            get(get_rule).put(update_rule).delete(delete_rule),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(condition: AlertCondition, hysteresis: f32, min_duration_secs: i64) -> AlertRule {
+        AlertRule {
+            id: 1,
+            name: "test".into(),
+            sensor_id: None,
+            kind: Some(ReadingKind::Temperature),
+            condition,
+            hysteresis,
+            min_duration_secs,
+            enabled: true,
+        }
+    }
+
+    fn reading(value: f32, secs: i64) -> Reading {
+        Reading {
+            sensor_id: 7,
+            kind: ReadingKind::Temperature,
+            value,
+            at: DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap(),
+        }
+    }
+
+    fn states(events: &[AlertEvent]) -> Vec<AlertState> {
+        events.iter().map(|e| e.state).collect()
+    }
+
+    #[test]
+    fn hysteresis_keeps_a_hovering_value_from_flapping() {
+        let mut engine = AlertEngine::new(vec![rule(AlertCondition::Above(30.0), 1.0, 0)]);
+
+        assert_eq!(states(&engine.on_reading(&reading(30.5, 0))), vec![AlertState::Fired]);
+        // Back under the threshold but inside the band: still firing, no new event.
+        assert!(engine.on_reading(&reading(29.5, 1)).is_empty());
+        assert!(engine.on_reading(&reading(30.5, 2)).is_empty());
+        assert_eq!(states(&engine.on_reading(&reading(28.9, 3))), vec![AlertState::Resolved]);
+    }
+
+    #[test]
+    fn min_duration_requires_the_breach_to_persist() {
+        let mut engine = AlertEngine::new(vec![rule(AlertCondition::Below(5.0), 0.0, 60)]);
+
+        assert!(engine.on_reading(&reading(4.0, 0)).is_empty());
+        // A blip back into range restarts the clock.
+        assert!(engine.on_reading(&reading(6.0, 30)).is_empty());
+        assert!(engine.on_reading(&reading(4.0, 40)).is_empty());
+        assert!(engine.on_reading(&reading(4.0, 90)).is_empty());
+        assert_eq!(states(&engine.on_reading(&reading(4.0, 100))), vec![AlertState::Fired]);
+    }
+
+    #[test]
+    fn rate_of_change_is_measured_per_minute_over_a_window() {
+        let mut engine = AlertEngine::new(vec![rule(AlertCondition::RateOfChange(2.0), 0.0, 0)]);
+
+        assert!(engine.on_reading(&reading(20.0, 0)).is_empty());
+        // A large jump mid-window is not evaluated until the window closes.
+        assert!(engine.on_reading(&reading(25.0, 30)).is_empty());
+        let events = engine.on_reading(&reading(23.0, 60));
+        assert_eq!(states(&events), vec![AlertState::Fired]);
+        assert!((events[0].value.unwrap() - 3.0).abs() < 0.001, "3 degrees over one minute");
+    }
+
+    #[test]
+    fn no_data_fires_on_silence_and_resolves_when_the_sensor_returns() {
+        let mut no_data = rule(AlertCondition::NoData(5.0), 0.0, 0);
+        no_data.kind = None;
+        let mut engine = AlertEngine::new(vec![no_data]);
+
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut device = DeviceInfo {
+            id: 7,
+            last_seen: Some(now - chrono::Duration::minutes(2)),
+            ..DeviceInfo::default()
+        };
+        assert!(engine.check_silence(std::slice::from_ref(&device), now).is_empty());
+
+        device.last_seen = Some(now - chrono::Duration::minutes(6));
+        let events = engine.check_silence(std::slice::from_ref(&device), now);
+        assert_eq!(states(&events), vec![AlertState::Fired]);
+        assert_eq!(events[0].value, None);
+
+        device.last_seen = Some(now);
+        assert_eq!(
+            states(&engine.check_silence(std::slice::from_ref(&device), now)),
+            vec![AlertState::Resolved]
+        );
+    }
+
+    #[test]
+    fn redefining_or_deleting_a_firing_rule_resolves_it() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut engine = AlertEngine::new(vec![rule(AlertCondition::Above(30.0), 0.0, 0)]);
+        assert_eq!(states(&engine.on_reading(&reading(31.0, 0))), vec![AlertState::Fired]);
+
+        let mut renamed = rule(AlertCondition::Above(30.0), 0.0, 0);
+        renamed.name = "renamed".into();
+        assert!(engine.set_rules(vec![renamed], now).is_empty(), "the name isn't part of the condition");
+        assert!(engine.on_reading(&reading(31.0, 1)).is_empty());
+
+        let events = engine.set_rules(vec![rule(AlertCondition::Above(35.0), 0.0, 0)], now);
+        assert_eq!(states(&events), vec![AlertState::Resolved]);
+        assert_eq!((events[0].rule_id, events[0].sensor_id, events[0].at), (1, 7, now));
+        // Judged afresh against the new threshold.
+        assert!(engine.on_reading(&reading(31.0, 2)).is_empty());
+        assert_eq!(states(&engine.on_reading(&reading(36.0, 3))), vec![AlertState::Fired]);
+
+        assert_eq!(states(&engine.set_rules(Vec::new(), now)), vec![AlertState::Resolved]);
+        assert!(engine.set_rules(Vec::new(), now).is_empty());
+    }
+
+    #[test]
+    fn rate_of_change_rules_must_be_able_to_clear() {
+        let request = |threshold, hysteresis| AlertRuleRequest {
+            name: "swing".into(),
+            sensor: None,
+            metric: Some("temperature".into()),
+            condition: "rate_of_change".into(),
+            threshold,
+            hysteresis,
+            min_duration_secs: 0,
+            enabled: true,
+        };
+        assert!(request(2.0, 1.5).into_rule(1).is_some());
+        assert!(request(2.0, 2.0).into_rule(1).is_none());
+        assert!(request(-1.0, 0.0).into_rule(1).is_none());
+    }
+
+    #[test]
+    fn open_alerts_are_resumed_instead_of_fired_again() {
+        let open = AlertEvent {
+            id: 1,
+            rule_id: 1,
+            sensor_id: 7,
+            state: AlertState::Fired,
+            value: Some(31.0),
+            at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        };
+        let now = DateTime::from_timestamp(1_700_000_100, 0).unwrap();
+        let (mut engine, resolved) =
+            AlertEngine::resume(vec![rule(AlertCondition::Above(30.0), 0.0, 0)], std::slice::from_ref(&open), now);
+        assert!(resolved.is_empty());
+
+        assert!(engine.on_reading(&reading(31.0, 0)).is_empty(), "already firing before restart");
+        assert_eq!(states(&engine.on_reading(&reading(29.0, 1))), vec![AlertState::Resolved]);
+
+        // Rules disabled or redefined while the server was down resolve what they fired.
+        let mut disabled = rule(AlertCondition::Above(30.0), 0.0, 0);
+        disabled.enabled = false;
+        let mut elsewhere = rule(AlertCondition::Above(30.0), 0.0, 0);
+        elsewhere.sensor_id = Some(8);
+        let raised = rule(AlertCondition::Above(35.0), 0.0, 0);
+        let silence = rule(AlertCondition::NoData(5.0), 0.0, 0);
+        for changed in [disabled, elsewhere, raised, silence] {
+            let (mut engine, resolved) = AlertEngine::resume(vec![changed.clone()], std::slice::from_ref(&open), now);
+            assert_eq!(states(&resolved), vec![AlertState::Resolved], "{changed:?}");
+            assert_eq!((resolved[0].rule_id, resolved[0].sensor_id, resolved[0].at), (1, 7, now));
+            assert!(engine.on_reading(&reading(29.0, 1)).is_empty(), "nothing left firing");
+        }
+    }
+}
@@ -4,13 +4,17 @@
 //! Only one process per host can practically own the sensor feed, and every extra listener
 //! duplicates the decode/registry logic, so downstreams should read from here.
 
+use std::collections::{BTreeMap, HashMap};
+
 use axum::extract::{Path, Query, State};
-use axum::routing::{get, post};
+use axum::routing::{get, post, put};
 use axum::{Json, Router};
-use chlorophyll_client::{DeviceInfo, ReadingKind};
+use chlorophyll_client::db::SensorRecord;
+use chlorophyll_client::{DeviceInfo, ReadingKind, RenameError};
 use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::groups::{Labels, SensorFilter};
 use crate::state::AppState;
 
 /// Upper bound on points returned per series when the caller doesn't pick a bucket.
@@ -25,9 +29,28 @@ pub struct SensorSummary {
     pub id_hex: String,
     pub name: Option<String>,
     pub last_seen: Option<DateTime<Utc>>,
-    pub temperature: Option<f32>,
-    pub humidity: Option<f32>,
-    pub light: Option<f32>,
+    /// `online`, `stale` or `offline`.
+    pub status: &'static str,
+    /// Latest value per metric id, flattened into the object: `"temperature": 21.5`.
+    /// Metrics the sensor hasn't reported are absent.
+    #[serde(flatten)]
+    pub values: BTreeMap<&'static str, f32>,
+    /// Advertised by sensors whose firmware sends the `SensorsInfo` capabilities extension.
+    pub firmware_version: Option<String>,
+    pub supported_metrics: Vec<&'static str>,
+    pub sample_interval_ms: Option<u32>,
+    pub authenticated: bool,
+    /// Name sent to the sensor that it hasn't confirmed yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_name: Option<String>,
+    /// Operator-set location and notes from the `sensors` table.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    pub tags: Vec<String>,
+    /// Ids of the groups the sensor belongs to.
+    pub groups: Vec<i64>,
 }
 
 impl From<DeviceInfo> for SensorSummary {
@@ -36,20 +59,72 @@ impl From<DeviceInfo> for SensorSummary {
             id_hex: format!("{:032x}", device.id),
             name: device.name,
             last_seen: device.last_seen,
-            temperature: device.temperature,
-            humidity: device.humidity,
-            light: device.light,
+            status: device.status.as_str(),
+            values: device.values.into_iter().map(|(kind, value)| (kind.as_str(), value)).collect(),
+            firmware_version: device.firmware_version,
+            supported_metrics: device.supported_metrics.into_iter().map(ReadingKind::as_str).collect(),
+            sample_interval_ms: device.sample_interval_ms,
+            authenticated: device.authenticated,
+            pending_name: device.pending_name,
+            location: None,
+            notes: None,
+            tags: Vec::new(),
+            groups: Vec::new(),
         }
     }
 }
 
-fn parse_id_hex(id_hex: &str) -> Option<u128> {
+pub(crate) fn parse_id_hex(id_hex: &str) -> Option<u128> {
     u128::from_str_radix(id_hex.trim_start_matches("0x"), 16).ok()
 }
 
-async fn sensors(State(state): State<AppState>) -> Json<Vec<SensorSummary>> {
+pub(crate) fn parse_metric(metric: &str) -> Option<ReadingKind> {
+    ReadingKind::from_id(metric)
+}
+
+/// A metric sensors report, as opposed to one only computed for history.
+pub(crate) fn parse_measured_metric(metric: &str) -> Option<ReadingKind> {
+    parse_metric(metric).filter(|kind| !kind.is_derived())
+}
+
+/// A device's summary with its server-side metadata filled in.
+fn summarize(device: DeviceInfo, record: Option<SensorRecord>, labels: &Labels) -> SensorSummary {
+    let (location, notes) = record.map(|r| (r.location, r.notes)).unwrap_or_default();
+    SensorSummary {
+        location,
+        notes,
+        tags: labels.tags(device.id).to_vec(),
+        groups: labels.groups_of(device.id),
+        ..SensorSummary::from(device)
+    }
+}
+
+async fn sensors(
+    State(state): State<AppState>,
+    Query(filter): Query<SensorFilter>,
+) -> Result<Json<Vec<SensorSummary>>, axum::http::StatusCode> {
+    let mut records: HashMap<u128, SensorRecord> = state
+        .db
+        .sensors()
+        .await
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?
+        .into_iter()
+        .map(|record| (record.id, record))
+        .collect();
+    let labels = Labels::load(&state.db)
+        .await
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
     let devices = state.client.devices();
-    Json(devices.into_iter().map(SensorSummary::from).collect())
+    Ok(Json(
+        devices
+            .into_iter()
+            .filter(|device| labels.matches(device.id, &filter))
+            .map(|device| {
+                let record = records.remove(&device.id);
+                summarize(device, record, &labels)
+            })
+            .collect(),
+    ))
 }
 
 async fn sensor(
@@ -57,13 +132,42 @@ async fn sensor(
     Path(id_hex): Path<String>,
 ) -> Result<Json<SensorSummary>, axum::http::StatusCode> {
     let id = parse_id_hex(&id_hex).ok_or(axum::http::StatusCode::BAD_REQUEST)?;
-    state
+    let device = state
         .client
         .devices()
         .into_iter()
         .find(|d| d.id == id)
-        .map(|d| Json(SensorSummary::from(d)))
-        .ok_or(axum::http::StatusCode::NOT_FOUND)
+        .ok_or(axum::http::StatusCode::NOT_FOUND)?;
+    let record = state
+        .db
+        .sensor(id)
+        .await
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
+    let labels = Labels::load(&state.db)
+        .await
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(summarize(device, record, &labels)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetNotesRequest {
+    /// `null` or empty clears the notes.
+    pub notes: Option<String>,
+}
+
+/// Replace a sensor's operator notes. Unlike its name, these live only on the server.
+async fn set_notes(
+    State(state): State<AppState>,
+    Path(id_hex): Path<String>,
+    Json(body): Json<SetNotesRequest>,
+) -> Result<axum::http::StatusCode, axum::http::StatusCode> {
+    let id = parse_id_hex(&id_hex).ok_or(axum::http::StatusCode::BAD_REQUEST)?;
+    let notes = body.notes.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    match state.db.set_sensor_notes(id, notes).await {
+        Ok(true) => Ok(axum::http::StatusCode::NO_CONTENT),
+        Ok(false) => Err(axum::http::StatusCode::NOT_FOUND),
+        Err(_) => Err(axum::http::StatusCode::INTERNAL_SERVER_ERROR),
+    }
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -76,18 +180,37 @@ pub struct HistoryQuery {
     /// Averaging window in seconds. Omitted means "pick one that keeps the response
     /// bounded", which is what most callers want.
     bucket: Option<i64>,
+    /// Only sensors in this group; see [`SensorFilter`].
+    group: Option<i64>,
+    /// Only sensors with this tag.
+    tag: Option<String>,
+}
+
+impl HistoryQuery {
+    fn filter(&self) -> SensorFilter {
+        SensorFilter {
+            group: self.group,
+            tag: self.tag.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct PointJson {
     /// Unix timestamp in milliseconds.
     pub t: i64,
+    /// Mean over the bucket.
     pub v: f32,
+    /// Lowest and highest reading in the bucket; both equal `v` for derived metrics.
+    pub min: f32,
+    pub max: f32,
 }
 
 #[derive(Debug, Serialize)]
 pub struct SensorSeries {
     pub id_hex: String,
+    /// From the `sensors` table, so series from sensors that are gone keep their label.
+    pub name: Option<String>,
     pub metric: &'static str,
     /// Width of the averaging window these points represent, in seconds.
     pub bucket_secs: i64,
@@ -141,22 +264,53 @@ async fn history_series(
         .history_bucketed(from, to, bucket)
         .await
         .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
+    let names = sensor_names(state).await?;
+    let filter = query.filter();
+    let labels = if filter.is_empty() {
+        Labels::default()
+    } else {
+        Labels::load(&state.db)
+            .await
+            .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?
+    };
 
     Ok(series
         .into_iter()
-        .filter(|(id_hex, _, _)| only.is_none_or(|id| parse_id_hex(id_hex) == Some(id)))
+        .filter(|(id_hex, _, _)| {
+            let id = parse_id_hex(id_hex);
+            only.is_none_or(|only| id == Some(only))
+                && id.is_some_and(|id| labels.matches(id, &filter))
+        })
         .map(|(id_hex, kind, points)| SensorSeries {
+            name: parse_id_hex(&id_hex).and_then(|id| names.get(&id).cloned()),
             id_hex,
             metric: kind.as_str(),
             bucket_secs: bucket,
             points: points
                 .into_iter()
-                .map(|(at, v)| PointJson { t: at.timestamp_millis(), v })
+                .map(|point| PointJson {
+                    t: point.at.timestamp_millis(),
+                    v: point.value,
+                    min: point.min,
+                    max: point.max,
+                })
                 .collect(),
         })
         .collect())
 }
 
+/// Recorded names by sensor id, for labelling history.
+pub(crate) async fn sensor_names(state: &AppState) -> Result<HashMap<u128, String>, axum::http::StatusCode> {
+    Ok(state
+        .db
+        .sensors()
+        .await
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?
+        .into_iter()
+        .filter_map(|record| Some((record.id, record.name?)))
+        .collect())
+}
+
 async fn history(
     State(state): State<AppState>,
     Query(query): Query<HistoryQuery>,
@@ -178,34 +332,93 @@ pub struct SetNameRequest {
     pub name: String,
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct SetNameQuery {
+    /// Wait for the sensor to confirm the name rather than answering once it is sent.
+    #[serde(default)]
+    pub wait: bool,
+}
+
 /// Push a name to a sensor's NVM over multicast.
 ///
 /// Exposed so downstream consumers don't need their own multicast socket just to rename a
 /// sensor; the sensor echoes the new name back and every consumer picks it up from
-/// `/api/sensors`.
+/// `/api/sensors`, where it shows as `pending_name` until then.
+///
+/// Answers 202 once `SetName` is sent. With `?wait=true`, answers 200 once the sensor
+/// confirms, 404 if no such sensor is known and 504 if it never confirms.
 async fn set_name(
     State(state): State<AppState>,
     Path(id_hex): Path<String>,
+    Query(query): Query<SetNameQuery>,
     Json(body): Json<SetNameRequest>,
 ) -> Result<axum::http::StatusCode, axum::http::StatusCode> {
     let id = parse_id_hex(&id_hex).ok_or(axum::http::StatusCode::BAD_REQUEST)?;
     if body.name.trim().is_empty() {
         return Err(axum::http::StatusCode::BAD_REQUEST);
     }
-    state
+    if query.wait && !state.client.devices().iter().any(|d| d.id == id) {
+        return Err(axum::http::StatusCode::NOT_FOUND);
+    }
+    let rename = state
         .client
         .set_name(id, &body.name)
         .map_err(|_| axum::http::StatusCode::BAD_GATEWAY)?;
-    Ok(axum::http::StatusCode::ACCEPTED)
+    if !query.wait {
+        spawn_rename(id, body.name, rename);
+        return Ok(axum::http::StatusCode::ACCEPTED);
+    }
+    match rename.await {
+        Ok(()) => Ok(axum::http::StatusCode::OK),
+        Err(RenameError::TimedOut) => Err(axum::http::StatusCode::GATEWAY_TIMEOUT),
+        Err(RenameError::Send(_)) => Err(axum::http::StatusCode::BAD_GATEWAY),
+    }
+}
+
+/// See a rename through in the background, so it is retried and its pending state
+/// cleared even though nobody waits on it.
+pub(crate) fn spawn_rename(
+    id: u128,
+    name: String,
+    rename: impl Future<Output = Result<(), RenameError>> + Send + 'static,
+) {
+    tokio::spawn(async move {
+        if let Err(e) = rename.await {
+            tracing::warn!("renaming {id:032x} to {name:?} failed: {e}");
+        }
+    });
+}
+
+/// A metric the API can return, with what a client needs to display it.
+#[derive(Debug, Serialize)]
+pub struct MetricSummary {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub precision: usize,
+    pub chart_span: f32,
+    /// Computed from other metrics for history; never in live readings.
+    pub derived: bool,
 }
 
-/// Metrics the API can return, so clients don't hardcode the list.
-async fn metrics() -> Json<Vec<&'static str>> {
-    Json(vec![
-        ReadingKind::Temperature.as_str(),
-        ReadingKind::Humidity.as_str(),
-        ReadingKind::Light.as_str(),
-    ])
+/// Metrics the API can return, in display order, so clients don't hardcode the list.
+async fn metrics() -> Json<Vec<MetricSummary>> {
+    Json(
+        ReadingKind::ALL
+            .into_iter()
+            .map(|kind| {
+                let info = kind.info();
+                MetricSummary {
+                    id: info.id,
+                    name: info.name,
+                    unit: info.unit,
+                    precision: info.precision,
+                    chart_span: info.chart_span,
+                    derived: kind.is_derived(),
+                }
+            })
+            .collect(),
+    )
 }
 
 pub fn router() -> Router<AppState> {
@@ -216,4 +429,5 @@ pub fn router() -> Router<AppState> {
         .route("/api/sensors/{id_hex}", get(sensor))
         .route("/api/sensors/{id_hex}/history", get(sensor_history))
         .route("/api/sensors/{id_hex}/name", post(set_name))
+        .route("/api/sensors/{id_hex}/notes", put(set_notes))
 }
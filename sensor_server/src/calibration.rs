@@ -0,0 +1,230 @@
+//! Calibration API: per-sensor, per-metric corrections applied as readings arrive.
+//!
+//! Every change is stored as a new version rather than overwriting the old one, so the
+//! calibration behind any stored reading can be looked up afterwards. After each change
+//! the listener's calibrations are reloaded from the database.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use chlorophyll_client::db::{CalibrationVersion, Db};
+use chlorophyll_client::{Calibration, ReadingKind};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{parse_id_hex, parse_measured_metric};
+use crate::state::AppState;
+
+/// Either `scale`/`offset` (each optional, defaulting to the identity) or `points`.
+#[derive(Debug, Deserialize)]
+pub struct CalibrationRequest {
+    pub scale: Option<f32>,
+    pub offset: Option<f32>,
+    /// `[raw, true]` reference pairs; at least two, with distinct raw values.
+    pub points: Option<Vec<(f32, f32)>>,
+}
+
+impl CalibrationRequest {
+    fn into_calibration(self) -> Option<Calibration> {
+        match (self.points, self.scale, self.offset) {
+            (Some(points), None, None) => Calibration::table(points),
+            (None, scale, offset) => {
+                let scale = scale.unwrap_or(1.0);
+                let offset = offset.unwrap_or(0.0);
+                (scale.is_finite() && offset.is_finite() && scale != 0.0)
+                    .then_some(Calibration::Linear { scale, offset })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CalibrationJson {
+    /// Version id; increases with every change.
+    pub version: i64,
+    pub metric: &'static str,
+    /// `linear`, `table`, or `none` for a removed calibration.
+    pub method: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub points: Option<Vec<(f32, f32)>>,
+    /// Unix milliseconds from which this version applied.
+    pub valid_from: i64,
+}
+
+impl From<CalibrationVersion> for CalibrationJson {
+    fn from(version: CalibrationVersion) -> Self {
+        let (method, scale, offset, points) = match version.calibration {
+            Some(Calibration::Linear { scale, offset }) => ("linear", Some(scale), Some(offset), None),
+            Some(Calibration::Table(points)) => ("table", None, None, Some(points)),
+            None => ("none", None, None, None),
+        };
+        Self {
+            version: version.id,
+            metric: version.kind.as_str(),
+            method,
+            scale,
+            offset,
+            points,
+            valid_from: version.valid_from.timestamp_millis(),
+        }
+    }
+}
+
+/// Push the database's current calibrations to the listener.
+async fn reload(state: &AppState) -> Result<(), StatusCode> {
+    let calibrations = state
+        .db
+        .current_calibrations()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.client.set_calibrations(calibrations);
+    Ok(())
+}
+
+/// Load the stored calibrations into the listener; call once at startup.
+pub async fn load(state: &AppState) -> anyhow::Result<()> {
+    state.client.set_calibrations(state.db.current_calibrations().await?);
+    Ok(())
+}
+
+async fn current_version(db: &Db, id: u128, kind: ReadingKind) -> Result<Option<CalibrationVersion>, StatusCode> {
+    let history = db
+        .calibration_history(id, Some(kind))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(history.into_iter().last().filter(|v| v.calibration.is_some()))
+}
+
+/// The calibrations currently in effect for a sensor, one per calibrated metric.
+async fn current(
+    State(state): State<AppState>,
+    Path(id_hex): Path<String>,
+) -> Result<Json<Vec<CalibrationJson>>, StatusCode> {
+    let id = parse_id_hex(&id_hex).ok_or(StatusCode::BAD_REQUEST)?;
+    let versions = state
+        .db
+        .current_calibration_versions()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(
+        versions
+            .into_iter()
+            .filter(|v| v.sensor_id == id && v.calibration.is_some())
+            .map(CalibrationJson::from)
+            .collect(),
+    ))
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct CalibrationHistoryQuery {
+    /// Only versions for this metric id.
+    metric: Option<String>,
+}
+
+/// Every calibration version for a sensor, oldest first, including removals.
+async fn history(
+    State(state): State<AppState>,
+    Path(id_hex): Path<String>,
+    Query(query): Query<CalibrationHistoryQuery>,
+) -> Result<Json<Vec<CalibrationJson>>, StatusCode> {
+    let id = parse_id_hex(&id_hex).ok_or(StatusCode::BAD_REQUEST)?;
+    let kind = query
+        .metric
+        .as_deref()
+        .map(|m| parse_measured_metric(m).ok_or(StatusCode::BAD_REQUEST))
+        .transpose()?;
+    let versions = state
+        .db
+        .calibration_history(id, kind)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(versions.into_iter().map(CalibrationJson::from).collect()))
+}
+
+/// Set a metric's calibration, superseding the previous version.
+async fn set(
+    State(state): State<AppState>,
+    Path((id_hex, metric)): Path<(String, String)>,
+    Json(body): Json<CalibrationRequest>,
+) -> Result<(StatusCode, Json<CalibrationJson>), StatusCode> {
+    let id = parse_id_hex(&id_hex).ok_or(StatusCode::BAD_REQUEST)?;
+    let kind = parse_measured_metric(&metric).ok_or(StatusCode::BAD_REQUEST)?;
+    let calibration = body.into_calibration().ok_or(StatusCode::BAD_REQUEST)?;
+    let mut version = CalibrationVersion {
+        id: 0,
+        sensor_id: id,
+        kind,
+        calibration: Some(calibration),
+        valid_from: Utc::now(),
+    };
+    version.id = state
+        .db
+        .insert_calibration(&version)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    reload(&state).await?;
+    Ok((StatusCode::CREATED, Json(CalibrationJson::from(version))))
+}
+
+/// Stop calibrating a metric. Recorded as a version of its own.
+async fn remove(
+    State(state): State<AppState>,
+    Path((id_hex, metric)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let id = parse_id_hex(&id_hex).ok_or(StatusCode::BAD_REQUEST)?;
+    let kind = parse_measured_metric(&metric).ok_or(StatusCode::BAD_REQUEST)?;
+    if current_version(&state.db, id, kind).await?.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let version = CalibrationVersion {
+        id: 0,
+        sensor_id: id,
+        kind,
+        calibration: None,
+        valid_from: Utc::now(),
+    };
+    state
+        .db
+        .insert_calibration(&version)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    reload(&state).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/sensors/{id_hex}/calibration", get(current))
+        .route("/api/sensors/{id_hex}/calibration/history", get(history))
+        .route("/api/sensors/{id_hex}/calibration/{metric}", put(set).delete(remove))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(scale: Option<f32>, offset: Option<f32>, points: Option<Vec<(f32, f32)>>) -> CalibrationRequest {
+        CalibrationRequest { scale, offset, points }
+    }
+
+    #[test]
+    fn requests_are_linear_or_a_table_but_not_both() {
+        assert_eq!(
+            request(None, Some(-1.5), None).into_calibration(),
+            Some(Calibration::Linear { scale: 1.0, offset: -1.5 })
+        );
+        assert!(matches!(
+            request(None, None, Some(vec![(0.0, 0.0), (10.0, 12.0)])).into_calibration(),
+            Some(Calibration::Table(_))
+        ));
+        assert_eq!(request(Some(2.0), None, Some(vec![(0.0, 0.0), (1.0, 1.0)])).into_calibration(), None);
+        assert_eq!(request(Some(0.0), None, None).into_calibration(), None);
+        assert_eq!(request(None, None, Some(vec![(0.0, 0.0)])).into_calibration(), None);
+    }
+}
@@ -0,0 +1,444 @@
+//! Arguments and output for the `sensor_server` subcommands that do one thing and exit,
+//! rather than serve.
+//!
+//! Exit codes: 0 on success, 1 on an error, 2 for a usage error (from clap),
+//! [`EXIT_TIMEOUT`] when no sensor answered in time and [`EXIT_NO_DATA`] when a history
+//! query matched nothing.
+
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use chlorophyll_client::db::{CompactionReport, Db, ExportQuery};
+use chlorophyll_client::{DeviceInfo, RenameRetry};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::StreamExt;
+
+use crate::api::parse_id_hex;
+use crate::export::{ExportParams, Format, default_file_name, parse_time};
+use crate::import::{ImportOptions, parse_mapping};
+
+/// No sensor was heard from, or the one renamed didn't confirm, before the deadline.
+pub const EXIT_TIMEOUT: u8 = 3;
+/// The history query matched no rows.
+pub const EXIT_NO_DATA: u8 = 4;
+
+/// Rows buffered before they are written out.
+const WRITE_BUFFER_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, clap::Args)]
+pub struct ListArgs {
+    /// Seconds to listen for sensors before printing what was heard
+    #[arg(long, value_name = "SECS", default_value_t = 5)]
+    pub listen: u64,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct RequestInfoArgs {
+    /// Seconds to wait for sensors to answer
+    #[arg(long, value_name = "SECS", default_value_t = 3)]
+    pub wait: u64,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct SetNameArgs {
+    /// Sensor id, hex
+    pub id: String,
+    pub name: String,
+    /// Times to send the name before giving up
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    pub attempts: u32,
+    /// Seconds each attempt waits for the sensor to announce its new name
+    #[arg(long, value_name = "SECS", default_value_t = 3)]
+    pub timeout: u64,
+}
+
+impl SetNameArgs {
+    #[must_use]
+    pub fn retry(&self) -> RenameRetry {
+        RenameRetry { attempts: self.attempts, timeout: Duration::from_secs(self.timeout) }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct CompactArgs {
+    /// Report what compacting would remove without changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct HistoryArgs {
+    /// Sensor id, hex
+    #[arg(long, value_name = "HEX")]
+    pub sensor: String,
+    /// Metric id, e.g. temperature
+    #[arg(long, value_name = "ID")]
+    pub metric: String,
+    /// Unix milliseconds or RFC 3339 [default: the oldest reading]
+    #[arg(long, value_name = "TIME")]
+    pub since: Option<String>,
+    /// Unix milliseconds or RFC 3339 [default: the newest reading]
+    #[arg(long, value_name = "TIME")]
+    pub until: Option<String>,
+    /// Average into buckets this many seconds wide [default: rows as stored]
+    #[arg(long, value_name = "SECS")]
+    pub bucket: Option<i64>,
+    /// [default: table on stdout, otherwise the output's extension, then csv]
+    #[arg(long, value_enum)]
+    pub format: Option<HistoryFormat>,
+    /// Write to this file instead of stdout
+    #[arg(long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HistoryFormat {
+    Table,
+    Csv,
+    Jsonl,
+}
+
+impl HistoryArgs {
+    /// The series asked for, validated the same way as `/api/export`.
+    pub fn query(&self) -> anyhow::Result<ExportQuery> {
+        let params = ExportParams {
+            since: self.since.as_deref().map(parse_time).transpose()?,
+            until: self.until.as_deref().map(parse_time).transpose()?,
+            bucket: self.bucket,
+            sensor: Some(self.sensor.clone()),
+            metric: Some(self.metric.clone()),
+            ..ExportParams::default()
+        };
+        params.query().context("invalid --sensor, --metric or --bucket")
+    }
+
+    #[must_use]
+    pub fn format(&self) -> HistoryFormat {
+        let from_extension = || {
+            let extension = self.output.as_ref()?.extension()?.to_str()?;
+            Format::parse(extension).map(|format| match format {
+                Format::Csv => HistoryFormat::Csv,
+                Format::Jsonl => HistoryFormat::Jsonl,
+            })
+        };
+        match (self.format, &self.output) {
+            (Some(format), _) => format,
+            (None, Some(_)) => from_extension().unwrap_or(HistoryFormat::Csv),
+            (None, None) => HistoryFormat::Table,
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ExportArgs {
+    /// Unix milliseconds or RFC 3339 [default: the oldest reading]
+    #[arg(long, value_name = "TIME")]
+    pub since: Option<String>,
+    /// Unix milliseconds or RFC 3339 [default: the newest reading]
+    #[arg(long, value_name = "TIME")]
+    pub until: Option<String>,
+    /// Average into buckets this many seconds wide [default: rows as stored]
+    #[arg(long, value_name = "SECS")]
+    pub bucket: Option<i64>,
+    /// Sensor id, hex [default: every sensor]
+    #[arg(long, value_name = "HEX")]
+    pub sensor: Option<String>,
+    /// Metric id, e.g. temperature [default: every measured metric]
+    #[arg(long, value_name = "ID")]
+    pub metric: Option<String>,
+    /// [default: the output's extension, then csv]
+    #[arg(long, value_enum)]
+    pub format: Option<Format>,
+    /// [default: a timestamped file in the working directory]
+    #[arg(long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+}
+
+impl ExportArgs {
+    /// The rows asked for, validated the same way as `/api/export`.
+    pub fn query(&self) -> anyhow::Result<ExportQuery> {
+        let params = ExportParams {
+            since: self.since.as_deref().map(parse_time).transpose()?,
+            until: self.until.as_deref().map(parse_time).transpose()?,
+            bucket: self.bucket,
+            sensor: self.sensor.clone(),
+            metric: self.metric.clone(),
+            ..ExportParams::default()
+        };
+        params.query().context("invalid --sensor, --metric or --bucket")
+    }
+
+    #[must_use]
+    pub fn format(&self) -> Format {
+        self.format
+            .or_else(|| Format::parse(self.output.as_ref()?.extension()?.to_str()?))
+            .unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn output(&self, now: DateTime<Utc>) -> PathBuf {
+        self.output
+            .clone()
+            .unwrap_or_else(|| default_file_name(self.format(), now).into())
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ImportArgs {
+    /// CSV or JSON Lines file, e.g. one written by `export`
+    pub path: PathBuf,
+    /// [default: the file's extension, then csv]
+    #[arg(long, value_enum)]
+    pub format: Option<Format>,
+    /// Seconds each row averages; 0 for raw samples
+    #[arg(long, value_name = "SECS", default_value_t = 0, value_parser = clap::value_parser!(i64).range(0..))]
+    pub bucket: i64,
+    /// Store readings from sensor OLD under NEW, both hex; repeatable
+    #[arg(long = "map", value_name = "OLD=NEW", value_parser = sensor_mapping)]
+    pub map: Vec<(u128, u128)>,
+}
+
+impl ImportArgs {
+    #[must_use]
+    pub fn options(&self) -> ImportOptions {
+        ImportOptions {
+            format: self
+                .format
+                .or_else(|| Format::parse(self.path.extension()?.to_str()?))
+                .unwrap_or_default(),
+            bucket_secs: self.bucket,
+            map: self.map.iter().copied().collect(),
+        }
+    }
+}
+
+fn sensor_mapping(mapping: &str) -> Result<(u128, u128), String> {
+    parse_mapping(mapping).ok_or_else(|| "expected OLD=NEW hex sensor ids".to_string())
+}
+
+/// Write the series `args` asks for to `out`, returning how many rows it had.
+pub async fn history(db: &Db, args: &HistoryArgs, out: &mut impl Write) -> anyhow::Result<u64> {
+    let query = args.query()?;
+    let format = args.format();
+    let mut buffer = match format {
+        HistoryFormat::Table => format!("{:<24}  value\n", "timestamp"),
+        HistoryFormat::Csv => Format::Csv.header().to_string(),
+        HistoryFormat::Jsonl => String::new(),
+    };
+    let mut count = 0;
+    let mut rows = db.export(&query);
+    while let Some(row) = rows.next().await {
+        let row = row?;
+        match format {
+            HistoryFormat::Table => {
+                let timestamp = row.at.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+                let _ = writeln!(buffer, "{timestamp:<24}  {}", row.kind.info().format(row.value));
+            }
+            HistoryFormat::Csv => Format::Csv.write_row(&mut buffer, &row),
+            HistoryFormat::Jsonl => Format::Jsonl.write_row(&mut buffer, &row),
+        }
+        count += 1;
+        if buffer.len() >= WRITE_BUFFER_BYTES {
+            out.write_all(buffer.as_bytes())?;
+            buffer.clear();
+        }
+    }
+    out.write_all(buffer.as_bytes())?;
+    out.flush()?;
+    Ok(count)
+}
+
+/// One line per sensor: id, name, status, when it was last heard, firmware and latest
+/// values.
+#[must_use]
+pub fn sensor_table(devices: &[DeviceInfo], now: DateTime<Utc>) -> String {
+    let name_width = devices.iter().filter_map(|d| d.name.as_ref()).map(|n| n.chars().count()).max().unwrap_or(0);
+    let name_width = name_width.max("name".len());
+    let mut table =
+        format!("{:<32}  {:<name_width$}  {:<7}  {:>9}  {:<8}  values\n", "id", "name", "status", "seen", "firmware");
+    for device in devices {
+        let seen = device
+            .last_seen
+            .map_or_else(|| "never".to_string(), |at| format!("{}s ago", (now - at).num_seconds().max(0)));
+        let values = device
+            .values
+            .iter()
+            .map(|(kind, value)| format!("{} {}", kind.as_str(), kind.info().format(*value)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ = writeln!(
+            table,
+            "{:032x}  {:<name_width$}  {:<7}  {seen:>9}  {:<8}  {values}",
+            device.id,
+            device.name.as_deref().unwrap_or("-"),
+            device.status.as_str(),
+            device.firmware_version.as_deref().unwrap_or("-"),
+        );
+    }
+    table
+}
+
+/// What a compaction did, or for a dry run would do, in a few lines.
+#[must_use]
+pub fn compaction_summary(report: &CompactionReport, dry_run: bool) -> String {
+    let (rolled, expired, removed) = if dry_run {
+        ("would roll up", "would expire", "would remove")
+    } else {
+        ("rolled up", "expired", "removed")
+    };
+    let mut summary = format!("{rolled} {} raw readings, {expired} {}\n", report.rolled_up, report.expired);
+    for tier in &report.tiers {
+        let _ = writeln!(
+            summary,
+            "{}s tier: {} buckets written, {} pruned, {} expired",
+            tier.bucket_secs, tier.written, tier.pruned, tier.expired
+        );
+    }
+    for tier in &report.dropped {
+        let _ = write!(summary, "{}s tier dropped: {} rows", tier.bucket_secs, tier.rows);
+        let _ = match tier.merged_into {
+            Some(into) => writeln!(summary, ", {} buckets merged into the {into}s tier", tier.written),
+            None => writeln!(summary),
+        };
+    }
+    let _ = writeln!(summary, "{removed} {} rows in total", report.removed());
+    summary
+}
+
+/// Parse a hex sensor id from the command line.
+pub fn sensor_id(id_hex: &str) -> anyhow::Result<u128> {
+    parse_id_hex(id_hex).with_context(|| format!("invalid sensor id {id_hex:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use chlorophyll_client::ReadingKind;
+    use chlorophyll_client::db::{DroppedTier, TierReport};
+
+    use super::*;
+
+    fn history_args(output: Option<&str>, format: Option<HistoryFormat>) -> HistoryArgs {
+        HistoryArgs {
+            sensor: "2a".into(),
+            metric: "humidity".into(),
+            since: Some("2023-11-14T00:00:00Z".into()),
+            until: None,
+            bucket: None,
+            format,
+            output: output.map(PathBuf::from),
+        }
+    }
+
+    #[test]
+    fn history_formats_default_to_a_table_on_stdout() {
+        assert_eq!(history_args(None, None).format(), HistoryFormat::Table);
+        assert_eq!(history_args(Some("out.jsonl"), None).format(), HistoryFormat::Jsonl);
+        assert_eq!(history_args(Some("out.txt"), None).format(), HistoryFormat::Csv);
+        assert_eq!(history_args(Some("out.csv"), Some(HistoryFormat::Table)).format(), HistoryFormat::Table);
+
+        let query = history_args(None, None).query().unwrap();
+        assert_eq!((query.sensor_id, query.kind), (Some(0x2a), Some(ReadingKind::Humidity)));
+        assert_eq!(query.from, DateTime::from_timestamp(1_699_920_000, 0));
+        let derived = HistoryArgs { metric: "vpd".into(), ..history_args(None, None) };
+        assert!(derived.query().is_err(), "derived metrics aren't stored");
+    }
+
+    #[derive(Debug, clap::Parser)]
+    enum Subcommand {
+        Export(ExportArgs),
+        Import(ImportArgs),
+    }
+
+    fn parse(args: &[&str]) -> Result<Subcommand, clap::Error> {
+        <Subcommand as clap::Parser>::try_parse_from(std::iter::once("sensor_server").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn export_args_share_the_api_validation() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let Ok(Subcommand::Export(args)) =
+            parse(&["export", "--output", "out.jsonl", "--since", "2023-11-14T00:00:00Z", "--metric", "humidity"])
+        else {
+            panic!("export args didn't parse");
+        };
+        assert_eq!(args.output(now), PathBuf::from("out.jsonl"));
+        assert_eq!(args.format(), Format::Jsonl);
+        let query = args.query().unwrap();
+        assert_eq!(query.from, DateTime::from_timestamp(1_699_920_000, 0));
+        assert_eq!((query.sensor_id, query.kind), (None, Some(ReadingKind::Humidity)));
+
+        let Ok(Subcommand::Export(args)) = parse(&["export"]) else {
+            panic!("export needs no args");
+        };
+        assert_eq!(args.output(now), PathBuf::from("chlorophyll-20231114T221320Z.csv"));
+        assert_eq!(args.format(), Format::Csv);
+
+        let Ok(Subcommand::Export(args)) = parse(&["export", "--bucket", "0"]) else {
+            panic!("export args didn't parse");
+        };
+        assert!(args.query().is_err());
+        let usage = parse(&["export", "--format"]).unwrap_err();
+        assert_eq!(usage.exit_code(), 2);
+        assert_eq!(parse(&["export", "--help"]).unwrap_err().kind(), clap::error::ErrorKind::DisplayHelp);
+    }
+
+    #[test]
+    fn import_args_take_the_format_from_the_extension() {
+        let Ok(Subcommand::Import(args)) = parse(&["import", "old.jsonl", "--bucket", "60", "--map", "0x1=2"]) else {
+            panic!("import args didn't parse");
+        };
+        assert_eq!(args.path, PathBuf::from("old.jsonl"));
+        let options = args.options();
+        assert_eq!(options.format, Format::Jsonl);
+        assert_eq!(options.bucket_secs, 60);
+        assert_eq!(options.map, [(1, 2)].into());
+
+        let Ok(Subcommand::Import(args)) = parse(&["import", "old.txt"]) else {
+            panic!("import args didn't parse");
+        };
+        assert_eq!(args.options().format, Format::Csv);
+        for usage in [&["import"][..], &["import", "old.csv", "--map", "1"], &["import", "old.csv", "--bucket", "-1"]] {
+            assert_eq!(parse(usage).unwrap_err().exit_code(), 2, "{usage:?}");
+        }
+    }
+
+    #[test]
+    fn sensor_tables_line_up() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let named = DeviceInfo {
+            id: 0x2a,
+            name: Some("greenhouse".into()),
+            last_seen: Some(now - chrono::Duration::seconds(4)),
+            values: [(ReadingKind::Temperature, 21.5)].into(),
+            firmware_version: Some("1.2.0".into()),
+            ..DeviceInfo::default()
+        };
+        let table = sensor_table(&[named, DeviceInfo { id: 7, ..DeviceInfo::default() }], now);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("0000000000000000000000000000002a  greenhouse  online      4s ago  1.2.0"), "{}", lines[1]);
+        assert!(lines[1].ends_with("temperature 21.5°C"), "{}", lines[1]);
+        assert!(lines[2].contains("  -           online       never  -"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn compaction_summaries_say_whether_anything_changed() {
+        let report = CompactionReport {
+            rolled_up: 120,
+            expired: 0,
+            tiers: vec![TierReport { bucket_secs: 60, written: 2, pruned: 0, expired: 1 }],
+            dropped: vec![DroppedTier { bucket_secs: 300, rows: 5, merged_into: Some(3_600), written: 1 }],
+        };
+        assert_eq!(
+            compaction_summary(&report, true),
+            "would roll up 120 raw readings, would expire 0\n\
+             60s tier: 2 buckets written, 0 pruned, 1 expired\n\
+             300s tier dropped: 5 rows, 1 buckets merged into the 3600s tier\n\
+             would remove 126 rows in total\n"
+        );
+        assert!(compaction_summary(&report, false).starts_with("rolled up 120"));
+    }
+}
@@ -0,0 +1,370 @@
+//! Server configuration: a `chlorophyll.toml` file, then `CHLOROPHYLL_*` environment
+//! variables, then command-line flags, each overriding the one before.
+//!
+//! The file is `--config` or `CHLOROPHYLL_CONFIG` if either is given, which must then
+//! exist; otherwise `chlorophyll.toml` in the working directory, if there is one. Every
+//! key is optional:
+//!
+//! ```toml
+//! database = "chlorophyll.db"
+//!
+//! [http]
+//! bind = "0.0.0.0"
+//! port = 5001
+//!
+//! [multicast]
+//! group = "239.0.0.1"
+//! port = 5000
+//!
+//! [ingest]
+//! bucket_secs = 60
+//!
+//! [compaction]
+//! interval_secs = 3600  # overrides the retention policy's interval
+//!
+//! [retention]           # replaces the stored policy at startup; see `retention`
+//! max_age_secs = 63072000
+//! ```
+//!
+//! `sensor_server config check` validates the layered result, checking the ingest bucket
+//! against the stored retention policy when the database exists, and prints it.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chlorophyll_client::ClientConfig;
+use chlorophyll_client::db::{Db, RetentionPolicy};
+use chlorophyll_client::rollup::INGEST_BUCKET_SECS;
+use serde::{Deserialize, Serialize};
+
+use crate::retention::RetentionJson;
+
+/// Read when neither `--config` nor `CHLOROPHYLL_CONFIG` names a file.
+pub const DEFAULT_CONFIG_PATH: &str = "chlorophyll.toml";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Path of the history database.
+    pub database: String,
+    pub http: HttpConfig,
+    pub multicast: MulticastConfig,
+    pub ingest: IngestConfig,
+    pub compaction: CompactionConfig,
+    /// Replaces the stored retention policy at startup when set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention: Option<RetentionJson>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub bind: IpAddr,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MulticastConfig {
+    pub group: Ipv4Addr,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IngestConfig {
+    /// Readings are averaged over buckets this long before they are stored.
+    pub bucket_secs: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompactionConfig {
+    /// Seconds between compactions; `None` keeps the retention policy's own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<i64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            database: "chlorophyll.db".to_string(),
+            http: HttpConfig::default(),
+            multicast: MulticastConfig::default(),
+            ingest: IngestConfig::default(),
+            compaction: CompactionConfig::default(),
+            retention: None,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self { bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED), port: 5001 }
+    }
+}
+
+impl Default for MulticastConfig {
+    fn default() -> Self {
+        let client = ClientConfig::default();
+        Self { group: client.group, port: client.port }
+    }
+}
+
+impl Default for IngestConfig {
+    fn default() -> Self {
+        Self { bucket_secs: INGEST_BUCKET_SECS }
+    }
+}
+
+/// Flags that override the file and the environment; every one is optional.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct ConfigArgs {
+    /// Configuration file [default: chlorophyll.toml, if present]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// History database path
+    #[arg(long, global = true, value_name = "PATH")]
+    pub db: Option<String>,
+    /// Address the HTTP server binds to
+    #[arg(long, global = true, value_name = "ADDR")]
+    pub bind: Option<IpAddr>,
+    /// Port the HTTP server listens on
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    /// Multicast group sensors send to
+    #[arg(long, global = true, value_name = "ADDR")]
+    pub multicast_group: Option<Ipv4Addr>,
+    /// Multicast port sensors send to
+    #[arg(long, global = true, value_name = "PORT")]
+    pub multicast_port: Option<u16>,
+    /// Seconds readings are averaged over before they are stored
+    #[arg(long, global = true, value_name = "SECS")]
+    pub ingest_bucket: Option<i64>,
+    /// Seconds between history compactions
+    #[arg(long, global = true, value_name = "SECS")]
+    pub compaction_interval: Option<i64>,
+    /// TOML retention policy replacing the stored one
+    #[arg(long, global = true, value_name = "PATH")]
+    pub retention: Option<PathBuf>,
+}
+
+impl Config {
+    /// The file, the process environment and `args`, layered and validated.
+    pub fn load(args: &ConfigArgs) -> anyhow::Result<Self> {
+        Self::layered(args, |name| std::env::var(name).ok())
+    }
+
+    /// [`Config::load`] with the environment looked up through `env`.
+    pub fn layered(args: &ConfigArgs, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let path = match &args.config {
+            Some(path) => Some(path.clone()),
+            None => env_value(&env, "CHLOROPHYLL_CONFIG")?,
+        };
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?,
+            None => Self::default(),
+        };
+        config.apply_env(env)?;
+        config.apply_args(args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid {}", path.display()))
+    }
+
+    /// Override with the `CHLOROPHYLL_*` variables that are set and not empty.
+    pub fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+        if let Some(db) = env_value(&env, "CHLOROPHYLL_DB")? {
+            self.database = db;
+        }
+        self.http.bind = env_value(&env, "CHLOROPHYLL_HTTP_BIND")?.unwrap_or(self.http.bind);
+        self.http.port = env_value(&env, "CHLOROPHYLL_HTTP_PORT")?.unwrap_or(self.http.port);
+        self.multicast.group = env_value(&env, "CHLOROPHYLL_MULTICAST_GROUP")?.unwrap_or(self.multicast.group);
+        self.multicast.port = env_value(&env, "CHLOROPHYLL_MULTICAST_PORT")?.unwrap_or(self.multicast.port);
+        self.ingest.bucket_secs = env_value(&env, "CHLOROPHYLL_INGEST_BUCKET_SECS")?.unwrap_or(self.ingest.bucket_secs);
+        self.compaction.interval_secs =
+            env_value(&env, "CHLOROPHYLL_COMPACTION_INTERVAL_SECS")?.or(self.compaction.interval_secs);
+        if let Some(path) = env_value::<PathBuf>(&env, "CHLOROPHYLL_RETENTION")? {
+            self.retention = Some(crate::retention::load_file(&path)?);
+        }
+        Ok(())
+    }
+
+    /// Override with the flags that were given.
+    pub fn apply_args(&mut self, args: &ConfigArgs) -> anyhow::Result<()> {
+        if let Some(db) = &args.db {
+            self.database.clone_from(db);
+        }
+        self.http.bind = args.bind.unwrap_or(self.http.bind);
+        self.http.port = args.port.unwrap_or(self.http.port);
+        self.multicast.group = args.multicast_group.unwrap_or(self.multicast.group);
+        self.multicast.port = args.multicast_port.unwrap_or(self.multicast.port);
+        self.ingest.bucket_secs = args.ingest_bucket.unwrap_or(self.ingest.bucket_secs);
+        self.compaction.interval_secs = args.compaction_interval.or(self.compaction.interval_secs);
+        if let Some(path) = &args.retention {
+            self.retention = Some(crate::retention::load_file(path)?);
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.database.is_empty(), "database must name a file");
+        anyhow::ensure!(self.ingest.bucket_secs > 0, "ingest.bucket_secs must be positive");
+        if let Some(secs) = self.compaction.interval_secs {
+            anyhow::ensure!(secs > 0, "compaction.interval_secs must be positive");
+        }
+        if let Some(policy) = self.retention_policy()? {
+            crate::retention::check_ingest_bucket(&policy, self.ingest.bucket_secs)?;
+        }
+        Ok(())
+    }
+
+    /// The `[retention]` section as a policy, with `[compaction]`'s interval applied.
+    pub fn retention_policy(&self) -> anyhow::Result<Option<RetentionPolicy>> {
+        let Some(retention) = self.retention.clone() else {
+            return Ok(None);
+        };
+        let mut policy = retention.into_policy().context("invalid [retention]")?;
+        policy.interval_secs = self.compaction.interval_secs.unwrap_or(policy.interval_secs);
+        Ok(Some(policy))
+    }
+
+    /// The retention policy the server runs under: the `[retention]` section, else the one
+    /// stored in `db` (or the default), with `[compaction]`'s interval applied either way.
+    /// Errors if ingest buckets don't tile its finest tier.
+    pub async fn effective_retention(&self, db: &Db) -> anyhow::Result<RetentionPolicy> {
+        let policy = if let Some(policy) = self.retention_policy()? {
+            policy
+        } else {
+            let stored = crate::retention::current(db).await?;
+            RetentionPolicy { interval_secs: self.compaction.interval_secs.unwrap_or(stored.interval_secs), ..stored }
+        };
+        crate::retention::check_ingest_bucket(&policy, self.ingest.bucket_secs)?;
+        Ok(policy)
+    }
+
+    /// Check ingest buckets against the policy the server would run under, as
+    /// [`Config::effective_retention`] does, without creating or migrating the database.
+    /// Errors if it exists with a schema older than this build's.
+    pub async fn check_retention(&self) -> anyhow::Result<()> {
+        if !Path::new(&self.database).exists() {
+            let policy = self.retention_policy()?.unwrap_or_default();
+            return crate::retention::check_ingest_bucket(&policy, self.ingest.bucket_secs);
+        }
+        let db = Db::open_read_only(&self.database).await?;
+        let checked = self.effective_retention(&db).await.map(drop);
+        db.close().await;
+        checked
+    }
+
+    /// Check the [`Config::effective_retention`], then store it if the configuration sets
+    /// any of it. Returns whether anything was written.
+    pub async fn store_retention(&self, db: &Db) -> anyhow::Result<bool> {
+        let policy = self.effective_retention(db).await?;
+        if self.retention.is_none() && self.compaction.interval_secs.is_none() {
+            return Ok(false);
+        }
+        db.set_retention_policy(&policy).await?;
+        Ok(true)
+    }
+
+    /// Multicast settings for the sensor client, with everything else at its default.
+    #[must_use]
+    pub fn client_config(&self) -> ClientConfig {
+        ClientConfig { group: self.multicast.group, port: self.multicast.port, ..ClientConfig::default() }
+    }
+
+    /// The effective configuration as TOML, as it would be written in the file.
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+
+/// `name` parsed, if it is set and not empty.
+fn env_value<T>(env: impl Fn(&str) -> Option<String>, name: &str) -> anyhow::Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    env(name)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse().with_context(|| format!("invalid {name}={value:?}")))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn temp_file(tag: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("chlorophyll-config-{}-{tag}.toml", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn flags_override_env_which_overrides_the_file() {
+        let file = temp_file(
+            "layers",
+            "database = \"file.db\"\n[http]\nport = 8000\nbind = \"127.0.0.1\"\n[ingest]\nbucket_secs = 30\n",
+        );
+        let env: HashMap<&str, &str> = [
+            ("CHLOROPHYLL_HTTP_PORT", "8001"),
+            ("CHLOROPHYLL_INGEST_BUCKET_SECS", "20"),
+            ("CHLOROPHYLL_DB", ""),
+        ]
+        .into();
+        let args = ConfigArgs { config: Some(file.clone()), port: Some(8002), ..ConfigArgs::default() };
+
+        let config = Config::layered(&args, |name| env.get(name).map(ToString::to_string)).unwrap();
+        assert_eq!(config.database, "file.db", "empty variables are ignored");
+        assert_eq!(config.http.bind, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(config.http.port, 8002);
+        assert_eq!(config.ingest.bucket_secs, 20);
+        assert_eq!(config.multicast, MulticastConfig::default());
+
+        let printed: Config = toml::from_str(&config.to_toml().unwrap()).unwrap();
+        assert_eq!(printed, config, "what config check prints loads back the same");
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn retention_sections_take_the_compaction_interval() {
+        let config: Config =
+            toml::from_str("[compaction]\ninterval_secs = 600\n[retention]\ninterval_secs = 60\nvacuum = \"full\"\n")
+                .unwrap();
+        let policy = config.retention_policy().unwrap().unwrap();
+        assert_eq!(policy.interval_secs, 600);
+        assert_eq!(Config::default().retention_policy().unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let check = |text: &str| toml::from_str::<Config>(text).map_err(anyhow::Error::from)?.validate();
+        assert!(check("").is_ok());
+        assert!(check("[http]\nprot = 80").is_err(), "unknown keys are typos");
+        assert!(check("[multicast]\ngroup = \"example.com\"").is_err());
+        assert!(check("[ingest]\nbucket_secs = 0").is_err());
+        assert!(check("[compaction]\ninterval_secs = -5").is_err());
+        assert!(check("[retention]\nvacuum = \"sometimes\"").is_err());
+        assert!(check("[ingest]\nbucket_secs = 45\n[retention]").is_err(), "45s buckets don't tile 60s ones");
+        assert!(check("[ingest]\nbucket_secs = 30\n[retention]").is_ok());
+
+        let bad_env = |name: &'static str, value: &'static str| {
+            Config::default().apply_env(|n| (n == name).then(|| value.to_string())).is_err()
+        };
+        assert!(bad_env("CHLOROPHYLL_HTTP_PORT", "http"));
+        assert!(bad_env("CHLOROPHYLL_HTTP_BIND", "everywhere"));
+        assert!(bad_env("CHLOROPHYLL_RETENTION", "/nonexistent/retention.toml"));
+        let missing = ConfigArgs { config: Some("/nonexistent/chlorophyll.toml".into()), ..ConfigArgs::default() };
+        assert!(Config::layered(&missing, |_| None).is_err(), "a named file has to exist");
+    }
+}
@@ -1,14 +1,19 @@
 //! HTML dashboard: sensor table + per-metric history charts with periodic refreshes.
+//! Both are split into sections by sensor group once any groups exist.
+
+use std::collections::HashMap;
 
 use askama::Template;
 use axum::Router;
 use axum::extract::{Query, State};
 use axum::response::Html;
 use axum::routing::get;
+use chlorophyll_client::db::HistoryPoint;
 use chlorophyll_client::{DeviceInfo, ReadingKind};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
+use crate::groups::Labels;
 use crate::state::AppState;
 use crate::svg;
 
@@ -44,14 +49,18 @@ pub struct RangeQuery {
 pub struct SensorRow {
     pub name: String,
     pub id_hex: String,
-    pub temperature: Option<f32>,
-    pub humidity: Option<f32>,
-    pub light: Option<f32>,
+    pub location: Option<String>,
+    /// Name sent to the sensor that it hasn't confirmed yet.
+    pub pending_name: Option<String>,
+    /// Formatted value per table column; `None` where the sensor hasn't reported it.
+    pub cells: Vec<Option<String>>,
+    /// `online`, `stale` or `offline`; also the status dot's CSS class.
+    pub status: &'static str,
     pub age: String,
 }
 
-impl From<DeviceInfo> for SensorRow {
-    fn from(device: DeviceInfo) -> Self {
+impl SensorRow {
+    fn new(device: &DeviceInfo, location: Option<String>, columns: &[ReadingKind]) -> Self {
         let id_hex = format!("{:032x}", device.id);
         let name = device
             .name
@@ -60,9 +69,13 @@ impl From<DeviceInfo> for SensorRow {
         Self {
             name,
             id_hex,
-            temperature: device.temperature,
-            humidity: device.humidity,
-            light: device.light,
+            location,
+            pending_name: device.pending_name.clone(),
+            cells: columns
+                .iter()
+                .map(|&kind| device.value(kind).map(|v| kind.info().format(v)))
+                .collect(),
+            status: device.status.as_str(),
             age: format_age(device.last_seen),
         }
     }
@@ -82,16 +95,47 @@ fn format_age(last_seen: Option<chrono::DateTime<Utc>>) -> String {
     }
 }
 
+/// Part of the table or chart list under one group heading. The title is `None` when no
+/// groups exist, so an ungrouped install looks as it always has.
+pub struct Section<T> {
+    pub title: Option<String>,
+    pub items: Vec<T>,
+}
+
+/// Split `ids` into one section per group, in group-name order, followed by the ids in no
+/// group. A sensor in several groups appears under each; empty sections are dropped.
+fn sections(labels: &Labels, ids: &[u128]) -> Vec<Section<usize>> {
+    let mut sections: Vec<Section<usize>> = labels
+        .groups
+        .iter()
+        .map(|group| Section {
+            title: Some(group.name.clone()),
+            items: (0..ids.len()).filter(|&i| group.members.contains(&ids[i])).collect(),
+        })
+        .collect();
+    let ungrouped = (0..ids.len())
+        .filter(|&i| !labels.groups.iter().any(|g| g.members.contains(&ids[i])))
+        .collect();
+    sections.push(Section {
+        title: (!labels.groups.is_empty()).then(|| "Ungrouped".to_string()),
+        items: ungrouped,
+    });
+    sections.retain(|section| !section.items.is_empty());
+    sections
+}
+
 #[derive(Template)]
 #[template(path = "sensors_table.html")]
 struct SensorsTableTemplate {
-    rows: Vec<SensorRow>,
+    /// Metric names, one column each.
+    columns: Vec<&'static str>,
+    sections: Vec<Section<SensorRow>>,
 }
 
 #[derive(Template)]
 #[template(path = "sensor_charts.html")]
 struct SensorChartsTemplate {
-    charts: Vec<MetricChart>,
+    sections: Vec<Section<MetricChart>>,
     range_label: &'static str,
     range_key: &'static str,
     ranges: &'static [HistoryRange],
@@ -110,16 +154,78 @@ struct DashboardTemplate {
     range_key: &'static str,
 }
 
-fn build_rows(state: &AppState) -> Vec<SensorRow> {
+/// The sensor table, with a column for every metric some sensor has reported.
+async fn build_table(state: &AppState) -> Result<SensorsTableTemplate, axum::http::StatusCode> {
     let mut devices = state.client.devices();
-    devices.sort_by(|a, b| a.id.cmp(&b.id));
-    devices.into_iter().map(SensorRow::from).collect()
+    devices.sort_by_key(|d| d.id);
+    let labels = Labels::load(&state.db)
+        .await
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
+    let locations: HashMap<u128, String> = state
+        .db
+        .sensors()
+        .await
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?
+        .into_iter()
+        .filter_map(|record| Some((record.id, record.location?)))
+        .collect();
+
+    let columns: Vec<ReadingKind> = ReadingKind::ALL
+        .into_iter()
+        .filter(|&kind| devices.iter().any(|d| d.value(kind).is_some()))
+        .collect();
+    let ids: Vec<u128> = devices.iter().map(|d| d.id).collect();
+    let sections = sections(&labels, &ids)
+        .into_iter()
+        .map(|section| Section {
+            title: section.title,
+            items: section
+                .items
+                .into_iter()
+                .map(|i| SensorRow::new(&devices[i], locations.get(&ids[i]).cloned(), &columns))
+                .collect(),
+        })
+        .collect();
+    Ok(SensorsTableTemplate {
+        columns: columns.iter().map(|kind| kind.info().name).collect(),
+        sections,
+    })
+}
+
+/// A chart for every metric with history among `members`, indexes into `series`.
+/// Colours follow the position in `series`, so a sensor keeps its colour across sections.
+fn metric_charts(
+    series: &[(String, ReadingKind, Vec<HistoryPoint>)],
+    labels: &[String],
+    members: &[usize],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<MetricChart> {
+    let mut charts = Vec::new();
+    for kind in ReadingKind::ALL {
+        let info = kind.info();
+        let svg_series: Vec<svg::Series> = members
+            .iter()
+            .filter(|&&i| series[i].1 == kind)
+            .map(|&i| svg::Series {
+                label: labels[i].as_str(),
+                color: svg::series_color(i),
+                points: series[i].2.as_slice(),
+            })
+            .collect();
+        if svg_series.is_empty() {
+            continue;
+        }
+        let svg = svg::line_chart(&svg_series, from, to, &info.suffix(), info.chart_span);
+        charts.push(MetricChart { title: info.name, svg });
+    }
+    charts
 }
 
 async fn build_charts(
     state: &AppState,
     range: &HistoryRange,
-) -> Result<Vec<MetricChart>, axum::http::StatusCode> {
+) -> Result<Vec<Section<MetricChart>>, axum::http::StatusCode> {
     let now = Utc::now();
     let from = now - chrono::Duration::hours(range.hours);
 
@@ -128,49 +234,30 @@ async fn build_charts(
         .history_bucketed(from, now, range.bucket_secs)
         .await
         .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
+    let groups = Labels::load(&state.db)
+        .await
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    // Look up display names for the legend/title from the live registry.
-    let names: std::collections::HashMap<String, String> = state
-        .client
-        .devices()
-        .into_iter()
-        .map(|d| {
-            (
-                format!("{:032x}", d.id),
-                d.name.unwrap_or_else(|| format!("sensor {:032x}", d.id)),
-            )
-        })
+    // Display names for the legend/title, from the `sensors` table so sensors that have
+    // gone quiet keep their label.
+    let names = crate::api::sensor_names(state).await?;
+    let ids: Vec<u128> = series
+        .iter()
+        .map(|(id_hex, _, _)| crate::api::parse_id_hex(id_hex).unwrap_or_default())
+        .collect();
+    let labels: Vec<String> = series
+        .iter()
+        .zip(&ids)
+        .map(|((id_hex, _, _), id)| names.get(id).cloned().unwrap_or_else(|| format!("sensor {id_hex}")))
         .collect();
 
-    let metrics = [
-        (ReadingKind::Temperature, "Temperature", "\u{b0}C"),
-        (ReadingKind::Humidity, "Humidity", "%"),
-        (ReadingKind::Light, "Light", " lux"),
-    ];
-
-    let mut charts = Vec::with_capacity(metrics.len());
-    for (kind, title, unit) in metrics {
-        let mut chart_series = Vec::new();
-        for (i, (id_hex, series_kind, points)) in series.iter().enumerate() {
-            if *series_kind != kind {
-                continue;
-            }
-            let label = names.get(id_hex).map_or(id_hex.as_str(), String::as_str);
-            chart_series.push((label, svg::series_color(i), points.as_slice()));
-        }
-        let svg_series: Vec<svg::Series> = chart_series
-            .into_iter()
-            .map(|(label, color, points)| svg::Series {
-                label,
-                color,
-                points,
-            })
-            .collect();
-        let svg = svg::line_chart(&svg_series, from, now, unit);
-        charts.push(MetricChart { title, svg });
-    }
-
-    Ok(charts)
+    Ok(sections(&groups, &ids)
+        .into_iter()
+        .map(|section| Section {
+            title: section.title,
+            items: metric_charts(&series, &labels, &section.items, from, now),
+        })
+        .collect())
 }
 
 async fn dashboard(
@@ -179,14 +266,14 @@ async fn dashboard(
 ) -> Result<Html<String>, axum::http::StatusCode> {
     let range = resolve_range(query.range.as_deref());
 
-    let rows = build_rows(&state);
-    let table = SensorsTableTemplate { rows }
+    let table = build_table(&state)
+        .await?
         .render()
         .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    let charts = build_charts(&state, range).await?;
+    let sections = build_charts(&state, range).await?;
     let charts = SensorChartsTemplate {
-        charts,
+        sections,
         range_label: range.label,
         range_key: range.key,
         ranges: RANGES,
@@ -208,8 +295,8 @@ async fn dashboard(
 async fn sensors_table_partial(
     State(state): State<AppState>,
 ) -> Result<Html<String>, axum::http::StatusCode> {
-    let rows = build_rows(&state);
-    let body = SensorsTableTemplate { rows }
+    let body = build_table(&state)
+        .await?
         .render()
         .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
     Ok(Html(body))
@@ -220,9 +307,9 @@ async fn sensor_charts_partial(
     Query(query): Query<RangeQuery>,
 ) -> Result<Html<String>, axum::http::StatusCode> {
     let range = resolve_range(query.range.as_deref());
-    let charts = build_charts(&state, range).await?;
+    let sections = build_charts(&state, range).await?;
     let body = SensorChartsTemplate {
-        charts,
+        sections,
         range_label: range.label,
         range_key: range.key,
         ranges: RANGES,
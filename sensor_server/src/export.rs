@@ -0,0 +1,237 @@
+//! History export as CSV or JSON Lines, for pulling data into notebooks without writing
+//! SQL against the database.
+//!
+//! Rows are streamed from [`Db::export`] a chunk at a time, over HTTP at `/api/export`
+//! and to a file by the `sensor_server export` subcommand.
+
+use std::fmt::Write;
+
+use anyhow::Context;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use chlorophyll_client::db::{Db, ExportQuery, ExportRow};
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::api::{parse_id_hex, parse_measured_metric};
+use crate::state::AppState;
+
+/// Most rows formatted into one chunk; fewer when the database hasn't produced them yet.
+const CHUNK_ROWS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl Format {
+    pub(crate) fn parse(format: &str) -> Option<Self> {
+        match format {
+            "csv" => Some(Format::Csv),
+            "jsonl" => Some(Format::Jsonl),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Jsonl => "application/jsonl",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Jsonl => "jsonl",
+        }
+    }
+
+    pub(crate) fn header(self) -> &'static str {
+        match self {
+            Format::Csv => "timestamp,sensor_id,name,metric,value\n",
+            Format::Jsonl => "",
+        }
+    }
+
+    pub(crate) fn write_row(self, out: &mut String, row: &ExportRow) {
+        let timestamp = row.at.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        match self {
+            Format::Csv => {
+                let name = row.name.as_deref().map(csv_field).unwrap_or_default();
+                let _ = writeln!(out, "{timestamp},{:032x},{name},{},{}", row.sensor_id, row.kind.as_str(), row.value);
+            }
+            Format::Jsonl => {
+                let line = JsonRow {
+                    timestamp,
+                    sensor_id: format!("{:032x}", row.sensor_id),
+                    name: row.name.as_deref(),
+                    metric: row.kind.as_str(),
+                    value: row.value,
+                };
+                if let Ok(line) = serde_json::to_string(&line) {
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRow<'a> {
+    timestamp: String,
+    sensor_id: String,
+    name: Option<&'a str>,
+    metric: &'static str,
+    value: f32,
+}
+
+/// `field`, quoted if it holds a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Everything `query` matches, in `format`, as chunks of up to [`CHUNK_ROWS`] rows.
+pub fn chunks<'a>(db: &'a Db, query: &ExportQuery, format: Format) -> impl Stream<Item = anyhow::Result<String>> + Send + 'a {
+    let header = stream::iter((!format.header().is_empty()).then(|| Ok(format.header().to_string())));
+    let rows = db.export(query).ready_chunks(CHUNK_ROWS).map(move |rows| {
+        let mut chunk = String::new();
+        for row in rows {
+            format.write_row(&mut chunk, &row?);
+        }
+        Ok(chunk)
+    });
+    header.chain(rows)
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct ExportParams {
+    #[serde(default)]
+    pub format: Format,
+    /// Unix milliseconds; from the oldest reading when absent.
+    pub since: Option<i64>,
+    /// Unix milliseconds; up to the newest reading when absent.
+    pub until: Option<i64>,
+    /// Average into buckets this many seconds wide; rows as stored when absent.
+    pub bucket: Option<i64>,
+    /// Sensor id, hex.
+    pub sensor: Option<String>,
+    /// Metric id; only measured metrics are stored.
+    pub metric: Option<String>,
+}
+
+impl ExportParams {
+    /// `None` if a value doesn't parse or the bucket isn't positive.
+    pub(crate) fn query(&self) -> Option<ExportQuery> {
+        let time = |ms: Option<i64>| match ms {
+            Some(ms) => Utc.timestamp_millis_opt(ms).single().map(Some),
+            None => Some(None),
+        };
+        let sensor_id = match self.sensor.as_deref() {
+            Some(id_hex) => Some(parse_id_hex(id_hex)?),
+            None => None,
+        };
+        let kind = match self.metric.as_deref() {
+            Some(metric) => Some(parse_measured_metric(metric)?),
+            None => None,
+        };
+        if self.bucket.is_some_and(|secs| secs <= 0) {
+            return None;
+        }
+        Some(ExportQuery {
+            from: time(self.since)?,
+            to: time(self.until)?,
+            bucket_secs: self.bucket,
+            sensor_id,
+            kind,
+        })
+    }
+}
+
+pub(crate) fn default_file_name(format: Format, now: DateTime<Utc>) -> String {
+    format!("chlorophyll-{}.{}", now.format("%Y%m%dT%H%M%SZ"), format.extension())
+}
+
+async fn export(State(state): State<AppState>, Query(params): Query<ExportParams>) -> Result<Response, StatusCode> {
+    let query = params.query().ok_or(StatusCode::BAD_REQUEST)?;
+    let format = params.format;
+
+    // The database stream borrows the pool, so a task drives it and hands chunks over;
+    // the bounded channel keeps it from running ahead of the client.
+    let (tx, rx) = mpsc::channel::<anyhow::Result<String>>(4);
+    tokio::spawn(async move {
+        let mut chunks = chunks(&state.db, &query, format);
+        while let Some(chunk) = chunks.next().await {
+            if tx.send(chunk).await.is_err() {
+                break; // client went away
+            }
+        }
+    });
+    let body = stream::unfold(rx, |mut rx| async { rx.recv().await.map(|chunk| (chunk, rx)) });
+
+    let disposition = format!("attachment; filename=\"{}\"", default_file_name(format, Utc::now()));
+    Ok((
+        [(header::CONTENT_TYPE, format.content_type().to_string()), (header::CONTENT_DISPOSITION, disposition)],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/export", get(export))
+}
+
+/// Unix milliseconds or an RFC 3339 timestamp, as unix milliseconds.
+pub(crate) fn parse_time(value: &str) -> anyhow::Result<i64> {
+    if let Ok(ms) = value.parse() {
+        return Ok(ms);
+    }
+    let at = DateTime::parse_from_rfc3339(value)
+        .with_context(|| format!("{value:?} is neither unix milliseconds nor RFC 3339"))?;
+    Ok(at.timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use chlorophyll_client::ReadingKind;
+
+    use super::*;
+
+    #[test]
+    fn rows_format_as_csv_and_json_lines() {
+        let row = ExportRow {
+            at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            sensor_id: 1,
+            name: Some("bench, \"north\"".into()),
+            kind: ReadingKind::Temperature,
+            value: 21.5,
+        };
+        let mut csv = String::new();
+        Format::Csv.write_row(&mut csv, &row);
+        assert_eq!(
+            csv,
+            "2023-11-14T22:13:20Z,00000000000000000000000000000001,\"bench, \"\"north\"\"\",temperature,21.5\n"
+        );
+
+        let mut jsonl = String::new();
+        Format::Jsonl.write_row(&mut jsonl, &ExportRow { name: None, ..row });
+        let value: serde_json::Value = serde_json::from_str(jsonl.trim_end()).unwrap();
+        assert_eq!(value["name"], serde_json::Value::Null);
+        assert_eq!(value["metric"], "temperature");
+        assert_eq!(value["timestamp"], "2023-11-14T22:13:20Z");
+    }
+}
@@ -0,0 +1,347 @@
+//! Server-side sensor labels: free-form tags, a location, and named groups.
+//!
+//! None of this reaches the sensors; it lives in the database alongside the `sensors`
+//! table and is used to filter the API and to lay out the dashboard.
+
+use std::collections::HashMap;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use chlorophyll_client::db::{Db, SensorGroup};
+use serde::{Deserialize, Serialize};
+
+use crate::api::parse_id_hex;
+use crate::state::AppState;
+
+/// `group=` / `tag=` query parameters shared by the sensor and history endpoints. Both
+/// must match when both are given.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct SensorFilter {
+    /// Group id.
+    pub group: Option<i64>,
+    pub tag: Option<String>,
+}
+
+impl SensorFilter {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.group.is_none() && self.tag.is_none()
+    }
+}
+
+/// Every sensor's tags and groups, loaded together for filtering and display.
+#[derive(Debug, Default)]
+pub struct Labels {
+    tags: HashMap<u128, Vec<String>>,
+    pub groups: Vec<SensorGroup>,
+}
+
+impl Labels {
+    pub async fn load(db: &Db) -> anyhow::Result<Self> {
+        let mut tags: HashMap<u128, Vec<String>> = HashMap::new();
+        for (id, tag) in db.sensor_tags().await? {
+            tags.entry(id).or_default().push(tag);
+        }
+        Ok(Self {
+            tags,
+            groups: db.groups().await?,
+        })
+    }
+
+    #[must_use]
+    pub fn tags(&self, id: u128) -> &[String] {
+        self.tags.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Ids of the groups `id` belongs to.
+    #[must_use]
+    pub fn groups_of(&self, id: u128) -> Vec<i64> {
+        self.groups
+            .iter()
+            .filter(|group| group.members.contains(&id))
+            .map(|group| group.id)
+            .collect()
+    }
+
+    #[must_use]
+    pub fn matches(&self, id: u128, filter: &SensorFilter) -> bool {
+        let in_group = filter.group.is_none_or(|group| {
+            self.groups
+                .iter()
+                .any(|g| g.id == group && g.members.contains(&id))
+        });
+        let tagged = filter
+            .tag
+            .as_ref()
+            .is_none_or(|tag| self.tags(id).contains(tag));
+        in_group && tagged
+    }
+}
+
+/// A tag as accepted from a client: trimmed, and not empty.
+fn clean_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim();
+    (!tag.is_empty()).then(|| tag.to_string())
+}
+
+async fn get_tags(
+    State(state): State<AppState>,
+    Path(id_hex): Path<String>,
+) -> Result<Json<Vec<String>>, StatusCode> {
+    let id = parse_id_hex(&id_hex).ok_or(StatusCode::BAD_REQUEST)?;
+    let tags = state
+        .db
+        .tags_of(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(tags))
+}
+
+/// Replace a sensor's tags with the given list.
+async fn set_tags(
+    State(state): State<AppState>,
+    Path(id_hex): Path<String>,
+    Json(body): Json<Vec<String>>,
+) -> Result<Json<Vec<String>>, StatusCode> {
+    let id = parse_id_hex(&id_hex).ok_or(StatusCode::BAD_REQUEST)?;
+    let mut tags = body
+        .iter()
+        .map(|tag| clean_tag(tag))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    tags.sort();
+    tags.dedup();
+    state
+        .db
+        .set_sensor_tags(id, &tags)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(tags))
+}
+
+async fn add_tag(
+    State(state): State<AppState>,
+    Path((id_hex, tag)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let id = parse_id_hex(&id_hex).ok_or(StatusCode::BAD_REQUEST)?;
+    let tag = clean_tag(&tag).ok_or(StatusCode::BAD_REQUEST)?;
+    let added = state
+        .db
+        .add_sensor_tag(id, &tag)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(if added { StatusCode::CREATED } else { StatusCode::NO_CONTENT })
+}
+
+async fn remove_tag(
+    State(state): State<AppState>,
+    Path((id_hex, tag)): Path<(String, String)>,
+) -> Result<StatusCode, StatusCode> {
+    let id = parse_id_hex(&id_hex).ok_or(StatusCode::BAD_REQUEST)?;
+    let tag = clean_tag(&tag).ok_or(StatusCode::BAD_REQUEST)?;
+    let removed = state
+        .db
+        .remove_sensor_tag(id, &tag)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !removed {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetLocationRequest {
+    /// `null` or empty clears the location.
+    pub location: Option<String>,
+}
+
+async fn set_location(
+    State(state): State<AppState>,
+    Path(id_hex): Path<String>,
+    Json(body): Json<SetLocationRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let id = parse_id_hex(&id_hex).ok_or(StatusCode::BAD_REQUEST)?;
+    let location = body.location.as_deref().map(str::trim).filter(|l| !l.is_empty());
+    match state.db.set_sensor_location(id, location).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GroupRequest {
+    pub name: String,
+    /// Member sensor ids, hex.
+    #[serde(default)]
+    pub sensors: Vec<String>,
+}
+
+impl GroupRequest {
+    /// `None` if the name is blank or a sensor id isn't hex.
+    fn into_group(self, id: i64) -> Option<SensorGroup> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut members = self
+            .sensors
+            .iter()
+            .map(|id_hex| parse_id_hex(id_hex))
+            .collect::<Option<Vec<_>>>()?;
+        members.sort_unstable();
+        members.dedup();
+        Some(SensorGroup {
+            id,
+            name: name.to_string(),
+            members,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupJson {
+    pub id: i64,
+    pub name: String,
+    pub sensors: Vec<String>,
+}
+
+impl From<SensorGroup> for GroupJson {
+    fn from(group: SensorGroup) -> Self {
+        Self {
+            id: group.id,
+            name: group.name,
+            sensors: group.members.iter().map(|id| format!("{id:032x}")).collect(),
+        }
+    }
+}
+
+/// Whether another group than `id` already has `name`.
+async fn name_taken(db: &Db, name: &str, id: i64) -> Result<bool, StatusCode> {
+    let groups = db.groups().await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(groups.iter().any(|g| g.name == name && g.id != id))
+}
+
+async fn list_groups(State(state): State<AppState>) -> Result<Json<Vec<GroupJson>>, StatusCode> {
+    let groups = state
+        .db
+        .groups()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(groups.into_iter().map(GroupJson::from).collect()))
+}
+
+async fn create_group(
+    State(state): State<AppState>,
+    Json(body): Json<GroupRequest>,
+) -> Result<(StatusCode, Json<GroupJson>), StatusCode> {
+    let mut group = body.into_group(0).ok_or(StatusCode::BAD_REQUEST)?;
+    if name_taken(&state.db, &group.name, 0).await? {
+        return Err(StatusCode::CONFLICT);
+    }
+    group.id = state
+        .db
+        .insert_group(&group)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok((StatusCode::CREATED, Json(GroupJson::from(group))))
+}
+
+async fn get_group(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<GroupJson>, StatusCode> {
+    state
+        .db
+        .group(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(|group| Json(GroupJson::from(group)))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn update_group(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(body): Json<GroupRequest>,
+) -> Result<Json<GroupJson>, StatusCode> {
+    let group = body.into_group(id).ok_or(StatusCode::BAD_REQUEST)?;
+    if name_taken(&state.db, &group.name, id).await? {
+        return Err(StatusCode::CONFLICT);
+    }
+    let updated = state
+        .db
+        .update_group(&group)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !updated {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(Json(GroupJson::from(group)))
+}
+
+async fn delete_group(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+    let deleted = state
+        .db
+        .delete_group(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !deleted {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/sensors/{id_hex}/tags", get(get_tags).put(set_tags))
+        .route("/api/sensors/{id_hex}/tags/{tag}", put(add_tag).delete(remove_tag))
+        .route("/api/sensors/{id_hex}/location", put(set_location))
+        .route("/api/groups", get(list_groups).post(create_group))
+        .route(
+            "/api/groups/{id}",
+            get(get_group).put(update_group).delete(delete_group),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_match_on_group_and_tag_together() {
+        let mut labels = Labels {
+            groups: vec![SensorGroup { id: 1, name: "bench".into(), members: vec![7, 8] }],
+            ..Labels::default()
+        };
+        labels.tags.insert(7, vec!["tomato".into()]);
+
+        let by_group = SensorFilter { group: Some(1), tag: None };
+        let by_both = SensorFilter { group: Some(1), tag: Some("tomato".into()) };
+        assert!(labels.matches(8, &SensorFilter::default()));
+        assert!(labels.matches(8, &by_group));
+        assert!(!labels.matches(9, &by_group));
+        assert!(labels.matches(7, &by_both));
+        assert!(!labels.matches(8, &by_both));
+        assert!(!labels.matches(7, &SensorFilter { group: Some(2), tag: None }));
+        assert_eq!(labels.groups_of(7), vec![1]);
+    }
+
+    #[test]
+    fn group_requests_are_validated() {
+        let request = |name: &str, sensors: &[&str]| GroupRequest {
+            name: name.into(),
+            sensors: sensors.iter().map(ToString::to_string).collect(),
+        };
+        let group = request(" bench ", &["8", "7", "0x8"]).into_group(3).unwrap();
+        assert_eq!(group, SensorGroup { id: 3, name: "bench".into(), members: vec![7, 8] });
+        assert!(request("  ", &[]).into_group(0).is_none());
+        assert!(request("bench", &["zz"]).into_group(0).is_none());
+    }
+}
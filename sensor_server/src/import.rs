@@ -0,0 +1,436 @@
+//! Bulk import of historical readings from CSV or JSON Lines: exports from another
+//! database (the layout [`crate::export`] writes) or logs from an older logger.
+//!
+//! Rows are validated one by one and invalid ones reported rather than aborting the
+//! import. Valid rows are inserted in batched transactions that skip readings already
+//! stored, or falling in a bucket already rolled up, so re-importing the same file adds
+//! nothing. Imported history is compacted afterwards like any other.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::Json;
+use chlorophyll_client::db::Db;
+use chlorophyll_client::{Reading, ReadingKind};
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::api::{parse_id_hex, parse_measured_metric};
+use crate::export::Format;
+use crate::state::AppState;
+
+/// Readings inserted per transaction.
+const BATCH_ROWS: usize = 1_000;
+
+/// Invalid rows reported individually; the rest are only counted.
+const MAX_ERRORS: usize = 100;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportOptions {
+    pub format: Format,
+    /// Width of the window each row averages, as stored in `bucket_secs`; 0 for raw samples.
+    pub bucket_secs: i64,
+    /// Sensor ids to store under a different id, e.g. a sensor that was re-flashed.
+    pub map: HashMap<u128, u128>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RowError {
+    /// 1-based line in the input.
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    /// Data rows read, valid or not.
+    pub rows: u64,
+    pub inserted: u64,
+    /// Valid rows skipped because the reading, or the rollup bucket it falls in, was
+    /// already stored.
+    pub duplicates: u64,
+    pub rejected: u64,
+    /// The first [`MAX_ERRORS`] rejected rows and why.
+    pub errors: Vec<RowError>,
+    /// History rows removed by the compaction run after the import.
+    pub compacted: u64,
+}
+
+/// Positions of the CSV columns; `sensor_id` and `name` are each optional, but a row
+/// needs one of them.
+#[derive(Debug, Clone, Copy)]
+struct Columns {
+    timestamp: usize,
+    sensor_id: Option<usize>,
+    name: Option<usize>,
+    metric: usize,
+    value: usize,
+}
+
+impl Columns {
+    fn from_header(fields: &[String]) -> anyhow::Result<Self> {
+        let find = |column: &str| fields.iter().position(|field| field.trim() == column);
+        let required = |column: &str| find(column).with_context(|| format!("CSV header has no {column} column"));
+        let columns = Self {
+            timestamp: required("timestamp")?,
+            sensor_id: find("sensor_id"),
+            name: find("name"),
+            metric: required("metric")?,
+            value: required("value")?,
+        };
+        anyhow::ensure!(
+            columns.sensor_id.is_some() || columns.name.is_some(),
+            "CSV header needs a sensor_id or a name column"
+        );
+        Ok(columns)
+    }
+}
+
+/// A row as read, before validation.
+#[derive(Debug, Default, Deserialize)]
+struct RawRow {
+    timestamp: Timestamp,
+    sensor_id: Option<String>,
+    name: Option<String>,
+    metric: String,
+    value: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Timestamp {
+    /// Unix milliseconds.
+    Millis(i64),
+    /// RFC 3339, or unix milliseconds in a CSV field.
+    Text(String),
+}
+
+impl Default for Timestamp {
+    fn default() -> Self {
+        Timestamp::Millis(0)
+    }
+}
+
+impl Timestamp {
+    fn parse(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Timestamp::Millis(ms) => Utc.timestamp_millis_opt(*ms).single(),
+            Timestamp::Text(text) => match text.trim().parse::<i64>() {
+                Ok(ms) => Utc.timestamp_millis_opt(ms).single(),
+                Err(_) => DateTime::parse_from_rfc3339(text.trim()).ok().map(|at| at.to_utc()),
+            },
+        }
+    }
+}
+
+/// First and last imported reading of a sensor, and the name rows gave it.
+#[derive(Debug)]
+struct ImportedSensor {
+    name: Option<String>,
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+}
+
+/// Feeds input a line at a time into the database.
+pub struct Importer<'a> {
+    db: &'a Db,
+    options: ImportOptions,
+    /// Known sensors by name, for rows without an id. Names shared by several sensors
+    /// are left out, since they can't be resolved.
+    ids_by_name: HashMap<String, u128>,
+    columns: Option<Columns>,
+    line: usize,
+    batch: Vec<Reading>,
+    sensors: HashMap<u128, ImportedSensor>,
+    summary: ImportSummary,
+}
+
+impl<'a> Importer<'a> {
+    pub async fn new(db: &'a Db, options: ImportOptions) -> anyhow::Result<Self> {
+        let mut ids_by_name: HashMap<String, Option<u128>> = HashMap::new();
+        for record in db.sensors().await? {
+            if let Some(name) = record.name {
+                ids_by_name
+                    .entry(name)
+                    .and_modify(|id| *id = None)
+                    .or_insert(Some(record.id));
+            }
+        }
+        Ok(Self {
+            db,
+            options,
+            ids_by_name: ids_by_name
+                .into_iter()
+                .filter_map(|(name, id)| Some((name, id?)))
+                .collect(),
+            columns: None,
+            line: 0,
+            batch: Vec::with_capacity(BATCH_ROWS),
+            sensors: HashMap::new(),
+            summary: ImportSummary::default(),
+        })
+    }
+
+    /// Take one line of input. Errors only for input that can't be imported at all (a
+    /// CSV header without the needed columns) or a database failure.
+    pub async fn push_line(&mut self, line: &str) -> anyhow::Result<()> {
+        self.line += 1;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        let raw = match self.options.format {
+            Format::Csv => {
+                let Some(fields) = split_csv(line) else {
+                    self.reject("unbalanced quotes".to_string());
+                    return Ok(());
+                };
+                let Some(columns) = self.columns else {
+                    self.columns = Some(Columns::from_header(&fields)?);
+                    return Ok(());
+                };
+                csv_row(&fields, columns)
+            }
+            Format::Jsonl => serde_json::from_str::<RawRow>(line).map_err(|e| e.to_string()),
+        };
+        self.summary.rows += 1;
+        match raw.and_then(|raw| self.validate(raw)) {
+            Ok((reading, name)) => {
+                self.note_sensor(&reading, name);
+                self.batch.push(reading);
+                if self.batch.len() >= BATCH_ROWS {
+                    self.flush().await?;
+                }
+            }
+            Err(message) => self.reject(message),
+        }
+        Ok(())
+    }
+
+    /// Insert what's left, record the sensors seen, and compact.
+    pub async fn finish(mut self) -> anyhow::Result<ImportSummary> {
+        self.flush().await?;
+        for (id, sensor) in &self.sensors {
+            self.db
+                .upsert_imported_sensor(*id, sensor.name.as_deref(), sensor.first, sensor.last)
+                .await?;
+        }
+        if self.summary.inserted > 0 {
+            let policy = crate::retention::current(self.db).await?;
+            self.summary.compacted = self.db.compact(Utc::now(), &policy).await?.removed();
+        }
+        Ok(self.summary)
+    }
+
+    fn validate(&self, raw: RawRow) -> Result<(Reading, Option<String>), String> {
+        let at = raw.timestamp.parse().ok_or("invalid timestamp")?;
+        let name = raw.name.map(|name| name.trim().to_string()).filter(|name| !name.is_empty());
+        let sensor_id = match (raw.sensor_id.as_deref().map(str::trim).filter(|id| !id.is_empty()), &name) {
+            (Some(id_hex), _) => parse_id_hex(id_hex).ok_or("invalid sensor_id")?,
+            (None, Some(name)) => *self
+                .ids_by_name
+                .get(name)
+                .ok_or_else(|| format!("no single known sensor is named {name:?}"))?,
+            (None, None) => return Err("no sensor_id or name".to_string()),
+        };
+        let sensor_id = self.options.map.get(&sensor_id).copied().unwrap_or(sensor_id);
+        let kind: ReadingKind = match parse_measured_metric(raw.metric.trim()) {
+            Some(kind) => kind,
+            None => return Err(format!("unknown or derived metric {:?}", raw.metric)),
+        };
+        #[allow(clippy::cast_possible_truncation)]
+        let value = raw.value as f32;
+        if !value.is_finite() {
+            return Err("value is not a finite number".to_string());
+        }
+        Ok((Reading { sensor_id, kind, value, at }, name))
+    }
+
+    fn note_sensor(&mut self, reading: &Reading, name: Option<String>) {
+        let sensor = self.sensors.entry(reading.sensor_id).or_insert(ImportedSensor {
+            name: None,
+            first: reading.at,
+            last: reading.at,
+        });
+        sensor.first = sensor.first.min(reading.at);
+        sensor.last = sensor.last.max(reading.at);
+        if name.is_some() {
+            sensor.name = name;
+        }
+    }
+
+    fn reject(&mut self, message: String) {
+        self.summary.rejected += 1;
+        if self.summary.errors.len() < MAX_ERRORS {
+            self.summary.errors.push(RowError { line: self.line, message });
+        }
+    }
+
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let inserted = self.db.import_readings(&self.batch, self.options.bucket_secs).await?;
+        self.summary.inserted += inserted;
+        self.summary.duplicates += self.batch.len() as u64 - inserted;
+        self.batch.clear();
+        Ok(())
+    }
+}
+
+fn csv_row(fields: &[String], columns: Columns) -> Result<RawRow, String> {
+    let field = |index: usize| fields.get(index).cloned().ok_or_else(|| format!("missing column {}", index + 1));
+    let optional = |index: Option<usize>| index.and_then(|index| fields.get(index).cloned());
+    let value = field(columns.value)?;
+    Ok(RawRow {
+        timestamp: Timestamp::Text(field(columns.timestamp)?),
+        sensor_id: optional(columns.sensor_id),
+        name: optional(columns.name),
+        metric: field(columns.metric)?,
+        value: value.trim().parse().map_err(|_| format!("invalid value {value:?}"))?,
+    })
+}
+
+/// The fields of one CSV line, with quoted fields unquoted. `None` if a quote is left
+/// open; quoted line breaks aren't supported.
+fn split_csv(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    (!quoted).then_some(fields)
+}
+
+/// `OLD=NEW`, both hex sensor ids.
+pub(crate) fn parse_mapping(mapping: &str) -> Option<(u128, u128)> {
+    let (old, new) = mapping.split_once('=')?;
+    Some((parse_id_hex(old.trim())?, parse_id_hex(new.trim())?))
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct ImportParams {
+    #[serde(default)]
+    pub format: Format,
+    /// Seconds each row averages; 0 (raw samples) when absent.
+    #[serde(default)]
+    pub bucket: i64,
+    /// Comma-separated `OLD=NEW` sensor id pairs.
+    pub map: Option<String>,
+}
+
+impl ImportParams {
+    fn options(&self) -> Option<ImportOptions> {
+        if self.bucket < 0 {
+            return None;
+        }
+        let map = match self.map.as_deref() {
+            Some(map) => map.split(',').map(parse_mapping).collect::<Option<_>>()?,
+            None => HashMap::new(),
+        };
+        Some(ImportOptions {
+            format: self.format,
+            bucket_secs: self.bucket,
+            map,
+        })
+    }
+}
+
+async fn import(
+    State(state): State<AppState>,
+    Query(params): Query<ImportParams>,
+    body: Body,
+) -> Result<Json<ImportSummary>, StatusCode> {
+    let options = params.options().ok_or(StatusCode::BAD_REQUEST)?;
+    let mut importer = Importer::new(&state.db, options)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // Split the body into lines as it arrives rather than buffering all of it.
+    let mut body = body.into_data_stream();
+    let mut pending = Vec::new();
+    loop {
+        let chunk = body.next().await.transpose().map_err(|_| StatusCode::BAD_REQUEST)?;
+        let done = chunk.is_none();
+        match chunk {
+            Some(chunk) => pending.extend_from_slice(&chunk),
+            None if pending.is_empty() => break,
+            None => pending.push(b'\n'),
+        }
+        while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = std::str::from_utf8(&line[..end]).map_err(|_| StatusCode::BAD_REQUEST)?;
+            push(&mut importer, line).await?;
+        }
+        if done {
+            break;
+        }
+    }
+
+    let summary = importer.finish().await.map_err(|e| {
+        error!("import failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if summary.compacted > 0 {
+        state.counters.record_compaction(summary.compacted);
+    }
+    Ok(Json(summary))
+}
+
+async fn push(importer: &mut Importer<'_>, line: &str) -> Result<(), StatusCode> {
+    let header = importer.columns.is_none() && importer.options.format == Format::Csv;
+    importer.push_line(line).await.map_err(|e| {
+        if header {
+            StatusCode::BAD_REQUEST
+        } else {
+            error!("import failed: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/import", post(import))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_fields_unquote_like_the_export_writes_them() {
+        assert_eq!(
+            split_csv(r#"2023-11-14T22:13:20Z,01,"bench, ""north""",temperature,21.5"#).unwrap(),
+            ["2023-11-14T22:13:20Z", "01", "bench, \"north\"", "temperature", "21.5"]
+        );
+        assert_eq!(split_csv("a,,b").unwrap(), ["a", "", "b"]);
+        assert_eq!(split_csv(r#"a,"open"#), None);
+    }
+
+    #[test]
+    fn timestamps_are_millis_or_rfc_3339() {
+        let at = DateTime::from_timestamp(1_700_000_000, 0);
+        assert_eq!(Timestamp::Millis(1_700_000_000_000).parse(), at);
+        assert_eq!(Timestamp::Text("1700000000000".into()).parse(), at);
+        assert_eq!(Timestamp::Text("2023-11-15T00:13:20+02:00".into()).parse(), at);
+        assert_eq!(Timestamp::Text("yesterday".into()).parse(), None);
+    }
+}
@@ -0,0 +1,76 @@
+//! Persisting readings as they arrive.
+//!
+//! Readings arrive at ~5 Hz per metric; they are averaged into one row per
+//! [`AppState::ingest_bucket_secs`] (a minute by default) rather than persisting every
+//! sample, and handed to the [`AppState::writer`] to be committed in batches. The
+//! dashboard's live values come from the in-memory registry, so this costs no visible
+//! freshness.
+//!
+//! On shutdown, readings still queued and every open bucket, partial as they are, are
+//! written before [`run`] returns, so a clean restart loses only what arrives while the
+//! server is down.
+
+use chlorophyll_client::Reading;
+use chlorophyll_client::rollup::{Averaged, ReadingAggregator};
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+use crate::state::AppState;
+
+async fn insert(state: &AppState, averaged: Averaged) {
+    if let Err(e) = state.writer.write(averaged, state.ingest_bucket_secs).await {
+        state.counters.record_db_insert_error();
+        tracing::error!("DB insert error: {e}");
+    }
+}
+
+/// Average `readings` into the database until the server shuts down, then flush.
+pub async fn run(state: AppState, mut readings: broadcast::Receiver<Reading>) {
+    let mut aggregator = ReadingAggregator::new(state.ingest_bucket_secs);
+    let mut flush = tokio::time::interval(std::time::Duration::from_secs(state.ingest_bucket_secs.unsigned_abs()));
+
+    loop {
+        tokio::select! {
+            received = readings.recv() => match received {
+                Ok(reading) => {
+                    if let Some(averaged) = aggregator.push(&reading) {
+                        insert(&state, averaged).await;
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    state.counters.record_lagged(n);
+                    tracing::warn!("readings channel lagged, dropped {n} messages");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // Closes buckets for sensors that stopped transmitting mid-window.
+            _ = flush.tick() => {
+                for averaged in aggregator.drain_before(Utc::now()) {
+                    insert(&state, averaged).await;
+                }
+            }
+            () = state.shutting_down() => break,
+        }
+    }
+
+    loop {
+        match readings.try_recv() {
+            Ok(reading) => {
+                if let Some(averaged) = aggregator.push(&reading) {
+                    insert(&state, averaged).await;
+                }
+            }
+            Err(TryRecvError::Lagged(n)) => state.counters.record_lagged(n),
+            Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+        }
+    }
+    let open = aggregator.drain_before(DateTime::<Utc>::MAX_UTC);
+    tracing::info!("flushing {} open reading buckets", open.len());
+    for averaged in open {
+        insert(&state, averaged).await;
+    }
+    if let Err(e) = state.writer.flush().await {
+        tracing::error!("cannot flush readings: {e}");
+    }
+}
@@ -1,18 +1,42 @@
 #![warn(clippy::pedantic)]
+#![allow(clippy::missing_errors_doc)]
 
+pub mod alerts;
 pub mod api;
+pub mod calibration;
+pub mod cli;
+pub mod config;
 pub mod dashboard;
+pub mod export;
+pub mod groups;
+pub mod import;
+pub mod ingest;
+pub mod metrics;
+pub mod mqtt;
+pub mod retention;
+pub mod sensors;
 pub mod state;
+pub mod stream;
 pub mod svg;
+pub mod webhooks;
 
 use axum::Router;
 use axum::routing::get;
 
 pub use state::AppState;
 
-/// Combined router for the JSON API and HTML dashboard.
+/// Combined router for the JSON API, event stream and HTML dashboard.
 pub fn router() -> Router<AppState> {
     api::router()
+        .merge(groups::router())
+        .merge(calibration::router())
+        .merge(alerts::router())
+        .merge(stream::router())
+        .merge(webhooks::router())
+        .merge(export::router())
+        .merge(import::router())
+        .merge(retention::router())
+        .merge(metrics::router())
         .merge(dashboard::router())
         .route("/healthz", get(|| async { "ok" }))
 }
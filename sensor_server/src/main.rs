@@ -1,128 +1,360 @@
 #![warn(clippy::pedantic)]
 
+use std::io::{BufRead, Write};
+use std::process::ExitCode;
 use std::sync::Arc;
+use std::time::Duration;
 
 use chlorophyll_client::db::Db;
-use chlorophyll_client::rollup::{INGEST_BUCKET_SECS, ReadingAggregator};
-use chlorophyll_client::{ClientConfig, SensorClient};
+use chlorophyll_client::{AuthConfig, ClientConfig, DeviceInfo, RenameError, SensorClient, StatusTimeouts};
 use chrono::Utc;
+use clap::{Parser, Subcommand};
+use futures_util::StreamExt;
 use sensor_server::AppState;
-use tracing::*;
+use sensor_server::cli::{
+    self, CompactArgs, ExportArgs, HistoryArgs, ImportArgs, ListArgs, RequestInfoArgs, SetNameArgs,
+};
+use sensor_server::config::{Config, ConfigArgs};
+use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
-const DEFAULT_HTTP_PORT: u16 = 5001;
+/// Collects sensor readings from the multicast group and serves their history over HTTP.
+///
+/// Settings come from chlorophyll.toml, then CHLOROPHYLL_* environment variables, then
+/// these flags.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
 
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Collect readings and serve the API and dashboard (the default)
+    Serve,
+    /// Sensors on the multicast group
+    Sensors {
+        #[command(subcommand)]
+        action: SensorsAction,
+    },
+    /// Print one stored series, or write it to a file
+    History(HistoryArgs),
+    /// Apply the retention policy to stored history now
+    Compact(CompactArgs),
+    /// Ask every sensor to announce itself, and print those that answer
+    RequestInfo(RequestInfoArgs),
+    /// Rename a sensor, waiting for it to confirm
+    SetName(SetNameArgs),
+    /// Write stored history to a file
+    Export(ExportArgs),
+    /// Load history exported elsewhere
+    Import(ImportArgs),
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SensorsAction {
+    /// Listen for a while, then print every sensor heard
+    List(ListArgs),
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Validate the file, environment and flags together and print the effective config
+    Check,
+}
+
+/// Exit codes are listed in [`sensor_server::cli`].
 #[tokio::main]
-async fn main() -> color_eyre::Result<()> {
+async fn main() -> color_eyre::Result<ExitCode> {
     color_eyre::install()?;
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
-        .init();
-
-    let args: Vec<String> = std::env::args().collect();
-
-    // set-name <hex_id> <name> — broadcast SetName to the multicast group
-    if args.get(1).map(String::as_str) == Some("set-name") {
-        let id_hex = args
-            .get(2)
-            .expect("usage: sensor_server set-name <hex_id> <name>");
-        let name = args
-            .get(3)
-            .expect("usage: sensor_server set-name <hex_id> <name>");
-        let sensor_id = u128::from_str_radix(id_hex.trim_start_matches("0x"), 16)
-            .expect("invalid sensor id hex");
-
-        let client = SensorClient::start(ClientConfig::default())
-            .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
-        client
-            .set_name(sensor_id, name)
-            .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
-        info!("Sent SetName(\"{name}\") for sensor {sensor_id:032x}");
-        return Ok(());
+    let cli = Cli::parse();
+    // Log to stderr when stdout carries a command's output.
+    let logging = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()));
+    if matches!(cli.command, None | Some(Command::Serve)) {
+        logging.init();
+    } else {
+        logging.with_writer(std::io::stderr).init();
     }
 
-    // Normal server mode
-    let db_path = std::env::var("CHLOROPHYLL_DB").unwrap_or_else(|_| "chlorophyll.db".to_string());
-    let db = Db::open(&db_path)
-        .await
-        .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
-    info!("Database opened at {db_path}");
+    let config = Config::load(&cli.config).map_err(|e| color_eyre::eyre::eyre!("{e:#}"))?;
 
-    let client = Arc::new(
-        SensorClient::start(ClientConfig::default()).map_err(|e| color_eyre::eyre::eyre!("{e}"))?,
-    );
-    info!("Listening for sensor readings");
+    match cli.command {
+        None | Some(Command::Serve) => serve(config).await.map(|()| ExitCode::SUCCESS),
+        Some(Command::Sensors { action: SensorsAction::List(args) }) => {
+            let client = start_client(&config)?;
+            tokio::time::sleep(Duration::from_secs(args.listen)).await;
+            Ok(print_sensors(&client.devices(), || format!("no sensors heard within {}s", args.listen)))
+        }
+        Some(Command::History(args)) => history(&config, &args).await,
+        Some(Command::Compact(args)) => compact(&config, &args).await.map(|()| ExitCode::SUCCESS),
+        Some(Command::RequestInfo(args)) => {
+            let client = start_client(&config)?;
+            client
+                .request_sensor_info()
+                .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+            tokio::time::sleep(Duration::from_secs(args.wait)).await;
+            Ok(print_sensors(&client.devices(), || format!("no sensor answered within {}s", args.wait)))
+        }
+        Some(Command::SetName(args)) => set_name(&config, &args).await,
+        Some(Command::Export(args)) => export(&config.database, &args).await.map(|()| ExitCode::SUCCESS),
+        Some(Command::Import(args)) => import(&config.database, &args).await.map(|()| ExitCode::SUCCESS),
+        Some(Command::Config { action: ConfigAction::Check }) => {
+            config.check_retention().await.map_err(|e| color_eyre::eyre::eyre!("{e:#}"))?;
+            print!("{}", config.to_toml().map_err(|e| color_eyre::eyre::eyre!("{e}"))?);
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
 
-    let port = std::env::var("CHLOROPHYLL_HTTP_PORT")
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(DEFAULT_HTTP_PORT);
+fn start_client(config: &Config) -> color_eyre::Result<SensorClient> {
+    SensorClient::start(client_config(config)).map_err(|e| color_eyre::eyre::eyre!("{e}"))
+}
+
+/// Print `devices`, or exit with [`cli::EXIT_TIMEOUT`] if there are none.
+fn print_sensors(devices: &[DeviceInfo], none_heard: impl FnOnce() -> String) -> ExitCode {
+    if devices.is_empty() {
+        error!("{}", none_heard());
+        return ExitCode::from(cli::EXIT_TIMEOUT);
+    }
+    print!("{}", cli::sensor_table(devices, Utc::now()));
+    ExitCode::SUCCESS
+}
+
+async fn set_name(config: &Config, args: &SetNameArgs) -> color_eyre::Result<ExitCode> {
+    let sensor_id = cli::sensor_id(&args.id).map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
 
-    let state = AppState {
-        client: client.clone(),
-        db: db.clone(),
+    let client = start_client(config)?;
+    let rename = client
+        .set_name_with(sensor_id, &args.name, args.retry())
+        .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    info!("Sent SetName(\"{}\") for sensor {sensor_id:032x}", args.name);
+    match rename.await {
+        Ok(()) => {
+            info!("Sensor {sensor_id:032x} is now named \"{}\"", args.name);
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(RenameError::TimedOut) => {
+            error!("sensor {sensor_id:032x} didn't confirm the new name after {} attempts", args.attempts);
+            Ok(ExitCode::from(cli::EXIT_TIMEOUT))
+        }
+        Err(e) => Err(color_eyre::eyre::eyre!("{e}")),
+    }
+}
+
+async fn history(config: &Config, args: &HistoryArgs) -> color_eyre::Result<ExitCode> {
+    let db = Db::open(&config.database)
+        .await
+        .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    let rows = match &args.output {
+        Some(path) => {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            cli::history(&db, args, &mut file).await
+        }
+        None => cli::history(&db, args, &mut std::io::stdout().lock()).await,
+    }
+    .map_err(|e| color_eyre::eyre::eyre!("{e:#}"))?;
+    if rows == 0 {
+        error!("no {} readings from sensor {} in that range", args.metric, args.sensor);
+        return Ok(ExitCode::from(cli::EXIT_NO_DATA));
+    }
+    if let Some(path) = &args.output {
+        info!("Wrote {rows} rows to {}", path.display());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Compact under the configured retention policy, or the stored one if none is
+/// configured.
+async fn compact(config: &Config, args: &CompactArgs) -> color_eyre::Result<()> {
+    let db = Db::open(&config.database)
+        .await
+        .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    let policy = match config.retention_policy().map_err(|e| color_eyre::eyre::eyre!("{e:#}"))? {
+        Some(policy) => policy,
+        None => sensor_server::retention::current(&db)
+            .await
+            .map_err(|e| color_eyre::eyre::eyre!("{e}"))?,
     };
-    let router = sensor_server::router().with_state(state);
-    let listener = tokio::net::TcpListener::bind((std::net::Ipv4Addr::UNSPECIFIED, port)).await?;
-    info!("Listening on http://{}", listener.local_addr()?);
+    let report = if args.dry_run {
+        db.compact_dry_run(Utc::now(), &policy).await
+    } else {
+        db.compact(Utc::now(), &policy).await
+    }
+    .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    print!("{}", cli::compaction_summary(&report, args.dry_run));
+    db.close().await;
+    Ok(())
+}
 
-    // Compact once at startup so a restart also catches up any backlog, then hourly.
+async fn serve(config: Config) -> color_eyre::Result<()> {
+    let db = Db::open(&config.database)
+        .await
+        .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    info!("Database opened at {}", config.database);
+    if config
+        .store_retention(&db)
+        .await
+        .map_err(|e| color_eyre::eyre::eyre!("{e:#}"))?
     {
-        let db = db.clone();
-        tokio::spawn(async move {
-            let mut ticker = tokio::time::interval(std::time::Duration::from_secs(3600));
-            loop {
-                ticker.tick().await;
-                match db.compact(Utc::now(), chlorophyll_client::db::DEFAULT_TIERS).await {
-                    Ok(0) => {}
-                    Ok(removed) => info!("compacted history, removed {removed} rows"),
-                    Err(e) => error!("compaction error: {e}"),
-                }
-            }
-        });
+        info!("Retention policy replaced from the configuration");
     }
 
-    let mut readings = client.subscribe();
-    tokio::spawn(async move {
-        // Readings arrive at ~5 Hz per metric; average them into one row per minute
-        // rather than persisting every sample. The dashboard's live values come from the
-        // in-memory registry, so this costs no visible freshness.
-        let mut aggregator = ReadingAggregator::new(INGEST_BUCKET_SECS);
-        let mut flush = tokio::time::interval(std::time::Duration::from_secs(INGEST_BUCKET_SECS as u64));
-
-        loop {
-            tokio::select! {
-                received = readings.recv() => match received {
-                    Ok(reading) => {
-                        if let Some(averaged) = aggregator.push(&reading) {
-                            if let Err(e) = db.insert_reading_at(&averaged, INGEST_BUCKET_SECS).await {
-                                error!("DB insert error: {e}");
-                            }
-                        }
-                    }
-                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                        warn!("readings channel lagged, dropped {n} messages");
-                    }
-                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
-                },
-                // Closes buckets for sensors that stopped transmitting mid-window.
-                _ = flush.tick() => {
-                    for averaged in aggregator.drain_before(Utc::now()) {
-                        if let Err(e) = db.insert_reading_at(&averaged, INGEST_BUCKET_SECS).await {
-                            error!("DB insert error: {e}");
-                        }
-                    }
-                }
-            }
-        }
-    });
+    let client = Arc::new(start_client(&config)?);
+    info!(
+        "Listening for sensor readings on {}:{}",
+        config.multicast.group, config.multicast.port
+    );
+    match db.sensors().await {
+        Ok(known) => client.seed(known.iter().map(DeviceInfo::from)),
+        Err(e) => error!("cannot load known sensors: {e}"),
+    }
+
+    let mut state = AppState::new(client.clone(), db.clone());
+    state.ingest_bucket_secs = config.ingest.bucket_secs;
+    if let Err(e) = sensor_server::calibration::load(&state).await {
+        error!("cannot load calibrations: {e}");
+    }
+    tokio::spawn(sensor_server::alerts::run(state.clone()));
+    tokio::spawn(sensor_server::webhooks::run(state.clone()));
+    tokio::spawn(sensor_server::sensors::run(state.clone()));
+    let compaction = tokio::spawn(sensor_server::retention::run(state.clone()));
+    if let Some(mqtt) = sensor_server::mqtt::MqttConfig::from_env() {
+        tokio::spawn(sensor_server::mqtt::run(state.clone(), mqtt));
+    }
+    let ingest = tokio::spawn(sensor_server::ingest::run(state.clone(), client.subscribe()));
+    let router = sensor_server::router().with_state(state.clone());
+    let listener = tokio::net::TcpListener::bind((config.http.bind, config.http.port)).await?;
+    info!("Listening on http://{}", listener.local_addr()?);
 
     axum::serve(listener, router)
-        .with_graceful_shutdown(async {
-            let _ = tokio::signal::ctrl_c().await;
-            info!("Shutting down");
+        .with_graceful_shutdown({
+            let state = state.clone();
+            async move {
+                shutdown_signal().await;
+                info!("Shutting down");
+                state.shut_down();
+            }
         })
         .await?;
 
+    // Requests are done; let the writers finish before the database closes under them.
+    let _ = tokio::join!(ingest, compaction);
+    state.writer.close().await;
+    db.close().await;
+    info!("Database closed");
     Ok(())
 }
+
+/// Resolves on Ctrl-C (SIGINT) or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                warn!("cannot listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        () = terminate => {}
+    }
+}
+
+async fn export(db_path: &str, args: &ExportArgs) -> color_eyre::Result<()> {
+    let query = args.query().map_err(|e| color_eyre::eyre::eyre!("{e:#}"))?;
+    let (output, format) = (args.output(Utc::now()), args.format());
+    let db = Db::open(db_path)
+        .await
+        .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&output)?);
+    let mut chunks = sensor_server::export::chunks(&db, &query, format);
+    while let Some(chunk) = chunks.next().await {
+        file.write_all(chunk.map_err(|e| color_eyre::eyre::eyre!("{e}"))?.as_bytes())?;
+    }
+    file.flush()?;
+    info!("Exported history to {}", output.display());
+    Ok(())
+}
+
+async fn import(db_path: &str, args: &ImportArgs) -> color_eyre::Result<()> {
+    let (path, options) = (&args.path, args.options());
+    let db = Db::open(db_path)
+        .await
+        .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    let mut importer = sensor_server::import::Importer::new(&db, options)
+        .await
+        .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    for line in std::io::BufReader::new(std::fs::File::open(path)?).lines() {
+        importer
+            .push_line(&line?)
+            .await
+            .map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    }
+    let summary = importer.finish().await.map_err(|e| color_eyre::eyre::eyre!("{e}"))?;
+    for e in &summary.errors {
+        warn!("line {}: {}", e.line, e.message);
+    }
+    info!(
+        "Imported {} of {} rows from {} ({} already stored, {} rejected)",
+        summary.inserted,
+        summary.rows,
+        path.display(),
+        summary.duplicates,
+        summary.rejected
+    );
+    Ok(())
+}
+
+/// Multicast client settings from `config`. `CHLOROPHYLL_AUTH_KEY` enables packet
+/// authentication; `CHLOROPHYLL_AUTH_MODE=flag` accepts unsigned packets but flags their
+/// sensors instead of dropping them.
+fn client_config(config: &Config) -> ClientConfig {
+    let auth = std::env::var("CHLOROPHYLL_AUTH_KEY")
+        .ok()
+        .filter(|key| !key.is_empty())
+        .map(|key| AuthConfig {
+            key: key.into_bytes(),
+            require: std::env::var("CHLOROPHYLL_AUTH_MODE").as_deref() != Ok("flag"),
+        });
+    let mut status_timeouts = StatusTimeouts::default();
+    if let Some(secs) = env_secs("CHLOROPHYLL_STALE_AFTER_SECS") {
+        status_timeouts.stale_after = secs;
+    }
+    if let Some(secs) = env_secs("CHLOROPHYLL_OFFLINE_AFTER_SECS") {
+        status_timeouts.offline_after = secs;
+    }
+    ClientConfig {
+        auth,
+        status_timeouts,
+        ..config.client_config()
+    }
+}
+
+fn env_secs(var: &str) -> Option<std::time::Duration> {
+    let value = std::env::var(var).ok()?;
+    if let Ok(secs) = value.parse() {
+        Some(std::time::Duration::from_secs(secs))
+    } else {
+        warn!("ignoring {var}={value:?}: expected whole seconds");
+        None
+    }
+}
+
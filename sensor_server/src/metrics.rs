@@ -0,0 +1,233 @@
+//! Prometheus scrape endpoint: live sensor values and internal counters at `/metrics`,
+//! in the text exposition format.
+//!
+//! Written by hand rather than through a metrics crate: the set is small and fixed, and
+//! the gauges are read straight from the registry at scrape time.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::Router;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use chlorophyll_client::db::WriterStats;
+use chlorophyll_client::{DeviceInfo, ListenerStats, ReadingKind};
+use chrono::{DateTime, Utc};
+
+use crate::state::AppState;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Server-side counters, bumped by the background tasks and exported at `/metrics`.
+#[derive(Debug, Default)]
+pub struct Counters {
+    broadcast_lagged: AtomicU64,
+    db_insert_errors: AtomicU64,
+    compaction_rows_removed: AtomicU64,
+}
+
+impl Counters {
+    /// A task's broadcast receiver fell behind and lost `n` messages.
+    pub fn record_lagged(&self, n: u64) {
+        self.broadcast_lagged.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn record_db_insert_error(&self) {
+        self.db_insert_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_compaction(&self, removed: u64) {
+        self.compaction_rows_removed.fetch_add(removed, Ordering::Relaxed);
+    }
+}
+
+/// Quote a label value per the exposition format.
+fn label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    let _ = writeln!(out, "{name} {value}");
+}
+
+/// One gauge family with a sample per device that has a value.
+fn device_gauge(
+    out: &mut String,
+    devices: &[DeviceInfo],
+    name: &str,
+    help: &str,
+    value: impl Fn(&DeviceInfo) -> Option<f64>,
+) {
+    header(out, name, "gauge", help);
+    for device in devices {
+        if let Some(v) = value(device) {
+            let _ = writeln!(
+                out,
+                "{name}{{id_hex=\"{:032x}\",name=\"{}\"}} {v}",
+                device.id,
+                label_value(device.name.as_deref().unwrap_or_default()),
+            );
+        }
+    }
+}
+
+/// Render every metric family as exposition text.
+#[must_use]
+pub fn render(
+    devices: &[DeviceInfo],
+    listener: &ListenerStats,
+    counters: &Counters,
+    writer: &WriterStats,
+    write_queue: usize,
+    now: DateTime<Utc>,
+) -> String {
+    let mut out = String::new();
+
+    for kind in ReadingKind::MEASURED {
+        let info = kind.info();
+        device_gauge(
+            &mut out,
+            devices,
+            &format!("chlorophyll_{}_{}", info.id, info.unit_name),
+            &format!("{}, latest reading per sensor.", info.name),
+            |d| d.value(kind).map(f64::from),
+        );
+    }
+    #[allow(clippy::cast_precision_loss)]
+    device_gauge(
+        &mut out,
+        devices,
+        "chlorophyll_last_seen_seconds",
+        "Seconds since the sensor was last heard from.",
+        |d| d.last_seen.map(|at| (now - at).num_milliseconds().max(0) as f64 / 1000.0),
+    );
+
+    counter(
+        &mut out,
+        "chlorophyll_packets_decoded_total",
+        "Multicast packets decoded.",
+        listener.packets_decoded(),
+    );
+    counter(
+        &mut out,
+        "chlorophyll_decode_failures_total",
+        "Multicast datagrams that failed to decode.",
+        listener.decode_failures(),
+    );
+    counter(
+        &mut out,
+        "chlorophyll_packets_skipped_total",
+        "Well-formed packets skipped as unknown to this protocol version.",
+        listener.packets_skipped(),
+    );
+    counter(
+        &mut out,
+        "chlorophyll_packets_rejected_total",
+        "Packets dropped by authentication: bad MAC, replayed, or unsigned when required.",
+        listener.packets_rejected(),
+    );
+    counter(
+        &mut out,
+        "chlorophyll_broadcast_lagged_total",
+        "Messages dropped because a server task fell behind a broadcast channel.",
+        counters.broadcast_lagged.load(Ordering::Relaxed),
+    );
+    counter(
+        &mut out,
+        "chlorophyll_db_insert_errors_total",
+        "Failed reading inserts.",
+        counters.db_insert_errors.load(Ordering::Relaxed) + writer.failed(),
+    );
+    counter(
+        &mut out,
+        "chlorophyll_db_rows_written_total",
+        "Readings committed by the batch writer.",
+        writer.written(),
+    );
+    counter(
+        &mut out,
+        "chlorophyll_db_batches_total",
+        "Transactions committed by the batch writer.",
+        writer.batches(),
+    );
+    counter(
+        &mut out,
+        "chlorophyll_db_write_stalls_total",
+        "Writes that waited for room in the batch writer's full queue.",
+        writer.stalls(),
+    );
+    header(&mut out, "chlorophyll_db_write_queue", "gauge", "Readings waiting to be committed.");
+    let _ = writeln!(out, "chlorophyll_db_write_queue {write_queue}");
+    counter(
+        &mut out,
+        "chlorophyll_compaction_rows_removed_total",
+        "History rows removed by compaction.",
+        counters.compaction_rows_removed.load(Ordering::Relaxed),
+    );
+
+    out
+}
+
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let body = render(
+        &state.client.devices(),
+        state.client.stats(),
+        &state.counters,
+        state.writer.stats(),
+        state.writer.queued(),
+        Utc::now(),
+    );
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], body)
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/metrics", get(metrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gauges_are_labelled_per_device_and_skip_missing_values() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let devices = vec![
+            DeviceInfo {
+                id: 1,
+                name: Some("bench \"a\"".into()),
+                last_seen: Some(now - chrono::Duration::seconds(90)),
+                values: [(ReadingKind::Temperature, 21.5)].into(),
+                ..DeviceInfo::default()
+            },
+            DeviceInfo { id: 2, ..DeviceInfo::default() },
+        ];
+        let counters = Counters::default();
+        counters.record_lagged(3);
+        counters.record_compaction(40);
+
+        let text = render(&devices, &ListenerStats::default(), &counters, &WriterStats::default(), 2, now);
+
+        let id = format!("{:032x}", 1_u128);
+        assert!(text.contains(&format!(
+            "chlorophyll_temperature_celsius{{id_hex=\"{id}\",name=\"bench \\\"a\\\"\"}} 21.5\n"
+        )));
+        assert!(text.contains(&format!("chlorophyll_last_seen_seconds{{id_hex=\"{id}\",name=\"bench \\\"a\\\"\"}} 90\n")));
+        assert!(!text.contains(&format!("{:032x}", 2_u128)), "device 2 has no values");
+        assert!(text.contains("# TYPE chlorophyll_humidity_percent gauge\n"));
+        assert!(text.contains("chlorophyll_broadcast_lagged_total 3\n"));
+        assert!(text.contains("chlorophyll_compaction_rows_removed_total 40\n"));
+        assert!(text.contains("chlorophyll_packets_decoded_total 0\n"));
+        assert!(text.contains("# TYPE chlorophyll_db_write_queue gauge\nchlorophyll_db_write_queue 2\n"));
+    }
+}
@@ -0,0 +1,537 @@
+//! MQTT bridge for Home Assistant: averaged readings, retained discovery configs,
+//! per-device availability, and a rename command topic.
+//!
+//! Topics, with the default prefixes:
+//!
+//! - `chlorophyll/<id_hex>/<metric>`: averaged reading, one per ingest bucket
+//! - `chlorophyll/<id_hex>/availability`: `online` / `offline` (retained), from `last_seen`
+//! - `chlorophyll/<id_hex>/set_name`: publish a name here to rename the sensor
+//! - `chlorophyll/bridge/availability`: the bridge itself (retained, with a last will)
+//! - `homeassistant/sensor/chlorophyll_<id_hex>/<metric>/config`: discovery (retained)
+//!
+//! The bridge logic in [`bridge`] talks to the broker only through channels, so it runs
+//! unchanged against an in-process stand-in; [`run`] connects those channels to a real
+//! broker.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chlorophyll_client::rollup::ReadingAggregator;
+use chlorophyll_client::{DeviceInfo, DeviceStatus, Reading, ReadingKind, RegistryEvent};
+use chrono::Utc;
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, QoS};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+
+use crate::api::parse_id_hex;
+use crate::state::AppState;
+
+/// How often finished averaging buckets are flushed.
+const DRAIN_INTERVAL: Duration = Duration::from_secs(15);
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Wait between reconnect attempts after the broker connection drops.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const CHANNEL_CAPACITY: usize = 256;
+
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// Broker connection and topic layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub client_id: String,
+    /// Root of the state, availability and command topics.
+    pub prefix: String,
+    /// Home Assistant's discovery prefix.
+    pub discovery_prefix: String,
+}
+
+impl MqttConfig {
+    #[must_use]
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            port: 1883,
+            username: None,
+            password: None,
+            client_id: "chlorophyll-sensor-server".to_string(),
+            prefix: "chlorophyll".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+
+    /// Read `CHLOROPHYLL_MQTT_*` variables. `None` (bridge disabled) unless
+    /// `CHLOROPHYLL_MQTT_HOST` is set.
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| std::env::var(format!("CHLOROPHYLL_MQTT_{name}")).ok().filter(|v| !v.is_empty());
+
+        let mut cfg = Self::new(var("HOST")?);
+        if let Some(port) = var("PORT").and_then(|p| p.parse().ok()) {
+            cfg.port = port;
+        }
+        cfg.username = var("USERNAME");
+        cfg.password = var("PASSWORD");
+        if let Some(client_id) = var("CLIENT_ID") {
+            cfg.client_id = client_id;
+        }
+        if let Some(prefix) = var("PREFIX") {
+            cfg.prefix = prefix.trim_end_matches('/').to_string();
+        }
+        if let Some(prefix) = var("DISCOVERY_PREFIX") {
+            cfg.discovery_prefix = prefix.trim_end_matches('/').to_string();
+        }
+        Some(cfg)
+    }
+
+    fn bridge_availability_topic(&self) -> String {
+        format!("{}/bridge/availability", self.prefix)
+    }
+
+    fn availability_topic(&self, id: u128) -> String {
+        format!("{}/{id:032x}/availability", self.prefix)
+    }
+
+    fn state_topic(&self, id: u128, kind: ReadingKind) -> String {
+        format!("{}/{id:032x}/{}", self.prefix, kind.as_str())
+    }
+
+    fn command_filter(&self) -> String {
+        format!("{}/+/set_name", self.prefix)
+    }
+
+    fn discovery_topic(&self, id: u128, kind: ReadingKind) -> String {
+        format!("{}/sensor/chlorophyll_{id:032x}/{}/config", self.discovery_prefix, kind.as_str())
+    }
+}
+
+/// A message for the broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outgoing {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+}
+
+/// Something that arrived from the broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incoming {
+    /// (Re)connected: subscriptions and retained state must be re-sent.
+    Connected,
+    Publish { topic: String, payload: Vec<u8> },
+}
+
+/// Home Assistant MQTT discovery payload for one sensor entity.
+#[derive(Debug, Serialize)]
+struct DiscoveryConfig {
+    name: &'static str,
+    unique_id: String,
+    object_id: String,
+    state_topic: String,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    unit_of_measurement: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'static str>,
+    state_class: &'static str,
+    suggested_display_precision: usize,
+    availability: [Availability; 2],
+    availability_mode: &'static str,
+    device: DiscoveryDevice,
+}
+
+#[derive(Debug, Serialize)]
+struct Availability {
+    topic: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DiscoveryDevice {
+    identifiers: [String; 1],
+    name: String,
+    manufacturer: &'static str,
+}
+
+/// Home Assistant device class for `kind`; name, unit and precision come from its
+/// [`chlorophyll_client::MetricInfo`].
+fn device_class(kind: ReadingKind) -> Option<&'static str> {
+    Some(match kind {
+        ReadingKind::Temperature | ReadingKind::DewPoint | ReadingKind::HeatIndex => "temperature",
+        ReadingKind::Humidity => "humidity",
+        ReadingKind::Light => "illuminance",
+        ReadingKind::Co2 => "carbon_dioxide",
+        ReadingKind::Pressure => "atmospheric_pressure",
+        ReadingKind::SoilMoisture => "moisture",
+        ReadingKind::Voc => "aqi",
+        ReadingKind::Battery => "voltage",
+        ReadingKind::Vpd => "pressure",
+        ReadingKind::Dli => return None,
+    })
+}
+
+/// What firmware reported before sensors advertised their metrics.
+const LEGACY_METRICS: [ReadingKind; 3] = [ReadingKind::Temperature, ReadingKind::Humidity, ReadingKind::Light];
+
+/// Entities to publish for `device`: what it advertises, otherwise the legacy set plus
+/// anything it has actually reported.
+fn device_metrics(device: &DeviceInfo) -> Vec<ReadingKind> {
+    if !device.supported_metrics.is_empty() {
+        return device.supported_metrics.clone();
+    }
+    ReadingKind::MEASURED
+        .into_iter()
+        .filter(|kind| LEGACY_METRICS.contains(kind) || device.value(*kind).is_some())
+        .collect()
+}
+
+/// Decides what to publish. Holds no connection, so it is driven directly in tests.
+#[derive(Debug)]
+pub struct Bridge {
+    cfg: MqttConfig,
+    /// Devices whose discovery configs are published, with the name and metrics they were
+    /// published under.
+    discovered: HashMap<u128, (Option<String>, Vec<ReadingKind>)>,
+    /// Last availability published per device: `true` for online.
+    availability: HashMap<u128, bool>,
+}
+
+impl Bridge {
+    #[must_use]
+    pub fn new(cfg: MqttConfig) -> Self {
+        Self {
+            cfg,
+            discovered: HashMap::new(),
+            availability: HashMap::new(),
+        }
+    }
+
+    /// Messages to send after (re)connecting: bridge availability plus discovery and
+    /// availability for every known device.
+    pub fn on_connected(&mut self, devices: &[DeviceInfo]) -> Vec<Outgoing> {
+        self.discovered.clear();
+        self.availability.clear();
+        let mut messages = vec![Outgoing {
+            topic: self.cfg.bridge_availability_topic(),
+            payload: ONLINE.to_string(),
+            retain: true,
+        }];
+        for device in devices {
+            messages.extend(self.discover(device));
+        }
+        messages.extend(self.check_availability(devices));
+        messages
+    }
+
+    /// Discovery configs for `device`, if it is new or its name or metrics changed since
+    /// they were last published.
+    pub fn discover(&mut self, device: &DeviceInfo) -> Vec<Outgoing> {
+        let published = (device.name.clone(), device_metrics(device));
+        if self.discovered.get(&device.id) == Some(&published) {
+            return Vec::new();
+        }
+        let kinds = published.1.clone();
+        self.discovered.insert(device.id, published);
+
+        let id_hex = format!("{:032x}", device.id);
+        let device_name = device
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("Chlorophyll {}", &id_hex[id_hex.len() - 6..]));
+
+        kinds
+            .into_iter()
+            .map(|kind| {
+                let info = kind.info();
+                let config = DiscoveryConfig {
+                    name: info.name,
+                    unique_id: format!("chlorophyll_{id_hex}_{}", kind.as_str()),
+                    object_id: format!("chlorophyll_{id_hex}_{}", kind.as_str()),
+                    state_topic: self.cfg.state_topic(device.id, kind),
+                    unit_of_measurement: info.unit,
+                    device_class: device_class(kind),
+                    state_class: "measurement",
+                    suggested_display_precision: info.precision,
+                    availability: [
+                        Availability { topic: self.cfg.bridge_availability_topic() },
+                        Availability { topic: self.cfg.availability_topic(device.id) },
+                    ],
+                    availability_mode: "all",
+                    device: DiscoveryDevice {
+                        identifiers: [format!("chlorophyll_{id_hex}")],
+                        name: device_name.clone(),
+                        manufacturer: "chlorophyll",
+                    },
+                };
+                Outgoing {
+                    topic: self.cfg.discovery_topic(device.id, kind),
+                    payload: serde_json::to_string(&config).unwrap_or_default(),
+                    retain: true,
+                }
+            })
+            .collect()
+    }
+
+    /// State message for an averaged reading.
+    #[must_use]
+    pub fn on_averaged(&self, reading: &Reading) -> Outgoing {
+        Outgoing {
+            topic: self.cfg.state_topic(reading.sensor_id, reading.kind),
+            payload: reading.value.to_string(),
+            retain: false,
+        }
+    }
+
+    /// Availability messages for devices whose online state changed. Stale sensors are
+    /// still published as online; only offline ones become unavailable.
+    pub fn check_availability(&mut self, devices: &[DeviceInfo]) -> Vec<Outgoing> {
+        let mut messages = Vec::new();
+        for device in devices {
+            let online = device.status != DeviceStatus::Offline;
+            if self.availability.insert(device.id, online) != Some(online) {
+                messages.push(Outgoing {
+                    topic: self.cfg.availability_topic(device.id),
+                    payload: if online { ONLINE } else { OFFLINE }.to_string(),
+                    retain: true,
+                });
+            }
+        }
+        messages
+    }
+
+    /// The sensor and new name, if `topic` is a well-formed rename command.
+    #[must_use]
+    pub fn parse_command(&self, topic: &str, payload: &[u8]) -> Option<(u128, String)> {
+        let rest = topic.strip_prefix(&self.cfg.prefix)?.strip_prefix('/')?;
+        let id_hex = rest.strip_suffix("/set_name")?;
+        let id = parse_id_hex(id_hex)?;
+        let name = std::str::from_utf8(payload).ok()?.trim();
+        if name.is_empty() {
+            return None;
+        }
+        Some((id, name.to_string()))
+    }
+}
+
+/// Bridge loop. Sends to `outgoing` and reacts to `incoming`; returns when the reading
+/// channel or `incoming` closes.
+pub async fn bridge(
+    state: AppState,
+    cfg: MqttConfig,
+    outgoing: mpsc::Sender<Outgoing>,
+    mut incoming: mpsc::Receiver<Incoming>,
+) {
+    let mut bridge = Bridge::new(cfg);
+    let mut readings = state.client.subscribe();
+    let mut events = state.client.subscribe_events();
+    // Averaged on the same buckets as the history table, so Home Assistant sees the
+    // values the dashboard charts.
+    let mut aggregator = ReadingAggregator::new(state.ingest_bucket_secs);
+    let mut tick = tokio::time::interval(DRAIN_INTERVAL);
+
+    loop {
+        let messages = tokio::select! {
+            received = readings.recv() => match received {
+                Ok(reading) => aggregator.push(&reading).map(|avg| bridge.on_averaged(&avg.reading)).into_iter().collect(),
+                Err(RecvError::Lagged(n)) => {
+                    state.counters.record_lagged(n);
+                    tracing::warn!("mqtt: readings channel lagged, dropped {n} messages");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            received = events.recv() => match received {
+                Ok(RegistryEvent::DeviceAdded { id, .. } | RegistryEvent::NameChanged { id, .. }) => {
+                    state
+                        .client
+                        .devices()
+                        .iter()
+                        .find(|d| d.id == id)
+                        .map(|device| bridge.discover(device))
+                        .unwrap_or_default()
+                }
+                Ok(RegistryEvent::StatusChanged { id, .. }) => {
+                    let devices: Vec<DeviceInfo> = state.client.devices().into_iter().filter(|d| d.id == id).collect();
+                    bridge.check_availability(&devices)
+                }
+                Ok(RegistryEvent::RenamePending { .. } | RegistryEvent::RenameSettled { .. }) | Err(_) => continue,
+            },
+            _ = tick.tick() => aggregator
+                .drain_before(Utc::now())
+                .iter()
+                .map(|avg| bridge.on_averaged(&avg.reading))
+                .collect(),
+            received = incoming.recv() => match received {
+                Some(Incoming::Connected) => bridge.on_connected(&state.client.devices()),
+                Some(Incoming::Publish { topic, payload }) => {
+                    if let Some((id, name)) = bridge.parse_command(&topic, &payload) {
+                        tracing::info!("mqtt: renaming {id:032x} to {name:?}");
+                        match state.client.set_name(id, &name) {
+                            Ok(rename) => crate::api::spawn_rename(id, name, rename),
+                            Err(e) => tracing::warn!("mqtt: set_name failed: {e:#}"),
+                        }
+                    } else {
+                        tracing::warn!("mqtt: ignoring malformed command on {topic}");
+                    }
+                    continue;
+                }
+                None => break,
+            },
+        };
+
+        for message in messages {
+            if outgoing.send(message).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Connect to the broker in `cfg` and run [`bridge`] against it, reconnecting as needed.
+pub async fn run(state: AppState, cfg: MqttConfig) {
+    let mut options = MqttOptions::new(cfg.client_id.clone(), cfg.host.clone(), cfg.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(cfg.bridge_availability_topic(), OFFLINE, QoS::AtLeastOnce, true));
+    if let Some(username) = &cfg.username {
+        options.set_credentials(username.clone(), cfg.password.clone().unwrap_or_default());
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, CHANNEL_CAPACITY);
+    let (outgoing_tx, mut outgoing_rx) = mpsc::channel::<Outgoing>(CHANNEL_CAPACITY);
+    let (incoming_tx, incoming_rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let command_filter = cfg.command_filter();
+    tracing::info!("mqtt: bridging to {}:{} under {}/", cfg.host, cfg.port, cfg.prefix);
+
+    tokio::spawn(bridge(state, cfg, outgoing_tx, incoming_rx));
+
+    let publisher = client.clone();
+    tokio::spawn(async move {
+        while let Some(message) = outgoing_rx.recv().await {
+            if let Err(e) = publisher
+                .publish(message.topic, QoS::AtLeastOnce, message.retain, message.payload)
+                .await
+            {
+                tracing::warn!("mqtt: publish failed: {e}");
+            }
+        }
+    });
+
+    loop {
+        let incoming = match eventloop.poll().await {
+            Ok(Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
+                tracing::info!("mqtt: connected");
+                if let Err(e) = client.subscribe(command_filter.clone(), QoS::AtLeastOnce).await {
+                    tracing::warn!("mqtt: subscribe failed: {e}");
+                }
+                Incoming::Connected
+            }
+            Ok(Event::Incoming(rumqttc::Packet::Publish(publish))) => Incoming::Publish {
+                topic: publish.topic,
+                payload: publish.payload.to_vec(),
+            },
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::warn!("mqtt: connection error: {e}; retrying in {}s", RECONNECT_DELAY.as_secs());
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        if incoming_tx.send(incoming).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    fn device(id: u128, name: Option<&str>, last_seen: DateTime<Utc>) -> DeviceInfo {
+        DeviceInfo {
+            id,
+            name: name.map(str::to_string),
+            last_seen: Some(last_seen),
+            ..DeviceInfo::default()
+        }
+    }
+
+    #[test]
+    fn discovery_is_published_once_per_name() {
+        let mut bridge = Bridge::new(MqttConfig::new("localhost"));
+        let now = Utc::now();
+
+        let first = bridge.discover(&device(7, None, now));
+        assert_eq!(first.len(), LEGACY_METRICS.len());
+        assert!(first.iter().all(|m| m.retain));
+        let id_hex = format!("{:032x}", 7_u128);
+        assert_eq!(first[0].topic, format!("homeassistant/sensor/chlorophyll_{id_hex}/temperature/config"));
+        let config: serde_json::Value = serde_json::from_str(&first[0].payload).unwrap();
+        assert_eq!(config["state_topic"], format!("chlorophyll/{id_hex}/temperature"));
+        assert_eq!(config["device_class"], "temperature");
+        assert_eq!(config["availability"][1]["topic"], format!("chlorophyll/{id_hex}/availability"));
+
+        assert!(bridge.discover(&device(7, None, now)).is_empty());
+
+        let renamed = bridge.discover(&device(7, Some("bench"), now));
+        let config: serde_json::Value = serde_json::from_str(&renamed[0].payload).unwrap();
+        assert_eq!(config["device"]["name"], "bench");
+    }
+
+    #[test]
+    fn discovery_follows_advertised_and_reported_metrics() {
+        let mut bridge = Bridge::new(MqttConfig::new("localhost"));
+        let now = Utc::now();
+
+        let mut soil = device(7, None, now);
+        soil.supported_metrics = vec![ReadingKind::SoilMoisture, ReadingKind::Battery];
+        let topics: Vec<_> = bridge.discover(&soil).into_iter().map(|m| m.topic).collect();
+        assert_eq!(topics.len(), 2);
+        assert!(topics[0].ends_with("/soil_moisture/config"));
+
+        // Without capabilities, an extra metric is discovered once it shows up.
+        let mut legacy = device(8, None, now);
+        assert_eq!(bridge.discover(&legacy).len(), LEGACY_METRICS.len());
+        legacy.values.insert(ReadingKind::Co2, 800.0);
+        let messages = bridge.discover(&legacy);
+        assert_eq!(messages.len(), LEGACY_METRICS.len() + 1);
+        let config: serde_json::Value = serde_json::from_str(&messages[3].payload).unwrap();
+        assert_eq!(config["device_class"], "carbon_dioxide");
+    }
+
+    #[test]
+    fn availability_follows_status() {
+        let mut bridge = Bridge::new(MqttConfig::new("localhost"));
+        let now = Utc::now();
+        let mut sensor = device(7, None, now);
+
+        let messages = bridge.check_availability(std::slice::from_ref(&sensor));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload, ONLINE);
+        assert!(bridge.check_availability(std::slice::from_ref(&sensor)).is_empty());
+
+        sensor.status = DeviceStatus::Stale;
+        assert!(bridge.check_availability(std::slice::from_ref(&sensor)).is_empty(), "stale is still available");
+
+        sensor.status = DeviceStatus::Offline;
+        let messages = bridge.check_availability(std::slice::from_ref(&sensor));
+        assert_eq!(messages[0].payload, OFFLINE);
+        assert!(messages[0].retain);
+    }
+
+    #[test]
+    fn rename_commands_need_a_valid_id_and_name() {
+        let bridge = Bridge::new(MqttConfig::new("localhost"));
+        let id_hex = format!("{:032x}", 7_u128);
+
+        assert_eq!(
+            bridge.parse_command(&format!("chlorophyll/{id_hex}/set_name"), b" bench "),
+            Some((7, "bench".to_string()))
+        );
+        assert_eq!(bridge.parse_command(&format!("chlorophyll/{id_hex}/set_name"), b"  "), None);
+        assert_eq!(bridge.parse_command("chlorophyll/zzzz/set_name", b"bench"), None);
+        assert_eq!(bridge.parse_command(&format!("other/{id_hex}/set_name"), b"bench"), None);
+    }
+}
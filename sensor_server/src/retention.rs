@@ -0,0 +1,347 @@
+//! Retention policy: how long history is kept and how coarsely, with per-sensor overrides,
+//! a maximum age and optional vacuuming.
+//!
+//! The policy lives in the database. The `[retention]` section of the server config (or
+//! a file named by `CHLOROPHYLL_RETENTION` or `--retention`) replaces it at startup, and
+//! `/api/retention` reads and replaces it at runtime; until either sets one, the built-in
+//! default applies. Dry runs report what a compaction would do
+//! without doing it. [`run`] compacts on the policy's interval, and right away whenever
+//! the policy changes.
+//!
+//! ```toml
+//! interval_secs = 3600
+//! max_age_secs = 63072000  # two years; omit to keep daily averages for good
+//! vacuum = "incremental"   # or "off", "full"
+//!
+//! [[tiers]]
+//! older_than_secs = 3600
+//! bucket_secs = 60
+//!
+//! [[sensors]]
+//! sensor_id = "0000000000000000000000000000002a"
+//! full_resolution_secs = 604800
+//! ```
+
+use std::path::Path;
+
+use anyhow::Context;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use chlorophyll_client::db::{
+    CompactionReport, DEFAULT_COMPACTION_INTERVAL_SECS, DEFAULT_TIERS, Db, RetentionPolicy, RetentionTier,
+    SensorRetention, Vacuum,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::api::parse_id_hex;
+use crate::state::AppState;
+
+/// A [`RetentionPolicy`] as written in the TOML file and sent over the API. Anything left
+/// out takes its default.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionJson {
+    #[serde(default = "default_interval")]
+    pub interval_secs: i64,
+    #[serde(default)]
+    pub max_age_secs: Option<i64>,
+    /// `off`, `incremental` or `full`.
+    #[serde(default = "default_vacuum")]
+    pub vacuum: String,
+    #[serde(default = "default_tiers")]
+    pub tiers: Vec<TierJson>,
+    #[serde(default)]
+    pub sensors: Vec<SensorRetentionJson>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TierJson {
+    pub older_than_secs: i64,
+    pub bucket_secs: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SensorRetentionJson {
+    /// Sensor id, hex.
+    pub sensor_id: String,
+    #[serde(default)]
+    pub full_resolution_secs: Option<i64>,
+    #[serde(default)]
+    pub max_age_secs: Option<i64>,
+}
+
+fn default_interval() -> i64 {
+    DEFAULT_COMPACTION_INTERVAL_SECS
+}
+
+fn default_vacuum() -> String {
+    Vacuum::Off.as_str().to_string()
+}
+
+fn default_tiers() -> Vec<TierJson> {
+    DEFAULT_TIERS
+        .iter()
+        .map(|tier| TierJson { older_than_secs: tier.older_than_secs, bucket_secs: tier.bucket_secs })
+        .collect()
+}
+
+impl RetentionJson {
+    /// The policy, once it parses and validates.
+    pub fn into_policy(self) -> anyhow::Result<RetentionPolicy> {
+        let sensors = self
+            .sensors
+            .into_iter()
+            .map(|sensor| {
+                Ok(SensorRetention {
+                    sensor_id: parse_id_hex(&sensor.sensor_id)
+                        .with_context(|| format!("invalid sensor id {:?}", sensor.sensor_id))?,
+                    full_resolution_secs: sensor.full_resolution_secs,
+                    max_age_secs: sensor.max_age_secs,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let policy = RetentionPolicy {
+            tiers: self
+                .tiers
+                .into_iter()
+                .map(|tier| RetentionTier { older_than_secs: tier.older_than_secs, bucket_secs: tier.bucket_secs })
+                .collect(),
+            max_age_secs: self.max_age_secs,
+            sensors,
+            vacuum: Vacuum::parse(&self.vacuum).context("vacuum must be off, incremental or full")?,
+            interval_secs: self.interval_secs,
+        };
+        policy.validate()?;
+        Ok(policy)
+    }
+}
+
+impl From<&RetentionPolicy> for RetentionJson {
+    fn from(policy: &RetentionPolicy) -> Self {
+        Self {
+            interval_secs: policy.interval_secs,
+            max_age_secs: policy.max_age_secs,
+            vacuum: policy.vacuum.as_str().to_string(),
+            tiers: policy
+                .tiers
+                .iter()
+                .map(|tier| TierJson { older_than_secs: tier.older_than_secs, bucket_secs: tier.bucket_secs })
+                .collect(),
+            sensors: policy
+                .sensors
+                .iter()
+                .map(|sensor| SensorRetentionJson {
+                    sensor_id: format!("{:032x}", sensor.sensor_id),
+                    full_resolution_secs: sensor.full_resolution_secs,
+                    max_age_secs: sensor.max_age_secs,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Rows a compaction touched, or would touch, per table.
+#[derive(Debug, Serialize)]
+pub struct ReportJson {
+    pub rolled_up: u64,
+    pub expired: u64,
+    pub tiers: Vec<TierReportJson>,
+    pub dropped: Vec<DroppedTierJson>,
+    /// Rows removed across every table.
+    pub removed: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TierReportJson {
+    pub bucket_secs: i64,
+    pub written: u64,
+    pub pruned: u64,
+    pub expired: u64,
+}
+
+/// A rollup no longer in the policy, and the tier its buckets went to.
+#[derive(Debug, Serialize)]
+pub struct DroppedTierJson {
+    pub bucket_secs: i64,
+    pub rows: u64,
+    pub merged_into: Option<i64>,
+    pub written: u64,
+}
+
+impl From<&CompactionReport> for ReportJson {
+    fn from(report: &CompactionReport) -> Self {
+        Self {
+            rolled_up: report.rolled_up,
+            expired: report.expired,
+            tiers: report
+                .tiers
+                .iter()
+                .map(|tier| TierReportJson {
+                    bucket_secs: tier.bucket_secs,
+                    written: tier.written,
+                    pruned: tier.pruned,
+                    expired: tier.expired,
+                })
+                .collect(),
+            dropped: report
+                .dropped
+                .iter()
+                .map(|tier| DroppedTierJson {
+                    bucket_secs: tier.bucket_secs,
+                    rows: tier.rows,
+                    merged_into: tier.merged_into,
+                    written: tier.written,
+                })
+                .collect(),
+            removed: report.removed(),
+        }
+    }
+}
+
+/// A policy from a TOML file, parsed but not yet validated.
+pub fn load_file(path: &Path) -> anyhow::Result<RetentionJson> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("invalid {}", path.display()))
+}
+
+/// Errors unless ingest buckets `ingest_bucket_secs` wide tile the buckets of `policy`'s
+/// finest tier; rolling them up into buckets they straddle would only misalign them.
+pub fn check_ingest_bucket(policy: &RetentionPolicy, ingest_bucket_secs: i64) -> anyhow::Result<()> {
+    if let Some(finest) = policy.tiers.first() {
+        anyhow::ensure!(
+            finest.bucket_secs % ingest_bucket_secs == 0,
+            "ingest.bucket_secs ({ingest_bucket_secs}) must divide the finest retention tier's bucket_secs ({})",
+            finest.bucket_secs
+        );
+    }
+    Ok(())
+}
+
+/// The stored policy, or the default if none was ever set.
+pub async fn current(db: &Db) -> anyhow::Result<RetentionPolicy> {
+    Ok(db.retention_policy().await?.unwrap_or_default())
+}
+
+async fn get_policy(State(state): State<AppState>) -> Result<Json<RetentionJson>, StatusCode> {
+    let policy = current(&state.db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(RetentionJson::from(&policy)))
+}
+
+async fn put_policy(
+    State(state): State<AppState>,
+    Json(body): Json<RetentionJson>,
+) -> Result<Json<RetentionJson>, StatusCode> {
+    let policy = body.into_policy().map_err(|_| StatusCode::BAD_REQUEST)?;
+    check_ingest_bucket(&policy, state.ingest_bucket_secs).map_err(|_| StatusCode::BAD_REQUEST)?;
+    state.db.check_retention_policy(&policy).await.map_err(|_| StatusCode::BAD_REQUEST)?;
+    state
+        .db
+        .set_retention_policy(&policy)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.retention_changed.notify_one();
+    Ok(Json(RetentionJson::from(&policy)))
+}
+
+/// What compacting now under the stored policy would do.
+async fn dry_run_current(State(state): State<AppState>) -> Result<Json<ReportJson>, StatusCode> {
+    let policy = current(&state.db).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    dry_run(&state.db, &policy).await
+}
+
+/// What compacting now under a proposed policy would do, without storing it.
+async fn dry_run_proposed(
+    State(state): State<AppState>,
+    Json(body): Json<RetentionJson>,
+) -> Result<Json<ReportJson>, StatusCode> {
+    let policy = body.into_policy().map_err(|_| StatusCode::BAD_REQUEST)?;
+    dry_run(&state.db, &policy).await
+}
+
+async fn dry_run(db: &Db, policy: &RetentionPolicy) -> Result<Json<ReportJson>, StatusCode> {
+    let report = db
+        .compact_dry_run(Utc::now(), policy)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(ReportJson::from(&report)))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/retention", get(get_policy).put(put_policy))
+        .route("/api/retention/dry-run", get(dry_run_current).post(dry_run_proposed))
+}
+
+/// Compact once at startup so a restart also catches up any backlog, then every
+/// `interval_secs` of the stored policy, and again as soon as the policy changes. A
+/// compaction under way when the server shuts down is finished first.
+pub async fn run(state: AppState) {
+    loop {
+        let policy = current(&state.db).await.unwrap_or_else(|e| {
+            tracing::error!("retention: cannot load policy, using the default: {e}");
+            RetentionPolicy::default()
+        });
+        match state.db.compact(Utc::now(), &policy).await {
+            Ok(report) if report.removed() == 0 => {}
+            Ok(report) => {
+                let removed = report.removed();
+                state.counters.record_compaction(removed);
+                tracing::info!("compacted history, removed {removed} rows");
+            }
+            Err(e) => tracing::error!("compaction error: {e}"),
+        }
+        let interval = std::time::Duration::from_secs(policy.interval_secs.unsigned_abs());
+        tokio::select! {
+            () = tokio::time::sleep(interval) => {}
+            () = state.retention_changed.notified() => {}
+            () = state.shutting_down() => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toml_policies_fill_in_defaults() {
+        let json: RetentionJson = toml::from_str(
+            r#"
+            max_age_secs = 63072000
+            vacuum = "incremental"
+
+            [[sensors]]
+            sensor_id = "2a"
+            full_resolution_secs = 604800
+            "#,
+        )
+        .unwrap();
+        let policy = json.into_policy().unwrap();
+        assert_eq!(policy.tiers, DEFAULT_TIERS);
+        assert_eq!(policy.interval_secs, DEFAULT_COMPACTION_INTERVAL_SECS);
+        assert_eq!(policy.vacuum, Vacuum::Incremental);
+        assert_eq!(
+            policy.sensors,
+            [SensorRetention { sensor_id: 0x2a, full_resolution_secs: Some(604_800), max_age_secs: None }]
+        );
+        assert_eq!(RetentionJson::from(&policy).into_policy().unwrap(), policy, "round trips");
+    }
+
+    #[test]
+    fn invalid_policies_are_rejected() {
+        let parse = |text: &str| toml::from_str::<RetentionJson>(text).map_err(anyhow::Error::from)?.into_policy();
+        assert!(parse("vacuum = \"sometimes\"").is_err());
+        assert!(parse("interval_secs = 0").is_err());
+        assert!(parse("max_age = 10").is_err(), "unknown keys are typos");
+        assert!(parse("[[sensors]]\nsensor_id = \"bench\"").is_err());
+        let untiled = "[[tiers]]\nolder_than_secs = 60\nbucket_secs = 60\n\
+                       [[tiers]]\nolder_than_secs = 120\nbucket_secs = 90";
+        assert!(parse(untiled).is_err(), "90s buckets don't tile 60s ones");
+        assert!(parse("").is_ok());
+    }
+}
@@ -0,0 +1,62 @@
+//! Keeps the database's `sensors` table in step with the live registry, so names and
+//! firmware details survive a restart and outlive the sensor itself.
+
+use std::time::Duration;
+
+use chlorophyll_client::RegistryEvent;
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::state::AppState;
+
+/// How often `last_seen` and firmware details are written back. New sensors and renames
+/// are written as soon as they happen.
+const SYNC_INTERVAL: Duration = Duration::from_mins(1);
+
+/// Record `id` as the registry currently knows it.
+async fn persist(state: &AppState, id: u128) {
+    let Some(device) = state.client.devices().into_iter().find(|d| d.id == id) else {
+        return;
+    };
+    if let Err(e) = state.db.upsert_sensor(&device).await {
+        tracing::error!("sensors: cannot record {id:032x}: {e}");
+    }
+}
+
+/// Sensor bookkeeping task. Runs until the registry event channel closes.
+pub async fn run(state: AppState) {
+    let mut events = state.client.subscribe_events();
+    let mut sync = tokio::time::interval(SYNC_INTERVAL);
+    let mut synced_until = DateTime::<Utc>::MIN_UTC;
+
+    loop {
+        tokio::select! {
+            received = events.recv() => match received {
+                Ok(RegistryEvent::DeviceAdded { id, .. } | RegistryEvent::NameChanged { id, .. }) => {
+                    persist(&state, id).await;
+                }
+                Ok(
+                    RegistryEvent::StatusChanged { .. }
+                    | RegistryEvent::RenamePending { .. }
+                    | RegistryEvent::RenameSettled { .. },
+                ) => {}
+                Err(RecvError::Lagged(n)) => {
+                    state.counters.record_lagged(n);
+                    tracing::warn!("sensors: registry event channel lagged, dropped {n} messages");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = sync.tick() => {
+                let now = Utc::now();
+                for device in state.client.devices() {
+                    if device.last_seen.is_some_and(|seen| seen >= synced_until)
+                        && let Err(e) = state.db.upsert_sensor(&device).await
+                    {
+                        tracing::error!("sensors: cannot record {:032x}: {e}", device.id);
+                    }
+                }
+                synced_until = now;
+            }
+        }
+    }
+}
@@ -1,7 +1,14 @@
 use std::sync::Arc;
 
-use chlorophyll_client::db::Db;
+use chlorophyll_client::db::{AlertEvent, BatchWriter, Db, WriterConfig};
 use chlorophyll_client::SensorClient;
+use chlorophyll_client::rollup::INGEST_BUCKET_SECS;
+use tokio::sync::{broadcast, watch, Notify};
+
+use crate::metrics::Counters;
+
+/// Alert transitions are rare; this only needs to absorb a burst.
+const ALERT_CHANNEL_CAPACITY: usize = 64;
 
 /// Shared state for the HTTP API and dashboard: the live sensor registry plus
 /// the SQLite-backed reading history.
@@ -9,4 +16,50 @@ use chlorophyll_client::SensorClient;
 pub struct AppState {
     pub client: Arc<SensorClient>,
     pub db: Db,
+    /// Batches ingested readings into `db`.
+    pub writer: BatchWriter,
+    /// Seconds readings are averaged over before they are stored.
+    pub ingest_bucket_secs: i64,
+    /// Signalled whenever an alert rule is created, changed or deleted, so the alert
+    /// engine reloads its rules.
+    pub alert_rules_changed: Arc<Notify>,
+    /// Every persisted alert transition, for sinks such as webhooks.
+    pub alerts_tx: broadcast::Sender<AlertEvent>,
+    /// Signalled whenever a webhook target is created, changed or deleted.
+    pub webhooks_changed: Arc<Notify>,
+    /// Signalled whenever the retention policy is replaced, so compaction applies it.
+    pub retention_changed: Arc<Notify>,
+    /// Internal counters exported at `/metrics`.
+    pub counters: Arc<Counters>,
+    /// Set once the server starts shutting down; see [`AppState::shutting_down`].
+    pub shutdown: Arc<watch::Sender<bool>>,
+}
+
+impl AppState {
+    /// Must be called within a Tokio runtime, which runs the writer.
+    #[must_use]
+    pub fn new(client: Arc<SensorClient>, db: Db) -> Self {
+        Self {
+            client,
+            writer: db.writer(WriterConfig::default()),
+            db,
+            ingest_bucket_secs: INGEST_BUCKET_SECS,
+            alert_rules_changed: Arc::new(Notify::new()),
+            alerts_tx: broadcast::channel(ALERT_CHANNEL_CAPACITY).0,
+            webhooks_changed: Arc::new(Notify::new()),
+            retention_changed: Arc::new(Notify::new()),
+            counters: Arc::new(Counters::default()),
+            shutdown: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    /// Tell background tasks and open event streams to finish what they hold and end.
+    pub fn shut_down(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Resolves once [`AppState::shut_down`] is called, or right away if it has been.
+    pub async fn shutting_down(&self) {
+        let _ = self.shutdown.subscribe().wait_for(|&down| down).await;
+    }
 }
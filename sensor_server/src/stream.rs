@@ -0,0 +1,259 @@
+//! Server-Sent Events: live readings and registry changes, pushed as they arrive.
+//!
+//! Lets scripts and the dashboard follow the sensor feed without polling `/api/sensors` or
+//! joining the multicast group themselves. Events are fed from the same broadcast channels
+//! the ingest task reads.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::Router;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use chlorophyll_client::{Reading, ReadingKind, RegistryEvent};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::api::{parse_id_hex, parse_metric};
+use crate::state::AppState;
+
+/// Comment frames sent on an idle stream, so proxies don't time the connection out.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize, Default)]
+pub struct StreamQuery {
+    /// Only forward events for this sensor (hex id).
+    sensor: Option<String>,
+    /// Only forward readings of this metric. Registry events carry no metric and always pass.
+    metric: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct StreamFilter {
+    sensor: Option<u128>,
+    metric: Option<ReadingKind>,
+}
+
+impl StreamFilter {
+    /// `None` if either filter is present but unparseable.
+    fn from_query(query: &StreamQuery) -> Option<Self> {
+        let sensor = match query.sensor.as_deref() {
+            Some(id_hex) => Some(parse_id_hex(id_hex)?),
+            None => None,
+        };
+        let metric = match query.metric.as_deref() {
+            Some(metric) => Some(parse_metric(metric)?),
+            None => None,
+        };
+        Some(Self { sensor, metric })
+    }
+
+    fn wants_reading(&self, reading: &Reading) -> bool {
+        self.sensor.is_none_or(|id| id == reading.sensor_id)
+            && self.metric.is_none_or(|kind| kind == reading.kind)
+    }
+
+    fn wants_event(&self, event: &RegistryEvent) -> bool {
+        self.sensor.is_none_or(|wanted| wanted == event.id())
+    }
+}
+
+/// Payload of a `reading` event.
+#[derive(Debug, Serialize)]
+pub struct ReadingEvent {
+    pub id_hex: String,
+    pub metric: &'static str,
+    pub value: f32,
+    /// Unix timestamp in milliseconds.
+    pub t: i64,
+}
+
+/// Payload of `device` (first sighting) and `name` (rename) events.
+#[derive(Debug, Serialize)]
+pub struct DeviceEvent {
+    pub id_hex: String,
+    pub name: Option<String>,
+    /// Unix timestamp in milliseconds.
+    pub t: i64,
+}
+
+/// Payload of a `status` event: the sensor went stale or offline, or came back.
+#[derive(Debug, Serialize)]
+pub struct StatusEvent {
+    pub id_hex: String,
+    pub status: &'static str,
+    pub previous: &'static str,
+    /// Unix timestamp in milliseconds.
+    pub t: i64,
+}
+
+/// Payload of a `rename` event: `SetName` was sent, or the sensor confirmed the name or
+/// was given up on.
+#[derive(Debug, Serialize)]
+pub struct RenameEvent {
+    pub id_hex: String,
+    pub name: String,
+    /// `pending`, `confirmed` or `failed`.
+    pub state: &'static str,
+    /// Unix timestamp in milliseconds.
+    pub t: i64,
+}
+
+/// Payload of a `lagged` event: the subscriber fell behind and `skipped` messages were lost.
+#[derive(Debug, Serialize)]
+pub struct LaggedEvent {
+    pub skipped: u64,
+}
+
+fn json_event<T: Serialize>(name: &'static str, payload: &T) -> Event {
+    // The payloads are plain strings and numbers, so serialization cannot fail.
+    Event::default().event(name).json_data(payload).unwrap_or_default()
+}
+
+fn reading_event(reading: &Reading) -> Event {
+    json_event(
+        "reading",
+        &ReadingEvent {
+            id_hex: format!("{:032x}", reading.sensor_id),
+            metric: reading.kind.as_str(),
+            value: reading.value,
+            t: reading.at.timestamp_millis(),
+        },
+    )
+}
+
+fn registry_event(event: &RegistryEvent) -> Event {
+    match event {
+        RegistryEvent::DeviceAdded { id, at } => json_event(
+            "device",
+            &DeviceEvent {
+                id_hex: format!("{id:032x}"),
+                name: None,
+                t: at.timestamp_millis(),
+            },
+        ),
+        RegistryEvent::NameChanged { id, name, at } => json_event(
+            "name",
+            &DeviceEvent {
+                id_hex: format!("{id:032x}"),
+                name: Some(name.clone()),
+                t: at.timestamp_millis(),
+            },
+        ),
+        RegistryEvent::StatusChanged { id, from, to, at } => json_event(
+            "status",
+            &StatusEvent {
+                id_hex: format!("{id:032x}"),
+                status: to.as_str(),
+                previous: from.as_str(),
+                t: at.timestamp_millis(),
+            },
+        ),
+        RegistryEvent::RenamePending { id, name, at } => rename_event(*id, name, "pending", *at),
+        RegistryEvent::RenameSettled { id, name, confirmed, at } => {
+            rename_event(*id, name, if *confirmed { "confirmed" } else { "failed" }, *at)
+        }
+    }
+}
+
+fn rename_event(id: u128, name: &str, state: &'static str, at: DateTime<Utc>) -> Event {
+    json_event(
+        "rename",
+        &RenameEvent {
+            id_hex: format!("{id:032x}"),
+            name: name.to_string(),
+            state,
+            t: at.timestamp_millis(),
+        },
+    )
+}
+
+/// `GET /api/stream`: readings as `reading` events, registry changes as `device` / `name` /
+/// `status` / `rename` events, each with a JSON body.
+async fn stream(
+    State(state): State<AppState>,
+    Query(query): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let filter = StreamFilter::from_query(&query).ok_or(StatusCode::BAD_REQUEST)?;
+    let readings = state.client.subscribe();
+    let events = state.client.subscribe_events();
+
+    // Ends at shutdown, which otherwise would wait on the client to hang up.
+    let stream = stream::unfold(
+        (readings, events, filter, state),
+        |(mut readings, mut events, filter, state)| async move {
+            loop {
+                // Registry events are sent ahead of the readings they precede, so with both
+                // ready a new device is still announced before its first reading.
+                let event = tokio::select! {
+                    biased;
+                    () = state.shutting_down() => return None,
+                    received = events.recv() => match received {
+                        Ok(event) if filter.wants_event(&event) => registry_event(&event),
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(skipped)) => json_event("lagged", &LaggedEvent { skipped }),
+                        Err(RecvError::Closed) => return None,
+                    },
+                    received = readings.recv() => match received {
+                        Ok(reading) if filter.wants_reading(&reading) => reading_event(&reading),
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(skipped)) => json_event("lagged", &LaggedEvent { skipped }),
+                        Err(RecvError::Closed) => return None,
+                    },
+                };
+                return Some((Ok(event), (readings, events, filter, state)));
+            }
+        },
+    );
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL)))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/api/stream", get(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn reading(sensor_id: u128, kind: ReadingKind) -> Reading {
+        Reading {
+            sensor_id,
+            kind,
+            value: 1.0,
+            at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn filters_narrow_readings_but_registry_events_ignore_the_metric() {
+        let query = StreamQuery {
+            sensor: Some(format!("{:032x}", 7_u128)),
+            metric: Some("humidity".into()),
+        };
+        let filter = StreamFilter::from_query(&query).expect("valid filter");
+
+        assert!(filter.wants_reading(&reading(7, ReadingKind::Humidity)));
+        assert!(!filter.wants_reading(&reading(7, ReadingKind::Temperature)));
+        assert!(!filter.wants_reading(&reading(8, ReadingKind::Humidity)));
+
+        let at = Utc::now();
+        assert!(filter.wants_event(&RegistryEvent::DeviceAdded { id: 7, at }));
+        assert!(!filter.wants_event(&RegistryEvent::NameChanged { id: 8, name: "bench".into(), at }));
+    }
+
+    #[test]
+    fn unparseable_filters_are_rejected() {
+        let bad_metric = StreamQuery { metric: Some("nonsense".into()), ..StreamQuery::default() };
+        assert!(StreamFilter::from_query(&bad_metric).is_none());
+
+        let bad_sensor = StreamQuery { sensor: Some("zzzz".into()), ..StreamQuery::default() };
+        assert!(StreamFilter::from_query(&bad_sensor).is_none());
+    }
+}
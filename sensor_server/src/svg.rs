@@ -2,7 +2,7 @@
 
 use std::fmt::Write;
 
-use chlorophyll_client::db::Point;
+use chlorophyll_client::db::HistoryPoint;
 use chrono::{DateTime, Utc};
 
 const WIDTH: f64 = 1000.0;
@@ -12,16 +12,34 @@ const PAD_RIGHT: f64 = 16.0;
 const PAD_TOP: f64 = 14.0;
 const PAD_BOTTOM: f64 = 30.0;
 
-/// A named series of points to plot as one polyline.
+/// A named series of points to plot as one polyline, over a band spanning each point's
+/// min and max.
 pub struct Series<'a> {
     pub label: &'a str,
     pub color: &'a str,
-    pub points: &'a [Point],
+    pub points: &'a [HistoryPoint],
 }
 
-/// Render an inline SVG line chart for `series` spanning `[from, to]`.
+/// Y-axis bounds for `values`: their extent, widened about its middle to at least `min_span`.
+/// The widening avoids a zero-height range when all values are identical, and blowing up
+/// noise when they barely move.
+fn y_range(values: &[f32], min_span: f32) -> (f32, f32) {
+    let min_v = values.iter().copied().fold(f32::INFINITY, f32::min);
+    let max_v = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let min_span = min_span.max(f32::EPSILON);
+    if max_v - min_v < min_span {
+        let mid = f32::midpoint(min_v, max_v);
+        (mid - min_span / 2.0, mid + min_span / 2.0)
+    } else {
+        (min_v, max_v)
+    }
+}
+
+/// Render an inline SVG line chart for `series` spanning `[from, to]`, each line over a
+/// faint envelope of its buckets' extremes.
 ///
-/// `unit_suffix` is appended to the y-axis labels (e.g. `"°C"`, `"%"`, `"lux"`).
+/// `unit_suffix` is appended to the y-axis labels (e.g. `"°C"`, `"%"`, `" lx"`). The y-axis
+/// spans at least `min_span`, centred on the data when it varies less than that.
 /// Returns `None` if every series is empty (nothing to plot).
 #[must_use]
 pub fn line_chart(
@@ -29,23 +47,17 @@ pub fn line_chart(
     from: DateTime<Utc>,
     to: DateTime<Utc>,
     unit_suffix: &str,
+    min_span: f32,
 ) -> Option<String> {
     let all_values: Vec<f32> = series
         .iter()
-        .flat_map(|s| s.points.iter().map(|(_, v)| *v))
+        .flat_map(|s| s.points.iter().flat_map(|p| [p.min, p.max]))
         .collect();
     if all_values.is_empty() {
         return None;
     }
 
-    let min_v = all_values.iter().copied().fold(f32::INFINITY, f32::min);
-    let max_v = all_values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
-    // Avoid a zero-height range when all values are identical.
-    let (min_v, max_v) = if (max_v - min_v).abs() < f32::EPSILON {
-        (min_v - 1.0, max_v + 1.0)
-    } else {
-        (min_v, max_v)
-    };
+    let (min_v, max_v) = y_range(&all_values, min_span);
 
     #[allow(clippy::cast_precision_loss)]
     let span_ms = (to - from).num_milliseconds().max(1) as f64;
@@ -122,17 +134,22 @@ pub fn line_chart(
         );
     }
 
+    // Envelopes first, so no band covers another series' line.
+    for s in series {
+        if let Some(band) = envelope(s.points, x_for, y_for) {
+            let _ = write!(
+                svg,
+                r#"<path d="{band}" fill="{}" fill-opacity="0.18" stroke="none" class="chart-band" />"#,
+                s.color,
+            );
+        }
+    }
+
     for s in series {
         if s.points.is_empty() {
             continue;
         }
-        let mut path = String::from("M ");
-        for (i, (at, value)) in s.points.iter().enumerate() {
-            if i > 0 {
-                path.push_str(" L ");
-            }
-            let _ = write!(path, "{:.1} {:.1}", x_for(*at), y_for(*value));
-        }
+        let path = trace(s.points.iter().map(|p| (x_for(p.at), y_for(p.value))));
         let label = escape_xml_text(s.label);
         let _ = write!(
             svg,
@@ -145,6 +162,33 @@ pub fn line_chart(
     Some(svg)
 }
 
+/// Path outlining `points`' extremes: along the maxima, then back along the minima. `None`
+/// when every bucket is a single value, leaving nothing to outline.
+fn envelope(
+    points: &[HistoryPoint],
+    x_for: impl Fn(DateTime<Utc>) -> f64,
+    y_for: impl Fn(f32) -> f64,
+) -> Option<String> {
+    if !points.iter().any(|p| p.min < p.max) {
+        return None;
+    }
+    let upper = points.iter().map(|p| (x_for(p.at), y_for(p.max)));
+    let lower = points.iter().rev().map(|p| (x_for(p.at), y_for(p.min)));
+    Some(trace(upper.chain(lower)) + " Z")
+}
+
+/// Path data visiting `coords` in order.
+fn trace(coords: impl Iterator<Item = (f64, f64)>) -> String {
+    let mut path = String::from("M ");
+    for (i, (x, y)) in coords.enumerate() {
+        if i > 0 {
+            path.push_str(" L ");
+        }
+        let _ = write!(path, "{x:.1} {y:.1}");
+    }
+    path
+}
+
 fn escape_xml_text(value: &str) -> String {
     let mut escaped = String::with_capacity(value.len());
     for ch in value.chars() {
@@ -173,22 +217,43 @@ pub fn series_color(index: usize) -> &'static str {
 mod tests {
     use super::*;
 
+    fn point(at: DateTime<Utc>, value: f32, min: f32, max: f32) -> HistoryPoint {
+        HistoryPoint { at, value, min, max, count: 1, secs: 60 }
+    }
+
     #[test]
     fn line_chart_escapes_series_labels() {
         let from = Utc::now();
-        let points = [(from, 1.0)];
+        let points = [point(from, 1.0, 1.0, 1.0)];
         let series = [Series {
-            label: r#"</title><script>alert('xss')</script><title>"#,
+            label: r"</title><script>alert('xss')</script><title>",
             color: "#000",
             points: &points,
         }];
 
         let chart =
-            line_chart(&series, from, from + chrono::Duration::seconds(1), "").expect("chart");
+            line_chart(&series, from, from + chrono::Duration::seconds(1), "", 1.0).expect("chart");
 
         assert!(!chart.contains("<script>"));
         assert!(chart.contains(
             "&lt;/title&gt;&lt;script&gt;alert(&#39;xss&#39;)&lt;/script&gt;&lt;title&gt;"
         ));
     }
+
+    #[test]
+    fn envelopes_span_the_extremes_behind_the_lines() {
+        let from = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let to = from + chrono::Duration::minutes(2);
+        let frost = [point(from, 2.0, 2.0, 2.0), point(to, 2.0, -3.0, 4.0)];
+        let series = [Series { label: "bench", color: "#000", points: &frost }];
+
+        let chart = line_chart(&series, from, to, "°C", 1.0).expect("chart");
+        let band = chart.find("chart-band").expect("a band where readings spread");
+        assert!(band < chart.find("<title>").unwrap(), "band drawn under the line");
+        assert!(chart.contains("-3.0°C"), "the y-axis reaches the minimum: {chart}");
+
+        let flat = [point(from, 2.0, 2.0, 2.0)];
+        let series = [Series { label: "bench", color: "#000", points: &flat }];
+        assert!(!line_chart(&series, from, to, "", 1.0).unwrap().contains("chart-band"));
+    }
 }
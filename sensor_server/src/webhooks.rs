@@ -0,0 +1,660 @@
+//! Webhook sink: POSTs a JSON notification to configured HTTP targets when a sensor goes
+//! offline or comes back, is renamed, crosses a target's own threshold, or an alert fires
+//! or resolves.
+//!
+//! Each delivery runs on its own task and retries with exponential backoff, so a slow or
+//! failing receiver never holds up the others. The outcome of every delivery is logged to
+//! the database and readable from `/api/webhooks/{id}/deliveries`.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use chlorophyll_client::db::{AlertEvent, AlertState, Db, WebhookDelivery, WebhookTarget};
+use chlorophyll_client::{DeviceStatus, Reading, ReadingKind, RegistryEvent};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::api::{parse_id_hex, parse_measured_metric};
+use crate::state::AppState;
+
+/// `sha256=<hex HMAC of the body>`, present when the target has a secret.
+pub const SIGNATURE_HEADER: &str = "x-chlorophyll-signature";
+/// The notification's `event` field, so receivers can route without parsing the body.
+pub const EVENT_HEADER: &str = "x-chlorophyll-event";
+
+/// Per-attempt timeout, so a hung receiver can't pin a delivery task forever.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_DELIVERY_LIMIT: i64 = 100;
+const MAX_DELIVERY_LIMIT: i64 = 1000;
+
+/// How often and how patiently a delivery is retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// Wait after the first failure; doubled after each further failure.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_mins(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before the attempt following failed attempt number `attempt` (1-based).
+    #[must_use]
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// Body of every webhook request.
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    /// `sensor.offline`, `sensor.online`, `sensor.renamed`, `threshold.crossed`,
+    /// `threshold.cleared`, `alert.fired` or `alert.resolved`.
+    pub event: &'static str,
+    #[serde(skip)]
+    pub sensor_id: u128,
+    pub id_hex: String,
+    pub name: Option<String>,
+    /// Unix timestamp in milliseconds.
+    pub t: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metric: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule_id: Option<i64>,
+}
+
+impl Notification {
+    fn new(event: &'static str, sensor_id: u128, at: DateTime<Utc>) -> Self {
+        Self {
+            event,
+            sensor_id,
+            id_hex: format!("{sensor_id:032x}"),
+            name: None,
+            t: at.timestamp_millis(),
+            metric: None,
+            value: None,
+            rule_id: None,
+        }
+    }
+
+    fn from_registry(event: &RegistryEvent) -> Option<Self> {
+        match event {
+            RegistryEvent::NameChanged { id, name, at } => {
+                let mut notification = Self::new("sensor.renamed", *id, *at);
+                notification.name = Some(name.clone());
+                Some(notification)
+            }
+            // Stale is a warning sign, not worth waking anyone for.
+            RegistryEvent::StatusChanged { id, from, to, at } => match (from, to) {
+                (_, DeviceStatus::Offline) => Some(Self::new("sensor.offline", *id, *at)),
+                (DeviceStatus::Offline, _) => Some(Self::new("sensor.online", *id, *at)),
+                _ => None,
+            },
+            RegistryEvent::DeviceAdded { .. }
+            | RegistryEvent::RenamePending { .. }
+            | RegistryEvent::RenameSettled { .. } => None,
+        }
+    }
+
+    fn from_alert(event: &AlertEvent) -> Self {
+        let name = match event.state {
+            AlertState::Fired => "alert.fired",
+            AlertState::Resolved => "alert.resolved",
+        };
+        let mut notification = Self::new(name, event.sensor_id, event.at);
+        notification.value = event.value;
+        notification.rule_id = Some(event.rule_id);
+        notification
+    }
+}
+
+/// `sha256=<hex>` HMAC-SHA256 of `body` under `secret`, as sent in [`SIGNATURE_HEADER`].
+#[must_use]
+#[allow(clippy::missing_panics_doc)] // the `expect` can't fail
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    let mut signature = String::from("sha256=");
+    for byte in mac.finalize().into_bytes() {
+        let _ = write!(signature, "{byte:02x}");
+    }
+    signature
+}
+
+/// Decides which notifications to send, and to which targets.
+#[derive(Debug, Default)]
+pub struct WebhookWatcher {
+    targets: Vec<WebhookTarget>,
+    /// `(target, sensor)` pairs whose threshold is currently crossed.
+    crossed: HashSet<(i64, u128)>,
+}
+
+impl WebhookWatcher {
+    #[must_use]
+    pub fn new(targets: Vec<WebhookTarget>) -> Self {
+        Self {
+            targets,
+            ..Self::default()
+        }
+    }
+
+    /// Replace the target set. Threshold state is kept for targets that still exist.
+    pub fn set_targets(&mut self, targets: Vec<WebhookTarget>) {
+        self.crossed
+            .retain(|(target_id, _)| targets.iter().any(|t| t.id == *target_id));
+        self.targets = targets;
+    }
+
+    /// Enabled targets interested in `sensor_id`.
+    pub fn targets_for(&self, sensor_id: u128) -> impl Iterator<Item = &WebhookTarget> {
+        self.targets
+            .iter()
+            .filter(move |t| t.enabled && t.sensor_id.is_none_or(|id| id == sensor_id))
+    }
+
+    /// Threshold crossings caused by `reading`, paired with the one target each is for.
+    pub fn on_reading(&mut self, reading: &Reading) -> Vec<(WebhookTarget, Notification)> {
+        let mut notifications = Vec::new();
+
+        for target in &self.targets {
+            if !target.enabled
+                || target.kind != Some(reading.kind)
+                || target.sensor_id.is_some_and(|id| id != reading.sensor_id)
+            {
+                continue;
+            }
+            let value = reading.value;
+            let h = target.hysteresis;
+            let outside = target.above.is_some_and(|a| value > a) || target.below.is_some_and(|b| value < b);
+            let inside = target.above.is_none_or(|a| value <= a - h) && target.below.is_none_or(|b| value >= b + h);
+
+            let key = (target.id, reading.sensor_id);
+            let event = if outside && self.crossed.insert(key) {
+                "threshold.crossed"
+            } else if inside && self.crossed.remove(&key) {
+                "threshold.cleared"
+            } else {
+                continue;
+            };
+            let mut notification = Notification::new(event, reading.sensor_id, reading.at);
+            notification.metric = Some(reading.kind.as_str());
+            notification.value = Some(value);
+            notifications.push((target.clone(), notification));
+        }
+
+        notifications
+    }
+}
+
+/// POST `notification` to `target`, retrying per `policy`, then log the outcome.
+pub async fn deliver(
+    http: &reqwest::Client,
+    db: &Db,
+    target: &WebhookTarget,
+    notification: &Notification,
+    policy: RetryPolicy,
+) -> WebhookDelivery {
+    let payload = serde_json::to_string(notification).unwrap_or_default();
+    let signature = target.secret.as_deref().map(|secret| sign(secret, payload.as_bytes()));
+
+    let mut delivery = WebhookDelivery {
+        id: 0,
+        target_id: target.id,
+        event: notification.event.to_string(),
+        payload: payload.clone(),
+        attempts: 0,
+        status: None,
+        error: None,
+        delivered: false,
+        at: Utc::now(),
+    };
+
+    for attempt in 1..=policy.max_attempts.max(1) {
+        delivery.attempts = i64::from(attempt);
+        let mut request = http
+            .post(&target.url)
+            .timeout(REQUEST_TIMEOUT)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, notification.event)
+            .body(payload.clone());
+        if let Some(signature) = &signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+
+        match request.send().await {
+            Ok(response) => {
+                delivery.status = Some(response.status().as_u16());
+                if response.status().is_success() {
+                    delivery.delivered = true;
+                    delivery.error = None;
+                    break;
+                }
+                delivery.error = Some(format!("HTTP {}", response.status()));
+            }
+            Err(e) => {
+                delivery.status = None;
+                delivery.error = Some(e.to_string());
+            }
+        }
+
+        if attempt < policy.max_attempts {
+            tokio::time::sleep(policy.delay(attempt)).await;
+        }
+    }
+
+    if !delivery.delivered {
+        tracing::warn!(
+            "webhook {} to {} failed after {} attempts: {}",
+            notification.event,
+            target.url,
+            delivery.attempts,
+            delivery.error.as_deref().unwrap_or("unknown error"),
+        );
+    }
+    match db.insert_webhook_delivery(&delivery).await {
+        Ok(id) => delivery.id = id,
+        Err(e) => tracing::error!("webhooks: cannot log delivery: {e}"),
+    }
+    delivery
+}
+
+/// Webhook task: watch readings, registry changes (including status), and alerts, and
+/// dispatch notifications to every interested target. Runs until the reading channel
+/// closes.
+pub async fn run(state: AppState) {
+    let http = reqwest::Client::new();
+    let mut readings = state.client.subscribe();
+    let mut registry_events = state.client.subscribe_events();
+    let mut alerts = state.alerts_tx.subscribe();
+    let mut watcher = match state.db.webhook_targets().await {
+        Ok(targets) => WebhookWatcher::new(targets),
+        Err(e) => {
+            tracing::error!("webhooks: cannot load targets: {e}");
+            WebhookWatcher::default()
+        }
+    };
+
+    let dispatch = |target: WebhookTarget, mut notification: Notification, name: Option<String>| {
+        if notification.name.is_none() {
+            notification.name = name;
+        }
+        let http = http.clone();
+        let db = state.db.clone();
+        tokio::spawn(async move {
+            deliver(&http, &db, &target, &notification, RetryPolicy::default()).await;
+        });
+    };
+    let name_of = |sensor_id: u128| {
+        state
+            .client
+            .devices()
+            .into_iter()
+            .find(|d| d.id == sensor_id)
+            .and_then(|d| d.name)
+    };
+
+    loop {
+        let broadcast: Vec<Notification> = tokio::select! {
+            received = readings.recv() => match received {
+                Ok(reading) => {
+                    for (target, notification) in watcher.on_reading(&reading) {
+                        dispatch(target, notification, name_of(reading.sensor_id));
+                    }
+                    continue;
+                }
+                Err(RecvError::Lagged(n)) => {
+                    state.counters.record_lagged(n);
+                    tracing::warn!("webhooks: readings channel lagged, dropped {n} messages");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            received = registry_events.recv() => match received {
+                Ok(event) => Notification::from_registry(&event).into_iter().collect(),
+                Err(_) => continue,
+            },
+            received = alerts.recv() => match received {
+                Ok(event) => vec![Notification::from_alert(&event)],
+                Err(_) => continue,
+            },
+            () = state.webhooks_changed.notified() => {
+                match state.db.webhook_targets().await {
+                    Ok(targets) => watcher.set_targets(targets),
+                    Err(e) => tracing::error!("webhooks: cannot reload targets: {e}"),
+                }
+                continue;
+            }
+        };
+
+        for notification in broadcast {
+            let targets: Vec<WebhookTarget> = watcher.targets_for(notification.sensor_id).cloned().collect();
+            if targets.is_empty() {
+                continue;
+            }
+            let name = name_of(notification.sensor_id);
+            for target in targets {
+                dispatch(target, notification.clone(), name.clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookTargetRequest {
+    pub url: String,
+    /// Key for the HMAC signature header; omitted sends unsigned requests.
+    pub secret: Option<String>,
+    /// Hex id; omitted notifies about every sensor.
+    pub sensor: Option<String>,
+    /// Metric for the threshold; requires `above` and/or `below`.
+    pub metric: Option<String>,
+    pub above: Option<f32>,
+    pub below: Option<f32>,
+    #[serde(default)]
+    pub hysteresis: f32,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl WebhookTargetRequest {
+    /// Validate into a target with the given id; `None` if any field is out of range.
+    fn into_target(self, id: i64) -> Option<WebhookTarget> {
+        let url = self.url.trim();
+        if !(url.starts_with("http://") || url.starts_with("https://"))
+            || !self.hysteresis.is_finite()
+            || self.hysteresis < 0.0
+            || self.above.is_some_and(|v| !v.is_finite())
+            || self.below.is_some_and(|v| !v.is_finite())
+        {
+            return None;
+        }
+        let sensor_id = match self.sensor.as_deref() {
+            Some(id_hex) => Some(parse_id_hex(id_hex)?),
+            None => None,
+        };
+        let kind = match self.metric.as_deref() {
+            Some(metric) => Some(parse_measured_metric(metric)?),
+            None => None,
+        };
+        // A threshold needs both a metric and at least one bound, or neither.
+        let has_bound = self.above.is_some() || self.below.is_some();
+        if kind.is_some() != has_bound {
+            return None;
+        }
+        Some(WebhookTarget {
+            id,
+            url: url.to_string(),
+            secret: self.secret.filter(|s| !s.is_empty()),
+            sensor_id,
+            kind,
+            above: self.above,
+            below: self.below,
+            hysteresis: self.hysteresis,
+            enabled: self.enabled,
+        })
+    }
+}
+
+/// A target as returned by the API. The secret itself is never echoed back.
+#[derive(Debug, Serialize)]
+pub struct WebhookTargetJson {
+    pub id: i64,
+    pub url: String,
+    pub signed: bool,
+    pub sensor: Option<String>,
+    pub metric: Option<&'static str>,
+    pub above: Option<f32>,
+    pub below: Option<f32>,
+    pub hysteresis: f32,
+    pub enabled: bool,
+}
+
+impl From<WebhookTarget> for WebhookTargetJson {
+    fn from(target: WebhookTarget) -> Self {
+        Self {
+            id: target.id,
+            url: target.url,
+            signed: target.secret.is_some(),
+            sensor: target.sensor_id.map(|id| format!("{id:032x}")),
+            metric: target.kind.map(ReadingKind::as_str),
+            above: target.above,
+            below: target.below,
+            hysteresis: target.hysteresis,
+            enabled: target.enabled,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhookDeliveryJson {
+    pub id: i64,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i64,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub delivered: bool,
+    /// Unix timestamp in milliseconds.
+    pub t: i64,
+}
+
+impl From<WebhookDelivery> for WebhookDeliveryJson {
+    fn from(delivery: WebhookDelivery) -> Self {
+        Self {
+            id: delivery.id,
+            event: delivery.event,
+            payload: delivery.payload,
+            attempts: delivery.attempts,
+            status: delivery.status,
+            error: delivery.error,
+            delivered: delivery.delivered,
+            t: delivery.at.timestamp_millis(),
+        }
+    }
+}
+
+async fn list_targets(State(state): State<AppState>) -> Result<Json<Vec<WebhookTargetJson>>, StatusCode> {
+    let targets = state
+        .db
+        .webhook_targets()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(targets.into_iter().map(WebhookTargetJson::from).collect()))
+}
+
+async fn create_target(
+    State(state): State<AppState>,
+    Json(body): Json<WebhookTargetRequest>,
+) -> Result<(StatusCode, Json<WebhookTargetJson>), StatusCode> {
+    let mut target = body.into_target(0).ok_or(StatusCode::BAD_REQUEST)?;
+    target.id = state
+        .db
+        .insert_webhook_target(&target)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    state.webhooks_changed.notify_one();
+    Ok((StatusCode::CREATED, Json(WebhookTargetJson::from(target))))
+}
+
+async fn get_target(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<WebhookTargetJson>, StatusCode> {
+    state
+        .db
+        .webhook_target(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(|target| Json(WebhookTargetJson::from(target)))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn update_target(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Json(body): Json<WebhookTargetRequest>,
+) -> Result<Json<WebhookTargetJson>, StatusCode> {
+    let target = body.into_target(id).ok_or(StatusCode::BAD_REQUEST)?;
+    let updated = state
+        .db
+        .update_webhook_target(&target)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !updated {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    state.webhooks_changed.notify_one();
+    Ok(Json(WebhookTargetJson::from(target)))
+}
+
+async fn delete_target(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, StatusCode> {
+    let deleted = state
+        .db
+        .delete_webhook_target(id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !deleted {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    state.webhooks_changed.notify_one();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct DeliveriesQuery {
+    limit: Option<i64>,
+}
+
+async fn deliveries(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(query): Query<DeliveriesQuery>,
+) -> Result<Json<Vec<WebhookDeliveryJson>>, StatusCode> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_DELIVERY_LIMIT)
+        .clamp(1, MAX_DELIVERY_LIMIT);
+    let deliveries = state
+        .db
+        .webhook_deliveries(id, limit)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(deliveries.into_iter().map(WebhookDeliveryJson::from).collect()))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/api/webhooks", get(list_targets).post(create_target))
+        .route(
+            "/api/webhooks/{id}",
+            get(get_target).put(update_target).delete(delete_target),
+        )
+        .route("/api/webhooks/{id}/deliveries", get(deliveries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(above: Option<f32>, below: Option<f32>, hysteresis: f32) -> WebhookTarget {
+        WebhookTarget {
+            id: 1,
+            url: "http://127.0.0.1:1/hook".into(),
+            secret: None,
+            sensor_id: None,
+            kind: Some(ReadingKind::Humidity),
+            above,
+            below,
+            hysteresis,
+            enabled: true,
+        }
+    }
+
+    fn reading(value: f32) -> Reading {
+        Reading {
+            sensor_id: 7,
+            kind: ReadingKind::Humidity,
+            value,
+            at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        }
+    }
+
+    fn events(notifications: &[(WebhookTarget, Notification)]) -> Vec<&'static str> {
+        notifications.iter().map(|(_, n)| n.event).collect()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+        };
+        let delays: Vec<u64> = (1..=5).map(|n| policy.delay(n).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+    }
+
+    #[test]
+    fn thresholds_notify_once_per_crossing_with_hysteresis() {
+        let mut watcher = WebhookWatcher::new(vec![target(Some(80.0), Some(30.0), 2.0)]);
+
+        assert!(watcher.on_reading(&reading(50.0)).is_empty());
+        assert_eq!(events(&watcher.on_reading(&reading(81.0))), vec!["threshold.crossed"]);
+        assert!(watcher.on_reading(&reading(85.0)).is_empty(), "still crossed");
+        assert!(watcher.on_reading(&reading(79.0)).is_empty(), "inside the hysteresis band");
+        assert_eq!(events(&watcher.on_reading(&reading(77.5))), vec!["threshold.cleared"]);
+        assert_eq!(events(&watcher.on_reading(&reading(29.0))), vec!["threshold.crossed"]);
+    }
+
+    #[test]
+    fn offline_and_online_follow_status_changes_but_stale_does_not() {
+        let at = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let event = |from, to| {
+            Notification::from_registry(&RegistryEvent::StatusChanged { id: 7, from, to, at }).map(|n| n.event)
+        };
+
+        assert_eq!(event(DeviceStatus::Online, DeviceStatus::Stale), None);
+        assert_eq!(event(DeviceStatus::Stale, DeviceStatus::Offline), Some("sensor.offline"));
+        assert_eq!(event(DeviceStatus::Offline, DeviceStatus::Online), Some("sensor.online"));
+        assert_eq!(event(DeviceStatus::Stale, DeviceStatus::Online), None);
+    }
+
+    #[test]
+    fn signature_matches_a_known_hmac_sha256_vector() {
+        // RFC 4231 test case 2.
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
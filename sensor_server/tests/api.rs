@@ -7,8 +7,9 @@ use axum::body::Body;
 use axum::http::{Request, StatusCode};
 use chlorophyll_client::db::Db;
 use chlorophyll_client::reading::{Reading, ReadingKind};
-use chlorophyll_client::{ClientConfig, SensorClient};
+use chlorophyll_client::{ClientConfig, DeviceInfo, SensorClient};
 use chrono::Utc;
+use futures_util::StreamExt;
 use http_body_util::BodyExt;
 use sensor_server::AppState;
 use tower::ServiceExt;
@@ -47,7 +48,7 @@ async fn test_state() -> (AppState, TempDb) {
     let cfg = ClientConfig { port: 0, ..ClientConfig::default() };
     let client = Arc::new(SensorClient::start(cfg).unwrap());
 
-    (AppState { client, db }, TempDb(path))
+    (AppState::new(client, db), TempDb(path))
 }
 
 fn uuid_like() -> String {
@@ -89,12 +90,18 @@ async fn api_sensors_history_since_zero_returns_seeded_readings() {
     let body = body_string(response).await;
     let series: serde_json::Value = serde_json::from_str(&body).unwrap();
     let series = series.as_array().unwrap();
-    assert_eq!(series.len(), 3, "expected one series per metric: {body}");
+    assert_eq!(series.len(), 7, "expected one series per measured and derived metric: {body}");
 
     let metrics: Vec<&str> = series.iter().map(|s| s["metric"].as_str().unwrap()).collect();
-    assert!(metrics.contains(&"temperature"));
-    assert!(metrics.contains(&"humidity"));
-    assert!(metrics.contains(&"light"));
+    for metric in ["temperature", "humidity", "light", "dew_point", "vpd", "heat_index", "dli"] {
+        assert!(metrics.contains(&metric), "missing {metric}: {body}");
+    }
+    let vpd = series.iter().find(|s| s["metric"] == "vpd").unwrap();
+    let kpa = vpd["points"][0]["v"].as_f64().unwrap();
+    assert!((kpa - 1.154).abs() < 0.01, "VPD at 21.5 °C / 55 % should be about 1.15 kPa, got {kpa}");
+    let temperature = series.iter().find(|s| s["metric"] == "temperature").unwrap();
+    let point = &temperature["points"][0];
+    assert_eq!((point["min"].as_f64(), point["max"].as_f64()), (Some(21.5), Some(21.5)), "{point}");
 }
 
 #[tokio::test]
@@ -196,6 +203,206 @@ async fn single_sensor_endpoints_filter_and_404() {
     assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 }
 
+/// A sensor remembered in the `sensors` table is listed by name after a restart, before
+/// it has sent anything, and its history keeps that name.
+#[tokio::test]
+async fn remembered_sensors_are_seeded_and_label_their_history() {
+    let (state, _db) = test_state().await;
+    state
+        .db
+        .upsert_sensor(&DeviceInfo {
+            id: 1,
+            name: Some("greenhouse".into()),
+            last_seen: Some(Utc::now() - chrono::Duration::days(2)),
+            ..DeviceInfo::default()
+        })
+        .await
+        .unwrap();
+    assert!(state.db.set_sensor_notes(1, Some("north wall")).await.unwrap());
+    state.client.seed(state.db.sensors().await.unwrap().iter().map(DeviceInfo::from));
+    let router = sensor_server::router().with_state(state);
+    let id_hex = format!("{:032x}", 1_u128);
+
+    let response = router
+        .clone()
+        .oneshot(Request::builder().uri(format!("/api/sensors/{id_hex}")).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let sensor: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    assert_eq!(sensor["name"], "greenhouse");
+    assert_eq!(sensor["status"], "offline");
+    assert_eq!(sensor["notes"], "north wall");
+
+    let response = router
+        .clone()
+        .oneshot(Request::builder().uri("/api/sensors/history?since=0").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let series: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    assert!(
+        series.as_array().unwrap().iter().all(|s| s["name"] == "greenhouse"),
+        "history should carry the recorded name: {series}"
+    );
+
+    let notes = |id_hex: &str, body: &'static str| {
+        Request::builder()
+            .method("PUT")
+            .uri(format!("/api/sensors/{id_hex}/notes"))
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let response = router.clone().oneshot(notes(&id_hex, r#"{"notes":null}"#)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let missing = format!("{:032x}", 999_u128);
+    let response = router.oneshot(notes(&missing, r#"{"notes":"x"}"#)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn tags_and_groups_filter_sensors_history_and_the_dashboard() {
+    let (state, _db) = test_state().await;
+    state.client.seed([DeviceInfo { id: 1, last_seen: Some(Utc::now()), ..DeviceInfo::default() }]);
+    let router = sensor_server::router().with_state(state);
+    let id_hex = format!("{:032x}", 1_u128);
+    let json = |method: &str, uri: String, body: String| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    let response = router
+        .clone()
+        .oneshot(json("PUT", format!("/api/sensors/{id_hex}/tags"), r#"["tomato", " shade ", "tomato"]"#.into()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, r#"["shade","tomato"]"#);
+    let response = router
+        .clone()
+        .oneshot(json("DELETE", format!("/api/sensors/{id_hex}/tags/%20"), String::new()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    // Tags are trimmed on the way out as on the way in.
+    let response = router
+        .clone()
+        .oneshot(json("DELETE", format!("/api/sensors/{id_hex}/tags/%20shade"), String::new()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = router
+        .clone()
+        .oneshot(json("POST", "/api/groups".into(), format!(r#"{{"name":"bench 1","sensors":["{id_hex}"]}}"#)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let group: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    let group_id = group["id"].as_i64().unwrap();
+    let response = router
+        .clone()
+        .oneshot(json("POST", "/api/groups".into(), r#"{"name":"bench 1"}"#.into()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let count = |body: &str| serde_json::from_str::<serde_json::Value>(body).unwrap().as_array().unwrap().len();
+    for (uri, expected) in [
+        (format!("/api/sensors?group={group_id}"), 1),
+        (format!("/api/sensors?group={}", group_id + 1), 0),
+        ("/api/sensors?tag=tomato".to_string(), 1),
+        ("/api/sensors?tag=shade".to_string(), 0),
+        (format!("/api/sensors/history?since=0&group={group_id}&tag=tomato"), 7),
+        ("/api/sensors/history?since=0&tag=shade".to_string(), 0),
+    ] {
+        let response = router.clone().oneshot(get(&uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(count(&body_string(response).await), expected, "{uri}");
+    }
+
+    let response = router.clone().oneshot(get(&format!("/api/sensors/{id_hex}"))).await.unwrap();
+    let sensor: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    assert_eq!(sensor["tags"], serde_json::json!(["tomato"]));
+    assert_eq!(sensor["groups"], serde_json::json!([group_id]));
+
+    let body = body_string(router.clone().oneshot(get("/")).await.unwrap()).await;
+    assert!(body.contains(r#"<tr class="group-row">"#), "expected a grouped table: {body}");
+    assert!(body.contains(r#"<h2 class="group-title">bench 1</h2>"#), "expected per-group charts: {body}");
+
+    let response = router
+        .clone()
+        .oneshot(json("DELETE", format!("/api/groups/{group_id}"), String::new()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = router.oneshot(get(&format!("/api/groups/{group_id}"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn calibration_changes_are_versioned() {
+    let (state, _db) = test_state().await;
+    let router = sensor_server::router().with_state(state);
+    let base = format!("/api/sensors/{:032x}/calibration", 1_u128);
+    let send = |method: &str, uri: String, body: &'static str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let response = router
+        .clone()
+        .oneshot(send("PUT", format!("{base}/temperature"), r#"{"offset":-1.5}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = router
+        .clone()
+        .oneshot(send("PUT", format!("{base}/light"), r#"{"points":[[0,0],[100,130]]}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = router
+        .clone()
+        .oneshot(send("PUT", format!("{base}/radiation"), r#"{"offset":1}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = router
+        .clone()
+        .oneshot(send("PUT", format!("{base}/vpd"), r#"{"offset":1}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST, "derived metrics are never calibrated");
+
+    let response = router.clone().oneshot(send("DELETE", format!("{base}/temperature"), "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = router.clone().oneshot(send("DELETE", format!("{base}/temperature"), "")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = router.clone().oneshot(send("GET", base.clone(), "")).await.unwrap();
+    let current: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    assert_eq!(current.as_array().unwrap().len(), 1, "only light is still calibrated: {current}");
+    assert_eq!(current[0]["method"], "table");
+
+    let response = router
+        .oneshot(send("GET", format!("{base}/history?metric=temperature"), ""))
+        .await
+        .unwrap();
+    let history: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    let methods: Vec<&str> = history.as_array().unwrap().iter().map(|v| v["method"].as_str().unwrap()).collect();
+    assert_eq!(methods, ["linear", "none"]);
+}
+
 #[tokio::test]
 async fn history_bucket_is_bounded_even_for_a_huge_window() {
     let (state, _db) = test_state().await;
@@ -238,6 +445,7 @@ async fn set_name_rejects_bad_input_before_touching_the_network() {
     assert_eq!(response.status(), StatusCode::BAD_REQUEST, "blank names are rejected");
 
     let response = router
+        .clone()
         .oneshot(
             Request::builder()
                 .method("POST")
@@ -249,4 +457,421 @@ async fn set_name_rejects_bad_input_before_touching_the_network() {
         .await
         .unwrap();
     assert_eq!(response.status(), StatusCode::BAD_REQUEST, "unparseable id is rejected");
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/api/sensors/{:032x}/name?wait=true", 0xdead_u128))
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"kitchen"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND, "waiting needs a sensor that can answer");
+}
+
+#[tokio::test]
+async fn stream_opens_an_event_stream_and_rejects_bad_filters() {
+    let (state, _db) = test_state().await;
+    let router = sensor_server::router().with_state(state);
+    let seeded = format!("{:032x}", 1_u128);
+
+    // The body never ends, so only the head is inspected.
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/stream?sensor={seeded}&metric=temperature"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+    let response = router
+        .clone()
+        .oneshot(Request::builder().uri("/api/stream?metric=nonsense").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST, "unknown metric is rejected");
+
+    let response = router
+        .oneshot(Request::builder().uri("/api/stream?sensor=nothex").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST, "unparseable sensor is rejected");
+}
+
+#[tokio::test]
+async fn stream_forwards_readings_and_registry_events_as_json() {
+    use chlorophyll_protocol::temperature::Celsius;
+    use chlorophyll_protocol::{DataType, Packet, PacketCommand, SensorInfo};
+
+    let (state, _db) = test_state().await;
+    let client = state.client.clone();
+    let router = sensor_server::router().with_state(state);
+    let response = router
+        .oneshot(Request::builder().uri(format!("/api/stream?sensor={:032x}", 9_u128)).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Another sensor's packets are filtered out ahead of these.
+    client.receive(&Packet::new(PacketCommand::DataReading(DataType::Temperature(Celsius::new(5.0))), 8));
+    client.receive(&Packet::new(PacketCommand::SensorsInfo(SensorInfo::new(Some("bench".into()))), 9));
+    client.receive(&Packet::new(PacketCommand::DataReading(DataType::Temperature(Celsius::new(21.5))), 9));
+
+    let mut body = response.into_body();
+    let mut text = String::new();
+    let mut events = Vec::new();
+    while events.len() < 3 {
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), body.frame())
+            .await
+            .expect("events arrive")
+            .unwrap()
+            .unwrap();
+        if let Ok(data) = frame.into_data() {
+            text.push_str(std::str::from_utf8(&data).unwrap());
+        }
+        while let Some(end) = text.find("\n\n") {
+            let message: String = text.drain(..end + 2).collect();
+            let field = |prefix: &str| message.lines().find_map(|line| line.strip_prefix(prefix)).map(str::to_string);
+            if let (Some(name), Some(data)) = (field("event: "), field("data: ")) {
+                events.push((name, serde_json::from_str::<serde_json::Value>(&data).unwrap()));
+            }
+        }
+    }
+
+    let id_hex = format!("{:032x}", 9_u128);
+    let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["device", "name", "reading"], "{events:?}");
+    assert_eq!(events[0].1["id_hex"], id_hex);
+    assert_eq!(events[0].1["name"], serde_json::Value::Null);
+    assert_eq!(events[1].1["name"], "bench");
+    let reading = &events[2].1;
+    assert_eq!(reading["id_hex"], id_hex);
+    assert_eq!(reading["metric"], "temperature");
+    assert_eq!(reading["value"], 21.5);
+    assert!(reading["t"].as_i64().unwrap() > 0, "{reading}");
+}
+
+#[tokio::test]
+async fn alert_rules_crud_and_validation() {
+    let (state, _db) = test_state().await;
+    let router = sensor_server::router().with_state(state);
+
+    let create = |body: &'static str| {
+        Request::builder()
+            .method("POST")
+            .uri("/api/alerts/rules")
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let response = router
+        .clone()
+        .oneshot(create(
+            r#"{"name":"too hot","metric":"temperature","condition":"above","threshold":30,"hysteresis":0.5,"min_duration_secs":60}"#,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    let id = created["id"].as_i64().unwrap();
+    assert_eq!(created["condition"], "above");
+    assert_eq!(created["enabled"], true, "rules are enabled by default");
+
+    // Value conditions need a metric; unknown conditions are rejected.
+    let response = router
+        .clone()
+        .oneshot(create(r#"{"name":"x","condition":"above","threshold":30}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = router
+        .clone()
+        .oneshot(create(r#"{"name":"x","metric":"humidity","condition":"sideways","threshold":1}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("PUT")
+                .uri(format!("/api/alerts/rules/{id}"))
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"silent","condition":"no_data","threshold":10}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = router
+        .clone()
+        .oneshot(Request::builder().uri("/api/alerts/rules").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let rules: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    assert_eq!(rules.as_array().unwrap().len(), 1);
+    assert_eq!(rules[0]["condition"], "no_data");
+    assert!(rules[0]["metric"].is_null());
+
+    let delete = || {
+        Request::builder()
+            .method("DELETE")
+            .uri(format!("/api/alerts/rules/{id}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+    assert_eq!(router.clone().oneshot(delete()).await.unwrap().status(), StatusCode::NO_CONTENT);
+    assert_eq!(router.clone().oneshot(delete()).await.unwrap().status(), StatusCode::NOT_FOUND);
+
+    let response = router
+        .oneshot(Request::builder().uri("/api/alerts/history?since=0").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let history: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    assert!(history.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn metrics_endpoint_serves_prometheus_text() {
+    let (state, _db) = test_state().await;
+    state.counters.record_db_insert_error();
+    let router = sensor_server::router().with_state(state);
+
+    let response = router.oneshot(Request::builder().uri("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let content_type = response.headers()["content-type"].to_str().unwrap().to_string();
+    assert!(content_type.starts_with("text/plain; version=0.0.4"), "{content_type}");
+
+    let body = body_string(response).await;
+    assert!(body.contains("# TYPE chlorophyll_temperature_celsius gauge"), "{body}");
+    assert!(body.contains("# TYPE chlorophyll_decode_failures_total counter"), "{body}");
+    assert!(body.contains("chlorophyll_db_insert_errors_total 1\n"), "{body}");
+}
+
+#[tokio::test]
+async fn export_streams_csv_and_json_lines_as_attachments() {
+    let (state, _db) = test_state().await;
+    let router = sensor_server::router().with_state(state);
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    let response = router.clone().oneshot(get("/api/export?format=csv")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/csv; charset=utf-8");
+    let disposition = response.headers()["content-disposition"].to_str().unwrap().to_string();
+    assert!(disposition.starts_with("attachment; filename=\"chlorophyll-") && disposition.ends_with(".csv\""));
+    let body = body_string(response).await;
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines[0], "timestamp,sensor_id,name,metric,value");
+    assert_eq!(lines.len(), 4, "header and the three seeded readings: {body}");
+
+    let uri = format!("/api/export?format=jsonl&sensor={:032x}&metric=humidity&bucket=60", 1_u128);
+    let body = body_string(router.clone().oneshot(get(&uri)).await.unwrap()).await;
+    let rows: Vec<serde_json::Value> = body.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(rows.len(), 1, "{body}");
+    assert_eq!(rows[0]["metric"], "humidity");
+    assert_eq!(rows[0]["value"], 55.0);
+
+    for uri in ["/api/export?format=xml", "/api/export?metric=vpd", "/api/export?bucket=0"] {
+        let response = router.clone().oneshot(get(uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn cli_history_prints_one_series() {
+    use sensor_server::cli::{HistoryArgs, HistoryFormat};
+
+    let (state, _db) = test_state().await;
+    let args = |metric: &str, format| HistoryArgs {
+        sensor: "1".into(),
+        metric: metric.into(),
+        since: None,
+        until: None,
+        bucket: Some(60),
+        format,
+        output: None,
+    };
+
+    let mut out = Vec::new();
+    let rows = sensor_server::cli::history(&state.db, &args("humidity", None), &mut out).await.unwrap();
+    let table = String::from_utf8(out).unwrap();
+    assert_eq!(rows, 1);
+    assert_eq!(table.lines().count(), 2, "{table}");
+    assert!(table.lines().nth(1).unwrap().ends_with("  55.0%"), "{table}");
+
+    let mut out = Vec::new();
+    let rows = sensor_server::cli::history(&state.db, &args("co2", Some(HistoryFormat::Csv)), &mut out).await.unwrap();
+    assert_eq!((rows, String::from_utf8(out).unwrap()), (0, "timestamp,sensor_id,name,metric,value\n".to_string()));
+}
+
+#[tokio::test]
+async fn import_validates_maps_and_dedupes_rows() {
+    let (state, _db) = test_state().await;
+    let db = state.db.clone();
+    let router = sensor_server::router().with_state(state);
+    let post = |uri: &str, body: String| Request::builder().method("POST").uri(uri).body(Body::from(body)).unwrap();
+
+    // Re-importing the server's own export adds nothing.
+    let export = body_string(
+        router
+            .clone()
+            .oneshot(Request::builder().uri("/api/export").body(Body::empty()).unwrap())
+            .await
+            .unwrap(),
+    )
+    .await;
+    let response = router.clone().oneshot(post("/api/import", export)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let summary: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    assert_eq!((summary["rows"].as_u64(), summary["duplicates"].as_u64()), (Some(3), Some(3)));
+
+    let lines = [
+        r#"{"timestamp":"2024-03-01T12:00:00Z","sensor_id":"0a","name":"old bench","metric":"temperature","value":19.5}"#,
+        r#"{"timestamp":1709294400000,"name":"old bench","metric":"humidity","value":61}"#,
+        r#"{"timestamp":"2024-03-01T12:00:00Z","sensor_id":"0a","metric":"vpd","value":1.1}"#,
+        r#"{"timestamp":"noon","sensor_id":"0a","metric":"humidity","value":61}"#,
+        "not json",
+    ];
+    // `old bench` only resolves by name once the first import has recorded it.
+    let uri = "/api/import?format=jsonl&bucket=60&map=0a=0b";
+    let response = router.clone().oneshot(post(uri, lines.join("\n"))).await.unwrap();
+    let summary: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    assert_eq!(summary["inserted"], 1, "{summary}");
+    assert_eq!(summary["rejected"], 4, "{summary}");
+    let failed: Vec<u64> = summary["errors"].as_array().unwrap().iter().map(|e| e["line"].as_u64().unwrap()).collect();
+    assert_eq!(failed, [2, 3, 4, 5]);
+
+    let response = router.clone().oneshot(post(uri, lines[1].to_string())).await.unwrap();
+    let summary: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+    assert_eq!(summary["inserted"], 1, "{summary}");
+    let record = db.sensor(0x0b).await.unwrap().expect("imported sensor is recorded");
+    assert_eq!(record.name.as_deref(), Some("old bench"));
+
+    let response = router.clone().oneshot(post("/api/import", "time,value\n1,2".into())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST, "CSV header without the needed columns");
+    let response = router.oneshot(post("/api/import?map=zz", String::new())).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn retention_policy_is_replaceable_and_dry_runs_leave_data_alone() {
+    let (state, _db) = test_state().await;
+    let db = state.db.clone();
+    let at = Utc::now() - chrono::Duration::days(10);
+    db.insert_reading(&Reading { sensor_id: 1, kind: ReadingKind::Temperature, value: 18.0, at })
+        .await
+        .unwrap();
+    let router = sensor_server::router().with_state(state);
+    let send = |method: &str, uri: &str, body: &'static str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+    let json = |response: axum::response::Response| async {
+        serde_json::from_str::<serde_json::Value>(&body_string(response).await).unwrap()
+    };
+
+    let policy = json(router.clone().oneshot(send("GET", "/api/retention", "")).await.unwrap()).await;
+    assert_eq!(policy["tiers"].as_array().unwrap().len(), 4, "the default ladder: {policy}");
+    assert_eq!(policy["vacuum"], "off");
+
+    let report = json(router.clone().oneshot(send("GET", "/api/retention/dry-run", "")).await.unwrap()).await;
+    assert_eq!((report["rolled_up"].as_u64(), report["expired"].as_u64()), (Some(1), Some(0)), "{report}");
+    let proposed = r#"{"max_age_secs":604800,"vacuum":"full"}"#;
+    let report = json(router.clone().oneshot(send("POST", "/api/retention/dry-run", proposed)).await.unwrap()).await;
+    assert_eq!((report["rolled_up"].as_u64(), report["expired"].as_u64()), (Some(0), Some(1)), "{report}");
+    assert_eq!(report["tiers"][0]["bucket_secs"], 60);
+    let stored = db.export(&chlorophyll_client::db::ExportQuery::default()).count().await;
+    assert_eq!(stored, 4, "dry runs change nothing");
+
+    let response = router.clone().oneshot(send("PUT", "/api/retention", r#"{"vacuum":"weekly"}"#)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = router
+        .clone()
+        .oneshot(send("PUT", "/api/retention", r#"{"sensors":[{"sensor_id":"01","max_age_secs":0}]}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let misaligned = r#"{"tiers":[{"older_than_secs":3600,"bucket_secs":90}]}"#;
+    let response = router.clone().oneshot(send("PUT", "/api/retention", misaligned)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST, "minute ingest buckets don't tile 90s ones");
+
+    let response = router.clone().oneshot(send("PUT", "/api/retention", proposed)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let policy = json(router.oneshot(send("GET", "/api/retention", "")).await.unwrap()).await;
+    assert_eq!((policy["max_age_secs"].as_i64(), policy["vacuum"].as_str()), (Some(604_800), Some("full")));
+    assert_eq!(db.retention_policy().await.unwrap().unwrap().max_age_secs, Some(604_800));
+}
+
+#[tokio::test]
+async fn ingest_buckets_are_checked_against_the_retention_policy_in_effect() {
+    let (state, temp_db) = test_state().await;
+    let policy = chlorophyll_client::db::RetentionPolicy {
+        tiers: vec![chlorophyll_client::db::RetentionTier { older_than_secs: 3_600, bucket_secs: 90 }],
+        ..Default::default()
+    };
+    state.db.set_retention_policy(&policy).await.unwrap();
+    let config = |text: &str| {
+        let mut config: sensor_server::config::Config = toml::from_str(text).unwrap();
+        config.database = temp_db.0.to_str().unwrap().to_string();
+        config.validate().unwrap();
+        config
+    };
+
+    let stored = config("[ingest]\nbucket_secs = 60\n");
+    assert!(stored.effective_retention(&state.db).await.is_err(), "60s buckets don't tile the stored 90s ones");
+    assert!(stored.store_retention(&state.db).await.is_err());
+    assert!(stored.check_retention().await.is_err());
+    assert!(config("[ingest]\nbucket_secs = 30\n").check_retention().await.is_ok());
+    let replaced = config("[ingest]\nbucket_secs = 60\n[retention]\n");
+    assert!(replaced.store_retention(&state.db).await.unwrap(), "the configured policy replaces the stored one");
+    assert_eq!(state.db.retention_policy().await.unwrap().unwrap().tiers[0].bucket_secs, 60);
+
+    let fresh = sensor_server::config::Config {
+        database: std::env::temp_dir().join("chlorophyll-not-created.db").to_str().unwrap().to_string(),
+        ingest: sensor_server::config::IngestConfig { bucket_secs: 45 },
+        ..Default::default()
+    };
+    assert!(fresh.check_retention().await.is_err(), "45s buckets don't tile the default minute ones");
+    assert!(!std::path::Path::new(&fresh.database).exists(), "checking creates no database");
+}
+
+#[tokio::test]
+async fn shutdown_writes_the_final_partial_bucket() {
+    let (state, temp_db) = test_state().await;
+    let (readings, receiver) = tokio::sync::broadcast::channel(16);
+    let ingest = tokio::spawn(sensor_server::ingest::run(state.clone(), receiver));
+
+    // Half a minute's readings: nothing closes their bucket before the server stops.
+    let at = Utc::now();
+    for value in [10.0, 20.0, 30.0] {
+        readings.send(Reading { sensor_id: 2, kind: ReadingKind::Temperature, value, at }).unwrap();
+    }
+    state.shut_down();
+    tokio::time::timeout(std::time::Duration::from_secs(5), ingest)
+        .await
+        .expect("ingest stops once shut down")
+        .unwrap();
+    state.db.close().await;
+
+    let db = Db::open(temp_db.0.to_str().unwrap()).await.unwrap();
+    let query = chlorophyll_client::db::ExportQuery { sensor_id: Some(2), ..Default::default() };
+    let rows: Vec<_> = db.export(&query).map(Result::unwrap).collect().await;
+    assert_eq!(rows.len(), 1, "{rows:?}");
+    assert!((rows[0].value - 20.0).abs() < 1e-4, "the partial bucket's mean: {rows:?}");
 }
@@ -0,0 +1,51 @@
+//! The MQTT bridge loop driven through its channels, standing in for a broker.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chlorophyll_client::db::Db;
+use chlorophyll_client::{ClientConfig, SensorClient};
+use sensor_server::AppState;
+use sensor_server::mqtt::{self, Incoming, MqttConfig};
+use tokio::sync::mpsc;
+
+#[tokio::test]
+async fn bridge_announces_itself_on_connect_and_stops_with_the_broker() {
+    let path = std::env::temp_dir().join(format!("chlorophyll-mqtt-{}.db", std::process::id()));
+    let db = Db::open(path.to_str().unwrap()).await.unwrap();
+    let client = Arc::new(SensorClient::start(ClientConfig { port: 0, ..ClientConfig::default() }).unwrap());
+    let state = AppState::new(client, db);
+
+    let mut cfg = MqttConfig::new("in-process");
+    cfg.prefix = "test".to_string();
+    let (outgoing_tx, mut outgoing_rx) = mpsc::channel(16);
+    let (incoming_tx, incoming_rx) = mpsc::channel(16);
+    let bridge = tokio::spawn(mqtt::bridge(state, cfg, outgoing_tx, incoming_rx));
+
+    incoming_tx.send(Incoming::Connected).await.unwrap();
+    let announced = tokio::time::timeout(Duration::from_secs(5), outgoing_rx.recv())
+        .await
+        .expect("bridge published within 5s")
+        .unwrap();
+    assert_eq!(announced.topic, "test/bridge/availability");
+    assert_eq!(announced.payload, "online");
+    assert!(announced.retain);
+
+    // Malformed commands are logged and ignored rather than ending the loop.
+    incoming_tx
+        .send(Incoming::Publish { topic: "test/not-an-id/set_name".into(), payload: b"x".to_vec() })
+        .await
+        .unwrap();
+
+    drop(incoming_tx);
+    tokio::time::timeout(Duration::from_secs(5), bridge)
+        .await
+        .expect("bridge exits once the broker side closes")
+        .unwrap();
+
+    for suffix in ["", "-wal", "-shm"] {
+        let mut p = path.clone().into_os_string();
+        p.push(suffix);
+        let _ = std::fs::remove_file(p);
+    }
+}
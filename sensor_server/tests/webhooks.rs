@@ -0,0 +1,154 @@
+//! Webhook delivery against a real local HTTP receiver: retries, signature and the
+//! delivery log.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::Router;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use chlorophyll_client::db::{Db, WebhookTarget};
+use chrono::Utc;
+use sensor_server::webhooks::{self, EVENT_HEADER, Notification, RetryPolicy, SIGNATURE_HEADER};
+
+/// Removes the sqlite db file (and its `-wal`/`-shm` siblings) when dropped.
+struct TempDb(std::path::PathBuf);
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.0.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+async fn temp_db(tag: &str) -> (Db, TempDb) {
+    let path = std::env::temp_dir().join(format!(
+        "chlorophyll-webhook-{tag}-{}-{}.db",
+        std::process::id(),
+        Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    ));
+    let db = Db::open(path.to_str().unwrap()).await.unwrap();
+    (db, TempDb(path))
+}
+
+#[derive(Clone, Default)]
+struct Receiver {
+    /// Requests to answer with 500 before accepting.
+    fail_first: usize,
+    calls: Arc<AtomicUsize>,
+    received: Arc<Mutex<Vec<(HeaderMap, String)>>>,
+}
+
+async fn receive(State(receiver): State<Receiver>, headers: HeaderMap, body: String) -> StatusCode {
+    let call = receiver.calls.fetch_add(1, Ordering::SeqCst);
+    receiver.received.lock().unwrap().push((headers, body));
+    if call < receiver.fail_first {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        StatusCode::NO_CONTENT
+    }
+}
+
+/// Serve `receiver` on an ephemeral local port and return the hook URL.
+async fn spawn_receiver(receiver: Receiver) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new().route("/hook", post(receive)).with_state(receiver);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{addr}/hook")
+}
+
+fn target(url: String, secret: Option<&str>) -> WebhookTarget {
+    WebhookTarget {
+        id: 0,
+        url,
+        secret: secret.map(str::to_string),
+        sensor_id: None,
+        kind: None,
+        above: None,
+        below: None,
+        hysteresis: 0.0,
+        enabled: true,
+    }
+}
+
+fn notification() -> Notification {
+    Notification {
+        event: "sensor.renamed",
+        sensor_id: 7,
+        id_hex: format!("{:032x}", 7_u128),
+        name: Some("bench".into()),
+        t: 1_700_000_000_000,
+        metric: None,
+        value: None,
+        rule_id: None,
+    }
+}
+
+const FAST_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    base_delay: Duration::from_millis(10),
+    max_delay: Duration::from_millis(50),
+};
+
+#[tokio::test]
+async fn failed_delivery_is_retried_signed_and_logged() {
+    let (db, _guard) = temp_db("retry").await;
+    let receiver = Receiver { fail_first: 1, ..Receiver::default() };
+    let url = spawn_receiver(receiver.clone()).await;
+
+    let mut target = target(url, Some("s3cret"));
+    target.id = db.insert_webhook_target(&target).await.unwrap();
+
+    let http = reqwest::Client::new();
+    let delivery = webhooks::deliver(&http, &db, &target, &notification(), FAST_RETRY).await;
+    assert!(delivery.delivered);
+    assert_eq!(delivery.attempts, 2);
+    assert_eq!(delivery.status, Some(204));
+
+    let received = receiver.received.lock().unwrap().clone();
+    assert_eq!(received.len(), 2);
+    let (headers, body) = &received[1];
+    assert_eq!(headers[EVENT_HEADER], "sensor.renamed");
+    assert_eq!(headers[SIGNATURE_HEADER], webhooks::sign("s3cret", body.as_bytes()).as_str());
+    let json: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(json["event"], "sensor.renamed");
+    assert_eq!(json["name"], "bench");
+    assert_eq!(json["id_hex"], format!("{:032x}", 7_u128));
+
+    let log = db.webhook_deliveries(target.id, 10).await.unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].id, delivery.id);
+    assert!(log[0].delivered);
+    assert_eq!(log[0].attempts, 2);
+}
+
+#[tokio::test]
+async fn exhausted_retries_are_logged_as_failed() {
+    let (db, _guard) = temp_db("fail").await;
+    let receiver = Receiver { fail_first: usize::MAX, ..Receiver::default() };
+    let url = spawn_receiver(receiver.clone()).await;
+
+    let mut target = target(url, None);
+    target.id = db.insert_webhook_target(&target).await.unwrap();
+
+    let http = reqwest::Client::new();
+    let delivery = webhooks::deliver(&http, &db, &target, &notification(), FAST_RETRY).await;
+    assert!(!delivery.delivered);
+    assert_eq!(delivery.attempts, 3);
+    assert_eq!(receiver.calls.load(Ordering::SeqCst), 3);
+
+    let received = receiver.received.lock().unwrap().clone();
+    assert!(received.iter().all(|(headers, _)| !headers.contains_key(SIGNATURE_HEADER)));
+
+    let log = db.webhook_deliveries(target.id, 10).await.unwrap();
+    assert_eq!(log.len(), 1);
+    assert!(!log[0].delivered);
+    assert_eq!(log[0].status, Some(500));
+    assert!(log[0].error.is_some());
+}
@@ -1,11 +1,12 @@
 use crate::event::{AppEvent, Event, EventHandler};
 use crate::log_widget::LogState;
-use chlorophyll_client::db::Db;
+use chlorophyll_client::db::{BatchWriter, Db, WriterConfig};
+use chlorophyll_client::rollup::Averaged;
 use chlorophyll_client::{ClientConfig, Reading, SensorClient};
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use ratatui::DefaultTerminal;
 use tokio::sync::broadcast;
-use tracing::*;
+use tracing::{error, info, warn};
 
 /// Keep up to ~24 h of readings at ~1 reading/sensor/5 s (generous headroom).
 const MAX_READINGS: usize = 100_000;
@@ -22,7 +23,8 @@ pub struct App {
 
     pub client: Option<SensorClient>,
     pub readings_rx: Option<broadcast::Receiver<Reading>>,
-    pub db: Option<Db>,
+    /// Persists raw readings in batches.
+    pub writer: Option<BatchWriter>,
     pub last_reading: Vec<Reading>,
     pub log_state: LogState,
 }
@@ -35,7 +37,7 @@ impl Default for App {
             events: EventHandler::new(),
             client: None,
             readings_rx: None,
-            db: None,
+            writer: None,
             last_reading: Vec::new(),
             log_state: LogState::new(true),
         }
@@ -44,6 +46,7 @@ impl Default for App {
 
 impl App {
     /// Constructs a new instance of [`App`].
+    #[must_use]
     pub fn new(log_state: LogState) -> Self {
         Self { log_state, ..Self::default() }
     }
@@ -55,7 +58,7 @@ impl App {
         match Db::open(&db_path).await {
             Ok(db) => {
                 info!("Database opened at {db_path}");
-                self.db = Some(db);
+                self.writer = Some(db.writer(WriterConfig::default()));
             }
             Err(e) => error!("Failed to open database at {db_path}: {e}"),
         }
@@ -76,7 +79,7 @@ impl App {
                     crossterm::event::Event::Key(key_event)
                         if key_event.kind == KeyEventKind::Press =>
                     {
-                        self.handle_key_events(key_event)?
+                        self.handle_key_events(key_event)?;
                     }
                     _ => {}
                 },
@@ -87,6 +90,9 @@ impl App {
                 },
             }
         }
+        if let Some(writer) = &self.writer {
+            writer.close().await;
+        }
         Ok(())
     }
 
@@ -95,7 +101,7 @@ impl App {
         match key_event.code {
             KeyCode::Esc | KeyCode::Char('q') => self.events.send(AppEvent::Quit),
             KeyCode::Char('c' | 'C') if key_event.modifiers == KeyModifiers::CONTROL => {
-                self.events.send(AppEvent::Quit)
+                self.events.send(AppEvent::Quit);
             }
             KeyCode::Char('r' | 'R') if key_event.modifiers == KeyModifiers::CONTROL => {
                 self.last_reading.clear();
@@ -103,26 +109,10 @@ impl App {
             KeyCode::Char('L') if key_event.modifiers == KeyModifiers::SHIFT => {
                 self.log_state.toggle();
             }
-            KeyCode::Up => {
-                if self.log_state.enabled {
-                    self.log_state.scroll_up(1);
-                }
-            }
-            KeyCode::Down => {
-                if self.log_state.enabled {
-                    self.log_state.scroll_down(1);
-                }
-            }
-            KeyCode::PageUp => {
-                if self.log_state.enabled {
-                    self.log_state.scroll_up(10);
-                }
-            }
-            KeyCode::PageDown => {
-                if self.log_state.enabled {
-                    self.log_state.scroll_down(10);
-                }
-            }
+            KeyCode::Up if self.log_state.enabled => self.log_state.scroll_up(1),
+            KeyCode::Down if self.log_state.enabled => self.log_state.scroll_down(1),
+            KeyCode::PageUp if self.log_state.enabled => self.log_state.scroll_up(10),
+            KeyCode::PageDown if self.log_state.enabled => self.log_state.scroll_down(10),
             KeyCode::Right => self.events.send(AppEvent::Increment),
             KeyCode::Left => self.events.send(AppEvent::Decrement),
             _ => {}
@@ -140,17 +130,16 @@ impl App {
         loop {
             match rx.try_recv() {
                 Ok(reading) => new_readings.push(reading),
-                Err(broadcast::error::TryRecvError::Empty) => break,
                 Err(broadcast::error::TryRecvError::Lagged(n)) => {
                     warn!("readings channel lagged, dropped {n} messages");
                 }
-                Err(broadcast::error::TryRecvError::Closed) => break,
+                Err(broadcast::error::TryRecvError::Empty | broadcast::error::TryRecvError::Closed) => break,
             }
         }
 
-        if let Some(db) = &self.db {
+        if let Some(writer) = &self.writer {
             for reading in &new_readings {
-                if let Err(e) = db.insert_reading(reading).await {
+                if let Err(e) = writer.write(Averaged::from(reading.clone()), 0).await {
                     error!("DB insert error: {e}");
                 }
             }
@@ -1,7 +1,6 @@
 use std::collections::VecDeque;
-use std::sync::Mutex;
+use std::sync::{LazyLock, Mutex};
 
-use lazy_static::lazy_static;
 use ratatui::{
     buffer::Buffer,
     layout::Rect,
@@ -9,9 +8,8 @@ use ratatui::{
     widgets::{Block, BorderType, List, ListItem, Widget},
 };
 
-lazy_static! {
-    pub static ref LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::with_capacity(1000));
-}
+pub static LOGS: LazyLock<Mutex<VecDeque<String>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::with_capacity(1000)));
 
 #[derive(Debug, Clone)]
 pub struct LogState {
@@ -20,6 +18,7 @@ pub struct LogState {
 }
 
 impl LogState {
+    #[must_use]
     pub fn new(enabled: bool) -> Self {
         Self { enabled, scroll: 0 }
     }
@@ -36,6 +35,11 @@ impl LogState {
         self.scroll = self.scroll.saturating_sub(amount);
     }
 
+    /// The captured log lines.
+    ///
+    /// # Panics
+    ///
+    /// If a thread panicked while appending to the log.
     pub fn logs(&self) -> std::sync::MutexGuard<'static, VecDeque<String>> {
         LOGS.lock().unwrap()
     }
@@ -55,6 +59,7 @@ impl Default for LogDebugWidget {
 }
 
 impl LogDebugWidget {
+    #[must_use]
     pub fn new(title: &str) -> Self {
         Self {
             title: title.to_string(),
@@ -76,9 +81,11 @@ impl Widget for &LogDebugWidget {
         block.render(area, buf);
 
         let log_items: Vec<ListItem> =
-            std::iter::repeat(ListItem::new("").style(Style::default().fg(Color::Gray)))
-                .take(inner_area.height as usize)
-                .collect();
+            std::iter::repeat_n(
+                ListItem::new("").style(Style::default().fg(Color::Gray)),
+                inner_area.height as usize,
+            )
+            .collect();
 
         let list = List::new(log_items)
             .block(Block::new())
@@ -95,6 +102,7 @@ pub struct LogListWidget<'a> {
 }
 
 impl<'a> LogListWidget<'a> {
+    #[must_use]
     pub fn new(logs: &'a VecDeque<String>, title: &str, scroll_offset: u16) -> Self {
         Self {
             logs,
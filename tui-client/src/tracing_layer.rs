@@ -6,7 +6,14 @@ use crate::log_widget::LOGS;
 
 pub struct TuiLayer;
 
+impl Default for TuiLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TuiLayer {
+    #[must_use]
     pub fn new() -> Self {
         Self
     }
@@ -30,9 +37,9 @@ where
 
         let ts = Local::now().format("%H:%M:%S%.3f");
         let log_entry = if message.is_empty() {
-            format!("{} [{}] {} ({}:{})", ts, level, target, file, line)
+            format!("{ts} [{level}] {target} ({file}:{line})")
         } else {
-            format!("{} [{}] {}: {} ({}:{})", ts, level, target, message, file, line)
+            format!("{ts} [{level}] {target}: {message} ({file}:{line})")
         };
 
         if let Ok(mut logs) = LOGS.lock() {
@@ -48,7 +55,7 @@ struct MessageVisitor<'a> {
     message: &'a mut String,
 }
 
-impl<'a> tracing::field::Visit for MessageVisitor<'a> {
+impl tracing::field::Visit for MessageVisitor<'_> {
     fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
         if field.name() == "message" {
             self.message.push_str(value);
@@ -58,7 +65,7 @@ impl<'a> tracing::field::Visit for MessageVisitor<'a> {
     fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
         if field.name() == "message" || field.name() == "value" {
             use std::fmt::Write;
-            let _ = write!(self.message, "{:?}", value);
+            let _ = write!(self.message, "{value:?}");
         }
     }
 }
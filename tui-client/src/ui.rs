@@ -1,13 +1,11 @@
-use std::collections::HashMap;
+// Chart coordinates are plain f64 seconds; precision loss past 2^52 s is irrelevant here.
+#![allow(clippy::cast_precision_loss, clippy::too_many_lines, clippy::type_complexity)]
+
+use std::collections::{BTreeMap, HashMap};
 use std::rc::Rc;
 
 use chlorophyll_client::ReadingKind;
 use chrono::{DateTime, Local, Utc};
-
-/// Convert a canonical Celsius reading value to Fahrenheit for display.
-fn celsius_to_f(c: f32) -> f32 {
-    c * 9.0 / 5.0 + 32.0
-}
 use ratatui::{
     buffer::Buffer,
     layout::{Constraint, Direction, Layout, Rect},
@@ -20,6 +18,112 @@ use ratatui::{
 use crate::app::App;
 use crate::log_widget::LogListWidget;
 
+/// Chart colours, assigned by position in [`ReadingKind::ALL`] so a metric keeps its colour.
+const COLORS: [Color; 8] = [
+    Color::Yellow,
+    Color::Blue,
+    Color::Cyan,
+    Color::Green,
+    Color::Magenta,
+    Color::LightYellow,
+    Color::LightRed,
+    Color::LightBlue,
+];
+
+fn color(kind: ReadingKind) -> Color {
+    let index = ReadingKind::ALL.iter().position(|k| *k == kind).unwrap_or_default();
+    COLORS[index % COLORS.len()]
+}
+
+/// Convert a canonical Celsius reading value to Fahrenheit for display.
+fn celsius_to_f(c: f32) -> f32 {
+    c * 9.0 / 5.0 + 32.0
+}
+
+/// A reading as shown here: temperatures in Fahrenheit, everything else as stored.
+fn display_value(kind: ReadingKind, value: f32) -> f32 {
+    if kind == ReadingKind::Temperature { celsius_to_f(value) } else { value }
+}
+
+fn display_suffix(kind: ReadingKind) -> String {
+    if kind == ReadingKind::Temperature { "°F".into() } else { kind.info().suffix() }
+}
+
+fn format_value(kind: ReadingKind, value: f32) -> String {
+    let precision = kind.info().precision;
+    format!("{:.precision$}{}", display_value(kind, value), display_suffix(kind))
+}
+
+/// One metric's history chart, with the y-axis fitted to `points` but never narrower than
+/// the metric's chart span.
+fn metric_chart<'a>(kind: ReadingKind, points: &'a [(f64, f64)], x_bounds: [f64; 2], x_labels: Vec<Line<'a>>) -> Chart<'a> {
+    let info = kind.info();
+    let span = f64::from(info.chart_span);
+    let y_min = points.iter().map(|(_, y)| *y).fold(f64::INFINITY, f64::min);
+    let y_max = points.iter().map(|(_, y)| *y).fold(f64::NEG_INFINITY, f64::max);
+    let (y_min, y_max) = if y_max - y_min < span {
+        let mid = f64::midpoint(y_min, y_max);
+        (mid - span / 2.0, mid + span / 2.0)
+    } else {
+        let padding = (y_max - y_min) * 0.1;
+        (y_min - padding, y_max + padding)
+    };
+
+    let precision = info.precision;
+    let suffix = display_suffix(kind);
+    let y_title = points
+        .last()
+        .map_or_else(|| suffix.trim().to_string(), |(_, v)| format!("{v:.precision$}{suffix}"));
+
+    let dataset = Dataset::default()
+        .name(info.name)
+        .style(Style::default().fg(color(kind)))
+        .graph_type(GraphType::Line)
+        .marker(Marker::Braille)
+        .data(points);
+
+    Chart::new(vec![dataset])
+        .block(Block::bordered().title(info.name).border_type(BorderType::Rounded))
+        .x_axis(
+            Axis::default()
+                .style(Style::default().fg(Color::Gray))
+                .bounds(x_bounds)
+                .labels(x_labels),
+        )
+        .y_axis(
+            Axis::default()
+                .title(y_title)
+                .style(Style::default().fg(Color::Gray))
+                .bounds([y_min, y_max])
+                .labels(vec![
+                    Line::from(format!("{y_min:.precision$}")),
+                    Line::from(format!("{:.precision$}", f64::midpoint(y_min, y_max))),
+                    Line::from(format!("{y_max:.precision$}")),
+                ]),
+        )
+}
+
+/// Split `area` into `n` chart cells: stacked for up to three, otherwise two columns.
+fn chart_cells(area: Rect, n: usize) -> Vec<Rect> {
+    let columns = if n > 3 { 2 } else { 1 };
+    let rows = n.div_ceil(columns);
+    let row_areas = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Fill(1); rows])
+        .split(area);
+    row_areas
+        .iter()
+        .flat_map(|row| {
+            Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Fill(1); columns])
+                .split(*row)
+                .to_vec()
+        })
+        .take(n)
+        .collect()
+}
+
 impl Widget for &App {
     fn render(self, area: Rect, buf: &mut Buffer) {
         // Outer: content + optional log
@@ -40,104 +144,56 @@ impl Widget for &App {
             .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
             .split(content_area);
 
-        let sensor_area = cols[0];
-
-        // Right column: temp/humidity on top, light on bottom
-        let right_rows = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
-            .split(cols[1]);
-
-        let (temp_area, light_area) = (right_rows[0], right_rows[1]);
+        let (sensor_area, chart_area) = (cols[0], cols[1]);
 
         let now = Utc::now();
         let x_end = now.timestamp() as f64;
 
-        let window: Vec<_> = self.last_reading.iter().collect();
-
-        // --- Data extraction (x = Unix timestamp) ---
-
-        let temperatures: Vec<(f64, f64)> = window
-            .iter()
-            .filter_map(|entry| {
-                if entry.kind == ReadingKind::Temperature {
-                    Some((entry.at.timestamp() as f64, celsius_to_f(entry.value) as f64))
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        let humidities: Vec<(f64, f64)> = window
-            .iter()
-            .filter_map(|entry| {
-                if entry.kind == ReadingKind::Humidity {
-                    Some((entry.at.timestamp() as f64, entry.value as f64))
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        let lights: Vec<(f64, f64)> = window
-            .iter()
-            .filter_map(|entry| {
-                if entry.kind == ReadingKind::Light {
-                    Some((entry.at.timestamp() as f64, entry.value as f64))
-                } else {
-                    None
-                }
-            })
-            .collect();
+        // --- Data extraction (x = Unix timestamp), one series per metric seen ---
+        let mut series: BTreeMap<ReadingKind, Vec<(f64, f64)>> = BTreeMap::new();
+        for entry in &self.last_reading {
+            series
+                .entry(entry.kind)
+                .or_default()
+                .push((entry.at.timestamp() as f64, f64::from(display_value(entry.kind, entry.value))));
+        }
 
-        // --- Sensor summary map: (temp_f, humidity_pct, lux, last_seen) ---
-        let mut sensor_map: HashMap<u128, (Option<f32>, Option<f32>, Option<f32>, Option<DateTime<Utc>>)> = HashMap::new();
+        // --- Sensor summary map: latest value per metric, and last seen ---
+        let mut sensor_map: HashMap<u128, (BTreeMap<ReadingKind, f32>, Option<DateTime<Utc>>)> = HashMap::new();
         for entry in self.last_reading.iter().rev() {
-            let e = sensor_map.entry(entry.sensor_id).or_default();
-            match entry.kind {
-                ReadingKind::Temperature if e.0.is_none() => {
-                    e.0 = Some(celsius_to_f(entry.value));
-                }
-                ReadingKind::Humidity if e.1.is_none() => {
-                    e.1 = Some(entry.value);
-                }
-                ReadingKind::Light if e.2.is_none() => {
-                    e.2 = Some(entry.value);
-                }
-                _ => {}
-            }
+            let (values, last_seen) = sensor_map.entry(entry.sensor_id).or_default();
+            values.entry(entry.kind).or_insert(entry.value);
             // Record most-recent timestamp (first time we see this sensor when iterating rev)
-            if e.3.is_none() {
-                e.3 = Some(entry.at);
+            if last_seen.is_none() {
+                *last_seen = Some(entry.at);
             }
         }
 
         // --- Left panel: sensor list ---
         let mut sensor_ids: Vec<u128> = sensor_map.keys().copied().collect();
-        sensor_ids.sort();
+        sensor_ids.sort_unstable();
 
         let items: Vec<ListItem> = sensor_ids
             .iter()
             .map(|id| {
-                let (temp, hum, lux, last_seen) = sensor_map[id];
-                let temp_str = temp.map_or("--".into(), |v| format!("{:.1}°F", v));
-                let hum_str = hum.map_or("--".into(), |v| format!("{:.1}%", v));
-                let lux_str = lux.map_or("--".into(), |v| format!("{:.0}lx", v));
+                let (values, last_seen) = &sensor_map[id];
                 let age_str = last_seen.map_or("--".into(), |ts| {
                     let secs = (now - ts).num_seconds().max(0);
                     if secs < 60 {
-                        format!("{}s", secs)
+                        format!("{secs}s")
                     } else if secs < 3600 {
                         format!("{}m{}s", secs / 60, secs % 60)
                     } else {
                         format!("{}h{}m", secs / 3600, (secs % 3600) / 60)
                     }
                 });
-                let text = format!(
-                    "{:16x} {} {} {} {}",
-                    id & 0xFFFFFFFFFFFFFFFF,
-                    temp_str, hum_str, lux_str, age_str
-                );
+                let mut text = format!("{:16x}", id & 0xFFFF_FFFF_FFFF_FFFF);
+                for (&kind, &value) in values {
+                    text.push(' ');
+                    text.push_str(&format_value(kind, value));
+                }
+                text.push(' ');
+                text.push_str(&age_str);
                 ListItem::new(text)
             })
             .collect();
@@ -149,146 +205,30 @@ impl Widget for &App {
         );
         sensor_list.render(sensor_area, buf);
 
-        // --- Center panel: Temp & Humidity chart ---
-        let temp_dataset = Dataset::default()
-            .name("Temp (°F)")
-            .style(Style::default().fg(Color::Yellow))
-            .graph_type(GraphType::Line)
-            .marker(Marker::Braille)
-            .data(&temperatures);
-
-        let hum_dataset = Dataset::default()
-            .name("Humidity (%)")
-            .style(Style::default().fg(Color::Blue))
-            .graph_type(GraphType::Line)
-            .marker(Marker::Braille)
-            .data(&humidities);
-
-        // Y-bounds across both temperature and humidity
-        let all_y = temperatures
-            .iter()
-            .chain(humidities.iter())
-            .map(|(_, y)| *y);
-        let cy_min = all_y.clone().fold(f64::INFINITY, f64::min);
-        let cy_max = all_y.fold(f64::NEG_INFINITY, f64::max);
-
-        let (cy_min, cy_max) = if temperatures.is_empty() && humidities.is_empty() {
-            (0.0, 100.0)
-        } else if (cy_max - cy_min).abs() < 1.0 {
-            (cy_min - 5.0, cy_max + 5.0)
+        // --- Right panel: a chart per metric ---
+        if series.is_empty() {
+            Block::bordered()
+                .title("Waiting for readings")
+                .border_type(BorderType::Rounded)
+                .render(chart_area, buf);
         } else {
-            let padding = (cy_max - cy_min) * 0.1;
-            (cy_min - padding, cy_max + padding)
-        };
-
-        // x_start = timestamp of the oldest reading, or 1 min before now if no data
-        let x_start = window
-            .first()
-            .map(|e| e.at.timestamp() as f64)
-            .unwrap_or(x_end - 60.0);
-
-        let x_labels: Vec<Line> = vec![
-            window
+            // x_start = timestamp of the oldest reading
+            let x_start = self
+                .last_reading
+                .first()
+                .map_or(x_end - 60.0, |e| e.at.timestamp() as f64);
+            let start_label = self
+                .last_reading
                 .first()
                 .map(|e| e.at.with_timezone(&Local).format("%H:%M").to_string())
-                .unwrap_or_else(|| "No data".into())
-                .bold()
-                .into(),
-            now.with_timezone(&Local)
-                .format("%H:%M")
-                .to_string()
-                .bold()
-                .into(),
-        ];
-
-        let cy_mid = (cy_min + cy_max) / 2.0;
-
-        let cur_temp = temperatures.last().map(|(_, v)| *v);
-        let cur_hum = humidities.last().map(|(_, v)| *v);
-        let cy_title = match (cur_temp, cur_hum) {
-            (Some(t), Some(h)) => format!("{:.1}°F / {:.1}%", t, h),
-            (Some(t), None) => format!("{:.1}°F / %", t),
-            (None, Some(h)) => format!("°F / {:.1}%", h),
-            (None, None) => "°F / %".into(),
-        };
-
-        let temp_chart = Chart::new(vec![temp_dataset, hum_dataset])
-            .block(
-                Block::bordered()
-                    .title("Temp & Humidity")
-                    .border_type(BorderType::Rounded),
-            )
-            .x_axis(
-                Axis::default()
-                    .title("Time")
-                    .style(Style::default().fg(Color::Gray))
-                    .bounds([x_start, x_end])
-                    .labels(x_labels),
-            )
-            .y_axis(
-                Axis::default()
-                    .title(cy_title)
-                    .style(Style::default().fg(Color::Gray))
-                    .bounds([cy_min, cy_max])
-                    .labels(vec![
-                        Line::from(format!("{:.1}", cy_min)),
-                        Line::from(format!("{:.1}", cy_mid)),
-                        Line::from(format!("{:.1}", cy_max)),
-                    ]),
-            );
-        temp_chart.render(temp_area, buf);
-
-        // --- Right panel: Light chart ---
-        let light_dataset = Dataset::default()
-            .name("Lux")
-            .style(Style::default().fg(Color::Cyan))
-            .graph_type(GraphType::Line)
-            .marker(Marker::Braille)
-            .data(&lights);
+                .unwrap_or_default();
+            let end_label = now.with_timezone(&Local).format("%H:%M").to_string();
 
-        let ly_min = lights.iter().map(|(_, y)| *y).fold(f64::INFINITY, f64::min);
-        let ly_max = lights
-            .iter()
-            .map(|(_, y)| *y)
-            .fold(f64::NEG_INFINITY, f64::max);
-
-        let (ly_min, ly_max) = if lights.is_empty() {
-            (0.0, 1000.0)
-        } else if (ly_max - ly_min).abs() < 1.0 {
-            (ly_min - 5.0, ly_max + 5.0)
-        } else {
-            let padding = (ly_max - ly_min) * 0.1;
-            (ly_min - padding, ly_max + padding)
-        };
-
-        let cur_lux = lights.last().map(|(_, v)| *v);
-        let ly_title = cur_lux.map_or("lux".into(), |v| format!("{:.0} lux", v));
-
-        let light_chart = Chart::new(vec![light_dataset])
-            .block(
-                Block::bordered()
-                    .title("Light")
-                    .border_type(BorderType::Rounded),
-            )
-            .x_axis(
-                Axis::default()
-                    .title("Time")
-                    .style(Style::default().fg(Color::Gray))
-                    .bounds([x_start, x_end])
-                    .labels(Vec::<Line>::new()),
-            )
-            .y_axis(
-                Axis::default()
-                    .title(ly_title)
-                    .style(Style::default().fg(Color::Gray))
-                    .bounds([ly_min, ly_max])
-                    .labels(vec![
-                        Line::from(format!("{:.0}", ly_min)),
-                        Line::from(format!("{:.0}", (ly_min + ly_max) / 2.0)),
-                        Line::from(format!("{:.0}", ly_max)),
-                    ]),
-            );
-        light_chart.render(light_area, buf);
+            for ((kind, points), cell) in series.iter().zip(chart_cells(chart_area, series.len())) {
+                let x_labels = vec![start_label.clone().bold().into(), end_label.clone().bold().into()];
+                metric_chart(*kind, points, [x_start, x_end], x_labels).render(cell, buf);
+            }
+        }
 
         if self.log_state.enabled && outer_chunks.len() > 1 {
             let log_area = outer_chunks[1];
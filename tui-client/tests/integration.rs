@@ -8,9 +8,8 @@
 /// replaced with a direct unicast send.
 use chlorophyll_client::registry::{dispatch, Registry};
 use chlorophyll_client::ReadingKind;
-use chlorophyll_protocol::postcard::{from_bytes, to_allocvec};
 use chlorophyll_protocol::temperature::Celsius;
-use chlorophyll_protocol::{DataType, Packet, PacketBuilder, PacketCommand};
+use chlorophyll_protocol::{DataType, Packet, PacketBuilder, PacketCommand, SensorInfo};
 use chrono::Utc;
 use tokio::net::UdpSocket;
 
@@ -29,18 +28,18 @@ async fn test_sensor_info_and_streaming() {
     let device_handle = tokio::spawn(async move {
         let mut buf = [0u8; 1500];
         let (len, src) = device_socket.recv_from(&mut buf).await.unwrap();
-        let packet = from_bytes::<Packet>(&buf[..len]).unwrap();
+        let packet = Packet::decode(&buf[..len]).unwrap().unwrap();
         assert_eq!(packet.command(), &PacketCommand::RequestSensorInfo);
 
         // Reply with SensorsInfo, then immediately stream DataReadings back to
         // the server (simulating multicast with a direct unicast send).
-        let resp = packet_builder.build(PacketCommand::SensorsInfo(Some("greenhouse".into())));
-        device_socket.send_to(&to_allocvec(&resp).unwrap(), src).await.unwrap();
+        let resp = packet_builder.build(PacketCommand::SensorsInfo(SensorInfo::new(Some("greenhouse".into()))));
+        device_socket.send_to(&resp.encode().unwrap(), src).await.unwrap();
 
         for i in 0..N_READINGS {
             let reading = DataType::Temperature(Celsius::new(20.0 + i as f32));
             let pkt = packet_builder.build(PacketCommand::DataReading(reading));
-            device_socket.send_to(&to_allocvec(&pkt).unwrap(), src).await.unwrap();
+            device_socket.send_to(&pkt.encode().unwrap(), src).await.unwrap();
         }
     });
 
@@ -53,14 +52,14 @@ async fn test_sensor_info_and_streaming() {
     // Send RequestSensorInfo directly to the fake device (unicast stand-in for
     // the multicast broadcast that the listener thread normally sends).
     let request = Packet::new(PacketCommand::RequestSensorInfo, 0);
-    server_socket.send_to(&to_allocvec(&request).unwrap(), device_addr).await.unwrap();
+    server_socket.send_to(&request.encode().unwrap(), device_addr).await.unwrap();
 
     // Poll until we have N readings (or 5 s timeout).
     let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(5);
     let mut buf = [0u8; 1500];
     loop {
         let (len, _src) = server_socket.recv_from(&mut buf).await.unwrap();
-        let packet = from_bytes::<Packet>(&buf[..len]).unwrap();
+        let packet = Packet::decode(&buf[..len]).unwrap().unwrap();
         if let Some(reading) = dispatch(&mut registry, &packet, Utc::now()) {
             readings.push(reading);
         }